@@ -1,4 +1,5 @@
 use super::client::{ApiClient, ApiClientError, ApiResult, GenericApiClient, HandleResponse};
+use super::retry::RetryPolicy;
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -16,8 +17,12 @@ impl ApiClient for AssetsClient {
         self.inner.client()
     }
 
+    fn retry_policy(&self) -> &RetryPolicy {
+        self.inner.retry_policy()
+    }
+
     fn base_url(&self) -> String {
-        let domain = std::env::var("EV_DOMAIN").unwrap_or_else(|_| String::from("evervault.com"));
+        let domain = super::client::target_domain();
         format!("https://cage-build-assets.{}", domain)
     }
 
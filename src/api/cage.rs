@@ -1,6 +1,7 @@
 use crate::config::ValidatedCageBuildConfig;
 
 use super::client::{ApiClient, ApiClientError, ApiResult, GenericApiClient, HandleResponse};
+use super::retry::RetryPolicy;
 use super::AuthMode;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
@@ -23,10 +24,18 @@ impl ApiClient for CagesClient {
         self.inner.client()
     }
 
+    fn retry_policy(&self) -> &RetryPolicy {
+        self.inner.retry_policy()
+    }
+
     fn base_url(&self) -> String {
         let api_base = self.inner.base_url();
         format!("{}/v2/cages", api_base)
     }
+
+    fn keys_url(&self) -> String {
+        self.inner.keys_url()
+    }
 }
 
 impl CagesClient {
@@ -36,6 +45,18 @@ impl CagesClient {
         }
     }
 
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.inner = self.inner.with_base_url(base_url);
+        self
+    }
+
+    /// A copy of the client which doesn't send credentials, for public endpoints such as App keys
+    pub fn without_auth(&self) -> Self {
+        Self {
+            inner: self.inner.clone().with_auth(AuthMode::NoAuth),
+        }
+    }
+
     pub async fn create_cage(&self, cage_create_payload: CreateCageRequest) -> ApiResult<Cage> {
         let create_cage_url = format!("{}/", self.base_url());
         self.post(&create_cage_url)
@@ -111,6 +132,7 @@ impl CagesClient {
             .send()
             .await
             .handle_no_op_response()
+            .await
     }
 
     pub async fn delete_env_var(&self, cage_uuid: String, name: String) -> ApiResult<()> {
@@ -119,6 +141,7 @@ impl CagesClient {
             .send()
             .await
             .handle_no_op_response()
+            .await
     }
 
     pub async fn get_cage_env(&self, cage_uuid: String) -> ApiResult<CageEnv> {
@@ -197,17 +220,18 @@ impl CagesClient {
         cage_uuid: &str,
         start_time: u128,
         end_time: u128,
+        next_token: Option<&str>,
     ) -> ApiResult<CageLogs> {
         let get_logs_url = format!(
             "{}/{}/logs?startTime={start_time}&endTime={end_time}",
             self.base_url(),
             cage_uuid
         );
-        self.get(&get_logs_url)
-            .send()
-            .await
-            .handle_json_response()
-            .await
+        let mut request = self.get(&get_logs_url);
+        if let Some(next_token) = next_token {
+            request = request.query(&[("nextToken", next_token)]);
+        }
+        request.send().await.handle_json_response().await
     }
 
     pub async fn delete_cage(&self, cage_uuid: &str) -> ApiResult<DeleteCageResponse> {
@@ -467,6 +491,14 @@ impl CageDeployment {
     pub fn is_finished(&self) -> bool {
         self.completed_at.is_some()
     }
+
+    pub fn completed_at(&self) -> Option<&str> {
+        self.completed_at.as_deref()
+    }
+
+    pub fn debug_mode(&self) -> bool {
+        self.debug_mode
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -486,9 +518,26 @@ pub struct CageVersion {
     control_plane_img_url: Option<String>,
     control_plane_version: Option<String>,
     data_plane_version: Option<String>,
+    installer_version: Option<String>,
     build_status: BuildStatus,
     failure_reason: Option<String>,
     started_at: Option<String>,
+    #[serde(flatten)]
+    pcrs: Option<crate::enclave::PCRs>,
+}
+
+impl CageVersion {
+    pub fn pcrs(&self) -> Option<&crate::enclave::PCRs> {
+        self.pcrs.as_ref()
+    }
+
+    pub fn data_plane_version(&self) -> Option<&str> {
+        self.data_plane_version.as_deref()
+    }
+
+    pub fn installer_version(&self) -> Option<&str> {
+        self.installer_version.as_deref()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
@@ -635,6 +684,32 @@ impl GetCageResponse {
     pub fn domain(&self) -> &str {
         self.cage.domain.as_str()
     }
+
+    pub fn cage(&self) -> &Cage {
+        &self.cage
+    }
+
+    /// Returns the most recently completed deployment, which is the one serving the Cage
+    pub fn active_deployment(&self) -> Option<&DeploymentsForGetCage> {
+        self.deployments
+            .iter()
+            .filter(|deployment| deployment.deployment.is_finished())
+            .max_by(|a, b| {
+                a.deployment
+                    .completed_at()
+                    .cmp(&b.deployment.completed_at())
+            })
+    }
+}
+
+impl DeploymentsForGetCage {
+    pub fn deployment(&self) -> &CageDeployment {
+        &self.deployment
+    }
+
+    pub fn version(&self) -> &CageVersion {
+        &self.version
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -703,6 +778,10 @@ impl CageLogs {
     pub fn log_events(&self) -> &Vec<LogEvent> {
         &self.log_events
     }
+
+    pub fn next_token(&self) -> Option<&str> {
+        self.next_token.as_deref()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -726,6 +805,10 @@ impl LogEvent {
     pub fn instance_id(&self) -> &str {
         self.instance_id.as_str()
     }
+
+    pub fn ingestion_time(&self) -> i64 {
+        self.ingestion_time
+    }
 }
 
 pub type DeleteCageResponse = Cage;
@@ -753,9 +836,11 @@ mod test {
             control_plane_img_url: Some("control-plane.com".to_string()),
             control_plane_version: Some("1.0.0".to_string()),
             data_plane_version: Some("1.0.0".to_string()),
+            installer_version: Some("1.0.0".to_string()),
             build_status: BuildStatus::Ready,
             failure_reason: None,
             started_at: None,
+            pcrs: None,
         }
     }
 
@@ -826,4 +911,56 @@ mod test {
             Some(detailed_failure_reason)
         );
     }
+
+    #[test]
+    fn test_active_deployment_is_latest_completed_with_pcrs() {
+        let cage_json = serde_json::json!({
+            "uuid": "cage_123",
+            "name": "my-cage",
+            "teamUuid": "team_123",
+            "appUuid": "app_123",
+            "domain": "my-cage.app-123.cages.evervault.com",
+            "state": "active",
+            "createdAt": "2023-01-01T00:00:00Z",
+            "updatedAt": "2023-01-01T00:00:00Z",
+            "teeCageDeployments": [
+                deployment_json("1", Some("2023-01-02T00:00:00Z"), Some("aaa")),
+                deployment_json("2", Some("2023-01-03T00:00:00Z"), None),
+                deployment_json("3", None, Some("ccc")),
+            ],
+        });
+        let cage: GetCageResponse = serde_json::from_value(cage_json).unwrap();
+        let active = cage.active_deployment().unwrap();
+        assert_eq!(active.version().uuid, "version_2");
+        assert!(active.version().pcrs().is_none());
+
+        let first = &cage.deployments[0];
+        assert_eq!(first.version().pcrs().unwrap().pcr0, "aaa");
+    }
+
+    fn deployment_json(
+        id: &str,
+        completed_at: Option<&str>,
+        pcr0: Option<&str>,
+    ) -> serde_json::Value {
+        let mut version = serde_json::json!({
+            "uuid": format!("version_{id}"),
+            "version": 1,
+            "buildStatus": "ready",
+        });
+        if let Some(pcr0) = pcr0 {
+            version["PCR0"] = pcr0.into();
+            version["PCR1"] = "111".into();
+            version["PCR2"] = "222".into();
+        }
+        serde_json::json!({
+            "uuid": format!("deployment_{id}"),
+            "cageUuid": "cage_123",
+            "versionUuid": format!("version_{id}"),
+            "signingCertUuid": "cert_123",
+            "debugMode": false,
+            "completedAt": completed_at,
+            "teeCageVersion": version,
+        })
+    }
 }
@@ -1,9 +1,11 @@
+use super::retry::{is_idempotent, RetryPolicy};
 use super::AuthMode;
 use crate::common::CliError;
 use async_trait::async_trait;
 use reqwest::{Client, RequestBuilder, Response};
 use reqwest::{Error, Result as ReqwestResult};
 use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use std::fmt::Formatter;
 use std::time::Duration;
 use thiserror::Error;
@@ -12,6 +14,8 @@ use thiserror::Error;
 pub struct GenericApiClient {
     client: Client,
     auth: AuthMode,
+    retry_policy: RetryPolicy,
+    base_url: Option<String>,
 }
 
 impl Default for GenericApiClient {
@@ -20,6 +24,8 @@ impl Default for GenericApiClient {
         Self {
             client: client.unwrap(),
             auth: AuthMode::NoAuth,
+            retry_policy: RetryPolicy::default(),
+            base_url: None,
         }
     }
 }
@@ -30,6 +36,8 @@ impl std::convert::From<AuthMode> for GenericApiClient {
         GenericApiClient {
             client: client.unwrap(),
             auth: auth_mode,
+            retry_policy: RetryPolicy::default(),
+            base_url: None,
         }
     }
 }
@@ -47,6 +55,82 @@ impl ApiClient for GenericApiClient {
     fn client(&self) -> &Client {
         &self.client
     }
+
+    fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    fn base_url(&self) -> String {
+        match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            None => default_base_url(),
+        }
+    }
+
+    fn keys_url(&self) -> String {
+        // A custom API, such as the mock from `ev-cage dev api`, serves keys alongside the rest of
+        // the API, as with EV_API_URL
+        match &self.base_url {
+            Some(base_url) => format!("{}/keys", base_url),
+            None => default_keys_url(&self.base_url()),
+        }
+    }
+}
+
+impl GenericApiClient {
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+
+    pub fn with_auth(mut self, auth: AuthMode) -> Self {
+        self.auth = auth;
+        self
+    }
+}
+
+// Allows the CLI to be pointed at a different API host, e.g. the mock API started by `ev-cage dev api`
+pub fn api_url_override() -> Option<String> {
+    std::env::var("EV_API_URL")
+        .ok()
+        .filter(|api_url| !api_url.is_empty())
+        .map(|api_url| api_url.trim_end_matches('/').to_string())
+}
+
+fn default_base_url() -> String {
+    if let Some(api_url) = api_url_override() {
+        return api_url;
+    }
+    format!("https://api.{}", target_domain())
+}
+
+/// The domain for API requests. Commands resolve their credentials before creating a client which
+/// needs them, so the default domain is only used by clients which don't.
+pub(crate) fn target_domain() -> String {
+    crate::auth::ev_domain().unwrap_or_else(|e| {
+        log::warn!(
+            "Using the default domain {} — {}",
+            crate::auth::DEFAULT_DOMAIN,
+            e
+        );
+        crate::auth::DEFAULT_DOMAIN.to_string()
+    })
+}
+
+fn default_keys_url(base_url: &str) -> String {
+    if let Some(api_url) = api_url_override() {
+        return format!("{}/keys", api_url);
+    }
+    if base_url.contains("evervault.com") {
+        "https://keys.evervault.com".to_string()
+    } else {
+        "https://keys.evervault.io".to_string()
+    }
 }
 
 pub enum ApiClientError {
@@ -57,18 +141,14 @@ pub trait ApiClient {
     fn auth(&self) -> &AuthMode;
     fn update_auth(&mut self, auth: AuthMode) -> Result<(), ApiClientError>;
     fn client(&self) -> &Client;
+    fn retry_policy(&self) -> &RetryPolicy;
 
     fn base_url(&self) -> String {
-        let domain = std::env::var("EV_DOMAIN").unwrap_or_else(|_| String::from("evervault.com"));
-        format!("https://api.{}", domain)
+        default_base_url()
     }
 
     fn keys_url(&self) -> String {
-        if self.base_url().contains("evervault.com") {
-            "https://keys.evervault.com".to_string()
-        } else {
-            "https://keys.evervault.io".to_string()
-        }
+        default_keys_url(&self.base_url())
     }
 
     fn user_agent(&self) -> String {
@@ -79,20 +159,32 @@ pub trait ApiClient {
         !matches!(self.auth(), AuthMode::NoAuth)
     }
 
-    fn get(&self, url: &String) -> RequestBuilder {
-        self.prepare(self.client().get(url))
+    fn get(&self, url: &String) -> ApiRequest {
+        ApiRequest::new(
+            self.prepare(self.client().get(url)),
+            self.retry_policy().clone(),
+        )
     }
 
-    fn post(&self, url: &String) -> RequestBuilder {
-        self.prepare(self.client().post(url))
+    fn post(&self, url: &String) -> ApiRequest {
+        ApiRequest::new(
+            self.prepare(self.client().post(url)),
+            self.retry_policy().clone(),
+        )
     }
 
-    fn put(&self, url: &String) -> RequestBuilder {
-        self.prepare(self.client().put(url))
+    fn put(&self, url: &String) -> ApiRequest {
+        ApiRequest::new(
+            self.prepare(self.client().put(url)),
+            self.retry_policy().clone(),
+        )
     }
 
-    fn delete(&self, url: &String) -> RequestBuilder {
-        self.prepare(self.client().delete(url))
+    fn delete(&self, url: &String) -> ApiRequest {
+        ApiRequest::new(
+            self.prepare(self.client().delete(url)),
+            self.retry_policy().clone(),
+        )
     }
 
     fn prepare(&self, mut request_builder: RequestBuilder) -> RequestBuilder {
@@ -105,11 +197,78 @@ pub trait ApiClient {
     }
 }
 
+/// A prepared request which is retried according to the client's RetryPolicy.
+/// Idempotent requests are retried by default, others must opt in using `retry(true)`.
+pub struct ApiRequest {
+    request_builder: RequestBuilder,
+    retry_policy: RetryPolicy,
+    retry: Option<bool>,
+}
+
+impl ApiRequest {
+    pub fn new(request_builder: RequestBuilder, retry_policy: RetryPolicy) -> Self {
+        Self {
+            request_builder,
+            retry_policy,
+            retry: None,
+        }
+    }
+
+    pub fn retry(mut self, retry: bool) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn json<T: serde::Serialize + ?Sized>(mut self, json: &T) -> Self {
+        self.request_builder = self.request_builder.json(json);
+        self
+    }
+
+    pub fn query<T: serde::Serialize + ?Sized>(mut self, query: &T) -> Self {
+        self.request_builder = self.request_builder.query(query);
+        self
+    }
+
+    fn should_retry(&self) -> bool {
+        self.retry.unwrap_or_else(|| {
+            self.request_builder
+                .try_clone()
+                .and_then(|request_builder| request_builder.build().ok())
+                .map(|request| is_idempotent(request.method()))
+                .unwrap_or(false)
+        })
+    }
+
+    pub async fn send(self) -> ReqwestResult<Response> {
+        if !self.should_retry() {
+            return self.request_builder.send().await;
+        }
+
+        let mut retry = 0;
+        loop {
+            // Streamed bodies can't be cloned, so they only get one attempt
+            let request_builder = match self.request_builder.try_clone() {
+                Some(request_builder) => request_builder,
+                None => return self.request_builder.send().await,
+            };
+            let result = request_builder.send().await;
+            retry += 1;
+            match self.retry_policy.delay_for(&result, retry) {
+                Some(delay) => {
+                    log::debug!("Request failed, retrying in {}ms", delay.as_millis());
+                    tokio::time::sleep(delay).await;
+                }
+                None => return result,
+            }
+        }
+    }
+}
+
 #[async_trait]
 pub trait HandleResponse {
     async fn handle_json_response<T: DeserializeOwned>(self) -> ApiResult<T>;
     async fn handle_text_response(self) -> ApiResult<String>;
-    fn handle_no_op_response(self) -> ApiResult<()>;
+    async fn handle_no_op_response(self) -> ApiResult<()>;
 }
 
 #[async_trait]
@@ -120,7 +279,7 @@ impl HandleResponse for ReqwestResult<Response> {
                 .json()
                 .await
                 .map_err(|e| ApiError::ParsingError(e.to_string())),
-            Ok(res) => Err(ApiError::get_error_from_status(res.status().as_u16())),
+            Ok(res) => Err(ApiError::from_error_response(res).await),
             Err(e) => Err(ApiError::Unknown(Some(e))),
         }
     }
@@ -131,28 +290,67 @@ impl HandleResponse for ReqwestResult<Response> {
                 .text()
                 .await
                 .map_err(|e| ApiError::ParsingError(e.to_string())),
-            Ok(res) => Err(ApiError::get_error_from_status(res.status().as_u16())),
+            Ok(res) => Err(ApiError::from_error_response(res).await),
             Err(e) => Err(ApiError::Unknown(Some(e))),
         }
     }
 
-    fn handle_no_op_response(self) -> ApiResult<()> {
+    async fn handle_no_op_response(self) -> ApiResult<()> {
         match self {
             Ok(res) if res.status().is_success() => Ok(()),
-            Ok(res) => Err(ApiError::get_error_from_status(res.status().as_u16())),
+            Ok(res) => Err(ApiError::from_error_response(res).await),
             Err(e) => Err(ApiError::Unknown(Some(e))),
         }
     }
 }
 
+/// Problem details returned by the API alongside an error status
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiErrorDetails {
+    pub title: Option<String>,
+    pub detail: Option<String>,
+    pub code: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl ApiErrorDetails {
+    pub fn from_body(body: &str) -> Option<Self> {
+        serde_json::from_str::<Self>(body)
+            .ok()
+            .filter(|details| details != &Self::default())
+    }
+}
+
+impl std::fmt::Display for ApiErrorDetails {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.title, &self.detail) {
+            (Some(title), Some(detail)) => write!(f, "{} - {}", title, detail)?,
+            (Some(message), None) | (None, Some(message)) => write!(f, "{}", message)?,
+            (None, None) => {}
+        }
+        if let Some(code) = &self.code {
+            write!(f, " (code: {})", code)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " [request id: {}]", request_id)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ApiError {
-    BadRequest,
-    NotFound,
-    Unauthorized,
-    Internal,
-    Forbidden,
-    Conflict,
+    BadRequest(Option<ApiErrorDetails>),
+    NotFound(Option<ApiErrorDetails>),
+    Unauthorized(Option<ApiErrorDetails>),
+    Internal(Option<ApiErrorDetails>),
+    Forbidden(Option<ApiErrorDetails>),
+    Conflict(Option<ApiErrorDetails>),
+    UnprocessableEntity(Option<ApiErrorDetails>),
+    TooManyRequests(Option<ApiErrorDetails>),
+    ServiceUnavailable(Option<ApiErrorDetails>),
+    UnexpectedStatus(u16, Option<ApiErrorDetails>),
     Unknown(Option<Error>),
     ParsingError(String),
 }
@@ -160,12 +358,15 @@ pub enum ApiError {
 impl CliError for ApiError {
     fn exitcode(&self) -> exitcode::ExitCode {
         match self {
-            Self::BadRequest | Self::NotFound => exitcode::DATAERR,
-            Self::Unauthorized => exitcode::NOUSER,
-            Self::Internal | Self::ParsingError(_) => exitcode::SOFTWARE,
-            Self::Forbidden => exitcode::NOPERM,
-            Self::Conflict => exitcode::DATAERR,
-            Self::Unknown(_) => exitcode::UNAVAILABLE,
+            Self::BadRequest(_) | Self::NotFound(_) => exitcode::DATAERR,
+            Self::Unauthorized(_) => exitcode::NOUSER,
+            Self::Internal(_) | Self::ParsingError(_) => exitcode::SOFTWARE,
+            Self::Forbidden(_) => exitcode::NOPERM,
+            Self::Conflict(_) | Self::UnprocessableEntity(_) => exitcode::DATAERR,
+            Self::TooManyRequests(_) => exitcode::TEMPFAIL,
+            Self::ServiceUnavailable(_) | Self::UnexpectedStatus(..) | Self::Unknown(_) => {
+                exitcode::UNAVAILABLE
+            }
         }
     }
 }
@@ -174,35 +375,132 @@ pub type ApiResult<T> = core::result::Result<T, ApiError>;
 
 impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.to_msg())
+        write!(f, "{}", self.to_msg())
     }
 }
 
 impl ApiError {
     pub fn get_error_from_status(code: u16) -> Self {
+        Self::from_status_and_details(code, None)
+    }
+
+    pub fn from_status_and_details(code: u16, details: Option<ApiErrorDetails>) -> Self {
         match code {
-            400 => Self::BadRequest,
-            401 => Self::Unauthorized,
-            403 => Self::Forbidden,
-            404 => Self::NotFound,
-            409 => Self::Conflict,
-            500 => Self::Internal,
-            _ => Self::Unknown(None),
+            400 => Self::BadRequest(details),
+            401 => Self::Unauthorized(details),
+            403 => Self::Forbidden(details),
+            404 => Self::NotFound(details),
+            409 => Self::Conflict(details),
+            422 => Self::UnprocessableEntity(details),
+            429 => Self::TooManyRequests(details),
+            500 => Self::Internal(details),
+            503 => Self::ServiceUnavailable(details),
+            _ => Self::UnexpectedStatus(code, details),
         }
     }
 
-    pub fn to_msg(&self) -> String {
+    pub async fn from_error_response(response: Response) -> Self {
+        let status = response.status().as_u16();
+        let details = response
+            .text()
+            .await
+            .ok()
+            .and_then(|body| ApiErrorDetails::from_body(&body));
+        Self::from_status_and_details(status, details)
+    }
+
+    pub fn details(&self) -> Option<&ApiErrorDetails> {
         match self {
-            Self::BadRequest => "400: Bad Request".to_owned(),
-            Self::Unauthorized => "401: Unauthorized".to_owned(),
-            Self::Forbidden => "403: Forbidden".to_owned(),
-            Self::NotFound => "404: Not Found".to_owned(),
-            Self::Conflict => "409: Conflict".to_owned(),
-            Self::Internal => "500: Internal Server Error".to_owned(),
+            Self::BadRequest(details)
+            | Self::NotFound(details)
+            | Self::Unauthorized(details)
+            | Self::Internal(details)
+            | Self::Forbidden(details)
+            | Self::Conflict(details)
+            | Self::UnprocessableEntity(details)
+            | Self::TooManyRequests(details)
+            | Self::ServiceUnavailable(details)
+            | Self::UnexpectedStatus(_, details) => details.as_ref(),
+            Self::Unknown(_) | Self::ParsingError(_) => None,
+        }
+    }
+
+    pub fn to_msg(&self) -> String {
+        let status_msg = match self {
+            Self::BadRequest(_) => "400: Bad Request".to_owned(),
+            Self::Unauthorized(_) => "401: Unauthorized".to_owned(),
+            Self::Forbidden(_) => "403: Forbidden".to_owned(),
+            Self::NotFound(_) => "404: Not Found".to_owned(),
+            Self::Conflict(_) => "409: Conflict".to_owned(),
+            Self::UnprocessableEntity(_) => "422: Unprocessable Entity".to_owned(),
+            Self::TooManyRequests(_) => "429: Too Many Requests".to_owned(),
+            Self::Internal(_) => "500: Internal Server Error".to_owned(),
+            Self::ServiceUnavailable(_) => "503: Service Unavailable".to_owned(),
+            Self::UnexpectedStatus(code, _) => format!("{}: Unexpected response status", code),
             Self::Unknown(e) => format!("An unexpected error occured: {:?}", e),
-            Self::ParsingError(_) => {
-                "An error occurred while parsing the server's response.to_owned()".to_owned()
+            Self::ParsingError(e) => {
+                format!(
+                    "An error occurred while parsing the server's response: {}",
+                    e
+                )
             }
+        };
+        match self.details() {
+            Some(details) => format!("{} - {}", status_msg, details),
+            None => status_msg,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_details_are_parsed_from_problem_body() {
+        let body = r#"{"title":"Invalid egress destination","detail":"*.example.com is not a valid domain","code":"invalid-egress","requestId":"req_123"}"#;
+        let err = ApiError::from_status_and_details(400, ApiErrorDetails::from_body(body));
+        assert_eq!(
+            err.to_msg(),
+            "400: Bad Request - Invalid egress destination - *.example.com is not a valid domain (code: invalid-egress) [request id: req_123]"
+        );
+        assert_eq!(err.exitcode(), exitcode::DATAERR);
+    }
+
+    #[test]
+    fn test_non_problem_bodies_are_ignored() {
+        assert_eq!(ApiErrorDetails::from_body("<html>Bad Gateway</html>"), None);
+        assert_eq!(ApiErrorDetails::from_body(r#"{"unrelated":true}"#), None);
+        let err = ApiError::from_status_and_details(404, ApiErrorDetails::from_body("{}"));
+        assert_eq!(err.to_msg(), "404: Not Found");
+    }
+
+    #[test]
+    fn test_new_statuses_map_to_exit_codes() {
+        let unprocessable = ApiError::get_error_from_status(422);
+        assert!(matches!(unprocessable, ApiError::UnprocessableEntity(None)));
+        assert_eq!(unprocessable.exitcode(), exitcode::DATAERR);
+
+        let rate_limited = ApiError::get_error_from_status(429);
+        assert!(matches!(rate_limited, ApiError::TooManyRequests(None)));
+        assert_eq!(rate_limited.exitcode(), exitcode::TEMPFAIL);
+
+        let unavailable = ApiError::get_error_from_status(503);
+        assert!(matches!(unavailable, ApiError::ServiceUnavailable(None)));
+        assert_eq!(unavailable.exitcode(), exitcode::UNAVAILABLE);
+
+        assert!(matches!(
+            ApiError::get_error_from_status(418),
+            ApiError::UnexpectedStatus(418, None)
+        ));
+    }
+
+    #[test]
+    fn test_parsing_error_message() {
+        let err = ApiError::ParsingError("expected value at line 1 column 1".to_string());
+        assert_eq!(
+            err.to_msg(),
+            "An error occurred while parsing the server's response: expected value at line 1 column 1"
+        );
+    }
+}
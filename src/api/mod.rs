@@ -1,6 +1,7 @@
 pub mod assets;
 pub mod cage;
 pub mod client;
+pub mod retry;
 
 pub use reqwest::Client;
 
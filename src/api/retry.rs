@@ -0,0 +1,235 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, Response, Result as ReqwestResult, StatusCode};
+use std::time::Duration;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: DEFAULT_BASE_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_retries,
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// Upper bound of the backoff window for the given retry (1-indexed), capped at max_delay
+    pub fn backoff_ceiling(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(16);
+        self.base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay)
+    }
+
+    /// Exponential backoff with equal jitter: half of the window is fixed, the other half is random
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = self.backoff_ceiling(retry);
+        let half = ceiling / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=(ceiling - half).as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+
+    /// Returns how long to wait before retrying the given result, or None if it should be returned as is
+    pub fn delay_for(&self, result: &ReqwestResult<Response>, retry: u32) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+        match result {
+            Ok(response) if is_retryable_status(response.status()) => {
+                let server_delay = parse_retry_after(response.headers(), Utc::now());
+                Some(self.delay_with_retry_after(retry, server_delay))
+            }
+            Ok(_) => None,
+            Err(e) if e.is_connect() || e.is_timeout() => Some(self.backoff(retry)),
+            Err(_) => None,
+        }
+    }
+
+    fn delay_with_retry_after(&self, retry: u32, server_delay: Option<Duration>) -> Duration {
+        match server_delay {
+            Some(server_delay) => server_delay.min(self.max_delay),
+            None => self.backoff(retry),
+        }
+    }
+}
+
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// Retry-After can either be a number of seconds or an HTTP date
+pub fn parse_retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = retry_at.with_timezone(&Utc) - now;
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{http::HeaderMap as AxumHeaderMap, routing::get, Extension, Router};
+    use reqwest::header::HeaderValue;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn retry_after_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        let headers = retry_after_headers("120");
+        assert_eq!(
+            parse_retry_after(&headers, Utc::now()),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after_http_date() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:27:30 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let headers = retry_after_headers("Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(
+            parse_retry_after(&headers, now),
+            Some(Duration::from_secs(30))
+        );
+
+        let headers = retry_after_headers("Wed, 21 Oct 2015 07:20:00 GMT");
+        assert_eq!(parse_retry_after(&headers, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_retry_after_invalid() {
+        let headers = retry_after_headers("soon");
+        assert_eq!(parse_retry_after(&headers, Utc::now()), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new(), Utc::now()), None);
+    }
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(100), Duration::from_secs(1));
+        for retry in 1..=10 {
+            let ceiling = policy.backoff_ceiling(retry);
+            let delay = policy.backoff(retry);
+            assert!(delay >= ceiling / 2 && delay <= ceiling);
+        }
+        assert_eq!(policy.backoff_ceiling(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff_ceiling(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff_ceiling(10), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_retry_after_is_capped_by_max_delay() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_secs(5));
+        assert_eq!(
+            policy.delay_with_retry_after(1, Some(Duration::from_secs(600))),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn test_idempotent_methods() {
+        assert!(is_idempotent(&Method::GET));
+        assert!(is_idempotent(&Method::PUT));
+        assert!(is_idempotent(&Method::DELETE));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+
+    async fn flaky_handler(
+        Extension(hits): Extension<Arc<AtomicU32>>,
+    ) -> (StatusCode, AxumHeaderMap, &'static str) {
+        let mut headers = AxumHeaderMap::new();
+        if hits.fetch_add(1, Ordering::SeqCst) < 2 {
+            headers.insert(RETRY_AFTER, HeaderValue::from_static("0"));
+            return (StatusCode::SERVICE_UNAVAILABLE, headers, "unavailable");
+        }
+        (StatusCode::OK, headers, "ok")
+    }
+
+    async fn start_flaky_server() -> (String, Arc<AtomicU32>) {
+        let hits = Arc::new(AtomicU32::new(0));
+        let app = Router::new()
+            .route("/", get(flaky_handler).post(flaky_handler))
+            .layer(Extension(hits.clone()));
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (format!("http://{}/", addr), hits)
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_requests_are_retried() {
+        use crate::api::client::{ApiClient, GenericApiClient};
+
+        let (url, hits) = start_flaky_server().await;
+        let client = GenericApiClient::default();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_post_requests_are_only_retried_when_opted_in() {
+        use crate::api::client::{ApiClient, GenericApiClient};
+
+        let (url, hits) = start_flaky_server().await;
+        let client = GenericApiClient::default();
+        let response = client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        let response = client.post(&url).retry(true).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+}
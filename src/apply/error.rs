@@ -0,0 +1,36 @@
+use crate::api::client::ApiError;
+use crate::cert::CertError;
+use crate::common::CliError;
+use crate::config::CageConfigError;
+use crate::encrypt::EncryptError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ApplyError {
+    #[error("An error occurred while reading the cage config — {0}")]
+    CageConfigError(#[from] CageConfigError),
+    #[error("An error contacting the API — {0}")]
+    ApiError(#[from] ApiError),
+    #[error("An error occurred reading the signing cert — {0}")]
+    CertError(#[from] CertError),
+    #[error("An error occurred encrypting {0} — {1}")]
+    EncryptError(String, EncryptError),
+    #[error("The Cage needs to be created before it can be changed. Check that uuid, app_uuid and team_uuid are set in cage.toml.")]
+    MissingCageDetails,
+    #[error("Failed to find the uploaded signing cert {0} to lock the Cage to")]
+    MissingSigningCert(String),
+}
+
+impl CliError for ApplyError {
+    fn exitcode(&self) -> exitcode::ExitCode {
+        match self {
+            Self::CageConfigError(config_err) => config_err.exitcode(),
+            Self::ApiError(api_err) => api_err.exitcode(),
+            Self::CertError(cert_err) => cert_err.exitcode(),
+            Self::EncryptError(_, EncryptError::ApiError(api_err)) => api_err.exitcode(),
+            Self::EncryptError(..) => exitcode::SOFTWARE,
+            Self::MissingCageDetails => exitcode::DATAERR,
+            Self::MissingSigningCert(_) => exitcode::UNAVAILABLE,
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::api::cage::{
+    AddSecretRequest, CagesClient, CreateCageRequest, CreateCageSigningCertRefRequest,
+    UpdateLockedCageSigningCertRequest,
+};
+use crate::api::client::ApiError;
+use crate::cert::{get_cert_pcr, get_cert_validity_period, CertError};
+use crate::cli::encrypt::CurveName;
+use crate::config::edit::ConfigFile;
+use crate::config::CageConfig;
+use crate::encrypt::encrypt_with;
+use std::path::Path;
+
+mod error;
+pub mod plan;
+pub use error::ApplyError;
+pub use plan::{ActiveDeployment, Change, DesiredState, Plan, RemoteState};
+
+impl DesiredState {
+    pub fn from_config(config: &CageConfig) -> Result<Self, CertError> {
+        let cert_hash = config
+            .cert()
+            .map(|cert| get_cert_pcr(Path::new(cert)))
+            .transpose()?;
+        Ok(Self {
+            name: config.name().to_string(),
+            cert_hash,
+            lock_cert: config
+                .signing
+                .as_ref()
+                .and_then(|signing| signing.lock_cert),
+            env: config.env.clone(),
+            pcrs: config
+                .attestation
+                .as_ref()
+                .map(|attestation| attestation.pcrs().clone()),
+        })
+    }
+}
+
+/// Reads the state of the Cage from the API. Cages which haven't been created yet, or have been
+/// deleted, are reported as missing rather than as an error.
+pub async fn fetch_remote_state(
+    client: &CagesClient,
+    cage_uuid: Option<&str>,
+) -> Result<RemoteState, ApiError> {
+    let cert_hashes = client
+        .get_signing_certs()
+        .await?
+        .certs
+        .iter()
+        .map(|cert| cert.cert_hash().to_string())
+        .collect();
+    let missing_cage = RemoteState {
+        cert_hashes,
+        ..Default::default()
+    };
+
+    let Some(cage_uuid) = cage_uuid else {
+        return Ok(missing_cage);
+    };
+    let cage = match client.get_cage(cage_uuid).await {
+        Ok(cage) if !cage.is_deleted() => cage,
+        Ok(_) | Err(ApiError::NotFound(_)) => return Ok(missing_cage),
+        Err(e) => return Err(e),
+    };
+
+    let env = client
+        .get_cage_env(cage_uuid.to_string())
+        .await?
+        .secrets
+        .into_iter()
+        .map(|secret| (secret.name, secret.secret))
+        .collect();
+    let locked_cert_hashes = client
+        .get_cage_locked_signing_certs(cage_uuid)
+        .await?
+        .iter()
+        .map(|cert| cert.cert_hash().to_string())
+        .collect();
+    let active_deployment = cage.active_deployment().map(|deployment| ActiveDeployment {
+        pcrs: deployment.version().pcrs().cloned(),
+    });
+
+    Ok(RemoteState {
+        cage_exists: true,
+        locked_cert_hashes,
+        env,
+        active_deployment,
+        ..missing_cage
+    })
+}
+
+// The identifiers of a Cage needed to change it, and to encrypt its secrets
+struct CageDetails {
+    uuid: String,
+    app_uuid: String,
+    team_uuid: String,
+}
+
+impl CageDetails {
+    fn from_config(config: &CageConfig) -> Option<Self> {
+        let uuid = config.uuid.clone()?;
+        // The app and team default to those of the active credentials profile
+        let (app_uuid, team_uuid) =
+            crate::auth::resolve_app_and_team(config.app_uuid.clone(), config.team_uuid.clone())?;
+        Some(Self {
+            uuid,
+            app_uuid,
+            team_uuid,
+        })
+    }
+}
+
+/// Makes every change in the plan other than deploying, which is left to the caller as it builds the
+/// Cage. Cages which are created have their uuids written back to the cage.toml at `config_path`.
+pub async fn apply_changes(
+    client: &CagesClient,
+    plan: &Plan,
+    config: &CageConfig,
+    config_path: &str,
+) -> Result<(), ApplyError> {
+    let mut cage = CageDetails::from_config(config);
+    let keys_client = client.without_auth();
+    for change in plan.changes() {
+        match change {
+            Change::CreateCage { name } => {
+                let created = client
+                    .create_cage(CreateCageRequest::new(name.clone(), false))
+                    .await?;
+                let mut config_file = ConfigFile::load(config_path)?;
+                config_file.set_cage(&created, config.active_profile.as_deref())?;
+                config_file.save()?;
+                log::info!("Created Cage {} ({})", created.name(), created.uuid());
+                cage = Some(CageDetails {
+                    uuid: created.uuid().to_string(),
+                    app_uuid: created.app_uuid().to_string(),
+                    team_uuid: created.team_uuid().to_string(),
+                });
+            }
+            Change::UploadCert { cert_hash } => {
+                let cert_path = config
+                    .cert()
+                    .expect("Certs are only uploaded when one is set");
+                let validity_period = get_cert_validity_period(Path::new(cert_path))?;
+                client
+                    .create_cage_signing_cert_ref(CreateCageSigningCertRefRequest::new(
+                        cert_hash.clone(),
+                        config.name().to_string(),
+                        validity_period.not_before,
+                        validity_period.not_after,
+                    ))
+                    .await?;
+                log::info!("Uploaded signing cert {}", cert_hash);
+            }
+            Change::LockCerts { to, .. } => {
+                let cage = cage.as_ref().ok_or(ApplyError::MissingCageDetails)?;
+                let certs = client.get_signing_certs().await?.certs;
+                let cert_uuids = to
+                    .iter()
+                    .map(|cert_hash| {
+                        certs
+                            .iter()
+                            .find(|cert| cert.cert_hash() == cert_hash)
+                            .map(|cert| cert.uuid().to_string())
+                            .ok_or_else(|| cert_hash.clone())
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(ApplyError::MissingSigningCert)?;
+                client
+                    .update_cage_locked_signing_certs(
+                        &cage.uuid,
+                        UpdateLockedCageSigningCertRequest::new(cert_uuids),
+                    )
+                    .await?;
+                if to.is_empty() {
+                    log::info!("Unlocked Cage from every signing cert");
+                } else {
+                    log::info!("Locked Cage to {} signing cert(s)", to.len());
+                }
+            }
+            Change::AddEnv { name, secret } | Change::UpdateEnv { name, secret } => {
+                let cage = cage.as_ref().ok_or(ApplyError::MissingCageDetails)?;
+                let value = config
+                    .env
+                    .as_ref()
+                    .and_then(|env| env.get(name))
+                    .expect("Env changes are only planned for variables in the config")
+                    .value()
+                    .to_string();
+                let value = if *secret {
+                    encrypt_with(
+                        &keys_client,
+                        value,
+                        cage.team_uuid.clone(),
+                        cage.app_uuid.clone(),
+                        CurveName::Nist,
+                    )
+                    .await
+                    .map_err(|e| ApplyError::EncryptError(name.clone(), e))?
+                } else {
+                    value
+                };
+                client
+                    .add_env_var(
+                        cage.uuid.clone(),
+                        AddSecretRequest {
+                            name: name.clone(),
+                            secret: value,
+                        },
+                    )
+                    .await?;
+                log::info!("Set environment variable {}", name);
+            }
+            Change::DeleteEnv { name } => {
+                let cage = cage.as_ref().ok_or(ApplyError::MissingCageDetails)?;
+                client
+                    .delete_env_var(cage.uuid.clone(), name.clone())
+                    .await?;
+                log::info!("Deleted environment variable {}", name);
+            }
+            Change::Deploy { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// Plans the changes needed to bring the Cage in line with its config
+pub async fn plan(client: &CagesClient, config: &CageConfig) -> Result<Plan, ApplyError> {
+    let desired = DesiredState::from_config(config)?;
+    let remote = fetch_remote_state(client, config.uuid.as_deref()).await?;
+    Ok(Plan::between(&desired, &remote))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::AuthMode;
+    use crate::config::EnvValue;
+    use crate::test_utils::start_mock_cages_api;
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    async fn mock_client() -> CagesClient {
+        let base_url = start_mock_cages_api().await;
+        CagesClient::new(AuthMode::ApiKey("test".to_string())).with_base_url(base_url)
+    }
+
+    fn write_config(dir: &TempDir, extra: &str) -> String {
+        let path = dir.path().join("cage.toml");
+        std::fs::write(
+            &path,
+            format!("name = \"apply-cage\"\ndebug = false\n\n[egress]\nenabled = false\n{extra}"),
+        )
+        .unwrap();
+        path.display().to_string()
+    }
+
+    async fn plan_and_apply(client: &CagesClient, config: &CageConfig, config_path: &str) -> Plan {
+        let plan = plan(client, config).await.unwrap();
+        apply_changes(client, &plan, config, config_path)
+            .await
+            .unwrap();
+        plan
+    }
+
+    #[tokio::test]
+    async fn test_created_cage_is_written_to_config() {
+        let client = mock_client().await;
+        let dir = TempDir::new().unwrap();
+        let config_path = write_config(&dir, "");
+        let config = CageConfig::try_from_filepath(&config_path).unwrap();
+
+        let plan = plan_and_apply(&client, &config, &config_path).await;
+        assert!(matches!(
+            plan.changes().first(),
+            Some(Change::CreateCage { name }) if name == "apply-cage"
+        ));
+
+        let config = CageConfig::read_uninterpolated(&config_path).unwrap();
+        let cage = client
+            .get_cage(config.uuid.as_deref().unwrap())
+            .await
+            .unwrap();
+        let cage = cage.cage();
+        assert_eq!(cage.name(), "apply-cage");
+        assert_eq!(config.app_uuid.as_deref(), Some(cage.app_uuid()));
+        assert_eq!(config.team_uuid.as_deref(), Some(cage.team_uuid()));
+
+        let remote = fetch_remote_state(&client, config.uuid.as_deref())
+            .await
+            .unwrap();
+        assert!(remote.cage_exists);
+        assert!(remote.active_deployment.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_certs_are_uploaded_locked_and_unlocked() {
+        let client = mock_client().await;
+        let dir = TempDir::new().unwrap();
+        let (cert, key) =
+            crate::cert::create_new_cert(dir.path(), crate::cert::DistinguishedName::default())
+                .unwrap();
+        let config_path = write_config(
+            &dir,
+            &format!(
+                "\n[signing]\ncertPath = '{}'\nkeyPath = '{}'\nlockCert = true\n",
+                cert.display(),
+                key.display()
+            ),
+        );
+        let config = CageConfig::try_from_filepath(&config_path).unwrap();
+        let cert_hash = get_cert_pcr(&cert).unwrap();
+
+        plan_and_apply(&client, &config, &config_path).await;
+        let mut config = CageConfig::try_from_filepath(&config_path).unwrap();
+        let remote = fetch_remote_state(&client, config.uuid.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(remote.cert_hashes, vec![cert_hash.clone()]);
+        assert_eq!(remote.locked_cert_hashes, vec![cert_hash.clone()]);
+
+        config.signing.as_mut().unwrap().lock_cert = Some(false);
+        let plan = plan_and_apply(&client, &config, &config_path).await;
+        assert!(plan.changes().iter().any(|change| matches!(
+            change,
+            Change::LockCerts { from, to } if from == &vec![cert_hash.clone()] && to.is_empty()
+        )));
+        let remote = fetch_remote_state(&client, config.uuid.as_deref())
+            .await
+            .unwrap();
+        assert!(remote.locked_cert_hashes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_env_is_added_updated_and_deleted() {
+        let client = mock_client().await;
+        let dir = TempDir::new().unwrap();
+        let config_path = write_config(
+            &dir,
+            "\n[env]\nREGION = \"eu-west-1\"\nDB_PASSWORD = { value = \"hunter2\", secret = true }\n",
+        );
+        let config = CageConfig::try_from_filepath(&config_path).unwrap();
+
+        plan_and_apply(&client, &config, &config_path).await;
+        let mut config = CageConfig::try_from_filepath(&config_path).unwrap();
+        let remote = fetch_remote_state(&client, config.uuid.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(remote.env["REGION"], "eu-west-1");
+        // Secrets are encrypted before they're sent to the API
+        let password = &remote.env["DB_PASSWORD"];
+        assert!(password.starts_with("ev:"));
+        assert!(!password.contains("hunter2"));
+
+        config.env = Some(BTreeMap::from([(
+            "REGION".to_string(),
+            EnvValue::Plain("us-east-1".to_string()),
+        )]));
+        let plan = plan_and_apply(&client, &config, &config_path).await;
+        assert!(plan.changes().iter().any(
+            |change| matches!(change, Change::UpdateEnv { name, secret: false } if name == "REGION")
+        ));
+        assert!(plan
+            .changes()
+            .iter()
+            .any(|change| matches!(change, Change::DeleteEnv { name } if name == "DB_PASSWORD")));
+
+        let remote = fetch_remote_state(&client, config.uuid.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(
+            remote.env,
+            BTreeMap::from([("REGION".to_string(), "us-east-1".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_cage_is_reported_as_missing() {
+        let client = mock_client().await;
+        let remote = fetch_remote_state(&client, Some("cage_missing"))
+            .await
+            .unwrap();
+        assert!(!remote.cage_exists);
+        assert!(remote.env.is_empty());
+    }
+}
@@ -0,0 +1,508 @@
+use crate::config::EnvValue;
+use crate::enclave::PCRs;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The state of a Cage as described by its cage.toml
+#[derive(Clone, Debug, Default)]
+pub struct DesiredState {
+    pub name: String,
+    /// PCR8 of the signing cert, which is the hash the API identifies the cert by
+    pub cert_hash: Option<String>,
+    /// Whether the Cage should be locked to the signing cert, or None if locked certs aren't managed
+    pub lock_cert: Option<bool>,
+    /// Environment variables of the Cage, or None if its environment isn't managed
+    pub env: Option<BTreeMap<String, EnvValue>>,
+    /// Measurements of the last build, from the attestation table
+    pub pcrs: Option<PCRs>,
+}
+
+/// The state of a Cage according to the Evervault API
+#[derive(Clone, Debug, Default)]
+pub struct RemoteState {
+    /// Whether the Cage exists and hasn't been deleted
+    pub cage_exists: bool,
+    /// Hashes of the signing certs uploaded to the App
+    pub cert_hashes: Vec<String>,
+    /// Hashes of the signing certs the Cage is locked to
+    pub locked_cert_hashes: Vec<String>,
+    /// Environment variables of the Cage. Secrets are encrypted.
+    pub env: BTreeMap<String, String>,
+    pub active_deployment: Option<ActiveDeployment>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ActiveDeployment {
+    /// Measurements of the deployed image, if the API returned them
+    pub pcrs: Option<PCRs>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    fn symbol(&self) -> char {
+        match self {
+            Self::Create => '+',
+            Self::Update => '~',
+            Self::Delete => '-',
+        }
+    }
+}
+
+/// A change needed to bring the Cage in line with its cage.toml
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "change")]
+pub enum Change {
+    CreateCage {
+        name: String,
+    },
+    UploadCert {
+        #[serde(rename = "certHash")]
+        cert_hash: String,
+    },
+    LockCerts {
+        from: Vec<String>,
+        to: Vec<String>,
+    },
+    AddEnv {
+        name: String,
+        secret: bool,
+    },
+    UpdateEnv {
+        name: String,
+        secret: bool,
+    },
+    DeleteEnv {
+        name: String,
+    },
+    Deploy {
+        reason: String,
+        initial: bool,
+    },
+}
+
+impl Change {
+    pub fn action(&self) -> Action {
+        match self {
+            Self::CreateCage { .. } | Self::UploadCert { .. } | Self::AddEnv { .. } => {
+                Action::Create
+            }
+            Self::LockCerts { to, .. } if to.is_empty() => Action::Delete,
+            Self::Deploy { initial: true, .. } => Action::Create,
+            Self::LockCerts { .. } | Self::UpdateEnv { .. } | Self::Deploy { .. } => Action::Update,
+            Self::DeleteEnv { .. } => Action::Delete,
+        }
+    }
+}
+
+fn format_hashes(hashes: &[String]) -> String {
+    if hashes.is_empty() {
+        return "none".to_string();
+    }
+    hashes
+        .iter()
+        .map(|hash| abbreviate(hash))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn abbreviate(hash: &str) -> String {
+    match hash.char_indices().nth(16) {
+        Some((index, _)) => format!("{}…", &hash[..index]),
+        None => hash.to_string(),
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ", self.action().symbol())?;
+        match self {
+            Self::CreateCage { name } => write!(f, "cage {name}"),
+            Self::UploadCert { cert_hash } => write!(f, "signing cert {}", abbreviate(cert_hash)),
+            Self::LockCerts { from, to } if to.is_empty() => {
+                write!(f, "locked signing certs {}", format_hashes(from))
+            }
+            Self::LockCerts { from, to } => write!(
+                f,
+                "locked signing certs {} -> {}",
+                format_hashes(from),
+                format_hashes(to)
+            ),
+            Self::AddEnv { name, secret } | Self::UpdateEnv { name, secret } => {
+                write!(f, "env {name}{}", if *secret { " (secret)" } else { "" })
+            }
+            Self::DeleteEnv { name } => write!(f, "env {name}"),
+            Self::Deploy { reason, .. } => write!(f, "deployment ({reason})"),
+        }
+    }
+}
+
+/// The changes needed to bring a Cage in line with its cage.toml, in the order they're applied
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Plan {
+    changes: Vec<Change>,
+    /// Differences which couldn't be checked
+    notes: Vec<String>,
+}
+
+// Values encrypted with the Evervault SDKs and CLI are prefixed with ev:
+fn is_encrypted(value: &str) -> bool {
+    value.starts_with("ev:")
+}
+
+fn same_pcrs(deployed: &PCRs, built: &PCRs) -> bool {
+    let same_pcr8 = match (&deployed.pcr8, &built.pcr8) {
+        (Some(deployed), Some(built)) => deployed == built,
+        _ => true,
+    };
+    deployed.pcr0 == built.pcr0
+        && deployed.pcr1 == built.pcr1
+        && deployed.pcr2 == built.pcr2
+        && same_pcr8
+}
+
+impl Plan {
+    /// Compares the desired state of the Cage with its remote state
+    pub fn between(desired: &DesiredState, remote: &RemoteState) -> Self {
+        let mut plan = Self::default();
+        if !remote.cage_exists {
+            plan.changes.push(Change::CreateCage {
+                name: desired.name.clone(),
+            });
+        }
+
+        if let Some(cert_hash) = &desired.cert_hash {
+            if !remote.cert_hashes.contains(cert_hash) {
+                plan.changes.push(Change::UploadCert {
+                    cert_hash: cert_hash.clone(),
+                });
+            }
+        }
+
+        let locked_cert_hashes = match (desired.lock_cert, &desired.cert_hash) {
+            (Some(true), Some(cert_hash)) => Some(vec![cert_hash.clone()]),
+            (Some(true), None) => {
+                plan.notes.push(
+                    "lockCert is set, but there's no signing cert to lock the Cage to".to_string(),
+                );
+                None
+            }
+            (Some(false), _) => Some(Vec::new()),
+            (None, _) => None,
+        };
+        if let Some(to) = locked_cert_hashes {
+            let mut from = remote.locked_cert_hashes.clone();
+            from.sort();
+            if from != to {
+                plan.changes.push(Change::LockCerts { from, to });
+            }
+        }
+
+        if let Some(env) = &desired.env {
+            let mut unchecked_secrets = false;
+            for (name, value) in env {
+                let secret = value.is_secret();
+                match remote.env.get(name) {
+                    None => plan.changes.push(Change::AddEnv {
+                        name: name.clone(),
+                        secret,
+                    }),
+                    // Secrets can't be decrypted to compare them, so only their presence is checked
+                    Some(existing) if secret && is_encrypted(existing) => unchecked_secrets = true,
+                    Some(existing) if secret || existing != value.value() => {
+                        plan.changes.push(Change::UpdateEnv {
+                            name: name.clone(),
+                            secret,
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+            for name in remote.env.keys().filter(|name| !env.contains_key(*name)) {
+                plan.changes.push(Change::DeleteEnv { name: name.clone() });
+            }
+            if unchecked_secrets {
+                plan.notes.push("Secrets can't be compared with the values stored in the Cage, so secrets which are already set are left as they are".to_string());
+            }
+        }
+        let env_changed = plan.changes.iter().any(|change| {
+            matches!(
+                change,
+                Change::AddEnv { .. } | Change::UpdateEnv { .. } | Change::DeleteEnv { .. }
+            )
+        });
+
+        let deploy_reason = match (&remote.active_deployment, &desired.pcrs) {
+            (None, _) => Some("the Cage has no active deployment"),
+            (Some(_), None) => Some("cage.toml has no attestation measurements"),
+            (
+                Some(ActiveDeployment {
+                    pcrs: Some(deployed),
+                }),
+                Some(built),
+            ) => (!same_pcrs(deployed, built))
+                .then_some("the PCRs in cage.toml don't match the active deployment"),
+            (Some(ActiveDeployment { pcrs: None }), Some(_)) => {
+                plan.notes.push("The API didn't return the PCRs of the active deployment, so they can't be compared with cage.toml".to_string());
+                None
+            }
+        };
+        let deploy_reason = deploy_reason.or_else(|| {
+            env_changed.then_some("environment changes take effect on the next deployment")
+        });
+        if let Some(reason) = deploy_reason {
+            plan.changes.push(Change::Deploy {
+                reason: reason.to_string(),
+                initial: remote.active_deployment.is_none(),
+            });
+        }
+        plan
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn requires_deploy(&self) -> bool {
+        self.changes
+            .iter()
+            .any(|change| matches!(change, Change::Deploy { .. }))
+    }
+
+    fn count(&self, action: Action) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| change.action() == action)
+            .count()
+    }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "  {change}")?;
+        }
+        if !self.changes.is_empty() {
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "Plan: {} to create, {} to update, {} to delete.",
+            self.count(Action::Create),
+            self.count(Action::Update),
+            self.count(Action::Delete)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::EnvVarSettings;
+
+    fn pcrs(pcr0: &str) -> PCRs {
+        PCRs {
+            pcr0: pcr0.to_string(),
+            pcr1: "111".to_string(),
+            pcr2: "222".to_string(),
+            pcr8: Some("cert_hash".to_string()),
+        }
+    }
+
+    fn desired() -> DesiredState {
+        DesiredState {
+            name: "my-cage".to_string(),
+            cert_hash: Some("cert_hash".to_string()),
+            lock_cert: Some(true),
+            env: Some(BTreeMap::from([
+                ("LOG_LEVEL".to_string(), EnvValue::Plain("info".to_string())),
+                (
+                    "API_TOKEN".to_string(),
+                    EnvValue::Detailed(EnvVarSettings {
+                        value: "token".to_string(),
+                        secret: true,
+                    }),
+                ),
+            ])),
+            pcrs: Some(pcrs("000")),
+        }
+    }
+
+    fn in_sync() -> RemoteState {
+        RemoteState {
+            cage_exists: true,
+            cert_hashes: vec!["old_hash".to_string(), "cert_hash".to_string()],
+            locked_cert_hashes: vec!["cert_hash".to_string()],
+            env: BTreeMap::from([
+                ("LOG_LEVEL".to_string(), "info".to_string()),
+                ("API_TOKEN".to_string(), "ev:Tk9D:abc".to_string()),
+            ]),
+            active_deployment: Some(ActiveDeployment {
+                pcrs: Some(pcrs("000")),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_cage_in_sync_has_no_changes() {
+        let plan = Plan::between(&desired(), &in_sync());
+        assert!(plan.is_empty());
+        assert_eq!(plan.notes().len(), 1);
+        assert_eq!(
+            plan.to_string(),
+            "Plan: 0 to create, 0 to update, 0 to delete."
+        );
+    }
+
+    #[test]
+    fn test_missing_cage_is_created_and_deployed() {
+        let remote = RemoteState {
+            cert_hashes: vec!["cert_hash".to_string()],
+            ..Default::default()
+        };
+        let plan = Plan::between(&desired(), &remote);
+        assert_eq!(
+            plan.changes(),
+            [
+                Change::CreateCage {
+                    name: "my-cage".to_string()
+                },
+                Change::LockCerts {
+                    from: vec![],
+                    to: vec!["cert_hash".to_string()]
+                },
+                Change::AddEnv {
+                    name: "API_TOKEN".to_string(),
+                    secret: true
+                },
+                Change::AddEnv {
+                    name: "LOG_LEVEL".to_string(),
+                    secret: false
+                },
+                Change::Deploy {
+                    reason: "the Cage has no active deployment".to_string(),
+                    initial: true
+                },
+            ]
+        );
+        assert_eq!(
+            plan.to_string(),
+            "  + cage my-cage
+  ~ locked signing certs none -> cert_hash
+  + env API_TOKEN (secret)
+  + env LOG_LEVEL
+  + deployment (the Cage has no active deployment)
+
+Plan: 4 to create, 1 to update, 0 to delete."
+        );
+    }
+
+    #[test]
+    fn test_env_changes_require_a_deployment() {
+        let mut remote = in_sync();
+        remote
+            .env
+            .insert("LOG_LEVEL".to_string(), "debug".to_string());
+        remote
+            .env
+            .insert("API_TOKEN".to_string(), "plaintext".to_string());
+        remote
+            .env
+            .insert("OLD_VAR".to_string(), "value".to_string());
+        let plan = Plan::between(&desired(), &remote);
+        assert_eq!(
+            plan.changes(),
+            [
+                Change::UpdateEnv {
+                    name: "API_TOKEN".to_string(),
+                    secret: true
+                },
+                Change::UpdateEnv {
+                    name: "LOG_LEVEL".to_string(),
+                    secret: false
+                },
+                Change::DeleteEnv {
+                    name: "OLD_VAR".to_string()
+                },
+                Change::Deploy {
+                    reason: "environment changes take effect on the next deployment".to_string(),
+                    initial: false
+                },
+            ]
+        );
+        assert!(plan.notes().is_empty());
+    }
+
+    #[test]
+    fn test_unmanaged_settings_are_left_alone() {
+        let desired = DesiredState {
+            lock_cert: None,
+            env: None,
+            ..desired()
+        };
+        let mut remote = in_sync();
+        remote.locked_cert_hashes = vec!["old_hash".to_string()];
+        remote
+            .env
+            .insert("OLD_VAR".to_string(), "value".to_string());
+        assert!(Plan::between(&desired, &remote).is_empty());
+    }
+
+    #[test]
+    fn test_certs_are_uploaded_and_unlocked() {
+        let desired = DesiredState {
+            cert_hash: Some("new_hash".to_string()),
+            lock_cert: Some(false),
+            ..desired()
+        };
+        let plan = Plan::between(&desired, &in_sync());
+        assert_eq!(
+            plan.changes(),
+            [
+                Change::UploadCert {
+                    cert_hash: "new_hash".to_string()
+                },
+                Change::LockCerts {
+                    from: vec!["cert_hash".to_string()],
+                    to: vec![]
+                },
+            ]
+        );
+        assert_eq!(plan.changes()[1].action(), Action::Delete);
+    }
+
+    #[test]
+    fn test_deployments_are_compared_by_pcrs() {
+        let desired = DesiredState {
+            pcrs: Some(pcrs("001")),
+            ..desired()
+        };
+        let plan = Plan::between(&desired, &in_sync());
+        assert_eq!(
+            plan.changes(),
+            [Change::Deploy {
+                reason: "the PCRs in cage.toml don't match the active deployment".to_string(),
+                initial: false
+            }]
+        );
+
+        let mut remote = in_sync();
+        remote.active_deployment = Some(ActiveDeployment { pcrs: None });
+        let plan = Plan::between(&desired, &remote);
+        assert!(!plan.requires_deploy());
+        assert_eq!(plan.notes().len(), 2);
+    }
+}
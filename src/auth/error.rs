@@ -0,0 +1,48 @@
+use crate::common::CliError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("No credentials found. Set the EV_API_KEY environment variable, add a profile using `ev-cage auth add` or run `ev-cage login`.")]
+    NoCredentials,
+    #[error("No profile named {0} was found in {1}")]
+    ProfileNotFound(String, String),
+    #[error("Could not determine the location of the credentials file. Set EV_CREDENTIALS_FILE to choose one.")]
+    NoCredentialsPath,
+    #[error("An IO error occurred while accessing the credentials file — {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to parse the credentials file — {0}")]
+    ParseError(#[from] toml::de::Error),
+    #[error("Failed to serialize the credentials file — {0}")]
+    SerializeError(#[from] toml::ser::Error),
+    #[error("An error occurred contacting the auth server — {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("Login failed — {0}")]
+    LoginFailed(String),
+    #[error("The auth server rejected the token request — {0} {}", .1.as_deref().unwrap_or_default())]
+    TokenRequestRejected(String, Option<String>),
+    #[error("The login request was denied")]
+    LoginDenied,
+    #[error("The login request expired before it was approved. Run `ev-cage login` to try again.")]
+    DeviceCodeExpired,
+    #[error("Your session has expired. Run `ev-cage login` to log in again.")]
+    TokenExpired,
+}
+
+impl CliError for AuthError {
+    fn exitcode(&self) -> exitcode::ExitCode {
+        match self {
+            Self::NoCredentials | Self::ProfileNotFound(..) => exitcode::NOUSER,
+            Self::NoCredentialsPath => exitcode::CONFIG,
+            Self::IoError(_) => exitcode::IOERR,
+            Self::ParseError(_) => exitcode::CONFIG,
+            Self::SerializeError(_) => exitcode::SOFTWARE,
+            Self::RequestError(_) => exitcode::UNAVAILABLE,
+            Self::LoginFailed(_) => exitcode::PROTOCOL,
+            Self::TokenRequestRejected(..)
+            | Self::LoginDenied
+            | Self::DeviceCodeExpired
+            | Self::TokenExpired => exitcode::NOPERM,
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use super::{credentials_path, ev_domain, AuthError};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const AUTH_URL_ENV_VAR: &str = "EV_AUTH_URL";
+const CLIENT_ID: &str = "ev-cage-cli";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+// Default polling interval from RFC 8628, used when the server doesn't specify one
+const DEFAULT_POLLING_INTERVAL_SECS: u64 = 5;
+const SLOW_DOWN_INCREMENT_SECS: u64 = 5;
+// Treat tokens as expired slightly early so they don't lapse mid-request
+const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// The OAuth server to log in against. Defaults to the auth subdomain of the target Evervault domain.
+pub fn auth_url() -> Result<String, AuthError> {
+    match std::env::var(AUTH_URL_ENV_VAR) {
+        Ok(auth_url) if !auth_url.is_empty() => Ok(auth_url.trim_end_matches('/').to_string()),
+        _ => Ok(format!("https://auth.{}", ev_domain()?)),
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeviceCodeResponse {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    pub interval: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+pub fn token_cache_path() -> Result<PathBuf, AuthError> {
+    let credentials_path = credentials_path()?;
+    let config_dir = credentials_path.parent().unwrap_or_else(|| Path::new("."));
+    Ok(config_dir.join("token.toml"))
+}
+
+/// A bearer token persisted between CLI invocations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedToken {
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Unix timestamp (seconds) at which the access token expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl CachedToken {
+    pub fn from_response(response: TokenResponse, now: i64) -> Self {
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            expires_at: response.expires_in.map(|expires_in| now + expires_in),
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at
+            .map(|expires_at| now + EXPIRY_LEEWAY_SECS >= expires_at)
+            .unwrap_or(false)
+    }
+
+    pub fn load() -> Result<Option<Self>, AuthError> {
+        Self::load_from(&token_cache_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Option<Self>, AuthError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(toml::from_str(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), AuthError> {
+        self.save_to(&token_cache_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), AuthError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
+
+/// Client for the OAuth 2.0 device authorization grant (RFC 8628)
+pub struct DeviceLogin {
+    client: reqwest::Client,
+    auth_url: String,
+}
+
+impl DeviceLogin {
+    pub fn new(auth_url: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            auth_url,
+        }
+    }
+
+    pub async fn request_device_code(&self) -> Result<DeviceCodeResponse, AuthError> {
+        let device_code_url = format!("{}/oauth/device/code", self.auth_url);
+        let response = self
+            .client
+            .post(device_code_url)
+            .form(&[("client_id", CLIENT_ID)])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(AuthError::LoginFailed(format!(
+                "Failed to request a device code, the server responded with {}",
+                response.status()
+            )));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Polls the token endpoint until the user approves or denies the login, or the device code expires
+    pub async fn poll_for_token(
+        &self,
+        device_code: &DeviceCodeResponse,
+    ) -> Result<TokenResponse, AuthError> {
+        let mut interval = device_code
+            .interval
+            .unwrap_or(DEFAULT_POLLING_INTERVAL_SECS);
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(device_code.expires_in);
+
+        loop {
+            let params = [
+                ("grant_type", DEVICE_CODE_GRANT_TYPE),
+                ("device_code", device_code.device_code.as_str()),
+                ("client_id", CLIENT_ID),
+            ];
+            match self.request_token(&params).await {
+                Err(AuthError::TokenRequestRejected(error, _))
+                    if error == "authorization_pending" => {}
+                Err(AuthError::TokenRequestRejected(error, _)) if error == "slow_down" => {
+                    interval += SLOW_DOWN_INCREMENT_SECS;
+                }
+                Err(AuthError::TokenRequestRejected(error, _)) if error == "access_denied" => {
+                    return Err(AuthError::LoginDenied);
+                }
+                Err(AuthError::TokenRequestRejected(error, _)) if error == "expired_token" => {
+                    return Err(AuthError::DeviceCodeExpired);
+                }
+                result => return result,
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AuthError::DeviceCodeExpired);
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    }
+
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, AuthError> {
+        let params = [
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", CLIENT_ID),
+        ];
+        self.request_token(&params).await
+    }
+
+    async fn request_token(&self, params: &[(&str, &str)]) -> Result<TokenResponse, AuthError> {
+        let token_url = format!("{}/oauth/token", self.auth_url);
+        let response = self.client.post(token_url).form(params).send().await?;
+        if response.status().is_success() {
+            return Ok(response.json().await?);
+        }
+        let status = response.status();
+        match response.json::<TokenErrorResponse>().await {
+            Ok(token_error) => Err(AuthError::TokenRequestRejected(
+                token_error.error,
+                token_error.error_description,
+            )),
+            Err(_) => Err(AuthError::LoginFailed(format!(
+                "The token endpoint responded with {}",
+                status
+            ))),
+        }
+    }
+}
+
+/// Refreshes the cached token if it has expired, returning the token that should be used
+pub async fn refresh_cached_token(cached_token: CachedToken) -> Result<CachedToken, AuthError> {
+    let now = chrono::Utc::now().timestamp();
+    if !cached_token.is_expired(now) {
+        return Ok(cached_token);
+    }
+    let refresh_token = cached_token
+        .refresh_token
+        .as_deref()
+        .ok_or(AuthError::TokenExpired)?;
+    log::debug!("Cached access token has expired, refreshing");
+    let token_response = DeviceLogin::new(auth_url()?).refresh(refresh_token).await?;
+    let mut refreshed = CachedToken::from_response(token_response, now);
+    // Servers may not rotate refresh tokens, in which case the existing one stays valid
+    if refreshed.refresh_token.is_none() {
+        refreshed.refresh_token = cached_token.refresh_token;
+    }
+    refreshed.save()?;
+    Ok(refreshed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::start_mock_cages_api;
+
+    #[test]
+    fn test_token_expiry_includes_leeway() {
+        let token = CachedToken {
+            access_token: "token".to_string(),
+            refresh_token: None,
+            expires_at: Some(1_000),
+        };
+        assert!(!token.is_expired(900));
+        assert!(token.is_expired(950));
+        assert!(token.is_expired(1_000));
+
+        let non_expiring = CachedToken {
+            expires_at: None,
+            ..token
+        };
+        assert!(!non_expiring.is_expired(i64::MAX - EXPIRY_LEEWAY_SECS));
+    }
+
+    #[test]
+    fn test_token_cache_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("token.toml");
+        assert_eq!(CachedToken::load_from(&path).unwrap(), None);
+
+        let token = CachedToken {
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: Some(1_700_000_000),
+        };
+        token.save_to(&path).unwrap();
+        assert_eq!(CachedToken::load_from(&path).unwrap(), Some(token));
+    }
+
+    #[tokio::test]
+    async fn test_device_login_against_local_token_endpoint() {
+        let auth_url = start_mock_cages_api().await;
+        let login = DeviceLogin::new(auth_url);
+
+        let device_code = login.request_device_code().await.unwrap();
+        assert!(!device_code.user_code.is_empty());
+
+        // The mock server reports the login as pending on the first poll
+        let token = login.poll_for_token(&device_code).await.unwrap();
+        let refresh_token = token.refresh_token.clone().unwrap();
+
+        // Device codes can only be exchanged once
+        assert!(matches!(
+            login.poll_for_token(&device_code).await,
+            Err(AuthError::TokenRequestRejected(error, _)) if error == "invalid_grant"
+        ));
+
+        let refreshed = login.refresh(&refresh_token).await.unwrap();
+        assert_ne!(refreshed.access_token, token.access_token);
+        assert!(matches!(
+            login.refresh("unknown").await,
+            Err(AuthError::TokenRequestRejected(error, _)) if error == "invalid_grant"
+        ));
+    }
+}
@@ -0,0 +1,436 @@
+use crate::api::AuthMode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+mod error;
+pub mod login;
+pub use error::AuthError;
+use login::CachedToken;
+
+pub const PROFILE_ENV_VAR: &str = "EV_PROFILE";
+pub const CREDENTIALS_FILE_ENV_VAR: &str = "EV_CREDENTIALS_FILE";
+pub const DEFAULT_DOMAIN: &str = "evervault.com";
+
+/// A named set of credentials stored in the user's credentials file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub api_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_uuid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CredentialsFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    if let Some(xdg_config_home) = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty())
+    {
+        return Some(PathBuf::from(xdg_config_home));
+    }
+    if cfg!(target_os = "windows") {
+        return std::env::var_os("APPDATA").map(PathBuf::from);
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+}
+
+pub fn credentials_path() -> Result<PathBuf, AuthError> {
+    if let Some(path) = std::env::var_os(CREDENTIALS_FILE_ENV_VAR).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    user_config_dir()
+        .map(|config_dir| config_dir.join("evervault").join("credentials.toml"))
+        .ok_or(AuthError::NoCredentialsPath)
+}
+
+impl CredentialsFile {
+    pub fn load() -> Result<Self, AuthError> {
+        Self::load_from(&credentials_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, AuthError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), AuthError> {
+        self.save_to(&credentials_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), AuthError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let serialized = toml::to_string(self)?;
+        std::fs::write(path, serialized)?;
+        // The file holds API keys, so keep it readable by the current user only
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    /// Adds or replaces a profile. The first profile added becomes the default.
+    pub fn add_profile(&mut self, name: String, profile: Profile) {
+        if self.default.is_none() {
+            self.default = Some(name.clone());
+        }
+        self.profiles.insert(name, profile);
+    }
+
+    pub fn remove_profile(&mut self, name: &str) -> Option<Profile> {
+        let removed = self.profiles.remove(name);
+        if self.default.as_deref() == Some(name) {
+            self.default = None;
+        }
+        removed
+    }
+
+    pub fn set_default(&mut self, name: &str) -> bool {
+        if !self.profiles.contains_key(name) {
+            return false;
+        }
+        self.default = Some(name.to_string());
+        true
+    }
+}
+
+#[derive(Clone)]
+pub struct ResolvedAuth {
+    pub auth: AuthMode,
+    pub profile: Option<(String, Profile)>,
+    pub cached_token: Option<CachedToken>,
+}
+
+impl ResolvedAuth {
+    /// The Evervault domain of the resolved profile, defaulting to evervault.com
+    pub fn domain(&self) -> &str {
+        self.profile
+            .as_ref()
+            .and_then(|(_, profile)| profile.domain.as_deref())
+            .unwrap_or(DEFAULT_DOMAIN)
+    }
+
+    /// Fills in an app or team uuid which isn't given with the default of the resolved profile
+    pub fn app_and_team(
+        &self,
+        app_uuid: Option<String>,
+        team_uuid: Option<String>,
+    ) -> Option<(String, String)> {
+        let profile = self.profile.as_ref().map(|(_, profile)| profile);
+        Some((
+            app_uuid.or_else(|| profile?.app_uuid.clone())?,
+            team_uuid.or_else(|| profile?.team_uuid.clone())?,
+        ))
+    }
+}
+
+// The domain is needed for every request, so it's kept once credentials have been resolved
+static RESOLVED_DOMAIN: OnceLock<String> = OnceLock::new();
+
+/// The profile selected using `--profile` or EV_PROFILE
+pub fn selected_profile_name() -> Option<String> {
+    std::env::var(PROFILE_ENV_VAR)
+        .ok()
+        .filter(|profile| !profile.is_empty())
+}
+
+fn env_api_key() -> Option<String> {
+    std::env::var("EV_API_KEY")
+        .ok()
+        .filter(|api_key| !api_key.is_empty())
+}
+
+/// Resolves the credentials to use for a command. An explicitly selected profile takes precedence,
+/// followed by EV_API_KEY, the default profile from the credentials file, then a token from `ev-cage login`.
+pub fn resolve_auth_from(
+    credentials: &CredentialsFile,
+    selected_profile: Option<&str>,
+    env_api_key: Option<String>,
+    cached_token: Option<CachedToken>,
+) -> Result<ResolvedAuth, AuthError> {
+    let profile_name = match (selected_profile, &env_api_key, &credentials.default) {
+        (Some(profile_name), _, _) => profile_name.to_string(),
+        (None, Some(api_key), _) => {
+            return Ok(ResolvedAuth {
+                auth: AuthMode::ApiKey(api_key.clone()),
+                profile: None,
+                cached_token: None,
+            })
+        }
+        (None, None, Some(default_profile)) => default_profile.clone(),
+        (None, None, None) => {
+            let cached_token = cached_token.ok_or(AuthError::NoCredentials)?;
+            return Ok(ResolvedAuth {
+                auth: AuthMode::BearerAuth(cached_token.access_token.clone()),
+                profile: None,
+                cached_token: Some(cached_token),
+            });
+        }
+    };
+
+    let profile = match credentials.profiles.get(&profile_name) {
+        Some(profile) => profile.clone(),
+        None => return Err(profile_not_found(profile_name)),
+    };
+    Ok(ResolvedAuth {
+        auth: AuthMode::ApiKey(profile.api_key.clone()),
+        profile: Some((profile_name, profile)),
+        cached_token: None,
+    })
+}
+
+fn profile_not_found(profile_name: String) -> AuthError {
+    let path = credentials_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    AuthError::ProfileNotFound(profile_name, path)
+}
+
+pub fn resolve_auth() -> Result<ResolvedAuth, AuthError> {
+    let selected_profile = selected_profile_name();
+    let env_api_key = env_api_key();
+    // Avoid touching the credentials file when the environment already has everything we need
+    let resolved = if selected_profile.is_none() && env_api_key.is_some() {
+        resolve_auth_from(&CredentialsFile::default(), None, env_api_key, None)?
+    } else {
+        let credentials = CredentialsFile::load()?;
+        let cached_token = CachedToken::load().ok().flatten();
+        resolve_auth_from(
+            &credentials,
+            selected_profile.as_deref(),
+            env_api_key,
+            cached_token,
+        )?
+    };
+    let _ = RESOLVED_DOMAIN.set(resolved.domain().to_string());
+    Ok(resolved)
+}
+
+/// Returns the app and team uuids to use for a command, as `(app_uuid, team_uuid)`. Either one which
+/// isn't given, such as by the cage.toml, defaults to the app or team of the active profile.
+pub fn resolve_app_and_team(
+    app_uuid: Option<String>,
+    team_uuid: Option<String>,
+) -> Option<(String, String)> {
+    match (app_uuid, team_uuid) {
+        (Some(app_uuid), Some(team_uuid)) => Some((app_uuid, team_uuid)),
+        (app_uuid, team_uuid) => resolve_auth().ok()?.app_and_team(app_uuid, team_uuid),
+    }
+}
+
+/// Resolves credentials as with `resolve_auth`, refreshing the cached login token if it has expired
+pub async fn resolve_auth_with_refresh() -> Result<ResolvedAuth, AuthError> {
+    let mut resolved = resolve_auth()?;
+    if let Some(cached_token) = resolved.cached_token.take() {
+        let cached_token = login::refresh_cached_token(cached_token).await?;
+        resolved.auth = AuthMode::BearerAuth(cached_token.access_token.clone());
+        resolved.cached_token = Some(cached_token);
+    }
+    Ok(resolved)
+}
+
+/// The Evervault domain to target. EV_DOMAIN takes precedence over the domain of the active profile.
+///
+/// Credentials are only resolved the first time the domain is needed, unless a command has already
+/// resolved them. Commands which don't need credentials, such as `build`, can run without any.
+pub fn ev_domain() -> Result<String, AuthError> {
+    if let Ok(domain) = std::env::var("EV_DOMAIN") {
+        return Ok(domain);
+    }
+    if let Some(domain) = RESOLVED_DOMAIN.get() {
+        return Ok(domain.clone());
+    }
+    match resolve_auth() {
+        Ok(resolved) => Ok(resolved.domain().to_string()),
+        Err(AuthError::NoCredentials) => Ok(RESOLVED_DOMAIN
+            .get_or_init(|| DEFAULT_DOMAIN.to_string())
+            .clone()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(api_key: &str) -> Profile {
+        Profile {
+            api_key: api_key.to_string(),
+            domain: None,
+            app_uuid: None,
+            team_uuid: None,
+        }
+    }
+
+    fn credentials() -> CredentialsFile {
+        let mut credentials = CredentialsFile::default();
+        credentials.add_profile("work".to_string(), profile("work-key"));
+        credentials.add_profile("personal".to_string(), profile("personal-key"));
+        credentials
+    }
+
+    fn api_key(resolved: &ResolvedAuth) -> &str {
+        match &resolved.auth {
+            AuthMode::ApiKey(api_key) => api_key,
+            _ => panic!("Expected api key auth"),
+        }
+    }
+
+    #[test]
+    fn test_first_profile_becomes_default() {
+        let credentials = credentials();
+        assert_eq!(credentials.default.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn test_selected_profile_takes_precedence_over_env() {
+        let resolved = resolve_auth_from(
+            &credentials(),
+            Some("personal"),
+            Some("env-key".into()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(api_key(&resolved), "personal-key");
+        assert_eq!(resolved.profile.unwrap().0, "personal");
+    }
+
+    #[test]
+    fn test_env_api_key_takes_precedence_over_default_profile() {
+        let resolved =
+            resolve_auth_from(&credentials(), None, Some("env-key".into()), None).unwrap();
+        assert_eq!(api_key(&resolved), "env-key");
+        assert!(resolved.profile.is_none());
+    }
+
+    #[test]
+    fn test_default_profile_is_used_as_fallback() {
+        let resolved = resolve_auth_from(&credentials(), None, None, None).unwrap();
+        assert_eq!(api_key(&resolved), "work-key");
+    }
+
+    #[test]
+    fn test_missing_credentials() {
+        assert!(matches!(
+            resolve_auth_from(&CredentialsFile::default(), None, None, None),
+            Err(AuthError::NoCredentials)
+        ));
+        assert!(matches!(
+            resolve_auth_from(&credentials(), Some("unknown"), None, None),
+            Err(AuthError::ProfileNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn test_domain_of_resolved_profile() {
+        let mut credentials = credentials();
+        credentials.add_profile(
+            "staging".to_string(),
+            Profile {
+                domain: Some("evervault.io".to_string()),
+                ..profile("staging-key")
+            },
+        );
+        let resolved = resolve_auth_from(&credentials, Some("staging"), None, None).unwrap();
+        assert_eq!(resolved.domain(), "evervault.io");
+
+        let resolved = resolve_auth_from(&credentials, None, None, None).unwrap();
+        assert_eq!(resolved.domain(), DEFAULT_DOMAIN);
+        let resolved = resolve_auth_from(&credentials, None, Some("env-key".into()), None).unwrap();
+        assert_eq!(resolved.domain(), DEFAULT_DOMAIN);
+    }
+
+    #[test]
+    fn test_cached_login_token_is_used_as_last_resort() {
+        let cached_token = CachedToken {
+            access_token: "access".to_string(),
+            refresh_token: None,
+            expires_at: None,
+        };
+        let resolved = resolve_auth_from(
+            &CredentialsFile::default(),
+            None,
+            None,
+            Some(cached_token.clone()),
+        )
+        .unwrap();
+        assert!(matches!(resolved.auth, AuthMode::BearerAuth(token) if token == "access"));
+
+        let resolved = resolve_auth_from(&credentials(), None, None, Some(cached_token)).unwrap();
+        assert_eq!(api_key(&resolved), "work-key");
+    }
+
+    #[test]
+    fn test_removing_default_profile_clears_default() {
+        let mut credentials = credentials();
+        assert!(credentials.remove_profile("work").is_some());
+        assert!(credentials.default.is_none());
+        assert!(!credentials.set_default("work"));
+        assert!(credentials.set_default("personal"));
+        assert_eq!(credentials.default.as_deref(), Some("personal"));
+    }
+
+    #[test]
+    fn test_app_and_team_default_to_the_profile() {
+        let mut credentials = credentials();
+        credentials.add_profile(
+            "staging".to_string(),
+            Profile {
+                app_uuid: Some("app_123".to_string()),
+                team_uuid: Some("team_123".to_string()),
+                ..profile("staging-key")
+            },
+        );
+        let staging = resolve_auth_from(&credentials, Some("staging"), None, None).unwrap();
+        assert_eq!(
+            staging.app_and_team(None, None),
+            Some(("app_123".to_string(), "team_123".to_string()))
+        );
+        assert_eq!(
+            staging.app_and_team(Some("app_456".to_string()), None),
+            Some(("app_456".to_string(), "team_123".to_string()))
+        );
+
+        let work = resolve_auth_from(&credentials, Some("work"), None, None).unwrap();
+        assert_eq!(work.app_and_team(Some("app_456".to_string()), None), None);
+    }
+
+    #[test]
+    fn test_credentials_file_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("nested").join("credentials.toml");
+        assert_eq!(
+            CredentialsFile::load_from(&path).unwrap(),
+            CredentialsFile::default()
+        );
+
+        let mut credentials = credentials();
+        credentials.add_profile(
+            "staging".to_string(),
+            Profile {
+                api_key: "staging-key".to_string(),
+                domain: Some("evervault.io".to_string()),
+                app_uuid: Some("app_123".to_string()),
+                team_uuid: Some("team_123".to_string()),
+            },
+        );
+        credentials.save_to(&path).unwrap();
+        assert_eq!(CredentialsFile::load_from(&path).unwrap(), credentials);
+    }
+}
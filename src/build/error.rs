@@ -1,6 +1,8 @@
+use crate::auth::AuthError;
 use crate::common::{CliError, OutputPathError};
 use crate::config::SigningInfoError;
 use crate::docker::error::DockerError;
+use crate::docker::span::{render_snippet, Span};
 use crate::enclave::error::EnclaveError;
 use thiserror::Error;
 
@@ -26,6 +28,60 @@ pub enum BuildError {
     EnclaveConversionError(String),
     #[error(transparent)]
     EnclaveError(#[from] EnclaveError),
+    #[error(transparent)]
+    AuthError(#[from] AuthError),
+    #[error("Invalid USER directive `{0}`. Expected a user or uid, optionally followed by a group or gid, such as app, 1000, app:staff or 1000:1000.")]
+    InvalidUser(String),
+    #[error("Traffic can't be forwarded to port {0}. Set forward_port in the cage.toml to a port between 1 and 65535.")]
+    InvalidForwardPort(u16),
+    #[error("{error}{}", .snippet.as_ref().map(|snippet| format!("\n{snippet}")).unwrap_or_default())]
+    InDockerfile {
+        error: Box<BuildError>,
+        span: Span,
+        snippet: Option<String>,
+    },
+}
+
+impl BuildError {
+    /// Attaches the location of the Dockerfile instruction which caused the error
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::InDockerfile { .. } => self,
+            error => Self::InDockerfile {
+                error: Box::new(error),
+                span,
+                snippet: None,
+            },
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::InDockerfile { span, .. } => Some(span),
+            Self::DockerError(DockerError::ParserDecodeError(e)) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// Adds a snippet of the instruction which caused the error to its message, if it's known
+    pub fn with_snippet(self, source: &str, path: &str) -> Self {
+        let Some(span) = self.span().copied() else {
+            return self;
+        };
+        let snippet = Some(render_snippet(source, path, &span));
+        match self {
+            Self::InDockerfile { error, span, .. } => Self::InDockerfile {
+                error,
+                span,
+                snippet,
+            },
+            error => Self::InDockerfile {
+                error: Box::new(error),
+                span,
+                snippet,
+            },
+        }
+    }
 }
 
 impl CliError for BuildError {
@@ -40,6 +96,9 @@ impl CliError for BuildError {
             Self::DockerError(_) | Self::DockerBuildError(_) => exitcode::SOFTWARE,
             Self::EnclaveConversionError(_) => exitcode::SOFTWARE,
             Self::EnclaveError(e) => e.exitcode(),
+            Self::AuthError(e) => e.exitcode(),
+            Self::InvalidUser(_) | Self::InvalidForwardPort(_) => exitcode::DATAERR,
+            Self::InDockerfile { error, .. } => error.exitcode(),
         }
     }
 }
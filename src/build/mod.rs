@@ -1,16 +1,20 @@
 pub mod error;
+mod user;
 use error::BuildError;
+use user::ServiceUser;
 
 use crate::common::{resolve_output_path, OutputPath};
 use crate::config::ValidatedCageBuildConfig;
 use crate::docker::error::DockerError;
-use crate::docker::parse::{Directive, DockerfileDecoder, Mode};
+use crate::docker::parse::{BuildStage, Directive, DockerfileDecoder, ExposedPort, Mode, Protocol};
+use crate::docker::span::Spanned;
 use crate::docker::utils::verify_docker_is_running;
+use crate::docker::vars::{expand_stages, BuildVariables};
 use crate::enclave;
 use serde_json::json;
+use std::collections::HashSet;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use tokio::fs::File;
 use tokio::io::AsyncRead;
 
 const EV_USER_DOCKERFILE_PATH: &str = "enclave.Dockerfile";
@@ -18,17 +22,32 @@ const INSTALLER_DIRECTORY: &str = "/opt/evervault";
 const USER_ENTRYPOINT_SERVICE_PATH: &str = "/etc/service/user-entrypoint";
 const DATA_PLANE_SERVICE_PATH: &str = "/etc/service/data-plane";
 
+/// How an enclave image is built, other than the Cage's config and where it's built
+pub struct BuildOptions<'a> {
+    pub verbose: bool,
+    pub docker_build_args: Option<Vec<&'a str>>,
+    pub data_plane_version: String,
+    pub installer_version: String,
+    pub timestamp: String,
+    pub from_existing: Option<String>,
+    pub images: &'a enclave::BuildImages,
+}
+
 pub async fn build_enclave_image_file(
     cage_config: &ValidatedCageBuildConfig,
     context_path: &str,
     output_dir: Option<&str>,
-    verbose: bool,
-    docker_build_args: Option<Vec<&str>>,
-    data_plane_version: String,
-    installer_version: String,
-    timestamp: String,
-    from_existing: Option<String>,
+    options: BuildOptions<'_>,
 ) -> Result<(enclave::BuiltEnclave, OutputPath), BuildError> {
+    let BuildOptions {
+        verbose,
+        docker_build_args,
+        data_plane_version,
+        installer_version,
+        timestamp,
+        from_existing,
+        images,
+    } = options;
     let context_path = Path::new(&context_path);
     if !context_path.exists() {
         log::error!(
@@ -53,6 +72,7 @@ pub async fn build_enclave_image_file(
                 verbose,
                 docker_build_args,
                 timestamp,
+                images,
             )?;
         }
         None => {
@@ -65,6 +85,7 @@ pub async fn build_enclave_image_file(
                 installer_version,
                 output_path.path(),
                 timestamp,
+                images,
             )
             .await?;
         }
@@ -75,9 +96,9 @@ pub async fn build_enclave_image_file(
         output_path.path().as_os_str().to_str().unwrap()
     );
 
-    enclave::build_nitro_cli_image(output_path.path(), Some(&signing_info), verbose)?;
+    enclave::build_nitro_cli_image(output_path.path(), Some(&signing_info), images, verbose)?;
     log::info!("Converting docker image to EIF...");
-    enclave::run_conversion_to_enclave(output_path.path(), verbose)
+    enclave::run_conversion_to_enclave(output_path.path(), images, verbose)
         .map(|built_enc| (built_enc, output_path))
         .map_err(|e| e.into())
 }
@@ -91,6 +112,7 @@ pub async fn build_from_scratch(
     installer_version: String,
     output_path: &PathBuf,
     timestamp: String,
+    images: &enclave::BuildImages,
 ) -> Result<(), BuildError> {
     if !verify_docker_is_running()? {
         return Err(DockerError::DaemonNotRunning.into());
@@ -104,17 +126,27 @@ pub async fn build_from_scratch(
         ));
     }
 
-    let dockerfile = File::open(dockerfile_path)
+    // the Dockerfile is kept in memory to show the offending instruction if it can't be processed
+    let dockerfile = tokio::fs::read(dockerfile_path)
         .await
         .map_err(|_| BuildError::DockerfileAccessError(cage_config.dockerfile().to_string()))?;
 
+    let build_args =
+        BuildVariables::from_docker_build_args(docker_build_args.as_deref().unwrap_or_default());
     let processed_dockerfile = process_dockerfile(
         cage_config,
-        dockerfile,
+        dockerfile.as_slice(),
         data_plane_version,
         installer_version,
+        &build_args,
     )
-    .await?;
+    .await
+    .map_err(|e| {
+        e.with_snippet(
+            &String::from_utf8_lossy(&dockerfile),
+            cage_config.dockerfile(),
+        )
+    })?;
 
     // write new dockerfile to fs
     let user_dockerfile_path = output_path.as_path().join(EV_USER_DOCKERFILE_PATH);
@@ -139,6 +171,7 @@ pub async fn build_from_scratch(
         verbose,
         docker_build_args,
         timestamp,
+        images,
     )?;
     log::debug!("User image built...");
     Ok(())
@@ -149,63 +182,88 @@ async fn process_dockerfile<R: AsyncRead + std::marker::Unpin>(
     dockerfile_src: R,
     data_plane_version: String,
     installer_version: String,
+    build_args: &BuildVariables,
 ) -> Result<Vec<Directive>, BuildError> {
     // Decode dockerfile from file
-    let instruction_set = DockerfileDecoder::decode_dockerfile_from_src(dockerfile_src).await?;
-
-    // Filter out unwanted directives
-    let mut last_cmd = None;
-    let mut last_entrypoint = None;
-    let mut last_user = None;
-    let mut exposed_port: Option<u16> = None;
-
-    let mut directive_parse_error = None;
-
-    let remove_unwanted_directives = |directive: &Directive| -> bool {
-        if directive.is_cmd() {
-            last_cmd = Some(directive.clone());
-        } else if directive.is_entrypoint() {
-            last_entrypoint = Some(directive.clone());
-        } else if let Directive::Expose { port } = directive {
-            exposed_port = *port;
-        } else if let Directive::User(b) = directive {
-            if let Ok(user) = String::from_utf8(b.to_vec()) {
-                last_user = Some(user);
-            } else {
-                directive_parse_error = Some(BuildError::DockerBuildError(
-                    "Could not parse username from USER directive".to_string(),
-                ))
-            };
-            return true;
-        } else {
-            return true;
+    let mut stages = DockerfileDecoder::decode_stages_from_src(dockerfile_src).await?;
+    // Runtime directives are interpreted with their ARG and ENV references expanded, but the original
+    // text is written to the processed Dockerfile for Docker to expand itself
+    let mut expanded_stages = expand_stages(&stages, build_args).map_err(DockerError::from)?;
+    // Stages are numbered by their FROM directives, which is how the reproducible build copies the
+    // final stage's filesystem
+    #[cfg_attr(not(feature = "repro_builds"), allow(unused_variables))]
+    let final_stage_index = stages
+        .iter()
+        .filter(|stage| stage.from_directive().is_some())
+        .count()
+        .saturating_sub(1);
+    let final_stage = stages.pop().expect("A Dockerfile has at least one stage");
+    let expanded_final_stage = expanded_stages
+        .pop()
+        .expect("A Dockerfile has at least one stage");
+
+    // The final stage inherits the runtime directives of any earlier stage it's built from, and
+    // overrides them with its own
+    let mut runtime = RuntimeDirectives::default();
+    for base_stage in base_stages(&expanded_stages, &expanded_final_stage) {
+        for directive in base_stage.directives() {
+            runtime.observe(directive)?;
         }
-        false
-    };
+    }
 
-    let cleaned_instructions: Vec<Directive> = instruction_set
+    // Only the final stage's runtime directives are removed, earlier stages are left untouched
+    let mut final_directives = Vec::new();
+    let final_from = final_stage
+        .from_directive()
+        .cloned()
+        .map(Spanned::into_inner);
+    for (directive, expanded_directive) in final_stage
+        .directives()
+        .iter()
+        .zip(expanded_final_stage.directives())
+    {
+        if !runtime.observe(expanded_directive)? {
+            final_directives.push(Directive::clone(directive));
+        }
+    }
+    let RuntimeDirectives {
+        last_cmd,
+        last_entrypoint,
+        last_user,
+        exposed_ports,
+    } = runtime;
+
+    let cleaned_instructions: Vec<Directive> = stages
         .into_iter()
-        .filter(remove_unwanted_directives)
+        .flat_map(BuildStage::into_directives)
+        .chain(final_from)
+        .chain(final_directives)
         .collect();
 
-    if let Some(directive_parse_error) = directive_parse_error {
-        return Err(directive_parse_error);
-    }
-
     let wait_for_env = if build_config.disable_tls_termination {
         "echo TLS termination is off, not waiting for environment to be ready"
     } else {
         r#"while ! grep -q \"EV_CAGE_INITIALIZED\" /etc/customer-env\n do echo \"Env not ready, sleeping user process for one second\"\n sleep 1\n done \n . /etc/customer-env\n"#
     };
+    let user_check = last_user.as_ref().and_then(ServiceUser::check_directive);
     let user_service_builder =
         crate::docker::utils::create_combined_docker_entrypoint(last_entrypoint, last_cmd)
-            .map(|entrypoint| build_user_service(entrypoint, wait_for_env, last_user))?;
-
-    if let Some(true) = exposed_port.map(|port| port == 443) {
-        return Err(DockerError::RestrictedPortExposed(exposed_port.unwrap()).into());
+            .map(|entrypoint| build_user_service(entrypoint, wait_for_env, last_user.as_ref()))?;
+
+    for exposed in &exposed_ports {
+        let error = match **exposed {
+            ExposedPort {
+                port,
+                protocol: Protocol::Udp,
+            } => DockerError::UdpPortExposed(port),
+            ExposedPort { port: 443, .. } => DockerError::RestrictedPortExposed(443),
+            _ => continue,
+        };
+        return Err(BuildError::from(error).at(*exposed.span()));
     }
+    let forwarded_port = forwarded_port(build_config.forward_port(), &exposed_ports)?;
 
-    let ev_domain = std::env::var("EV_DOMAIN").unwrap_or_else(|_| String::from("evervault.com"));
+    let ev_domain = crate::auth::ev_domain()?;
 
     let data_plane_url = format!(
         "https://cage-build-assets.{}/runtime/{}/data-plane/{}",
@@ -216,7 +274,7 @@ async fn process_dockerfile<R: AsyncRead + std::marker::Unpin>(
 
     let mut data_plane_run_script =
         r#"echo \"Booting Evervault data plane...\"\nexec /opt/evervault/data-plane"#.to_string();
-    if let Some(port) = exposed_port {
+    if let Some(port) = forwarded_port {
         data_plane_run_script = format!("{data_plane_run_script} {port}");
     }
 
@@ -257,29 +315,36 @@ async fn process_dockerfile<R: AsyncRead + std::marker::Unpin>(
         dataplane_info.to_string().replace("\"", "\\\"")
     );
 
-    let injected_directives = vec![
-        Directive::new_user("root"),
-        // install dependencies
-        Directive::new_run(format!("mkdir -p {INSTALLER_DIRECTORY}")),
-        Directive::new_add(&installer_bundle_url, &installer_destination),
-        Directive::new_run(format!("cd {INSTALLER_DIRECTORY} ; tar -xzf {installer_bundle} ; sh ./installer.sh ; rm {installer_bundle}")),
-        Directive::new_run(dataplane_env),
-        // create user service directory
-        Directive::new_run(format!("mkdir -p {USER_ENTRYPOINT_SERVICE_PATH}")),
-        // add user service runner
-        user_service_builder,
-        // add data-plane executable
-        Directive::new_add(data_plane_url, "/opt/evervault/data-plane".into()),
-        Directive::new_run("chmod +x /opt/evervault/data-plane"),
-        // add data-plane service directory
-        Directive::new_run(format!("mkdir -p {DATA_PLANE_SERVICE_PATH}")),
-        // add data-plane service runner
-        Directive::new_run(crate::docker::utils::write_command_to_script(
-            data_plane_run_script.as_str(),
-            format!("{DATA_PLANE_SERVICE_PATH}/run").as_str(),
-            &[],
-        ))
-    ];
+    let injected_directives = [
+        vec![
+            Directive::new_user("root"),
+            // install dependencies
+            Directive::new_run(format!("mkdir -p {INSTALLER_DIRECTORY}")),
+            Directive::new_add(&installer_bundle_url, &installer_destination),
+            Directive::new_run(format!("cd {INSTALLER_DIRECTORY} ; tar -xzf {installer_bundle} ; sh ./installer.sh ; rm {installer_bundle}")),
+            Directive::new_run(dataplane_env),
+            // create user service directory
+            Directive::new_run(format!("mkdir -p {USER_ENTRYPOINT_SERVICE_PATH}")),
+        ],
+        // check the user the service runs as exists in the image
+        user_check.into_iter().collect(),
+        vec![
+            // add user service runner
+            user_service_builder,
+            // add data-plane executable
+            Directive::new_add(data_plane_url, "/opt/evervault/data-plane".into()),
+            Directive::new_run("chmod +x /opt/evervault/data-plane"),
+            // add data-plane service directory
+            Directive::new_run(format!("mkdir -p {DATA_PLANE_SERVICE_PATH}")),
+            // add data-plane service runner
+            Directive::new_run(crate::docker::utils::write_command_to_script(
+                data_plane_run_script.as_str(),
+                format!("{DATA_PLANE_SERVICE_PATH}/run").as_str(),
+                &[],
+            ))
+        ],
+    ]
+    .concat();
 
     // add custom directives to end of dockerfile
     Ok([
@@ -293,7 +358,7 @@ async fn process_dockerfile<R: AsyncRead + std::marker::Unpin>(
             ),
         )],
         #[cfg(feature = "repro_builds")]
-        reproducible_build_directives(),
+        reproducible_build_directives(final_stage_index),
         vec![Directive::new_entrypoint(
             Mode::Exec,
             vec!["/bootstrap".to_string(), "1>&2".to_string()],
@@ -303,30 +368,136 @@ async fn process_dockerfile<R: AsyncRead + std::marker::Unpin>(
 }
 
 #[cfg(feature = "repro_builds")]
-fn reproducible_build_directives() -> Vec<Directive> {
+fn reproducible_build_directives(final_stage_index: usize) -> Vec<Directive> {
     let repro_time = r#"find $( ls / | grep -E -v "^(dev|mnt|proc|sys)$" ) -xdev | xargs touch --date="@0" --no-dereference || true"#.to_string();
     vec![
         Directive::new_run(repro_time),
         // add entrypoint which starts the runit services
         Directive::new_from("scratch".to_string()),
-        Directive::new_copy("--from=0 / /".to_string()),
+        Directive::new_copy(format!("--from={final_stage_index} / /")),
     ]
 }
 
+// The CMD, ENTRYPOINT, USER and EXPOSE directives which control how the user's service is run
+#[derive(Default)]
+struct RuntimeDirectives {
+    last_cmd: Option<Directive>,
+    last_entrypoint: Option<Directive>,
+    last_user: Option<ServiceUser>,
+    exposed_ports: Vec<Spanned<ExposedPort>>,
+}
+
+impl RuntimeDirectives {
+    // Records the directive if it affects the runtime, and returns whether it should be removed from
+    // the Dockerfile. USER directives are kept, as they apply to the rest of the build.
+    fn observe(&mut self, directive: &Spanned<Directive>) -> Result<bool, BuildError> {
+        match &**directive {
+            Directive::Cmd { .. } => self.last_cmd = Some(Directive::clone(directive)),
+            Directive::Entrypoint { .. } => {
+                self.last_entrypoint = Some(Directive::clone(directive))
+            }
+            Directive::Expose { ports, .. } => self.exposed_ports.extend(
+                ports
+                    .iter()
+                    .flatten()
+                    .map(|port| Spanned::new(*port, *directive.span())),
+            ),
+            Directive::User(b) => {
+                let user = String::from_utf8(b.to_vec()).map_err(|_| {
+                    BuildError::DockerBuildError(
+                        "Could not parse username from USER directive".to_string(),
+                    )
+                    .at(*directive.span())
+                })?;
+                let user = user
+                    .parse()
+                    .map_err(|e: BuildError| e.at(*directive.span()))?;
+                self.last_user = Some(user);
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+// Returns the port the data plane forwards traffic to. The port set in the cage.toml is used if there
+// is one, otherwise it's the last port exposed in the Dockerfile.
+fn forwarded_port(
+    configured_port: Option<u16>,
+    exposed_ports: &[Spanned<ExposedPort>],
+) -> Result<Option<u16>, BuildError> {
+    // A port exposed more than once is only counted the first time it's seen
+    let mut seen = HashSet::new();
+    let exposed: Vec<u16> = exposed_ports
+        .iter()
+        .map(|exposed| exposed.port)
+        .filter(|port| seen.insert(*port))
+        .collect();
+    match (configured_port, exposed.as_slice()) {
+        (Some(0), _) => Err(BuildError::InvalidForwardPort(0)),
+        (Some(443), _) => Err(DockerError::RestrictedPortExposed(443).into()),
+        (Some(port), exposed) => {
+            if !exposed.is_empty() && !exposed.contains(&port) {
+                log::warn!(
+                    "Forwarding traffic to port {port}, which isn't exposed in the Dockerfile"
+                );
+            }
+            Ok(Some(port))
+        }
+        (None, [_, .., last_port]) => {
+            log::warn!(
+                "The Dockerfile exposes more than one port, forwarding traffic to the last one ({last_port}). Set forward_port in the cage.toml to choose another."
+            );
+            Ok(Some(*last_port))
+        }
+        (None, exposed) => Ok(exposed.first().copied()),
+    }
+}
+
+// Returns the earlier stages the final stage is built from, starting with the furthest ancestor.
+// Stage names are matched case-insensitively, as they are by Docker.
+fn base_stages<'a>(stages: &'a [BuildStage], final_stage: &BuildStage) -> Vec<&'a BuildStage> {
+    let mut base_stages: Vec<&BuildStage> = Vec::new();
+    let mut base_image = final_stage.base_image();
+    while let Some(image) = base_image {
+        let Some(base_stage) = stages.iter().rev().find(|stage| {
+            stage
+                .name()
+                .is_some_and(|name| name.eq_ignore_ascii_case(image))
+        }) else {
+            break;
+        };
+        // Stages can only build on stages before them, but guard against cycles all the same
+        if base_stages
+            .iter()
+            .any(|stage| std::ptr::eq(*stage, base_stage))
+        {
+            break;
+        }
+        base_stages.push(base_stage);
+        base_image = base_stage.base_image();
+    }
+    base_stages.reverse();
+    base_stages
+}
+
 pub fn build_user_service(
     entrypoint: String,
     wait_for_env: &str,
-    last_user: Option<String>,
+    last_user: Option<&ServiceUser>,
 ) -> Directive {
-    let su_cmd = if let Some(last_user) = last_user {
-        format!("su {last_user}")
-    } else {
-        "".to_string()
+    // The entrypoint's variables are expanded when the service starts, so the Cage's environment
+    // can override them, rather than by the RUN which writes the script
+    let entrypoint = crate::docker::utils::escape_for_script(&entrypoint);
+    // runit's chpst drops privileges to the USER before running the entrypoint, while the checks
+    // before it still run as root
+    let exec_cmd = match last_user {
+        Some(user) => format!("exec chpst -u {} {entrypoint}", user.chpst_user()),
+        None => format!("exec {entrypoint}"),
     };
-    let exec_cmd = format!("exec {}", entrypoint);
 
     let cmds = vec![
-        su_cmd.as_str(),
         "sleep 5",
         r#"echo \"Checking status of data-plane\""#,
         "SVDIR=/etc/service sv check data-plane || exit 1",
@@ -355,12 +526,15 @@ pub fn build_user_service(
 
 #[cfg(test)]
 mod test {
-    use super::{process_dockerfile, BuildError};
+    use super::{forwarded_port, process_dockerfile, BuildError};
     use crate::cert::CertValidityPeriod;
     use crate::config::EgressSettings;
     use crate::config::ValidatedCageBuildConfig;
     use crate::config::ValidatedSigningInfo;
     use crate::docker;
+    use crate::docker::parse::ExposedPort;
+    use crate::docker::span::{Span, Spanned};
+    use crate::docker::vars::BuildVariables;
     use crate::enclave;
     use crate::test_utils;
     use std::iter::zip;
@@ -393,6 +567,7 @@ mod test {
             trx_logging_enabled: true,
             runtime: None,
             forward_proxy_protocol: false,
+            forward_port: None,
         }
     }
 
@@ -416,6 +591,7 @@ ENTRYPOINT ["sh", "/hello-script"]"#;
             &mut readable_contents,
             data_plane_version,
             installer_version,
+            &BuildVariables::default(),
         )
         .await;
         assert_eq!(processed_file.is_ok(), true);
@@ -477,16 +653,21 @@ ENTRYPOINT ["sh", "/hello-script"]"#;
             &mut readable_contents,
             data_plane_version,
             installer_version,
+            &BuildVariables::default(),
         )
         .await;
         assert_eq!(processed_file.is_err(), true);
 
+        let Err(BuildError::InDockerfile { error, span, .. }) = processed_file else {
+            panic!("Expected the error to be located in the Dockerfile");
+        };
         assert!(matches!(
-            processed_file,
-            Err(BuildError::DockerError(
-                crate::docker::error::DockerError::RestrictedPortExposed(443)
+            *error,
+            BuildError::DockerError(crate::docker::error::DockerError::RestrictedPortExposed(
+                443
             ))
         ));
+        assert_eq!((span.line, span.column), (5, 1));
     }
 
     #[tokio::test]
@@ -508,6 +689,7 @@ ENTRYPOINT ["sh", "/hello-script"]"#;
             &mut readable_contents,
             data_plane_version,
             installer_version,
+            &BuildVariables::default(),
         )
         .await;
         assert_eq!(processed_file.is_ok(), true);
@@ -570,6 +752,7 @@ ENTRYPOINT ["sh", "/hello-script"]"#;
             &mut readable_contents,
             data_plane_version,
             installer_version,
+            &BuildVariables::default(),
         )
         .await;
         assert_eq!(processed_file.is_ok(), true);
@@ -585,7 +768,8 @@ ADD https://cage-build-assets.evervault.com/installer/abcdef.tar.gz /opt/evervau
 RUN cd /opt/evervault ; tar -xzf runtime-dependencies.tar.gz ; sh ./installer.sh ; rm runtime-dependencies.tar.gz
 RUN echo {\"api_key_auth\":true,\"trx_logging_enabled\":true} > /etc/dataplane-config.json
 RUN mkdir -p /etc/service/user-entrypoint
-RUN printf "#!/bin/sh\nsu someuser\nsleep 5\necho \"Checking status of data-plane\"\nSVDIR=/etc/service sv check data-plane || exit 1\necho \"Data-plane up and running\"\nwhile ! grep -q \"EV_CAGE_INITIALIZED\" /etc/customer-env\n do echo \"Env not ready, sleeping user process for one second\"\n sleep 1\n done \n . /etc/customer-env\n\necho \"Booting user service...\"\ncd %s\nexec sh /hello-script\n" "$PWD"  > /etc/service/user-entrypoint/run && chmod +x /etc/service/user-entrypoint/run
+RUN (id -u someuser > /dev/null 2>&1 || (echo "User someuser from the USER directive does not exist in the image" && exit 1))
+RUN printf "#!/bin/sh\nsleep 5\necho \"Checking status of data-plane\"\nSVDIR=/etc/service sv check data-plane || exit 1\necho \"Data-plane up and running\"\nwhile ! grep -q \"EV_CAGE_INITIALIZED\" /etc/customer-env\n do echo \"Env not ready, sleeping user process for one second\"\n sleep 1\n done \n . /etc/customer-env\n\necho \"Booting user service...\"\ncd %s\nexec chpst -u someuser sh /hello-script\n" "$PWD"  > /etc/service/user-entrypoint/run && chmod +x /etc/service/user-entrypoint/run
 ADD https://cage-build-assets.evervault.com/runtime/0.0.0/data-plane/egress-disabled/tls-termination-enabled /opt/evervault/data-plane
 RUN chmod +x /opt/evervault/data-plane
 RUN mkdir -p /etc/service/data-plane
@@ -613,6 +797,413 @@ ENTRYPOINT ["/bootstrap", "1>&2"]
         }
     }
 
+    #[tokio::test]
+    async fn test_process_dockerfile_with_multiple_stages() {
+        let sample_dockerfile_contents = r#"FROM node:18 AS builder
+USER builder
+EXPOSE 8080
+RUN npm run build
+CMD ["npm", "start"]
+
+FROM alpine
+COPY --from=builder /app /app
+EXPOSE 3443
+ENTRYPOINT ["sh", "/app/start.sh"]"#;
+        let mut readable_contents = sample_dockerfile_contents.as_bytes();
+
+        let config = get_config();
+
+        let data_plane_version = "0.0.0".to_string();
+        let installer_version = "abcdef".to_string();
+        let processed_file = process_dockerfile(
+            &config,
+            &mut readable_contents,
+            data_plane_version,
+            installer_version,
+            &BuildVariables::default(),
+        )
+        .await;
+        assert!(processed_file.is_ok());
+        let processed_file = processed_file.unwrap();
+
+        let expected_output_contents = r##"FROM node:18 AS builder
+USER builder
+EXPOSE 8080
+RUN npm run build
+CMD ["npm", "start"]
+FROM alpine
+COPY --from=builder /app /app
+USER root
+RUN mkdir -p /opt/evervault
+ADD https://cage-build-assets.evervault.com/installer/abcdef.tar.gz /opt/evervault/runtime-dependencies.tar.gz
+RUN cd /opt/evervault ; tar -xzf runtime-dependencies.tar.gz ; sh ./installer.sh ; rm runtime-dependencies.tar.gz
+RUN echo {\"api_key_auth\":true,\"trx_logging_enabled\":true} > /etc/dataplane-config.json
+RUN mkdir -p /etc/service/user-entrypoint
+RUN printf "#!/bin/sh\nsleep 5\necho \"Checking status of data-plane\"\nSVDIR=/etc/service sv check data-plane || exit 1\necho \"Data-plane up and running\"\nwhile ! grep -q \"EV_CAGE_INITIALIZED\" /etc/customer-env\n do echo \"Env not ready, sleeping user process for one second\"\n sleep 1\n done \n . /etc/customer-env\n\necho \"Booting user service...\"\ncd %s\nexec sh /app/start.sh\n" "$PWD"  > /etc/service/user-entrypoint/run && chmod +x /etc/service/user-entrypoint/run
+ADD https://cage-build-assets.evervault.com/runtime/0.0.0/data-plane/egress-disabled/tls-termination-enabled /opt/evervault/data-plane
+RUN chmod +x /opt/evervault/data-plane
+RUN mkdir -p /etc/service/data-plane
+RUN printf "#!/bin/sh\necho \"Booting Evervault data plane...\"\nexec /opt/evervault/data-plane 3443\n" > /etc/service/data-plane/run && chmod +x /etc/service/data-plane/run
+RUN printf "#!/bin/sh\nifconfig lo 127.0.0.1\n echo \"enclave.local\" > /etc/hostname \n echo \"127.0.0.1 enclave.local\" >> /etc/hosts \n hostname -F /etc/hostname \necho \"Booting enclave...\"\nexec runsvdir /etc/service\n" > /bootstrap && chmod +x /bootstrap
+RUN find $( ls / | grep -E -v "^(dev|mnt|proc|sys)$" ) -xdev | xargs touch --date="@0" --no-dereference || true
+FROM scratch
+COPY --from=1 / /
+ENTRYPOINT ["/bootstrap", "1>&2"]
+"##;
+
+        let expected_directives = docker::parse::DockerfileDecoder::decode_dockerfile_from_src(
+            expected_output_contents.as_bytes(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(expected_directives.len(), processed_file.len());
+        for (expected_directive, processed_directive) in
+            zip(expected_directives.iter(), processed_file.iter())
+        {
+            let expected_directive = expected_directive.to_string();
+            let processed_directive = processed_directive.to_string();
+            assert_eq!(expected_directive, processed_directive);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_inherits_runtime_directives_from_base_stage() {
+        let sample_dockerfile_contents = r#"FROM alpine AS base
+USER someuser
+EXPOSE 3443
+ENTRYPOINT ["sh", "/hello-script"]
+
+FROM alpine AS unrelated
+USER otheruser
+EXPOSE 8080
+
+FROM base
+RUN touch /hello-script"#;
+        let mut readable_contents = sample_dockerfile_contents.as_bytes();
+
+        let processed_file = process_dockerfile(
+            &get_config(),
+            &mut readable_contents,
+            "0.0.0".to_string(),
+            "abcdef".to_string(),
+            &BuildVariables::default(),
+        )
+        .await
+        .unwrap();
+        let processed_file: Vec<String> = processed_file
+            .iter()
+            .map(|directive| directive.to_string())
+            .collect();
+
+        // Earlier stages are kept as they are
+        assert_eq!(
+            &processed_file[..7],
+            &[
+                "FROM alpine AS base",
+                "USER someuser",
+                "EXPOSE 3443",
+                r#"ENTRYPOINT ["sh", "/hello-script"]"#,
+                "FROM alpine AS unrelated",
+                "USER otheruser",
+                "EXPOSE 8080",
+            ]
+        );
+        let user_service = processed_file
+            .iter()
+            .find(|directive| directive.contains("/etc/service/user-entrypoint/run"))
+            .unwrap();
+        assert!(user_service.contains(r"exec chpst -u someuser sh /hello-script\n"));
+        assert!(processed_file
+            .iter()
+            .any(|directive| directive.contains(r"exec /opt/evervault/data-plane 3443\n")));
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_expands_args_and_env() {
+        let sample_dockerfile_contents = r#"ARG BASE=alpine
+FROM ${BASE}
+ARG PORT=3000
+ARG BIN
+ENV APP_USER=someuser
+USER $APP_USER
+EXPOSE ${PORT}
+ENTRYPOINT ["$BIN", "--level", "${LOG_LEVEL:-info}"]"#;
+        let mut readable_contents = sample_dockerfile_contents.as_bytes();
+
+        let build_args = BuildVariables::from_docker_build_args(&[
+            "--build-arg",
+            "PORT=4000",
+            "--build-arg",
+            "BIN=/usr/bin/server",
+        ]);
+        let processed_file = process_dockerfile(
+            &get_config(),
+            &mut readable_contents,
+            "0.0.0".to_string(),
+            "abcdef".to_string(),
+            &build_args,
+        )
+        .await
+        .unwrap();
+        let processed_file: Vec<String> = processed_file
+            .iter()
+            .map(|directive| directive.to_string())
+            .collect();
+
+        // The Dockerfile keeps the original text, for Docker to expand
+        assert_eq!(
+            &processed_file[..6],
+            &[
+                "ARG BASE=alpine",
+                "FROM ${BASE}",
+                "ARG PORT=3000",
+                "ARG BIN",
+                "ENV APP_USER=someuser",
+                "USER $APP_USER",
+            ]
+        );
+        let user_service = processed_file
+            .iter()
+            .find(|directive| directive.contains("/etc/service/user-entrypoint/run"))
+            .unwrap();
+        assert!(user_service.contains(r"exec chpst -u someuser /usr/bin/server --level"));
+        // ENV references are left for the shell, as the Cage's environment can override them
+        assert!(user_service.contains(r#"/usr/bin/server --level \"\${LOG_LEVEL:-info}\"\n"#));
+        assert!(processed_file
+            .iter()
+            .any(|directive| directive.contains(r"exec /opt/evervault/data-plane 4000\n")));
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_with_restricted_port_from_arg() {
+        let sample_dockerfile_contents = r#"FROM alpine
+ARG PORT=443
+EXPOSE $PORT
+ENTRYPOINT ["sh", "/hello-script"]"#;
+        let mut readable_contents = sample_dockerfile_contents.as_bytes();
+
+        let processed_file = process_dockerfile(
+            &get_config(),
+            &mut readable_contents,
+            "0.0.0".to_string(),
+            "abcdef".to_string(),
+            &BuildVariables::default(),
+        )
+        .await;
+        let error = processed_file
+            .unwrap_err()
+            .with_snippet(sample_dockerfile_contents, "./Dockerfile");
+        let BuildError::InDockerfile { error: cause, .. } = &error else {
+            panic!("Expected the error to be located in the Dockerfile");
+        };
+        assert!(matches!(
+            **cause,
+            BuildError::DockerError(crate::docker::error::DockerError::RestrictedPortExposed(
+                443
+            ))
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Restricted port exposed. Cannot forward traffic to :443, address is already in use.
+ --> ./Dockerfile:3:1
+  |
+3 | EXPOSE $PORT
+  | ^^^^^^^^^^^^"
+        );
+    }
+
+    async fn data_plane_run_script(
+        config: &ValidatedCageBuildConfig,
+        dockerfile: &str,
+    ) -> Result<String, BuildError> {
+        let processed_file = process_dockerfile(
+            config,
+            dockerfile.as_bytes(),
+            "0.0.0".to_string(),
+            "abcdef".to_string(),
+            &BuildVariables::default(),
+        )
+        .await?;
+        Ok(processed_file
+            .iter()
+            .map(ToString::to_string)
+            .find(|directive| directive.contains("/etc/service/data-plane/run"))
+            .unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_with_multiple_exposed_ports() {
+        let sample_dockerfile_contents =
+            "FROM alpine\nEXPOSE 8080/tcp 9090\nEXPOSE 3000/TCP\nCMD sh\n";
+
+        let run_script = data_plane_run_script(&get_config(), sample_dockerfile_contents)
+            .await
+            .unwrap();
+        assert!(run_script.contains(r"exec /opt/evervault/data-plane 3000\n"));
+
+        let config = ValidatedCageBuildConfig {
+            forward_port: Some(9090),
+            ..get_config()
+        };
+        let run_script = data_plane_run_script(&config, sample_dockerfile_contents)
+            .await
+            .unwrap();
+        assert!(run_script.contains(r"exec /opt/evervault/data-plane 9090\n"));
+    }
+
+    #[test]
+    fn test_forwarded_port_counts_repeated_ports_once() {
+        let exposed = |ports: &[u16]| -> Vec<Spanned<ExposedPort>> {
+            ports
+                .iter()
+                .map(|port| Spanned::new(ExposedPort::tcp(*port), Span::default()))
+                .collect()
+        };
+        assert_eq!(
+            forwarded_port(None, &exposed(&[8080, 9090, 8080])).unwrap(),
+            Some(9090)
+        );
+        assert_eq!(
+            forwarded_port(None, &exposed(&[8080, 8080])).unwrap(),
+            Some(8080)
+        );
+        assert!(matches!(
+            forwarded_port(Some(0), &exposed(&[8080])),
+            Err(BuildError::InvalidForwardPort(0))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_checks_every_exposed_port() {
+        let restricted = data_plane_run_script(
+            &get_config(),
+            "FROM alpine\nEXPOSE 8080 443/tcp 3000\nCMD sh\n",
+        )
+        .await;
+        let Err(BuildError::InDockerfile { error, span, .. }) = restricted else {
+            panic!("Expected the error to be located in the Dockerfile");
+        };
+        assert!(matches!(
+            *error,
+            BuildError::DockerError(crate::docker::error::DockerError::RestrictedPortExposed(
+                443
+            ))
+        ));
+        assert_eq!(span.line, 2);
+
+        let udp = data_plane_run_script(
+            &get_config(),
+            "FROM alpine\nEXPOSE 8080\nEXPOSE 53/udp\nCMD sh\n",
+        )
+        .await;
+        let Err(BuildError::InDockerfile { error, span, .. }) = udp else {
+            panic!("Expected the error to be located in the Dockerfile");
+        };
+        assert!(matches!(
+            *error,
+            BuildError::DockerError(crate::docker::error::DockerError::UdpPortExposed(53))
+        ));
+        assert_eq!(span.line, 3);
+
+        let config = ValidatedCageBuildConfig {
+            forward_port: Some(443),
+            ..get_config()
+        };
+        let forwarded_to_restricted =
+            data_plane_run_script(&config, "FROM alpine\nEXPOSE 8080\nCMD sh\n").await;
+        assert!(matches!(
+            forwarded_to_restricted,
+            Err(BuildError::DockerError(
+                crate::docker::error::DockerError::RestrictedPortExposed(443)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_runs_service_as_user() {
+        let process = |dockerfile: &'static str| async move {
+            process_dockerfile(
+                &get_config(),
+                dockerfile.as_bytes(),
+                "0.0.0".to_string(),
+                "abcdef".to_string(),
+                &BuildVariables::default(),
+            )
+            .await
+            .map(|directives| {
+                directives
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let user_service = |directives: &[String]| {
+            directives
+                .iter()
+                .find(|directive| directive.contains("/etc/service/user-entrypoint/run"))
+                .unwrap()
+                .clone()
+        };
+
+        let directives = process("FROM alpine\nUSER 1000:1000\nCMD sh\n")
+            .await
+            .unwrap();
+        assert!(user_service(&directives).contains(r"exec chpst -u :1000:1000 sh\n"));
+        assert!(!directives
+            .iter()
+            .any(|directive| directive.contains("id -u")));
+
+        let directives = process("FROM alpine\nUSER app:staff\nCMD sh\n")
+            .await
+            .unwrap();
+        assert!(user_service(&directives).contains(r"exec chpst -u app:staff sh\n"));
+        let check_index = directives
+            .iter()
+            .position(|directive| {
+                directive.starts_with("RUN (id -u app ")
+                    && directive.contains(r#"grep -q "^staff:" /etc/group"#)
+            })
+            .unwrap();
+        assert_eq!(directives[check_index + 1], user_service(&directives));
+
+        let directives = process("FROM alpine\nCMD sh\n").await.unwrap();
+        assert!(user_service(&directives).contains(r"exec sh\n"));
+
+        let invalid = process("FROM alpine\nUSER app;root\nCMD sh\n").await;
+        let Err(BuildError::InDockerfile { error, span, .. }) = invalid else {
+            panic!("Expected the error to be located in the Dockerfile");
+        };
+        assert!(matches!(*error, BuildError::InvalidUser(_)));
+        assert_eq!(span.line, 2);
+    }
+
+    #[tokio::test]
+    async fn test_process_dockerfile_with_decode_error_shows_snippet() {
+        let sample_dockerfile_contents = "FROM alpine\nRUN echo hello\n  !oops\nCMD sh\n";
+        let mut readable_contents = sample_dockerfile_contents.as_bytes();
+
+        let processed_file = process_dockerfile(
+            &get_config(),
+            &mut readable_contents,
+            "0.0.0".to_string(),
+            "abcdef".to_string(),
+            &BuildVariables::default(),
+        )
+        .await;
+        let error = processed_file
+            .unwrap_err()
+            .with_snippet(sample_dockerfile_contents, "Dockerfile");
+        assert_eq!(
+            error.to_string(),
+            "Unexpected token found in the dockerfile
+ --> Dockerfile:3:3
+  |
+3 |   !oops
+  |   ^"
+        );
+    }
+
     #[tokio::test]
     async fn test_choose_output_dir() {
         let output_dir = TempDir::new().unwrap();
@@ -0,0 +1,147 @@
+use super::error::BuildError;
+use crate::docker::parse::Directive;
+
+/// The user the Cage's service runs as, taken from the last USER directive. Docker accepts a user
+/// name or uid, optionally followed by a group name or gid: `user`, `uid`, `user:group` or `uid:gid`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceUser {
+    user: String,
+    group: Option<String>,
+}
+
+fn is_id(value: &str) -> bool {
+    value.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+// Names are written into the service's run script, so only the characters allowed in portable user
+// and group names are accepted
+fn is_valid_name(value: &str) -> bool {
+    !value.is_empty()
+        && !value.starts_with('-')
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-'))
+}
+
+impl std::str::FromStr for ServiceUser {
+    type Err = BuildError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (user, group) = match value.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (value, None),
+        };
+        if !is_valid_name(user) || !group.is_none_or(is_valid_name) {
+            return Err(BuildError::InvalidUser(value.to_string()));
+        }
+        Ok(Self {
+            user: user.to_string(),
+            group: group.map(str::to_string),
+        })
+    }
+}
+
+impl ServiceUser {
+    /// The argument to runit's `chpst -u` which drops privileges to this user. chpst looks up names
+    /// itself, but takes ids in the form `:uid:gid`, so a name mixed with an id is resolved to its id
+    /// when the run script is written during the build. As with Docker, a uid without a gid uses the
+    /// primary group of its entry in /etc/passwd, or the root group if it has none.
+    pub fn chpst_user(&self) -> String {
+        let user = &self.user;
+        match self.group.as_deref() {
+            None if !is_id(user) => user.clone(),
+            Some(group) if !is_id(user) && !is_id(group) => format!("{user}:{group}"),
+            None => format!(
+                ":{user}:$(awk -F: '$3 == {user} {{ gid = $4 }} END {{ print gid + 0 }}' /etc/passwd)"
+            ),
+            Some(gid) if is_id(gid) && is_id(user) => format!(":{user}:{gid}"),
+            Some(gid) if is_id(gid) => format!(":$(id -u {user}):{gid}"),
+            Some(group) => format!(
+                ":{user}:$(awk -F: '$1 == \"{group}\" {{ print $3 }}' /etc/group)"
+            ),
+        }
+    }
+
+    /// A RUN directive which fails the build if a user or group named by the USER directive doesn't
+    /// exist in the image. Ids aren't checked, as Docker runs containers with ids which have no entry.
+    pub fn check_directive(&self) -> Option<Directive> {
+        let mut checks = Vec::new();
+        if !is_id(&self.user) {
+            checks.push(format!(
+                r#"(id -u {0} > /dev/null 2>&1 || (echo "User {0} from the USER directive does not exist in the image" && exit 1))"#,
+                self.user
+            ));
+        }
+        if let Some(group) = self.group.as_deref().filter(|group| !is_id(group)) {
+            checks.push(format!(
+                r#"(grep -q "^{0}:" /etc/group || (echo "Group {0} from the USER directive does not exist in the image" && exit 1))"#,
+                group
+            ));
+        }
+        if checks.is_empty() {
+            None
+        } else {
+            Some(Directive::new_run(checks.join(" && ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing_of_user_forms() {
+        let user = |value: &str| value.parse::<ServiceUser>().map(|user| user.chpst_user());
+        assert_eq!(user("app").unwrap(), "app");
+        assert_eq!(user("app:staff").unwrap(), "app:staff");
+        assert_eq!(user("1000:1001").unwrap(), ":1000:1001");
+        assert_eq!(
+            user(" 1000 ").unwrap(),
+            ":1000:$(awk -F: '$3 == 1000 { gid = $4 } END { print gid + 0 }' /etc/passwd)"
+        );
+        assert_eq!(user("app:1001").unwrap(), ":$(id -u app):1001");
+        assert_eq!(
+            user("1000:staff").unwrap(),
+            r#":1000:$(awk -F: '$1 == "staff" { print $3 }' /etc/group)"#
+        );
+
+        for invalid in [
+            "",
+            "app:",
+            ":staff",
+            "app:staff:wheel",
+            "-u",
+            "app;rm",
+            "a b",
+            "$USER",
+        ] {
+            assert!(
+                matches!(user(invalid), Err(BuildError::InvalidUser(_))),
+                "{invalid} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_user_check_directive() {
+        let check = |value: &str| {
+            value
+                .parse::<ServiceUser>()
+                .unwrap()
+                .check_directive()
+                .map(|directive| directive.to_string())
+        };
+        assert_eq!(
+            check("app").unwrap(),
+            r#"RUN (id -u app > /dev/null 2>&1 || (echo "User app from the USER directive does not exist in the image" && exit 1))"#
+        );
+        assert_eq!(
+            check("1000:staff").unwrap(),
+            r#"RUN (grep -q "^staff:" /etc/group || (echo "Group staff from the USER directive does not exist in the image" && exit 1))"#
+        );
+        assert!(check("app:staff").unwrap().contains(" && (grep -q"));
+        assert_eq!(check("1000:1000"), None);
+    }
+}
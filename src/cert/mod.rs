@@ -85,7 +85,7 @@ pub fn get_cert_pcr(cert_path: &Path) -> Result<String, CertError> {
 
 pub async fn upload_new_cert_ref(
     cert_path: &str,
-    api_key: &str,
+    auth: AuthMode,
     name: String,
 ) -> Result<CreateCageSigningCertRefResponse, CertError> {
     let path = std::path::Path::new(cert_path);
@@ -93,7 +93,7 @@ pub async fn upload_new_cert_ref(
     let pcr8 = get_cert_pcr(path)?;
     let validity_period = get_cert_validity_period(path)?;
 
-    let cage_api = api::cage::CagesClient::new(AuthMode::ApiKey(api_key.to_string()));
+    let cage_api = api::cage::CagesClient::new(auth);
 
     let payload = CreateCageSigningCertRefRequest::new(
         pcr8.clone(),
@@ -211,11 +211,11 @@ fn sort_certs_by_expiry(
 }
 
 pub async fn lock_cage_to_certs(
-    api_key: &str,
+    auth: AuthMode,
     cage_uuid: &str,
     cage_name: &str,
 ) -> Result<(), CertError> {
-    let cage_api = api::cage::CagesClient::new(AuthMode::ApiKey(api_key.to_string()));
+    let cage_api = api::cage::CagesClient::new(auth);
 
     let certs_for_select = get_certs_for_selection(cage_api.clone(), cage_uuid).await?;
 
@@ -376,6 +376,18 @@ pub fn get_cert_validity_period(path: &Path) -> Result<CertValidityPeriod, CertE
     extract_cert_validity_period_from_x509(&x509)
 }
 
+/// Checks that the private key at `key_path` belongs to the certificate at `cert_path`
+pub fn cert_matches_key(cert_path: &Path, key_path: &Path) -> Result<bool, CertError> {
+    let cert_contents = read_cert_bytes_from_fs(cert_path)?;
+    let (_, pem) = parse_x509_pem(&cert_contents).map_err(CertError::PEMError)?;
+    let (_, x509) = parse_x509_certificate(&pem.contents).map_err(CertError::X509Error)?;
+
+    let key_contents = std::fs::read_to_string(key_path)?;
+    let key_pair = rcgen::KeyPair::from_pem(&key_contents)?;
+
+    Ok(x509.public_key().subject_public_key.data.as_ref() == key_pair.public_key_raw())
+}
+
 fn read_cert_bytes_from_fs(path: &Path) -> Result<Vec<u8>, CertError> {
     let cert_file = std::fs::File::open(path)?;
     let mut cert_reader = std::io::BufReader::new(cert_file);
@@ -485,6 +497,19 @@ mod test {
         assert_eq!(expected_not_after, cert_validity_period.not_after);
     }
 
+    #[test]
+    fn test_cert_matches_key() {
+        let first_dir = tempfile::TempDir::new().unwrap();
+        let second_dir = tempfile::TempDir::new().unwrap();
+        let (first_cert, first_key) =
+            create_new_cert(first_dir.path(), DistinguishedName::default()).unwrap();
+        let (_, second_key) =
+            create_new_cert(second_dir.path(), DistinguishedName::default()).unwrap();
+
+        assert!(cert_matches_key(&first_cert, &first_key).unwrap());
+        assert!(!cert_matches_key(&first_cert, &second_key).unwrap());
+    }
+
     #[test]
     fn test_sort_certs_by_expiry() {
         let cert1 = CageSigningCert::new(
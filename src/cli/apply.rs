@@ -0,0 +1,71 @@
+use crate::api::cage::CagesClient;
+use crate::apply::apply_changes;
+use crate::cli::deploy::{self, DeployArgs};
+use crate::cli::plan::{log_plan, read_plan};
+use crate::common::CliError;
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
+use clap::Parser;
+use dialoguer::Confirm;
+
+/// Create or update the Cage, its signing cert, environment and deployment to match its cage.toml
+#[derive(Debug, Parser)]
+#[clap(name = "apply", about)]
+pub struct ApplyArgs {
+    /// Path to cage.toml config file
+    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    pub config: String,
+
+    /// Apply the changes without asking for confirmation
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+}
+
+pub async fn run(apply_args: ApplyArgs) -> exitcode::ExitCode {
+    let cages_client = CagesClient::new(get_auth_mode!());
+    let (config, plan) = match read_plan(&cages_client, &apply_args.config).await {
+        Ok(planned) => planned,
+        Err(code) => return code,
+    };
+    log_plan(&plan);
+    if plan.is_empty() {
+        output::data(&plan);
+        return exitcode::OK;
+    }
+
+    if !apply_args.force {
+        let should_apply = match Confirm::new()
+            .with_prompt("Do you want to apply these changes?")
+            .default(false)
+            .interact()
+        {
+            Ok(should_apply) => should_apply,
+            Err(_) => {
+                report_error!("An error occurred while attempting to confirm the changes. Pass --force to apply them without confirmation.");
+                return exitcode::IOERR;
+            }
+        };
+        if !should_apply {
+            log::info!("No changes were made.");
+            return exitcode::OK;
+        }
+    }
+
+    if let Err(e) = apply_changes(&cages_client, &plan, &config, &apply_args.config).await {
+        report_error!("Failed to apply changes to the Cage - {}", e);
+        return e.exitcode();
+    }
+
+    if plan.requires_deploy() {
+        let deploy_args = DeployArgs::parse_from(["deploy", "--config", &apply_args.config]);
+        let deploy_result = deploy::run(deploy_args).await;
+        if deploy_result != exitcode::OK {
+            return deploy_result;
+        }
+    }
+
+    output::data(&plan);
+    log::info!("Applied {} change(s) to the Cage.", plan.changes().len());
+    exitcode::OK
+}
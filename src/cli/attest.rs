@@ -1,6 +1,10 @@
 use crate::attest::attest_connection_to_cage;
-use crate::config::CageConfig;
+use crate::common::CliError;
+use crate::config::{CageConfig, CageConfigError};
 use crate::describe::describe_eif;
+use crate::enclave::EIFMeasurements;
+use crate::report_error;
+use crate::workspace::{report_summary, MemberReport, Outcome, WorkspaceArgs};
 use attestation_doc_validation::PCRs;
 use clap::Parser;
 
@@ -9,11 +13,19 @@ use clap::Parser;
 #[clap(name = "attest", about)]
 pub struct AttestArgs {
     /// Path to cage.toml config file
-    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    #[clap(
+        short = 'c',
+        long = "config",
+        default_value = "./cage.toml",
+        conflicts_with = "manifest"
+    )]
     pub config: String,
     /// Path to EIF file. When included, the attestation measures returned from the Cage will be compared to the measures of the EIF.
-    #[clap(long = "eif-path")]
+    #[clap(long = "eif-path", conflicts_with = "manifest")]
     pub eif_path: Option<String>,
+
+    #[clap(flatten)]
+    pub workspace: WorkspaceArgs,
 }
 
 macro_rules! unwrap_or_exit_with_error {
@@ -21,7 +33,7 @@ macro_rules! unwrap_or_exit_with_error {
         match $res {
             Ok(val) => val,
             Err(e) => {
-                log::error!("{}", e.to_string());
+                report_error!("{}", e.to_string());
                 return exitcode::SOFTWARE;
             }
         }
@@ -29,6 +41,9 @@ macro_rules! unwrap_or_exit_with_error {
 }
 
 pub async fn run(attest_args: AttestArgs) -> i32 {
+    if attest_args.workspace.is_workspace() {
+        return run_workspace(attest_args).await;
+    }
     let config = unwrap_or_exit_with_error!(CageConfig::try_from_filepath(&attest_args.config));
     let domain = unwrap_or_exit_with_error!(config.get_cage_domain());
 
@@ -39,17 +54,7 @@ pub async fn run(attest_args: AttestArgs) -> i32 {
         unwrap_or_exit_with_error!(config.get_attestation()).clone()
     };
 
-    let expected_pcrs = PCRs {
-        pcr_0: expected_pcrs.pcrs().pcr0.clone(),
-        pcr_1: expected_pcrs.pcrs().pcr1.clone(),
-        pcr_2: expected_pcrs.pcrs().pcr2.clone(),
-        pcr_8: expected_pcrs
-            .pcrs()
-            .pcr8
-            .as_ref()
-            .expect("When PCRs are set in the toml file, PCR8 should always be present")
-            .clone(),
-    };
+    let expected_pcrs = attestation_pcrs(&expected_pcrs);
 
     match attest_connection_to_cage(&domain, expected_pcrs.clone()).await {
         Ok(_) => {
@@ -57,8 +62,76 @@ pub async fn run(attest_args: AttestArgs) -> i32 {
             exitcode::OK
         }
         Err(e) => {
-            log::error!("Failed to attest Cage - {}", e);
+            report_error!("Failed to attest Cage - {}", e);
             exitcode::SOFTWARE
         }
     }
 }
+
+fn attestation_pcrs(measurements: &EIFMeasurements) -> PCRs {
+    PCRs {
+        pcr_0: measurements.pcrs().pcr0.clone(),
+        pcr_1: measurements.pcrs().pcr1.clone(),
+        pcr_2: measurements.pcrs().pcr2.clone(),
+        pcr_8: measurements
+            .pcrs()
+            .pcr8
+            .as_ref()
+            .expect("When PCRs are set in the toml file, PCR8 should always be present")
+            .clone(),
+    }
+}
+
+/// Attests each selected workspace member against the measurements in its cage.toml
+async fn run_workspace(attest_args: AttestArgs) -> i32 {
+    let members = match attest_args.workspace.load_members() {
+        Ok(members) => members,
+        Err(e) => {
+            report_error!("Failed to load the workspace — {}", e);
+            return e.exitcode();
+        }
+    };
+
+    let read_expected_attestation = |config_path: &str| {
+        let config = CageConfig::try_from_filepath(config_path)?;
+        let domain = config.get_cage_domain()?;
+        let measurements = config.get_attestation()?.clone();
+        Ok::<_, CageConfigError>((domain, measurements))
+    };
+
+    let mut reports = Vec::new();
+    for member in &members {
+        let (domain, measurements) = match read_expected_attestation(member.config_path()) {
+            Ok(attestation) => attestation,
+            Err(e) => {
+                report_error!("Failed to read the config for {} - {}", member.name(), e);
+                reports.push(MemberReport::failed(
+                    member,
+                    e.to_string(),
+                    exitcode::SOFTWARE,
+                ));
+                continue;
+            }
+        };
+        match attest_connection_to_cage(&domain, attestation_pcrs(&measurements)).await {
+            Ok(_) => {
+                log::info!(
+                    "Attestation of {} at https://{} was successful",
+                    member.name(),
+                    domain
+                );
+                reports.push(
+                    MemberReport::new(member, Outcome::Attested).with_measurements(&measurements),
+                );
+            }
+            Err(e) => {
+                report_error!("Failed to attest {} - {}", member.name(), e);
+                reports.push(
+                    MemberReport::failed(member, e.to_string(), exitcode::SOFTWARE)
+                        .with_measurements(&measurements),
+                );
+            }
+        }
+    }
+    report_summary(&members, reports)
+}
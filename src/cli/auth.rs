@@ -0,0 +1,151 @@
+use crate::auth::{AuthError, CredentialsFile, Profile};
+use crate::common::CliError;
+use crate::output;
+use crate::report_error;
+use clap::{Parser, Subcommand};
+use serde_json::json;
+
+/// Manage named credential profiles
+#[derive(Debug, Parser)]
+#[clap(name = "auth", about)]
+pub struct AuthArgs {
+    #[clap(subcommand)]
+    action: AuthCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuthCommands {
+    /// Add a credentials profile, or replace an existing one
+    Add(AddProfileArgs),
+    /// List the stored credentials profiles
+    List,
+    /// Remove a credentials profile
+    Remove(ProfileNameArgs),
+    /// Set the default credentials profile
+    Use(ProfileNameArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct AddProfileArgs {
+    /// Name of the profile
+    pub name: String,
+
+    /// API key for the profile. You will be prompted for it if omitted.
+    #[clap(long = "api-key")]
+    pub api_key: Option<String>,
+
+    /// Evervault domain to target when using this profile, e.g. evervault.com
+    #[clap(long = "domain")]
+    pub domain: Option<String>,
+
+    /// Default app uuid for the profile
+    #[clap(long = "app-uuid")]
+    pub app_uuid: Option<String>,
+
+    /// Default team uuid for the profile
+    #[clap(long = "team-uuid")]
+    pub team_uuid: Option<String>,
+
+    /// Make this the default profile
+    #[clap(long = "default")]
+    pub make_default: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ProfileNameArgs {
+    /// Name of the profile
+    pub name: String,
+}
+
+pub async fn run(auth_args: AuthArgs) -> exitcode::ExitCode {
+    let mut credentials = match CredentialsFile::load() {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            report_error!("Failed to load credentials — {}", e);
+            return e.exitcode();
+        }
+    };
+
+    match auth_args.action {
+        AuthCommands::Add(add_args) => {
+            let api_key = match add_args.api_key {
+                Some(api_key) => api_key,
+                None => match dialoguer::Password::new().with_prompt("API Key").interact() {
+                    Ok(api_key) => api_key,
+                    Err(e) => {
+                        report_error!("Failed to read API key — {}", e);
+                        return exitcode::IOERR;
+                    }
+                },
+            };
+            let profile = Profile {
+                api_key,
+                domain: add_args.domain,
+                app_uuid: add_args.app_uuid,
+                team_uuid: add_args.team_uuid,
+            };
+            credentials.add_profile(add_args.name.clone(), profile);
+            if add_args.make_default {
+                credentials.set_default(&add_args.name);
+            }
+            save(&credentials, &format!("Profile {} saved.", add_args.name))
+        }
+        AuthCommands::List => {
+            let profiles: Vec<_> = credentials
+                .profiles
+                .iter()
+                .map(|(name, profile)| {
+                    json!({
+                        "name": name,
+                        "default": credentials.default.as_ref() == Some(name),
+                        "domain": profile.domain,
+                        "appUuid": profile.app_uuid,
+                        "teamUuid": profile.team_uuid,
+                    })
+                })
+                .collect();
+            output::data(&profiles);
+            exitcode::OK
+        }
+        AuthCommands::Remove(remove_args) => {
+            if credentials.remove_profile(&remove_args.name).is_none() {
+                return profile_not_found(remove_args.name);
+            }
+            save(
+                &credentials,
+                &format!("Profile {} removed.", remove_args.name),
+            )
+        }
+        AuthCommands::Use(use_args) => {
+            if !credentials.set_default(&use_args.name) {
+                return profile_not_found(use_args.name);
+            }
+            save(
+                &credentials,
+                &format!("Profile {} is now the default.", use_args.name),
+            )
+        }
+    }
+}
+
+fn save(credentials: &CredentialsFile, success_msg: &str) -> exitcode::ExitCode {
+    match credentials.save() {
+        Ok(_) => {
+            log::info!("{}", success_msg);
+            exitcode::OK
+        }
+        Err(e) => {
+            report_error!("Failed to save credentials — {}", e);
+            e.exitcode()
+        }
+    }
+}
+
+fn profile_not_found(name: String) -> exitcode::ExitCode {
+    let path = crate::auth::credentials_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let e = AuthError::ProfileNotFound(name, path);
+    report_error!("{}", e);
+    e.exitcode()
+}
@@ -1,8 +1,21 @@
 use crate::api::assets::AssetsClient;
-use crate::build::build_enclave_image_file;
+use crate::build::{build_enclave_image_file, BuildOptions};
+use crate::cli::config::validate_before_build;
 use crate::common::{prepare_build_args, CliError};
-use crate::config::{read_and_validate_config, BuildTimeConfig, RuntimeVersions};
+use crate::config::edit::ConfigFile;
+use crate::config::validate::CageUuids;
+use crate::config::{
+    read_and_validate_config, BuildTimeConfig, CageConfig, RuntimeVersions,
+    ValidatedCageBuildConfig,
+};
 use crate::docker::command::get_source_date_epoch;
+use crate::enclave::BuildImages;
+use crate::output;
+use crate::report_error;
+use crate::workspace::{
+    build_members, report_summary, MemberBuild, MemberReport, Outcome, WorkspaceArgs,
+    WorkspaceMember,
+};
 use clap::Parser;
 
 /// Build a Cage from a Dockerfile
@@ -10,15 +23,20 @@ use clap::Parser;
 #[clap(name = "build", about)]
 pub struct BuildArgs {
     /// Path to cage.toml config file. This can be generated using the init command
-    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    #[clap(
+        short = 'c',
+        long = "config",
+        default_value = "./cage.toml",
+        conflicts_with = "manifest"
+    )]
     pub config: String,
 
     /// Path to Dockerfile for Cage. Will override any dockerfile specified in the .toml file.
-    #[clap(short = 'f', long = "file")]
+    #[clap(short = 'f', long = "file", conflicts_with = "manifest")]
     pub dockerfile: Option<String>,
 
     /// Path to use for Docker context. Defaults to the current directory.
-    #[clap(default_value = ".")]
+    #[clap(default_value = ".", conflicts_with = "manifest")]
     pub context_path: String,
 
     /// Certificate used to sign the enclave image file
@@ -37,8 +55,14 @@ pub struct BuildArgs {
     #[clap(long, from_global)]
     pub json: bool,
 
-    /// Path to directory where the processed dockerfile and enclave will be saved
-    #[clap(short = 'o', long = "output", default_value = ".")]
+    /// Path to directory where the processed dockerfile and enclave will be saved. Workspace members
+    /// are saved to a directory named after them within it.
+    #[clap(
+        short = 'o',
+        long = "output",
+        default_value = ".",
+        conflicts_with = "manifest"
+    )]
     pub output_dir: String,
 
     /// Build time arguments to provide to docker
@@ -47,12 +71,15 @@ pub struct BuildArgs {
 
     #[cfg(feature = "repro_builds")]
     /// Path to an enclave dockerfile to build from existing
-    #[clap(long = "from-existing")]
+    #[clap(long = "from-existing", conflicts_with = "manifest")]
     pub from_existing: Option<String>,
 
     /// Enables forwarding proxy protocol when TLS Termination is disabled
     #[clap(long = "forward-proxy-protocol")]
     pub forward_proxy_protocol: bool,
+
+    #[clap(flatten)]
+    pub workspace: WorkspaceArgs,
 }
 
 impl BuildTimeConfig for BuildArgs {
@@ -70,41 +97,36 @@ impl BuildTimeConfig for BuildArgs {
 }
 
 pub async fn run(build_args: BuildArgs) -> exitcode::ExitCode {
-    let (mut cage_config, validated_config) =
+    if build_args.workspace.is_workspace() {
+        return run_workspace(build_args).await;
+    }
+    if let Err(code) = validate_before_build(&build_args.config, &build_args, CageUuids::Required) {
+        return code;
+    }
+    let (cage_config, validated_config) =
         match read_and_validate_config(&build_args.config, &build_args) {
             Ok(config) => config,
             Err(e) => {
-                log::error!("Failed to read cage config from file system — {}", e);
+                report_error!("Failed to read cage config from file system — {}", e);
                 return e.exitcode();
             }
         };
+    let mut config_file = match ConfigFile::load(&build_args.config) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            report_error!("Failed to read cage config from file system — {}", e);
+            return e.exitcode();
+        }
+    };
 
     let formatted_args = prepare_build_args(&build_args.docker_build_args);
     let borrowed_args = formatted_args
         .as_ref()
         .map(|args| args.iter().map(AsRef::as_ref).collect());
 
-    let cage_build_assets_client = AssetsClient::new();
-    let data_plane_version = match cage_build_assets_client
-        .get_latest_data_plane_version()
-        .await
-    {
-        Ok(version) => version,
-        Err(e) => {
-            log::error!("Failed to retrieve the latest data plane version - {e:?}");
-            return e.exitcode();
-        }
-    };
-
-    let installer_version = match cage_build_assets_client
-        .get_latest_installer_version()
-        .await
-    {
-        Ok(version) => version,
-        Err(e) => {
-            log::error!("Failed to retrieve the latest installer version - {e:?}");
-            return e.exitcode();
-        }
+    let (data_plane_version, installer_version) = match get_latest_versions().await {
+        Ok(versions) => versions,
+        Err(code) => return code,
     };
 
     let timestamp = get_source_date_epoch();
@@ -119,27 +141,30 @@ pub async fn run(build_args: BuildArgs) -> exitcode::ExitCode {
         &validated_config,
         &build_args.context_path,
         Some(&build_args.output_dir),
-        !build_args.quiet,
-        borrowed_args,
-        data_plane_version,
-        installer_version,
-        timestamp,
-        from_existing,
+        BuildOptions {
+            verbose: !build_args.quiet,
+            docker_build_args: borrowed_args,
+            data_plane_version,
+            installer_version,
+            timestamp,
+            from_existing,
+            images: &BuildImages::default(),
+        },
     )
     .await
     {
         Ok((built_enclave, _)) => built_enclave,
         Err(e) => {
-            log::error!("An error occurred while building your enclave — {e}");
+            report_error!("An error occurred while building your enclave — {e}");
             return e.exitcode();
         }
     };
 
     crate::common::update_cage_config_with_eif_measurements(
-        &mut cage_config,
-        &build_args.config,
+        &mut config_file,
         built_enclave.measurements(),
         Some(runtime_info),
+        cage_config.active_profile.as_deref(),
     );
 
     if cage_config.debug {
@@ -147,12 +172,172 @@ pub async fn run(build_args: BuildArgs) -> exitcode::ExitCode {
     }
 
     // Write enclave measures to stdout
-    let success_msg = serde_json::json!({
-        "status": "success",
+    output::data(&serde_json::json!({
         "message": "EIF built successfully",
         "enclaveMeasurements": built_enclave.measurements()
-    });
-
-    println!("{}", serde_json::to_string_pretty(&success_msg).unwrap());
+    }));
     exitcode::OK
 }
+
+async fn get_latest_versions() -> Result<(String, String), exitcode::ExitCode> {
+    let cage_build_assets_client = AssetsClient::new();
+    let data_plane_version = match cage_build_assets_client
+        .get_latest_data_plane_version()
+        .await
+    {
+        Ok(version) => version,
+        Err(e) => {
+            report_error!("Failed to retrieve the latest data plane version - {e:?}");
+            return Err(e.exitcode());
+        }
+    };
+
+    let installer_version = match cage_build_assets_client
+        .get_latest_installer_version()
+        .await
+    {
+        Ok(version) => version,
+        Err(e) => {
+            report_error!("Failed to retrieve the latest installer version - {e:?}");
+            return Err(e.exitcode());
+        }
+    };
+    Ok((data_plane_version, installer_version))
+}
+
+/// Reads and validates a workspace member's config ahead of its build. Failures are reported, and
+/// returned as the member's report.
+pub(crate) fn read_member_config<B: BuildTimeConfig>(
+    member: &WorkspaceMember,
+    args: &B,
+) -> Result<(CageConfig, ValidatedCageBuildConfig, ConfigFile), Box<MemberReport>> {
+    let member_args = member.with_args(args);
+    if let Err(code) =
+        validate_before_build(member.config_path(), &member_args, CageUuids::Required)
+    {
+        let error = format!("{} is invalid", member.config_path());
+        return Err(Box::new(MemberReport::failed(member, error, code)));
+    }
+    let failed = |e: crate::config::CageConfigError| {
+        report_error!(
+            "Failed to read cage config for {} from file system — {}",
+            member.name(),
+            e
+        );
+        Box::new(MemberReport::failed(member, e.to_string(), e.exitcode()))
+    };
+    let (cage_config, validated_config) =
+        read_and_validate_config(member.config_path(), &member_args).map_err(failed)?;
+    let config_file = ConfigFile::load(member.config_path()).map_err(failed)?;
+    Ok((cage_config, validated_config, config_file))
+}
+
+/// Creates the directory a workspace member's artifacts are saved to. Failures are reported, and
+/// returned as the member's report.
+fn create_member_output_dir(
+    output_dir: &str,
+    member: &WorkspaceMember,
+) -> Result<String, Box<MemberReport>> {
+    let member_output_dir = member.output_dir(output_dir);
+    std::fs::create_dir_all(&member_output_dir).map_err(|e| {
+        report_error!(
+            "Failed to create the output directory for {} — {}",
+            member.name(),
+            e
+        );
+        Box::new(MemberReport::failed(member, e.to_string(), exitcode::IOERR))
+    })?;
+    Ok(member_output_dir.display().to_string())
+}
+
+/// Builds each selected workspace member side by side, then writes their measurements back to their
+/// cage.toml files
+async fn run_workspace(build_args: BuildArgs) -> exitcode::ExitCode {
+    let members = match build_args.workspace.load_members() {
+        Ok(members) => members,
+        Err(e) => {
+            report_error!("Failed to load the workspace — {}", e);
+            return e.exitcode();
+        }
+    };
+
+    let mut reports = Vec::new();
+    let mut prepared = Vec::new();
+    for member in &members {
+        let member_build = read_member_config(member, &build_args).and_then(|configs| {
+            create_member_output_dir(&build_args.output_dir, member)
+                .map(|output_dir| (configs, output_dir))
+        });
+        match member_build {
+            Ok(((cage_config, validated_config, config_file), output_dir)) => prepared.push((
+                member,
+                cage_config,
+                validated_config,
+                config_file,
+                output_dir,
+            )),
+            Err(report) => reports.push(*report),
+        }
+    }
+
+    if !prepared.is_empty() {
+        let (data_plane_version, installer_version) = match get_latest_versions().await {
+            Ok(versions) => versions,
+            Err(code) => return code,
+        };
+        let runtime_info =
+            RuntimeVersions::new(data_plane_version.clone(), installer_version.clone());
+        let timestamp = get_source_date_epoch();
+        let docker_build_args = prepare_build_args(&build_args.docker_build_args);
+
+        let builds = prepared
+            .iter()
+            .map(|(member, _, validated_config, _, output_dir)| {
+                let build = MemberBuild {
+                    config: validated_config.clone(),
+                    context_path: member.dir().display().to_string(),
+                    output_dir: Some(output_dir.clone()),
+                    docker_build_args: docker_build_args.clone(),
+                    data_plane_version: data_plane_version.clone(),
+                    installer_version: installer_version.clone(),
+                    timestamp: timestamp.clone(),
+                    images: member.build_images(),
+                };
+                (member.name().to_string(), build)
+            })
+            .collect();
+
+        let results = build_members(builds).await;
+        for ((member, cage_config, _, mut config_file, _), result) in
+            prepared.into_iter().zip(results)
+        {
+            match result {
+                Ok((built_enclave, _)) => {
+                    crate::common::update_cage_config_with_eif_measurements(
+                        &mut config_file,
+                        built_enclave.measurements(),
+                        Some(runtime_info.clone()),
+                        cage_config.active_profile.as_deref(),
+                    );
+                    if cage_config.debug {
+                        log::warn!("{} is in debug mode.", member.name());
+                        crate::common::log_debug_mode_attestation_warning();
+                    }
+                    reports.push(
+                        MemberReport::new(member, Outcome::Built)
+                            .with_measurements(built_enclave.measurements()),
+                    );
+                }
+                Err(e) => {
+                    report_error!(
+                        "An error occurred while building the enclave for {} — {e}",
+                        member.name()
+                    );
+                    reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+                }
+            }
+        }
+    }
+
+    report_summary(&members, reports)
+}
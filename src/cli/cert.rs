@@ -1,8 +1,9 @@
 use crate::cert::{self, DistinguishedName};
 use crate::common::CliError;
 use crate::config::CageConfig;
-use crate::get_api_key;
-use atty::Stream;
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
 use clap::{Parser, Subcommand};
 use exitcode::DATAERR;
 
@@ -70,7 +71,7 @@ pub async fn run(cert_args: CertArgs) -> exitcode::ExitCode {
                 match try_resolve_distinguished_name(new_args.subject.as_deref()) {
                     Ok(distinguished_name) => distinguished_name,
                     Err(e) => {
-                        log::error!("{}", e);
+                        report_error!("{}", e);
                         return e.exitcode();
                     }
                 };
@@ -79,28 +80,24 @@ pub async fn run(cert_args: CertArgs) -> exitcode::ExitCode {
             {
                 Ok(paths) => paths,
                 Err(e) => {
-                    log::error!("An error occurred while generating your cert - {}", e);
+                    report_error!("An error occurred while generating your cert - {}", e);
                     return e.exitcode();
                 }
             };
 
-            if atty::is(Stream::Stdout) {
+            if output::is_json() {
+                output::data(&serde_json::json!({
+                    "certificate": cert_path,
+                    "privateKey": key_path
+                }));
+            } else {
                 log::info!("Signing cert successfully generated...");
                 log::info!("> Certificate saved to {}", cert_path.display());
                 log::info!("> Key saved to {}", key_path.display());
-            } else {
-                let success_msg = serde_json::json!({
-                    "status": "success",
-                    "output": {
-                        "certificate": cert_path,
-                        "privateKey": key_path
-                    }
-                });
-                println!("{}", serde_json::to_string(&success_msg).unwrap());
             };
         }
         CertCommands::Upload(upload_args) => {
-            let api_key = get_api_key!();
+            let auth = get_auth_mode!();
 
             let cert_path = match upload_args.cert_path {
                 Some(cert_path) => cert_path,
@@ -110,61 +107,57 @@ pub async fn run(cert_args: CertArgs) -> exitcode::ExitCode {
                             signing_info.cert.unwrap()
                         }
                         _ => {
-                            log::error!("No signing info found in cage.toml");
+                            report_error!("No signing info found in cage.toml");
                             return DATAERR;
                         }
                     },
                     Err(e) => {
-                        log::error!("An error occurred while reading cage.toml - {}", e);
+                        report_error!("An error occurred while reading cage.toml - {}", e);
                         return e.exitcode();
                     }
                 },
             };
 
-            let cert_ref =
-                match cert::upload_new_cert_ref(&cert_path, &api_key, upload_args.name).await {
-                    Ok(pcr8) => pcr8,
-                    Err(e) => {
-                        log::error!(
-                            "An error occurred while generating PCR8 for your cert - {}",
-                            e
-                        );
-                        return e.exitcode();
-                    }
-                };
+            let cert_ref = match cert::upload_new_cert_ref(&cert_path, auth, upload_args.name).await
+            {
+                Ok(pcr8) => pcr8,
+                Err(e) => {
+                    report_error!(
+                        "An error occurred while generating PCR8 for your cert - {}",
+                        e
+                    );
+                    return e.exitcode();
+                }
+            };
 
-            if atty::is(Stream::Stdout) {
+            if output::is_json() {
+                output::data(&cert_ref);
+            } else {
                 log::info!("PCR8: {}", cert_ref.cert_hash());
                 log::info!("Not Before: {}", cert_ref.not_before());
                 log::info!("Not After: {}", cert_ref.not_after());
                 log::info!("Certificate metadata uploaded to Evervault");
-            } else {
-                let success_msg = serde_json::json!({
-                    "status": "success",
-                    "output": cert_ref,
-                });
-                println!("{}", serde_json::to_string(&success_msg).unwrap());
             };
         }
         CertCommands::Lock(lock_cert_args) => {
-            let api_key = get_api_key!();
+            let auth = get_auth_mode!();
 
             let (cage_uuid, cage_name) = match CageConfig::try_from_filepath(&lock_cert_args.config)
             {
                 Ok(cage_config) => match (cage_config.uuid, cage_config.name) {
                     (Some(uuid), name) => (uuid, name),
                     _ => {
-                        log::error!("No cage details found in cage.toml");
+                        report_error!("No cage details found in cage.toml");
                         return DATAERR;
                     }
                 },
                 Err(_) => {
-                    log::error!("Failed to load cage configuration");
+                    report_error!("Failed to load cage configuration");
                     return DATAERR;
                 }
             };
 
-            cert::lock_cage_to_certs(&api_key, &cage_uuid, &cage_name)
+            cert::lock_cage_to_certs(auth, &cage_uuid, &cage_name)
                 .await
                 .unwrap();
         }
@@ -0,0 +1,240 @@
+use crate::common::CliError;
+use crate::config::edit::ConfigFile;
+use crate::config::keys::{apply_change, changed_values, get_value, ConfigChange, ConfigKey};
+use crate::config::schema::cage_config_schema;
+use crate::config::validate::{validate_config_file_with, CageUuids, Diagnostic};
+use crate::config::{BuildTimeConfig, CageConfig, CageConfigError};
+use crate::output;
+use crate::report_error;
+use clap::{Parser, Subcommand};
+
+/// Inspect, change and validate the Cage's config
+#[derive(Debug, Parser)]
+#[clap(name = "config", about)]
+pub struct ConfigArgs {
+    #[clap(subcommand)]
+    action: ConfigCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommands {
+    /// Check the cage.toml for syntax errors, unknown keys and invalid settings
+    Validate(ValidateArgs),
+    /// Print the value of a key, such as egress.destinations
+    Get(GetArgs),
+    /// Change the value of a key. The value is checked against the type of the key before it's written.
+    Set(SetArgs),
+    /// Print a JSON Schema for cage.toml files, for use in editors and CI checks
+    Schema,
+}
+
+#[derive(Debug, Parser)]
+pub struct ValidateArgs {
+    /// Path to the toml file containing the Cage's config
+    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    pub config: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct GetArgs {
+    /// Dotted path to the key, such as egress.destinations
+    pub key: String,
+
+    /// Path to the toml file containing the Cage's config
+    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    pub config: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct SetArgs {
+    /// Dotted path to the key, such as egress.destinations
+    pub key: String,
+
+    /// The new value. Lists can be given as comma separated values (e.g. 443,8443)
+    #[clap(
+        required_unless_present_any = &["add", "remove"],
+        conflicts_with_all = &["add", "remove"]
+    )]
+    pub value: Option<String>,
+
+    /// Add a value to a list, such as egress.destinations. Can be given more than once.
+    #[clap(long = "add")]
+    pub add: Vec<String>,
+
+    /// Remove a value from a list, such as egress.destinations. Can be given more than once.
+    #[clap(long = "remove", conflicts_with = "add")]
+    pub remove: Vec<String>,
+
+    /// Path to the toml file containing the Cage's config
+    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    pub config: String,
+}
+
+pub async fn run(config_args: ConfigArgs) -> exitcode::ExitCode {
+    match config_args.action {
+        ConfigCommands::Validate(validate_args) => validate(validate_args),
+        ConfigCommands::Get(get_args) => get(get_args),
+        ConfigCommands::Set(set_args) => set(set_args),
+        ConfigCommands::Schema => {
+            output::data(&cage_config_schema());
+            exitcode::OK
+        }
+    }
+}
+
+fn get(get_args: GetArgs) -> exitcode::ExitCode {
+    let value = match get_config_value(&get_args) {
+        Ok(value) => value,
+        Err(e) => {
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+
+    if output::is_json() {
+        output::data(&serde_json::json!({
+            "key": get_args.key,
+            "value": value,
+        }));
+    } else {
+        println!("{}", format_value(&value));
+    }
+    exitcode::OK
+}
+
+fn get_config_value(get_args: &GetArgs) -> Result<toml::Value, CageConfigError> {
+    let key: ConfigKey = get_args.key.parse()?;
+    let config = CageConfig::try_from_filepath(&get_args.config)?;
+    get_value(&config, &key)?.ok_or_else(|| CageConfigError::ConfigKeyNotSet(key.to_string()))
+}
+
+// Prints strings without quotes and lists one item per line, so values are easy to use in scripts
+fn format_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(format_value)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        toml::Value::Table(_) => toml::to_string(value)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn set(set_args: SetArgs) -> exitcode::ExitCode {
+    let change = match set_args.value.clone() {
+        Some(value) => ConfigChange::Set(value),
+        None if !set_args.add.is_empty() => ConfigChange::Add(set_args.add.clone()),
+        None => ConfigChange::Remove(set_args.remove.clone()),
+    };
+    let (key, value) = match set_config_value(&set_args, &change) {
+        Ok(updated) => updated,
+        Err(e) => {
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+
+    if output::is_json() {
+        output::data(&serde_json::json!({
+            "key": key.to_string(),
+            "value": value,
+        }));
+    } else {
+        match &value {
+            Some(value) => log::info!("Set {} to {}", key, value),
+            None => log::info!("{} is not set", key),
+        }
+    }
+    exitcode::OK
+}
+
+fn set_config_value(
+    set_args: &SetArgs,
+    change: &ConfigChange,
+) -> Result<(ConfigKey, Option<toml::Value>), CageConfigError> {
+    let key: ConfigKey = set_args.key.parse()?;
+    // Changes are made to the config as written, so `${VAR}` references elsewhere are kept
+    let config = CageConfig::read_uninterpolated(&set_args.config)?
+        .with_selected_profile(crate::config::selected_profile_name().as_deref())?;
+    let updated = apply_change(&config, &key, change)?;
+
+    let changes = changed_values(&config, &updated, &key)?;
+    if changes.is_empty() {
+        log::debug!("{} is unchanged", key);
+    } else {
+        let mut config_file = ConfigFile::load(&set_args.config)?;
+        for (path, value) in &changes {
+            config_file.set_value(path, value, config.active_profile.as_deref())?;
+        }
+        config_file.save()?;
+    }
+    let value = get_value(&updated, &key)?;
+    Ok((key, value))
+}
+
+fn validate(validate_args: ValidateArgs) -> exitcode::ExitCode {
+    let diagnostics =
+        match validate_config_file_with(&validate_args.config, CageUuids::Required, |_| {}) {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => {
+                report_error!("{}", e);
+                return e.exitcode();
+            }
+        };
+    let valid = !diagnostics.iter().any(Diagnostic::is_error);
+
+    log_diagnostics(&validate_args.config, &diagnostics);
+    if output::is_json() {
+        output::data(&serde_json::json!({
+            "valid": valid,
+            "diagnostics": diagnostics,
+        }));
+    }
+
+    if valid {
+        log::info!("{} is valid.", validate_args.config);
+        exitcode::OK
+    } else {
+        exitcode::DATAERR
+    }
+}
+
+fn log_diagnostics(config_path: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        if diagnostic.is_error() {
+            report_error!("{}", diagnostic.render(config_path));
+        } else {
+            log::warn!("{}", diagnostic.render(config_path));
+        }
+    }
+}
+
+/// Validates the config before a build starts, so problems are reported up front rather than partway
+/// through. Command line overrides for the cert, key and Dockerfile are taken into account.
+pub fn validate_before_build<B: BuildTimeConfig>(
+    config_path: &str,
+    args: &B,
+    uuids: CageUuids,
+) -> Result<(), exitcode::ExitCode> {
+    let diagnostics = match validate_config_file_with(config_path, uuids, |config| {
+        *config = args.merge_with_config(config)
+    }) {
+        Ok(diagnostics) => diagnostics,
+        // Reading the config is reported by the command itself
+        Err(_) => return Ok(()),
+    };
+    log_diagnostics(config_path, &diagnostics);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        report_error!(
+            "{} is invalid. Run `ev-cage config validate` after fixing the errors above.",
+            config_path
+        );
+        return Err(exitcode::DATAERR);
+    }
+    Ok(())
+}
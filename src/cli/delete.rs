@@ -1,6 +1,7 @@
 use crate::common::CliError;
 use crate::delete::delete_cage;
-use crate::get_api_key;
+use crate::get_auth_mode;
+use crate::report_error;
 use clap::Parser;
 
 /// Delete a Cage from a toml file.
@@ -35,7 +36,7 @@ pub async fn run(delete_args: DeleteArgs) -> exitcode::ExitCode {
     {
         Ok(should_delete) => should_delete,
         Err(_) => {
-            log::error!("An error occurred while attempting to confirm this Cage delete.");
+            report_error!("An error occurred while attempting to confirm this Cage delete.");
             return exitcode::IOERR;
         }
     };
@@ -45,11 +46,11 @@ pub async fn run(delete_args: DeleteArgs) -> exitcode::ExitCode {
         return exitcode::OK;
     }
 
-    let api_key = get_api_key!();
+    let auth = get_auth_mode!();
     match delete_cage(
         delete_args.config.as_str(),
         delete_args.cage_uuid.as_deref(),
-        api_key.as_str(),
+        auth,
         delete_args.background,
     )
     .await
@@ -62,7 +63,7 @@ pub async fn run(delete_args: DeleteArgs) -> exitcode::ExitCode {
             }
         }
         Err(e) => {
-            log::info!("{}", e);
+            report_error!("{}", e);
             return e.exitcode();
         }
     };
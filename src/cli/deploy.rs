@@ -1,15 +1,26 @@
-use crate::api::{self, assets::AssetsClient, AuthMode};
-use crate::build::build_enclave_image_file;
+use crate::api::{self, assets::AssetsClient};
+use crate::build::{build_enclave_image_file, BuildOptions};
+use crate::cli::build::read_member_config;
+use crate::cli::config::validate_before_build;
+use crate::cli::init::attach_config;
 use crate::common::prepare_build_args;
 use crate::docker::command::get_source_date_epoch;
-use crate::get_api_key;
+use crate::enclave::BuildImages;
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
+use crate::workspace::{
+    build_members, report_summary, MemberBuild, MemberReport, Outcome, WorkspaceArgs,
+};
 use crate::{
     common::{CliError, OutputPath},
-    config::{read_and_validate_config, BuildTimeConfig, ValidatedCageBuildConfig},
+    config::{
+        edit::ConfigFile, read_and_validate_config, validate::CageUuids, BuildTimeConfig,
+        ValidatedCageBuildConfig,
+    },
     deploy::{deploy_eif, get_eif},
     enclave::EIFMeasurements,
 };
-use atty::Stream;
 use clap::Parser;
 use exitcode::ExitCode;
 
@@ -18,19 +29,24 @@ use exitcode::ExitCode;
 #[clap(name = "deploy", about)]
 pub struct DeployArgs {
     /// Path to cage.toml config file
-    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    #[clap(
+        short = 'c',
+        long = "config",
+        default_value = "./cage.toml",
+        conflicts_with = "manifest"
+    )]
     pub config: String,
 
     /// Path to Dockerfile for Cage. Will override any dockerfile specified in the .toml file.
-    #[clap(short = 'f', long = "file")]
+    #[clap(short = 'f', long = "file", conflicts_with = "manifest")]
     pub dockerfile: Option<String>,
 
     /// Path to EIF for Cage. Will not build if EIF is provided.
-    #[clap(long = "eif-path")]
+    #[clap(long = "eif-path", conflicts_with = "manifest")]
     pub eif_path: Option<String>,
 
     /// Path to use for docker context
-    #[clap(default_value = ".")]
+    #[clap(default_value = ".", conflicts_with = "manifest")]
     pub context_path: String,
 
     /// Certificate used to sign the enclave image file
@@ -51,8 +67,11 @@ pub struct DeployArgs {
 
     #[cfg(feature = "repro_builds")]
     /// Path to an enclave dockerfile to build from existing
-    #[clap(long = "from-existing")]
+    #[clap(long = "from-existing", conflicts_with = "manifest")]
     pub from_existing: Option<String>,
+
+    #[clap(flatten)]
+    pub workspace: WorkspaceArgs,
 }
 
 impl BuildTimeConfig for DeployArgs {
@@ -70,22 +89,39 @@ impl BuildTimeConfig for DeployArgs {
 }
 
 pub async fn run(deploy_args: DeployArgs) -> exitcode::ExitCode {
-    let api_key = get_api_key!();
-    let (mut cage_config, validated_config) =
+    if deploy_args.workspace.is_workspace() {
+        return run_workspace(deploy_args).await;
+    }
+    let cage_api = api::cage::CagesClient::new(get_auth_mode!());
+    if let Err(code) =
+        validate_before_build(&deploy_args.config, &deploy_args, CageUuids::NotRequiredYet)
+    {
+        return code;
+    }
+    // A cage.toml written by `init --offline` gets its Cage on the first deploy
+    if let Err(code) = attach_config(&cage_api, &deploy_args.config, false).await {
+        return code;
+    }
+    let (cage_config, validated_config) =
         match read_and_validate_config(&deploy_args.config, &deploy_args) {
             Ok(configs) => configs,
             Err(e) => {
-                log::error!("Failed to validate Cage config - {}", e);
+                report_error!("Failed to validate Cage config - {}", e);
                 return e.exitcode();
             }
         };
-
-    let cage_api = api::cage::CagesClient::new(AuthMode::ApiKey(api_key));
+    let mut config_file = match ConfigFile::load(&deploy_args.config) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            report_error!("Failed to read Cage config - {}", e);
+            return e.exitcode();
+        }
+    };
 
     let cage = match cage_api.get_cage(validated_config.cage_uuid()).await {
         Ok(cage) => cage,
         Err(e) => {
-            log::error!("Failed to retrieve Cage details from Evervault API – {}", e);
+            report_error!("Failed to retrieve Cage details from Evervault API – {}", e);
             return e.exitcode();
         }
     };
@@ -101,7 +137,7 @@ pub async fn run(deploy_args: DeployArgs) -> exitcode::ExitCode {
         match get_data_plane_and_installer_version(&validated_config).await {
             Ok(versions) => versions,
             Err(e) => {
-                log::error!("Failed to get data plane and installer versions – {}", e);
+                report_error!("Failed to get data plane and installer versions – {}", e);
                 return e;
             }
         };
@@ -138,10 +174,10 @@ pub async fn run(deploy_args: DeployArgs) -> exitcode::ExitCode {
     );
 
     crate::common::update_cage_config_with_eif_measurements(
-        &mut cage_config,
-        &deploy_args.config,
+        &mut config_file,
         &eif_measurements,
         None,
+        cage_config.active_profile.as_deref(),
     );
 
     if let Err(e) = deploy_eif(
@@ -154,23 +190,148 @@ pub async fn run(deploy_args: DeployArgs) -> exitcode::ExitCode {
     )
     .await
     {
-        log::error!("{}", e);
+        report_error!("{}", e);
         return e.exitcode();
     };
 
-    if atty::is(Stream::Stdout) {
-        log::info!("Your Cage is now available at https://{}", cage.domain());
-    } else {
-        let success_msg = serde_json::json!({
-            "status": "success",
+    if output::is_json() {
+        output::data(&serde_json::json!({
             "cageDomain": cage.domain(),
             "measurements": &eif_measurements
-        });
-        println!("{}", serde_json::to_string(&success_msg).unwrap());
+        }));
+    } else {
+        log::info!("Your Cage is now available at https://{}", cage.domain());
     };
     exitcode::OK
 }
 
+/// Builds each selected workspace member side by side, then deploys them one at a time
+async fn run_workspace(deploy_args: DeployArgs) -> exitcode::ExitCode {
+    let members = match deploy_args.workspace.load_members() {
+        Ok(members) => members,
+        Err(e) => {
+            report_error!("Failed to load the workspace — {}", e);
+            return e.exitcode();
+        }
+    };
+    let auth = get_auth_mode!();
+    let cage_api = api::cage::CagesClient::new(auth);
+
+    let mut reports = Vec::new();
+    let mut prepared = Vec::new();
+    for member in &members {
+        let (cage_config, validated_config, config_file) =
+            match read_member_config(member, &deploy_args) {
+                Ok(configs) => configs,
+                Err(report) => {
+                    reports.push(*report);
+                    continue;
+                }
+            };
+        let cage = match cage_api.get_cage(validated_config.cage_uuid()).await {
+            Ok(cage) => cage,
+            Err(e) => {
+                report_error!(
+                    "Failed to retrieve details of {} from Evervault API – {}",
+                    member.name(),
+                    e
+                );
+                reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+                continue;
+            }
+        };
+        let versions = match get_data_plane_and_installer_version(&validated_config).await {
+            Ok(versions) => versions,
+            Err(code) => {
+                let error = "Failed to get data plane and installer versions".to_string();
+                reports.push(MemberReport::failed(member, error, code));
+                continue;
+            }
+        };
+        prepared.push((
+            member,
+            cage_config,
+            validated_config,
+            config_file,
+            cage,
+            versions,
+        ));
+    }
+
+    let timestamp = get_source_date_epoch();
+    let docker_build_args = prepare_build_args(&deploy_args.docker_build_args);
+    let builds = prepared
+        .iter()
+        .map(|(member, _, validated_config, _, _, versions)| {
+            let build = MemberBuild {
+                config: validated_config.clone(),
+                context_path: member.dir().display().to_string(),
+                output_dir: None,
+                docker_build_args: docker_build_args.clone(),
+                data_plane_version: versions.0.clone(),
+                installer_version: versions.1.clone(),
+                timestamp: timestamp.clone(),
+                images: member.build_images(),
+            };
+            (member.name().to_string(), build)
+        })
+        .collect();
+    let results = build_members(builds).await;
+
+    // Uploads and deployments are watched with their own progress, so they run one at a time
+    for ((member, cage_config, validated_config, mut config_file, cage, versions), result) in
+        prepared.into_iter().zip(results)
+    {
+        let (built_enclave, output_path) = match result {
+            Ok(built) => built,
+            Err(e) => {
+                report_error!("Failed to build EIF for {} - {}", member.name(), e);
+                reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+                continue;
+            }
+        };
+        let eif_measurements = built_enclave.measurements();
+        if cage_config.debug {
+            log::warn!("{} is in debug mode.", member.name());
+            crate::common::log_debug_mode_attestation_warning();
+        }
+        crate::common::update_cage_config_with_eif_measurements(
+            &mut config_file,
+            eif_measurements,
+            None,
+            cage_config.active_profile.as_deref(),
+        );
+
+        log::info!("Deploying {}...", member.name());
+        if let Err(e) = deploy_eif(
+            &validated_config,
+            cage_api.clone(),
+            output_path,
+            eif_measurements,
+            versions.0,
+            versions.1,
+        )
+        .await
+        {
+            report_error!("Failed to deploy {} - {}", member.name(), e);
+            reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+            continue;
+        }
+        log::info!(
+            "{} is now available at https://{}",
+            member.name(),
+            cage.domain()
+        );
+        reports.push(
+            MemberReport::new(member, Outcome::Deployed)
+                .with_measurements(eif_measurements)
+                .with_data(&serde_json::json!({ "cageDomain": cage.domain() })),
+        );
+    }
+
+    report_summary(&members, reports)
+}
+
 async fn resolve_eif(
     validated_config: &ValidatedCageBuildConfig,
     context_path: &str,
@@ -184,7 +345,7 @@ async fn resolve_eif(
 ) -> Result<(EIFMeasurements, OutputPath), exitcode::ExitCode> {
     if let Some(path) = eif_path {
         get_eif(path, verbose).map_err(|e| {
-            log::error!("Failed to access the EIF at {}", path);
+            report_error!("Failed to access the EIF at {}", path);
             e.exitcode()
         })
     } else {
@@ -192,16 +353,19 @@ async fn resolve_eif(
             validated_config,
             context_path,
             None,
-            verbose,
-            build_args,
-            data_plane_version,
-            installer_version,
-            timestamp,
-            from_existing,
+            BuildOptions {
+                verbose,
+                docker_build_args: build_args,
+                data_plane_version,
+                installer_version,
+                timestamp,
+                from_existing,
+                images: &BuildImages::default(),
+            },
         )
         .await
         .map_err(|build_err| {
-            log::error!("Failed to build EIF - {}", build_err);
+            report_error!("Failed to build EIF - {}", build_err);
             build_err.exitcode()
         })?;
         Ok((built_enclave.measurements().to_owned(), output_path))
@@ -221,7 +385,7 @@ async fn get_data_plane_and_installer_version(
             {
                 Ok(version) => version,
                 Err(e) => {
-                    log::error!("Failed to retrieve the latest data plane version - {e:?}");
+                    report_error!("Failed to retrieve the latest data plane version - {e:?}");
                     return Err(e.exitcode());
                 }
             };
@@ -231,7 +395,7 @@ async fn get_data_plane_and_installer_version(
             {
                 Ok(version) => version,
                 Err(e) => {
-                    log::error!("Failed to retrieve the latest installer version - {e:?}");
+                    report_error!("Failed to retrieve the latest installer version - {e:?}");
                     return Err(e.exitcode());
                 }
             };
@@ -1,5 +1,7 @@
 use crate::common::CliError;
 use crate::describe::describe_eif;
+use crate::output;
+use crate::report_error;
 use clap::Parser;
 
 /// Get the PCRs of a built EIF
@@ -19,11 +21,11 @@ pub async fn run(describe_args: DescribeArgs) -> exitcode::ExitCode {
     let description = match describe_eif(&describe_args.eif_path, !describe_args.quiet) {
         Ok(measurements) => measurements,
         Err(e) => {
-            log::error!("{}", e);
+            report_error!("{}", e);
             return e.exitcode();
         }
     };
 
-    println!("{}", serde_json::to_string_pretty(&description).unwrap());
+    output::data(&description);
     exitcode::OK
 }
@@ -1,5 +1,6 @@
+use crate::dev::api::run_mock_cages_api;
 use crate::dev::run_mock_crypto_api;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// Start a mock encryption API for local testing
 #[derive(Debug, Parser)]
@@ -8,9 +9,29 @@ pub struct DevArgs {
     /// The port to run the crypto API on
     #[clap(short = 'p', long = "port", default_value = "9999")]
     pub port: u16,
+
+    #[clap(subcommand)]
+    pub command: Option<DevCommands>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DevCommands {
+    /// Start a mock of the Cages API for offline testing. Point the CLI at it by setting EV_API_URL.
+    Api(DevApiArgs),
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "api", about)]
+pub struct DevApiArgs {
+    /// The port to run the Cages API on
+    #[clap(short = 'p', long = "port", default_value = "9998")]
+    pub port: u16,
 }
 
 pub async fn run(dev_args: DevArgs) -> exitcode::ExitCode {
-    run_mock_crypto_api(dev_args.port).await;
+    match dev_args.command {
+        Some(DevCommands::Api(api_args)) => run_mock_cages_api(api_args.port).await,
+        None => run_mock_crypto_api(dev_args.port).await,
+    }
     exitcode::OK
 }
@@ -1,4 +1,7 @@
+use crate::output;
+use crate::report_error;
 use crate::{
+    auth,
     config::CageConfig,
     encrypt::{self, EncryptError},
 };
@@ -38,38 +41,42 @@ pub async fn run(encrypt_args: EncryptArgs) -> exitcode::ExitCode {
     let (team_uuid, app_uuid) = match get_cage_details(encrypt_args.clone()) {
         Ok((team_uuid, app_uuid)) => (team_uuid, app_uuid),
         Err(e) => {
-            log::error!("Config error {}", e);
+            report_error!("Config error {}", e);
             return exitcode::SOFTWARE;
         }
     };
     match encrypt::encrypt(encrypt_args.value, team_uuid, app_uuid, encrypt_args.curve).await {
         Ok(encrypted_string) => {
-            println!("{}", encrypted_string);
+            if output::is_json() {
+                output::data(&serde_json::json!({ "encryptedValue": encrypted_string }));
+            } else {
+                println!("{}", encrypted_string);
+            }
             exitcode::OK
         }
         Err(e) => {
-            log::info!("{}", e);
+            report_error!("{}", e);
             exitcode::SOFTWARE
         }
     }
 }
 
 fn get_cage_details(encrypt_args: EncryptArgs) -> Result<(String, String), EncryptError> {
-    if encrypt_args.team_uuid.is_none() || encrypt_args.app_uuid.is_none() {
-        let cage_config = CageConfig::try_from_filepath(&encrypt_args.config)?;
+    if let (Some(team_uuid), Some(app_uuid)) = (encrypt_args.team_uuid, encrypt_args.app_uuid) {
+        return Ok((team_uuid, app_uuid));
+    }
 
-        if cage_config.app_uuid.is_none() || cage_config.team_uuid.is_none() {
-            Err(EncryptError::MissingUuid)
-        } else {
-            Ok((
-                cage_config.team_uuid.unwrap(),
-                cage_config.app_uuid.unwrap(),
-            ))
-        }
-    } else {
-        Ok((
-            encrypt_args.team_uuid.unwrap(),
-            encrypt_args.app_uuid.unwrap(),
-        ))
+    // Falls back to the defaults of the active credentials profile
+    let config = CageConfig::try_from_filepath(&encrypt_args.config);
+    let (app_uuid, team_uuid) = match &config {
+        Ok(config) => (config.app_uuid.clone(), config.team_uuid.clone()),
+        Err(_) => (None, None),
+    };
+    match auth::resolve_app_and_team(app_uuid, team_uuid) {
+        Some((app_uuid, team_uuid)) => Ok((team_uuid, app_uuid)),
+        None => Err(match config {
+            Err(e) => e.into(),
+            Ok(_) => EncryptError::MissingUuid,
+        }),
     }
 }
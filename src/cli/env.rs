@@ -1,12 +1,11 @@
 use clap::{Parser, Subcommand};
 
-use crate::{
-    api::{cage::CagesClient, AuthMode},
-    get_api_key,
-};
+use crate::{api::cage::CagesClient, get_auth_mode};
 
 use super::encrypt::CurveName;
 use crate::env::env;
+use crate::output;
+use crate::report_error;
 
 #[derive(Clone, Debug, clap::ArgEnum, Subcommand)]
 pub enum EnvAction {
@@ -82,14 +81,12 @@ pub struct GetEnvArgs {
 }
 
 pub async fn run(env_args: EnvArgs) -> exitcode::ExitCode {
-    let api_key = get_api_key!();
-    let cages_client = CagesClient::new(AuthMode::ApiKey(api_key));
+    let cages_client = CagesClient::new(get_auth_mode!());
 
     match env(cages_client, env_args.action).await {
         Ok(result) => match result {
             Some(env) => {
-                let success_msg = serde_json::json!(env);
-                println!("{}", serde_json::to_string_pretty(&success_msg).unwrap());
+                output::data(&env);
                 exitcode::OK
             }
             None => {
@@ -98,7 +95,7 @@ pub async fn run(env_args: EnvArgs) -> exitcode::ExitCode {
             }
         },
         Err(e) => {
-            log::error!("Error updating environment {}", e);
+            report_error!("Error updating environment {}", e);
             exitcode::SOFTWARE
         }
     }
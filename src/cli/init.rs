@@ -1,9 +1,15 @@
 use crate::api;
+use crate::api::cage::Cage;
 use crate::api::cage::CreateCageRequest;
-use crate::api::{cage::Cage, AuthMode};
+use crate::cli::link::find_cage_by_name;
 use crate::common::CliError;
-use crate::config::{default_dockerfile, CageConfig, EgressSettings, SigningInfo};
-use crate::get_api_key;
+use crate::config::edit::ConfigFile;
+use crate::config::{
+    convert_comma_list, default_dockerfile, CageConfig, EgressSettings, SigningInfo,
+};
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
 use clap::{ArgGroup, Parser};
 
 /// Initialize a Cage.toml in the current directory
@@ -25,8 +31,8 @@ pub struct InitArgs {
     pub output_dir: String,
 
     /// Name of Cage to deploy
-    #[clap(long = "name")]
-    pub cage_name: String,
+    #[clap(long = "name", required_unless_present = "attach")]
+    pub cage_name: Option<String>,
 
     /// Debug setting for the Cage
     #[clap(long = "debug")]
@@ -75,6 +81,33 @@ pub struct InitArgs {
     /// Enables forwarding proxy protocol when TLS Termination is disabled
     #[clap(long = "forward-proxy-protocol")]
     pub forward_proxy_protocol: bool,
+
+    /// Write the cage.toml and signing credentials without creating the Cage. It's created by
+    /// `init --attach`, `link` or the first deploy.
+    #[clap(long = "offline")]
+    pub offline: bool,
+
+    /// Create the Cage for a cage.toml written with --offline, or look it up by name if it already
+    /// exists, and write its uuids to the cage.toml
+    #[clap(
+        long = "attach",
+        conflicts_with_all = &[
+            "offline",
+            "cage-name",
+            "debug",
+            "egress",
+            "dockerfile",
+            "cert-path",
+            "key-path",
+            "disable-tls-termination",
+            "disable-api-key-auth",
+            "trx-logging-disabled",
+            "egress-ports",
+            "egress-destinations",
+            "forward-proxy-protocol",
+        ]
+    )]
+    pub attach: bool,
 }
 
 impl std::convert::From<InitArgs> for CageConfig {
@@ -85,11 +118,14 @@ impl std::convert::From<InitArgs> for CageConfig {
             Some(SigningInfo {
                 cert: val.cert_path,
                 key: val.key_path,
+                lock_cert: None,
             })
         };
 
         CageConfig {
-            name: val.cage_name,
+            name: val
+                .cage_name
+                .expect("A Cage name is required unless attaching"),
             uuid: None,
             app_uuid: None,
             team_uuid: None,
@@ -106,39 +142,156 @@ impl std::convert::From<InitArgs> for CageConfig {
             api_key_auth: !val.disable_api_key_auth,
             trx_logging: !val.trx_logging_disabled,
             runtime: None,
+            env: None,
             forward_proxy_protocol: val.forward_proxy_protocol,
+            forward_port: None,
+            profiles: Default::default(),
+            active_profile: None,
         }
     }
 }
 
-fn convert_comma_list(maybe_str: Option<String>) -> Option<Vec<String>> {
-    maybe_str.map(|str| str.split(',').map(|value| value.to_string()).collect())
-}
-
 pub async fn run(init_args: InitArgs) -> exitcode::ExitCode {
-    let api_key = get_api_key!();
-    let cages_client = api::cage::CagesClient::new(AuthMode::ApiKey(api_key.clone()));
+    if init_args.offline {
+        return init_local_config(init_args, None).await;
+    }
 
-    let create_cage_request =
-        CreateCageRequest::new(init_args.cage_name.clone(), init_args.is_time_bound);
+    let cages_client = api::cage::CagesClient::new(get_auth_mode!());
+
+    if init_args.attach {
+        let config_path = std::path::Path::new(&init_args.output_dir).join("cage.toml");
+        return match attach_config(
+            &cages_client,
+            &config_path.to_string_lossy(),
+            init_args.is_time_bound,
+        )
+        .await
+        {
+            Ok(config) => {
+                log::info!(
+                    "{} is attached to Cage {}",
+                    config_path.display(),
+                    config.uuid.as_deref().unwrap_or_default()
+                );
+                if output::is_json() {
+                    output::data(&serde_json::json!({
+                        "cageUuid": config.uuid,
+                        "cageName": config.name,
+                        "configPath": config_path,
+                    }));
+                }
+                exitcode::OK
+            }
+            Err(code) => code,
+        };
+    }
+
+    let create_cage_request = CreateCageRequest::new(
+        init_args.cage_name.clone().unwrap_or_default(),
+        init_args.is_time_bound,
+    );
     let created_cage = match cages_client.create_cage(create_cage_request).await {
         Ok(cage_ref) => cage_ref,
         Err(e) => {
-            log::error!("Error creating Cage record — {:?}", e);
+            report_error!("Error creating Cage record — {:?}", e);
             return e.exitcode();
         }
     };
 
-    init_local_config(init_args, created_cage).await
+    init_local_config(init_args, Some(created_cage)).await
 }
 
-async fn init_local_config(init_args: InitArgs, created_cage: Cage) -> exitcode::ExitCode {
+/// Gives a cage.toml written by `init --offline` a Cage, by looking it up by name or creating it,
+/// and writes the Cage's uuids to the config. Configs which already have a uuid are left as they are.
+pub(crate) async fn attach_config(
+    cages_client: &api::cage::CagesClient,
+    config_path: &str,
+    is_time_bound: bool,
+) -> Result<CageConfig, exitcode::ExitCode> {
+    let config = match CageConfig::try_from_filepath(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error!("Failed to read Cage config - {}", e);
+            return Err(e.exitcode());
+        }
+    };
+    if config.uuid.is_some() {
+        return Ok(config);
+    }
+
+    let cage = match find_cage_by_name(cages_client, &config.name).await? {
+        Some(cage_uuid) => match cages_client.get_cage(&cage_uuid).await {
+            Ok(cage) => cage.cage().clone(),
+            Err(e) => {
+                report_error!("Failed to retrieve Cage details from Evervault API – {}", e);
+                return Err(e.exitcode());
+            }
+        },
+        None => {
+            let create_cage_request = CreateCageRequest::new(config.name.clone(), is_time_bound);
+            match cages_client.create_cage(create_cage_request).await {
+                Ok(cage) => {
+                    log::info!("Created Cage {} ({})", cage.name(), cage.uuid());
+                    cage
+                }
+                Err(e) => {
+                    report_error!("Error creating Cage record — {:?}", e);
+                    return Err(e.exitcode());
+                }
+            }
+        }
+    };
+
+    let mut config_file = match ConfigFile::load(config_path) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            report_error!("Failed to read Cage config - {}", e);
+            return Err(e.exitcode());
+        }
+    };
+    if let Err(e) = config_file
+        .set_cage(&cage, config.active_profile.as_deref())
+        .and_then(|_| config_file.save())
+    {
+        report_error!("Error writing cage.toml — {}", e);
+        return Err(e.exitcode());
+    }
+    let mut config = config;
+    config.annotate(cage);
+    Ok(config)
+}
+
+async fn init_local_config(init_args: InitArgs, created_cage: Option<Cage>) -> exitcode::ExitCode {
     let output_dir = init_args.output_dir.clone();
     let output_path = std::path::Path::new(output_dir.as_str());
     let config_path = output_path.join("cage.toml");
+    let mut config_file = match ConfigFile::load(&config_path) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            report_error!("Error reading existing cage.toml — {}", e);
+            return e.exitcode();
+        }
+    };
 
+    let offline = created_cage.is_none();
     let mut initial_config: CageConfig = init_args.into();
-    initial_config.annotate(created_cage);
+    if let Some(created_cage) = created_cage {
+        initial_config.annotate(created_cage);
+    }
+    // Keep any profiles and environment from a cage.toml being re-initialised
+    if let Some(existing_config) = config_path
+        .to_str()
+        .and_then(|path| CageConfig::read_uninterpolated(path).ok())
+    {
+        // An offline init of the same Cage shouldn't detach the config from it
+        if offline && existing_config.name == initial_config.name {
+            initial_config.uuid = existing_config.uuid;
+            initial_config.app_uuid = existing_config.app_uuid;
+            initial_config.team_uuid = existing_config.team_uuid;
+        }
+        initial_config.profiles = existing_config.profiles;
+        initial_config.env = existing_config.env;
+    }
 
     if initial_config.signing.is_none() {
         log::info!("Generating signing credentials for cage");
@@ -148,25 +301,31 @@ async fn init_local_config(init_args: InitArgs, created_cage: Cage) -> exitcode:
                 initial_config.set_key(format!("{}", key_path.display()));
             }
             Err(e) => {
-                log::error!("Failed to generate cage signing credentials - {}", e);
+                report_error!("Failed to generate cage signing credentials - {}", e);
                 return e.exitcode();
             }
         }
     }
 
-    let serialized_config = match toml::ser::to_vec(&initial_config) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            log::error!("Error serializing cage.toml — {:?}", e);
-            return exitcode::SOFTWARE;
-        }
-    };
-
-    if let Err(e) = std::fs::write(config_path, serialized_config) {
-        log::error!("Error writing cage.toml — {:?}", e);
-        exitcode::IOERR
+    if let Err(e) = config_file
+        .set_config(&initial_config)
+        .and_then(|_| config_file.save())
+    {
+        report_error!("Error writing cage.toml — {}", e);
+        e.exitcode()
     } else {
-        log::info!("Cage.toml initialized successfully. You can now deploy a Cage using the deploy command");
+        if output::is_json() {
+            output::data(&serde_json::json!({
+                "cageUuid": initial_config.uuid,
+                "cageName": initial_config.name,
+                "configPath": config_path,
+            }));
+        }
+        if initial_config.uuid.is_none() {
+            log::info!("Cage.toml initialized without creating a Cage. Run `ev-cage init --attach` to create it, or it will be created by the first deploy");
+        } else {
+            log::info!("Cage.toml initialized successfully. You can now deploy a Cage using the deploy command");
+        }
         exitcode::OK
     }
 }
@@ -175,6 +334,7 @@ async fn init_local_config(init_args: InitArgs, created_cage: Cage) -> exitcode:
 mod init_tests {
     use super::*;
     use crate::api::cage::CageState;
+    use crate::test_utils::start_mock_cages_api;
 
     use std::fs::read;
     use tempfile::TempDir;
@@ -194,7 +354,7 @@ mod init_tests {
         };
         let init_args = InitArgs {
             output_dir: output_dir.path().to_str().unwrap().to_string(),
-            cage_name: "hello".to_string(),
+            cage_name: Some("hello".to_string()),
             debug: false,
             egress: true,
             dockerfile: Some("Dockerfile".into()),
@@ -207,8 +367,10 @@ mod init_tests {
             egress_ports: Some("443".to_string()),
             egress_destinations: Some("evervault.com".to_string()),
             forward_proxy_protocol: false,
+            offline: false,
+            attach: false,
         };
-        init_local_config(init_args, sample_cage).await;
+        init_local_config(init_args, Some(sample_cage)).await;
         let config_path = output_dir.path().join("cage.toml");
         assert!(config_path.exists());
         let config_content = String::from_utf8(read(config_path).unwrap()).unwrap();
@@ -234,4 +396,68 @@ keyPath = "./key.pem"
 "#;
         assert_eq!(config_content, expected_config_content);
     }
+
+    fn offline_init_args(output_dir: &TempDir) -> InitArgs {
+        InitArgs {
+            output_dir: output_dir.path().to_str().unwrap().to_string(),
+            cage_name: Some("hello".to_string()),
+            debug: false,
+            egress: false,
+            dockerfile: None,
+            disable_tls_termination: false,
+            cert_path: Some("./cert.pem".to_string()),
+            key_path: Some("./key.pem".to_string()),
+            is_time_bound: false,
+            disable_api_key_auth: false,
+            trx_logging_disabled: false,
+            egress_ports: None,
+            egress_destinations: None,
+            forward_proxy_protocol: false,
+            offline: true,
+            attach: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn init_offline_writes_config_without_uuids() {
+        let output_dir = TempDir::new().unwrap();
+        let code = init_local_config(offline_init_args(&output_dir), None).await;
+        assert_eq!(code, exitcode::OK);
+
+        let config_path = output_dir.path().join("cage.toml");
+        let config = CageConfig::read_uninterpolated(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(config.name, "hello");
+        assert_eq!(config.uuid, None);
+        assert_eq!(config.app_uuid, None);
+        assert_eq!(config.cert(), Some("./cert.pem"));
+    }
+
+    #[tokio::test]
+    async fn attach_creates_cage_then_reuses_it() {
+        let output_dir = TempDir::new().unwrap();
+        init_local_config(offline_init_args(&output_dir), None).await;
+        let config_path = output_dir.path().join("cage.toml");
+        let config_path = config_path.to_str().unwrap();
+
+        let cages_client = api::cage::CagesClient::new(api::AuthMode::ApiKey("test".to_string()))
+            .with_base_url(start_mock_cages_api().await);
+        let attached = attach_config(&cages_client, config_path, false)
+            .await
+            .unwrap();
+        let cage_uuid = attached.uuid.clone().unwrap();
+        let written = CageConfig::read_uninterpolated(config_path).unwrap();
+        assert_eq!(written.uuid.as_deref(), Some(cage_uuid.as_str()));
+        assert!(written.app_uuid.is_some());
+        assert!(written.team_uuid.is_some());
+
+        // A second offline config for the same Cage is attached to the existing Cage by name
+        let other_dir = TempDir::new().unwrap();
+        init_local_config(offline_init_args(&other_dir), None).await;
+        let other_path = other_dir.path().join("cage.toml");
+        let reattached = attach_config(&cages_client, other_path.to_str().unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(reattached.uuid, Some(cage_uuid));
+        assert_eq!(cages_client.get_cages().await.unwrap().cages().len(), 1);
+    }
 }
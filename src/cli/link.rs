@@ -0,0 +1,393 @@
+use crate::api::cage::{CageState, CagesClient, GetCageResponse};
+use crate::common::CliError;
+use crate::config::edit::ConfigFile;
+use crate::config::{
+    default_dockerfile, selected_profile_name, CageConfig, CageConfigError, EgressSettings,
+    RuntimeVersions,
+};
+use crate::enclave::EIFMeasurements;
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
+use clap::{ArgGroup, Parser};
+use std::path::Path;
+
+// Nitro enclaves measure their PCRs with SHA384, which nitro-cli reports in this form
+const PCR_HASH_ALGORITHM: &str = "Sha384 { ... }";
+
+/// Write a cage.toml for an existing Cage, such as in a fresh checkout of its repo
+#[derive(Debug, Parser)]
+#[clap(name = "link", about)]
+#[clap(group(
+  ArgGroup::new("cage")
+    .args(&["cage-uuid", "cage-name"])
+))]
+pub struct LinkArgs {
+    /// Directory to write the Cage toml to. Defaults to the current directory.
+    #[clap(short = 'o', long = "output", default_value = ".")]
+    pub output_dir: String,
+
+    /// Uuid of the Cage to link
+    #[clap(long = "cage-uuid")]
+    pub cage_uuid: Option<String>,
+
+    /// Name of the Cage to link. Defaults to the name in an existing cage.toml.
+    #[clap(long = "name")]
+    pub cage_name: Option<String>,
+
+    /// Path to the signing cert used for the Cage. If provided, the private-key must also be set.
+    #[clap(long = "signing-cert", requires = "key-path")]
+    pub cert_path: Option<String>,
+
+    /// Path to the signing key used for the Cage. If provided, the signing-cert must also be set.
+    #[clap(long = "private-key", requires = "cert-path")]
+    pub key_path: Option<String>,
+}
+
+pub async fn run(link_args: LinkArgs) -> exitcode::ExitCode {
+    let cages_client = CagesClient::new(get_auth_mode!());
+
+    let cage_uuid = match (&link_args.cage_uuid, &link_args.cage_name) {
+        (Some(cage_uuid), _) => cage_uuid.clone(),
+        (None, cage_name) => {
+            let Some(cage_name) = cage_name.clone().or_else(|| existing_cage_name(&link_args))
+            else {
+                report_error!("No Cage to link. Pass --cage-uuid or --name, or run link in a directory with a cage.toml");
+                return exitcode::USAGE;
+            };
+            match find_cage_by_name(&cages_client, &cage_name).await {
+                Ok(Some(cage_uuid)) => cage_uuid,
+                Ok(None) => {
+                    report_error!("No Cage named {} was found", cage_name);
+                    return exitcode::DATAERR;
+                }
+                Err(code) => return code,
+            }
+        }
+    };
+
+    let cage = match cages_client.get_cage(&cage_uuid).await {
+        Ok(cage) => cage,
+        Err(e) => {
+            report_error!("Failed to retrieve Cage details from Evervault API – {}", e);
+            return e.exitcode();
+        }
+    };
+    if cage.is_deleted() {
+        report_error!("Cage {} has been deleted, so it can't be linked", cage_uuid);
+        return exitcode::DATAERR;
+    }
+
+    link_local_config(link_args, cage, selected_profile_name())
+}
+
+fn existing_cage_name(link_args: &LinkArgs) -> Option<String> {
+    let config_path = Path::new(&link_args.output_dir).join("cage.toml");
+    let config = CageConfig::try_from_filepath(config_path.to_str()?).ok()?;
+    Some(config.name)
+}
+
+/// Returns the uuid of the Cage with the given name, if there is one. Deleted Cages are ignored, and
+/// names shared by more than one Cage are reported as errors.
+pub(crate) async fn find_cage_by_name(
+    cages_client: &CagesClient,
+    cage_name: &str,
+) -> Result<Option<String>, exitcode::ExitCode> {
+    let cages = match cages_client.get_cages().await {
+        Ok(cages) => cages,
+        Err(e) => {
+            report_error!("Failed to retrieve Cages from Evervault API – {}", e);
+            return Err(e.exitcode());
+        }
+    };
+    let matching: Vec<_> = cages
+        .cages()
+        .iter()
+        .filter(|cage| cage.name() == cage_name && cage.state != CageState::Deleted)
+        .collect();
+    match matching.as_slice() {
+        [cage] => Ok(Some(cage.uuid().to_string())),
+        [] => Ok(None),
+        _ => {
+            let uuids: Vec<_> = matching.iter().map(|cage| cage.uuid()).collect();
+            report_error!(
+                "More than one Cage is named {}. Link one of them with --cage-uuid: {}",
+                cage_name,
+                uuids.join(", ")
+            );
+            Err(exitcode::USAGE)
+        }
+    }
+}
+
+fn link_local_config(
+    link_args: LinkArgs,
+    cage: GetCageResponse,
+    profile: Option<String>,
+) -> exitcode::ExitCode {
+    let config_path = Path::new(&link_args.output_dir).join("cage.toml");
+    let mut config_file = match ConfigFile::load(&config_path) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            report_error!("Error reading existing cage.toml — {}", e);
+            return e.exitcode();
+        }
+    };
+    let existing_config = match read_existing_config(&config_path, profile) {
+        Ok(existing_config) => existing_config,
+        Err(e) => {
+            report_error!("Error reading existing cage.toml — {}", e);
+            return e.exitcode();
+        }
+    };
+
+    let mut config = linked_config(existing_config.clone(), &cage);
+    if let (Some(cert), Some(key)) = (link_args.cert_path, link_args.key_path) {
+        config.set_cert(cert);
+        config.set_key(key);
+    }
+
+    let write_result = match (config.active_profile.as_deref(), &existing_config) {
+        (Some(profile), Some(existing_config)) => {
+            set_linked_profile(&mut config_file, existing_config, &config, &cage, profile)
+        }
+        _ => config_file.set_config(&config),
+    };
+    if let Err(e) = write_result.and_then(|_| config_file.save()) {
+        report_error!("Error writing cage.toml — {}", e);
+        return e.exitcode();
+    }
+
+    if output::is_json() {
+        output::data(&serde_json::json!({
+            "cageUuid": config.uuid,
+            "cageName": config.name,
+            "configPath": config_path,
+        }));
+    }
+    if config.signing.is_none() {
+        log::warn!("cage.toml has no signing credentials. Pass the cert and key used to sign the Cage with --signing-cert and --private-key, or set them with `ev-cage config set signing.certPath`.");
+    }
+    log::info!(
+        "Linked {} to Cage {}. Settings such as egress aren't stored with the Cage, so check them before deploying.",
+        config_path.display(),
+        cage.cage().name()
+    );
+    exitcode::OK
+}
+
+// Reads the existing config with the selected profile merged in. A profile can only be linked when
+// the cage.toml defining it exists.
+fn read_existing_config(
+    config_path: &Path,
+    profile: Option<String>,
+) -> Result<Option<CageConfig>, CageConfigError> {
+    match CageConfig::read_uninterpolated(&config_path.to_string_lossy()) {
+        Ok(config) => config.with_selected_profile(profile.as_deref()).map(Some),
+        Err(e) if profile.is_some() => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+// Writes the linked details into the profile, so the base config and other profiles keep their Cage
+fn set_linked_profile(
+    config_file: &mut ConfigFile,
+    existing_config: &CageConfig,
+    config: &CageConfig,
+    cage: &GetCageResponse,
+    profile: &str,
+) -> Result<(), CageConfigError> {
+    let profile = Some(profile);
+    config_file.set_cage(cage.cage(), profile)?;
+    if config.name != existing_config.name {
+        config_file.set_value(
+            &["name"],
+            &toml::Value::String(config.name.clone()),
+            profile,
+        )?;
+    }
+    if config.debug != existing_config.debug {
+        config_file.set_value(&["debug"], &toml::Value::Boolean(config.debug), profile)?;
+    }
+    if let Some(attestation) = &config.attestation {
+        config_file.set_attestation(attestation, profile)?;
+    }
+    if let Some(runtime) = &config.runtime {
+        config_file.set_runtime_info(runtime, profile)?;
+    }
+    for (key, value, existing_value) in [
+        ("certPath", config.cert(), existing_config.cert()),
+        ("keyPath", config.key(), existing_config.key()),
+    ] {
+        if let Some(value) = value.filter(|value| Some(*value) != existing_value) {
+            let value = toml::Value::String(value.to_string());
+            config_file.set_value(&["signing", key], &value, profile)?;
+        }
+    }
+    Ok(())
+}
+
+// Fills in the Cage's details over an existing config, or over the same defaults used by init
+fn linked_config(existing_config: Option<CageConfig>, cage: &GetCageResponse) -> CageConfig {
+    let mut config = existing_config.unwrap_or_else(|| CageConfig {
+        name: String::new(),
+        uuid: None,
+        app_uuid: None,
+        team_uuid: None,
+        debug: false,
+        dockerfile: default_dockerfile(),
+        api_key_auth: true,
+        trx_logging: true,
+        disable_tls_termination: false,
+        forward_proxy_protocol: false,
+        forward_port: None,
+        egress: EgressSettings::new(None, None, false),
+        signing: None,
+        attestation: None,
+        runtime: None,
+        env: None,
+        profiles: Default::default(),
+        active_profile: None,
+    });
+    config.name = cage.cage().name().to_string();
+    config.annotate(cage.cage().clone());
+
+    let Some(deployment) = cage.active_deployment() else {
+        return config;
+    };
+    config.debug = deployment.deployment().debug_mode();
+    let version = deployment.version();
+    if let Some(pcrs) = version.pcrs() {
+        config.attestation = Some(EIFMeasurements::new(
+            PCR_HASH_ALGORITHM.to_string(),
+            pcrs.clone(),
+        ));
+    }
+    if let (Some(data_plane_version), Some(installer_version)) =
+        (version.data_plane_version(), version.installer_version())
+    {
+        config.set_runtime_info(RuntimeVersions::new(
+            data_plane_version.to_string(),
+            installer_version.to_string(),
+        ));
+    }
+    config
+}
+
+#[cfg(test)]
+mod link_tests {
+    use super::*;
+    use crate::api::AuthMode;
+    use crate::test_utils::{deploy_mock_cage, start_mock_cages_api};
+    use tempfile::TempDir;
+
+    async fn mock_cage() -> GetCageResponse {
+        let base_url = start_mock_cages_api().await;
+        let cage_uuid = deploy_mock_cage(&base_url).await;
+        CagesClient::new(AuthMode::ApiKey("test".to_string()))
+            .with_base_url(base_url)
+            .get_cage(&cage_uuid)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_link_writes_cage_details_and_latest_deployment() {
+        let output_dir = TempDir::new().unwrap();
+        let cage = mock_cage().await;
+        let link_args = LinkArgs {
+            output_dir: output_dir.path().to_str().unwrap().to_string(),
+            cage_uuid: Some(cage.cage().uuid().to_string()),
+            cage_name: None,
+            cert_path: Some("./cert.pem".to_string()),
+            key_path: Some("./key.pem".to_string()),
+        };
+        assert_eq!(
+            link_local_config(link_args, cage.clone(), None),
+            exitcode::OK
+        );
+
+        let config_path = output_dir.path().join("cage.toml");
+        let config = CageConfig::read_uninterpolated(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(config.name, "mock-cage");
+        assert_eq!(config.uuid.as_deref(), Some(cage.cage().uuid()));
+        assert_eq!(config.app_uuid.as_deref(), Some(cage.cage().app_uuid()));
+        assert_eq!(config.cert(), Some("./cert.pem"));
+        assert_eq!(config.get_attestation().unwrap().pcrs().pcr0, "000");
+        let runtime = config.runtime.unwrap();
+        assert_eq!(runtime.data_plane_version, "0.0.0");
+        assert_eq!(runtime.installer_version, "0.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_link_writes_into_the_selected_profile() {
+        let output_dir = TempDir::new().unwrap();
+        let config_path = output_dir.path().join("cage.toml");
+        std::fs::write(
+            &config_path,
+            r#"name = "base-cage"
+uuid = "base-uuid"
+debug = false
+dockerfile = "./Dockerfile"
+
+[egress]
+enabled = false
+
+[profiles.production]
+debug = false
+"#,
+        )
+        .unwrap();
+        let cage = mock_cage().await;
+        let link_args = LinkArgs {
+            output_dir: output_dir.path().to_str().unwrap().to_string(),
+            cage_uuid: Some(cage.cage().uuid().to_string()),
+            cage_name: None,
+            cert_path: None,
+            key_path: None,
+        };
+        assert_eq!(
+            link_local_config(link_args, cage.clone(), Some("production".to_string())),
+            exitcode::OK
+        );
+
+        let config = CageConfig::read_uninterpolated(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(config.name, "base-cage");
+        assert_eq!(config.uuid.as_deref(), Some("base-uuid"));
+        assert!(config.attestation.is_none());
+        assert!(config.runtime.is_none());
+
+        let production = config.with_profile("production").unwrap();
+        assert_eq!(production.name, "mock-cage");
+        assert_eq!(production.uuid.as_deref(), Some(cage.cage().uuid()));
+        assert_eq!(production.app_uuid.as_deref(), Some(cage.cage().app_uuid()));
+        assert_eq!(production.get_attestation().unwrap().pcrs().pcr0, "000");
+        assert_eq!(production.runtime.unwrap().data_plane_version, "0.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_link_keeps_existing_settings() {
+        let cage = mock_cage().await;
+        let existing: CageConfig = toml::from_str(
+            r#"name = "old-name"
+debug = false
+dockerfile = "./app.Dockerfile"
+
+[egress]
+enabled = true
+destinations = ["api.example.com"]
+ports = ["443"]
+"#,
+        )
+        .unwrap();
+
+        let config = linked_config(Some(existing), &cage);
+        assert_eq!(config.name, "mock-cage");
+        assert_eq!(config.dockerfile(), "./app.Dockerfile");
+        assert_eq!(
+            config.egress.destinations,
+            Some(vec!["api.example.com".to_string()])
+        );
+        assert!(config.attestation.is_some());
+    }
+}
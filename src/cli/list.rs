@@ -1,7 +1,9 @@
-use crate::api::AuthMode;
 use crate::common::CliError;
 use crate::config::{read_and_validate_config, BuildTimeConfig};
-use crate::{api, get_api_key};
+use crate::output;
+use crate::report_error;
+use crate::workspace::{self, MemberReport, Outcome, WorkspaceArgs};
+use crate::{api, get_auth_mode};
 use clap::Parser;
 
 /// List your Cages and Deployments
@@ -28,18 +30,25 @@ pub enum ListCommands {
 #[derive(Debug, Parser)]
 pub struct DeploymentArgs {
     /// The cage uuid to get deployments for
-    #[clap(long = "cage-uuid")]
+    #[clap(long = "cage-uuid", conflicts_with = "manifest")]
     cage_uuid: Option<String>,
 
     /// The file containing the Cage config
-    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    #[clap(
+        short = 'c',
+        long = "config",
+        default_value = "./cage.toml",
+        conflicts_with = "manifest"
+    )]
     config: String,
+
+    #[clap(flatten)]
+    workspace: WorkspaceArgs,
 }
 impl BuildTimeConfig for DeploymentArgs {}
 
 pub async fn run(list_action: List) -> exitcode::ExitCode {
-    let api_key = get_api_key!();
-    let auth = AuthMode::ApiKey(api_key);
+    let auth = get_auth_mode!();
 
     let cage_client = api::cage::CagesClient::new(auth);
 
@@ -55,13 +64,12 @@ async fn list_cages(cage_client: &api::cage::CagesClient) -> exitcode::ExitCode
     let cages = match cage_client.get_cages().await {
         Ok(cages) => cages,
         Err(e) => {
-            log::error!("An error occurred while retrieving your Cages — {:?}", e);
+            report_error!("An error occurred while retrieving your Cages — {:?}", e);
             return e.exitcode();
         }
     };
 
-    let serialized_cages = serde_json::to_string_pretty(&cages).unwrap();
-    println!("{}", serialized_cages);
+    output::data(&cages);
     exitcode::OK
 }
 
@@ -69,13 +77,16 @@ async fn list_deployments(
     cage_client: &api::cage::CagesClient,
     deployment_args: DeploymentArgs,
 ) -> exitcode::ExitCode {
+    if deployment_args.workspace.is_workspace() {
+        return list_workspace_deployments(cage_client, deployment_args).await;
+    }
     let cage_uuid = if let Some(uuid) = deployment_args.cage_uuid.clone() {
         uuid
     } else {
         match read_and_validate_config(&deployment_args.config, &deployment_args) {
             Ok((_, validated_config)) => validated_config.cage_uuid().to_string(),
             Err(e) => {
-                log::error!(
+                report_error!(
                     "No Cage uuid provided, and failed to parse the Cage config - {}",
                     e
                 );
@@ -87,12 +98,55 @@ async fn list_deployments(
     let cages = match cage_client.get_cage(&cage_uuid).await {
         Ok(cages) => cages,
         Err(e) => {
-            log::error!("An error occurred while retrieving your Cages — {:?}", e);
+            report_error!("An error occurred while retrieving your Cages — {:?}", e);
             return e.exitcode();
         }
     };
 
-    let serialized_deployments = serde_json::to_string_pretty(&cages).unwrap();
-    println!("{}", serialized_deployments);
+    output::data(&cages);
     exitcode::OK
 }
+
+async fn list_workspace_deployments(
+    cage_client: &api::cage::CagesClient,
+    deployment_args: DeploymentArgs,
+) -> exitcode::ExitCode {
+    let members = match deployment_args.workspace.load_members() {
+        Ok(members) => members,
+        Err(e) => {
+            report_error!("Failed to load the workspace — {}", e);
+            return e.exitcode();
+        }
+    };
+
+    let mut reports = Vec::new();
+    for member in &members {
+        let member_args = member.with_args(&deployment_args);
+        let cage_uuid = match read_and_validate_config(member.config_path(), &member_args) {
+            Ok((_, validated_config)) => validated_config.cage_uuid().to_string(),
+            Err(e) => {
+                report_error!(
+                    "Failed to parse the Cage config for {} - {}",
+                    member.name(),
+                    e
+                );
+                reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+                continue;
+            }
+        };
+        match cage_client.get_cage(&cage_uuid).await {
+            Ok(cage) => reports.push(MemberReport::new(member, Outcome::Listed).with_data(&cage)),
+            Err(e) => {
+                report_error!(
+                    "An error occurred while retrieving {} — {:?}",
+                    member.name(),
+                    e
+                );
+                reports.push(MemberReport::failed(member, e.to_string(), e.exitcode()));
+            }
+        }
+    }
+    // Deployments don't fit in the summary table, so the Cage of each member is written in full
+    output::data(&reports);
+    workspace::exit_code(&reports)
+}
@@ -0,0 +1,80 @@
+use crate::api::cage::CagesClient;
+use crate::api::client::ApiClient;
+use crate::api::AuthMode;
+use crate::auth::login::{auth_url, CachedToken, DeviceLogin};
+use crate::common::CliError;
+use crate::report_error;
+use clap::Parser;
+
+/// Log in to Evervault from your browser, instead of using an API key
+#[derive(Debug, Parser)]
+#[clap(name = "login", about)]
+pub struct LoginArgs {}
+
+pub async fn run(_login_args: LoginArgs) -> exitcode::ExitCode {
+    let device_login = match auth_url() {
+        Ok(auth_url) => DeviceLogin::new(auth_url),
+        Err(e) => {
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+    let device_code = match device_login.request_device_code().await {
+        Ok(device_code) => device_code,
+        Err(e) => {
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+
+    log::info!(
+        "To log in, visit {} and enter the code {}",
+        device_code.verification_uri,
+        device_code.user_code
+    );
+    if let Some(verification_uri_complete) = device_code.verification_uri_complete.as_ref() {
+        log::info!("Or open {} directly", verification_uri_complete);
+    }
+
+    let progress_bar = crate::progress::get_tracker("Waiting for login to be approved...", None);
+    let token_response = match device_login.poll_for_token(&device_code).await {
+        Ok(token_response) => token_response,
+        Err(e) => {
+            progress_bar.finish();
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+    let cached_token = CachedToken::from_response(token_response, chrono::Utc::now().timestamp());
+
+    // Check the token is accepted by the Cages API before caching it
+    let mut cages_client = CagesClient::new(AuthMode::NoAuth);
+    if cages_client
+        .update_auth(AuthMode::BearerAuth(cached_token.access_token.clone()))
+        .is_err()
+    {
+        progress_bar.finish();
+        report_error!("The Cages API client does not support bearer authentication");
+        return exitcode::SOFTWARE;
+    }
+    if let Err(e) = cages_client.get_cages().await {
+        progress_bar.finish();
+        report_error!(
+            "The token issued at login was rejected by the Cages API — {}",
+            e
+        );
+        return e.exitcode();
+    }
+
+    match cached_token.save() {
+        Ok(_) => {
+            progress_bar.finish_with_message("Logged in to Evervault.");
+            exitcode::OK
+        }
+        Err(e) => {
+            progress_bar.finish();
+            report_error!("Failed to cache login token — {}", e);
+            e.exitcode()
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use crate::api;
-use crate::api::AuthMode;
+use crate::api::cage::CageLogs;
+use crate::api::cage::LogEvent;
 use crate::common::CliError;
 use crate::config::CageConfig;
-use crate::get_api_key;
+use crate::get_auth_mode;
+use crate::logs::{
+    format_coloured_log_event, format_log_event, format_timestamp, parse_time, resolve_time_range,
+    LogFilter, LogFollower, LogLevel, LogPageStream,
+};
+use crate::output;
+use crate::report_error;
 
-use chrono::TimeZone;
+use atty::Stream;
 use clap::Parser;
 use std::fmt::Write;
 
@@ -19,11 +26,69 @@ pub struct LogArgs {
     /// Path to the toml file containing the Cage's config
     #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
     pub config: String,
+
+    /// Show logs from this time onwards. Accepts an RFC 3339 timestamp, or a relative time such as 30m, 2h or 1d
+    #[clap(long = "since", default_value = "30m")]
+    pub since: String,
+
+    /// Show logs up to this time. Accepts an RFC 3339 timestamp, a relative time such as 30m, or now. Defaults to now
+    #[clap(long = "until", conflicts_with = "follow")]
+    pub until: Option<String>,
+
+    /// Keep polling for new logs and print them as they arrive, until interrupted with Ctrl-C
+    #[clap(short = 'f', long = "follow")]
+    pub follow: bool,
+
+    /// Only show logs from instances whose id contains this value, e.g. the six characters shown in the log prefix
+    #[clap(long = "instance")]
+    pub instance: Option<String>,
+
+    /// Only show logs whose message matches this regex
+    #[clap(long = "grep")]
+    pub grep: Option<String>,
+
+    /// Only show logs at this level or above. The level is taken from the first level name found in each message
+    #[clap(arg_enum, long = "level")]
+    pub level: Option<LogLevel>,
+
+    /// Output format. The pager is only used for plain output to a terminal
+    #[clap(arg_enum, long = "output", default_value = "plain")]
+    pub output: LogOutputFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ArgEnum)]
+pub enum LogOutputFormat {
+    Plain,
+    Json,
+    Ndjson,
 }
 
+const FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub async fn run(log_args: LogArgs) -> i32 {
-    let api_key = get_api_key!();
-    let cages_client = api::cage::CagesClient::new(AuthMode::ApiKey(api_key));
+    // The global --json flag wraps the logs in the output envelope, so they can only be collected as a single document
+    let output_format = if output::is_json() {
+        LogOutputFormat::Json
+    } else {
+        log_args.output
+    };
+    if log_args.follow && output_format == LogOutputFormat::Json {
+        report_error!("JSON output can't be used with --follow, use --output ndjson instead.");
+        return exitcode::USAGE;
+    }
+    let filter = match LogFilter::new(
+        log_args.instance.clone(),
+        log_args.grep.as_deref(),
+        log_args.level,
+    ) {
+        Ok(filter) => filter,
+        Err(e) => {
+            report_error!("{}", e);
+            return e.exitcode();
+        }
+    };
+
+    let cages_client = api::cage::CagesClient::new(get_auth_mode!());
 
     let cage_uuid = match log_args.cage_uuid.clone() {
         Some(cage_uuid) => cage_uuid,
@@ -31,118 +96,243 @@ pub async fn run(log_args: LogArgs) -> i32 {
             let cage_uuid = match CageConfig::try_from_filepath(&log_args.config) {
                 Ok(config) => config.uuid,
                 Err(e) => {
-                    log::error!("An error occurred while resolving your Cage toml.\n\nPlease make sure you have a cage.toml file in the current directory, or have supplied a path with the --config flag.");
+                    report_error!("An error occurred while resolving your Cage toml.\n\nPlease make sure you have a cage.toml file in the current directory, or have supplied a path with the --config flag.");
                     return e.exitcode();
                 }
             };
             match cage_uuid {
                 Some(uuid) => uuid,
                 None => {
-                    log::error!("Cage uuid is missing from toml");
+                    report_error!("Cage uuid is missing from toml");
                     return exitcode::DATAERR;
                 }
             }
         }
     };
 
-    let now = std::time::SystemTime::now();
-    let end_time = match now.duration_since(std::time::UNIX_EPOCH).ok() {
-        Some(end_time) => end_time,
-        None => {
-            log::error!("Failed to compute current time");
-            return exitcode::OSERR;
-        }
+    if log_args.follow {
+        let start_time = match parse_time(&log_args.since, chrono::Utc::now()) {
+            Ok(start_time) => start_time.timestamp_millis(),
+            Err(e) => {
+                report_error!("{}", e);
+                return e.exitcode();
+            }
+        };
+        return follow_logs(
+            &cages_client,
+            &cage_uuid,
+            start_time,
+            &filter,
+            output_format,
+        )
+        .await;
+    }
+
+    let until = log_args.until.as_deref().unwrap_or("now");
+    let (start_time, end_time) =
+        match resolve_time_range(&log_args.since, until, chrono::Utc::now()) {
+            Ok(time_range) => time_range,
+            Err(e) => {
+                report_error!("{}", e);
+                return e.exitcode();
+            }
+        };
+    let (Some(logs_start), Some(logs_end)) =
+        (format_timestamp(start_time), format_timestamp(end_time))
+    else {
+        report_error!("Failed to parse timestamps.");
+        return exitcode::SOFTWARE;
     };
 
-    let start_time = match now
-        .checked_sub(std::time::Duration::from_secs(60 * 30))
-        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
-    {
-        Some(start_time) => start_time,
-        None => {
-            log::error!("Failed to compute start time.");
-            return exitcode::SOFTWARE;
+    let mut log_pages = LogPageStream::new(&cages_client, &cage_uuid, start_time, end_time);
+
+    // Skip any leading pages without matching events, so we can report when there are no logs at all
+    let first_page = loop {
+        match log_pages.next_page().await {
+            Ok(Some(page)) if filter_page(&page, &filter).next().is_none() => continue,
+            Ok(page) => break page,
+            Err(e) => {
+                report_error!("Failed to retrieve logs for Cage - {}", e);
+                return e.exitcode();
+            }
         }
     };
-
-    let cage_logs = match cages_client
-        .get_cage_logs(
-            cage_uuid.as_str(),
-            start_time.as_millis(),
-            end_time.as_millis(),
-        )
-        .await
-    {
-        Ok(logs) => logs,
-        Err(e) => {
-            log::error!("Failed to retrieve logs for Cage - {:?}", e);
-            return e.exitcode();
+    let Some(first_page) = first_page else {
+        if output_format == LogOutputFormat::Json {
+            output::data(&Vec::<LogEvent>::new());
         }
+        log::info!("No logs found between {logs_start} and {logs_end}");
+        return exitcode::OK;
     };
 
-    let start_time = cage_logs.start_time().parse::<i64>().unwrap();
-    let Some(logs_start) = format_timestamp(start_time) else {
-        log::error!("Failed to parse timestamps.");
-        return exitcode::SOFTWARE;
-    };
-    let end_time = cage_logs.end_time().parse::<i64>().unwrap();
-    let Some(logs_end) = format_timestamp(end_time) else {
-        log::error!("Failed to parse timestamps.");
-        return exitcode::SOFTWARE;
+    match output_format {
+        LogOutputFormat::Plain if atty::is(Stream::Stdout) => {
+            page_logs(first_page, log_pages, &filter, &logs_start, &logs_end).await
+        }
+        LogOutputFormat::Json => print_json_logs(first_page, log_pages, &filter).await,
+        output => print_logs(first_page, log_pages, &filter, output).await,
+    }
+}
+
+async fn follow_logs(
+    cages_client: &api::cage::CagesClient,
+    cage_uuid: &str,
+    start_time: i64,
+    filter: &LogFilter,
+    output: LogOutputFormat,
+) -> exitcode::ExitCode {
+    let mut follower = LogFollower::new(start_time);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        let now = chrono::Utc::now().timestamp_millis();
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            new_events = follower.poll(cages_client, cage_uuid, now) => match new_events {
+                Ok(new_events) => new_events
+                    .iter()
+                    .filter(|event| filter.matches(event))
+                    .filter_map(|event| match output {
+                        LogOutputFormat::Ndjson => serde_json::to_string(event).ok(),
+                        _ => format_coloured_log_event(event),
+                    })
+                    .for_each(|log_event| println!("{}", log_event)),
+                Err(e) => {
+                    report_error!("Failed to retrieve logs for Cage - {}", e);
+                    return e.exitcode();
+                }
+            },
+        }
+
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = tokio::time::sleep(FOLLOW_POLL_INTERVAL) => {}
+        }
+    }
+
+    log::info!("Stopped following logs.");
+    exitcode::OK
+}
+
+fn filter_page<'a>(
+    page: &'a CageLogs,
+    filter: &'a LogFilter,
+) -> impl Iterator<Item = &'a LogEvent> + 'a {
+    page.log_events()
+        .iter()
+        .filter(move |event| filter.matches(event))
+}
+
+fn format_page<'a>(page: &'a CageLogs, filter: &'a LogFilter) -> impl Iterator<Item = String> + 'a {
+    filter_page(page, filter).filter_map(format_log_event)
+}
+
+// Writes pages straight to stdout as they're retrieved, for when the output is piped or newline delimited JSON
+async fn print_logs(
+    first_page: CageLogs,
+    mut log_pages: LogPageStream<'_>,
+    filter: &LogFilter,
+    output: LogOutputFormat,
+) -> exitcode::ExitCode {
+    let print_page = |page: &CageLogs| match output {
+        LogOutputFormat::Ndjson => filter_page(page, filter)
+            .filter_map(|event| serde_json::to_string(event).ok())
+            .for_each(|log_event| println!("{}", log_event)),
+        _ => format_page(page, filter).for_each(|log_event| println!("{}", log_event)),
     };
 
-    if cage_logs.log_events().is_empty() {
-        log::info!("No logs found between {logs_start} and {logs_end}",);
-        return exitcode::OK;
+    print_page(&first_page);
+    loop {
+        match log_pages.next_page().await {
+            Ok(Some(page)) => print_page(&page),
+            Ok(None) => return exitcode::OK,
+            Err(e) => {
+                report_error!("Failed to retrieve logs for Cage - {}", e);
+                return e.exitcode();
+            }
+        }
     }
+}
+
+async fn print_json_logs(
+    first_page: CageLogs,
+    mut log_pages: LogPageStream<'_>,
+    filter: &LogFilter,
+) -> exitcode::ExitCode {
+    let mut log_events: Vec<LogEvent> = filter_page(&first_page, filter).cloned().collect();
+    loop {
+        match log_pages.next_page().await {
+            Ok(Some(page)) => log_events.extend(filter_page(&page, filter).cloned()),
+            Ok(None) => break,
+            Err(e) => {
+                report_error!("Failed to retrieve logs for Cage - {}", e);
+                return e.exitcode();
+            }
+        }
+    }
+    output::data(&log_events);
+    exitcode::OK
+}
 
+// Opens the pager on the first page of logs, and keeps appending pages while it is open
+async fn page_logs(
+    first_page: CageLogs,
+    mut log_pages: LogPageStream<'_>,
+    filter: &LogFilter,
+    logs_start: &str,
+    logs_end: &str,
+) -> exitcode::ExitCode {
     let mut output = minus::Pager::new();
+    let mut retrieved = 0;
+    let mut write_page = |output: &mut minus::Pager, page: &CageLogs| {
+        for log_event in format_page(page, filter) {
+            writeln!(output, "{}", log_event)?;
+            retrieved += 1;
+        }
+        output
+            .set_prompt(format!(
+                "Retrieved {retrieved} logs from {logs_start} to {logs_end}"
+            ))
+            .map_err(|_| std::fmt::Error)
+    };
 
     if output
-        .set_prompt(format!(
-            "Retrieved {} logs from {logs_start} to {logs_end}",
-            cage_logs.log_events().len()
-        ))
+        .set_exit_strategy(minus::ExitStrategy::PagerQuit)
         .is_err()
+        || write_page(&mut output, &first_page).is_err()
     {
-        log::error!("An error occurred while displaying your Cage's logs.");
+        report_error!("An error occurred while displaying your Cage's logs.");
         return exitcode::TEMPFAIL;
     }
 
-    // TODO: add support for loading more logs at end of page
-    cage_logs
-        .log_events()
-        .iter()
-        .filter_map(|event| {
-            let mut instance_id = event.instance_id().to_string();
-            let instance_len = instance_id.len();
-            let _ = instance_id.drain(0..instance_len - 6);
-            format_timestamp(event.timestamp()).map(|timestamp| {
-                format!(
-                    "[ Instance-{} @ {} ] {}",
-                    instance_id,
-                    timestamp,
-                    event.message()
-                )
-            })
-        })
-        .for_each(|log_event| {
-            writeln!(output, "{}", log_event).unwrap();
-        });
-
-    if let Err(e) = minus::page_all(output) {
-        log::error!("An error occurred while paginating your log data - {:?}", e);
-        exitcode::SOFTWARE
-    } else {
-        exitcode::OK
+    let pager = output.clone();
+    let pager_handle = tokio::task::spawn_blocking(move || minus::dynamic_paging(pager));
+
+    while !pager_handle.is_finished() {
+        match log_pages.next_page().await {
+            Ok(Some(page)) => {
+                if write_page(&mut output, &page).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                let _ = output.send_message(format!("Failed to load more logs - {}", e));
+                break;
+            }
+        }
     }
-}
 
-fn format_timestamp(epoch: i64) -> Option<String> {
-    let epoch_secs = epoch / 1000;
-    let epoch_nsecs = epoch % 1000;
-    chrono::Utc
-        .timestamp_opt(epoch_secs, epoch_nsecs as u32)
-        .single()
-        .map(|timestamp| timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    match pager_handle.await {
+        Ok(Ok(())) => exitcode::OK,
+        Ok(Err(e)) => {
+            report_error!("An error occurred while paginating your log data - {:?}", e);
+            exitcode::SOFTWARE
+        }
+        Err(e) => {
+            report_error!("An error occurred while paginating your log data - {:?}", e);
+            exitcode::SOFTWARE
+        }
+    }
 }
@@ -1,8 +1,11 @@
 use clap::Subcommand;
+pub mod apply;
 #[cfg(not(target_os = "windows"))]
 pub mod attest;
+pub mod auth;
 pub mod build;
 pub mod cert;
+pub mod config;
 pub mod delete;
 pub mod deploy;
 pub mod describe;
@@ -10,21 +13,30 @@ pub mod dev;
 pub mod encrypt;
 pub mod env;
 pub mod init;
+pub mod link;
 pub mod list;
+pub mod login;
 pub mod logs;
+pub mod plan;
 pub mod update;
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
+    Apply(apply::ApplyArgs),
+    Auth(auth::AuthArgs),
     Build(build::BuildArgs),
     Cert(cert::CertArgs),
+    Config(config::ConfigArgs),
     Delete(delete::DeleteArgs),
     Describe(describe::DescribeArgs),
     Deploy(deploy::DeployArgs),
     Dev(dev::DevArgs),
     Init(init::InitArgs),
+    Link(link::LinkArgs),
     List(list::List),
+    Login(login::LoginArgs),
     Logs(logs::LogArgs),
+    Plan(plan::PlanArgs),
     Update(update::UpdateArgs),
     #[cfg(not(target_os = "windows"))]
     Attest(attest::AttestArgs),
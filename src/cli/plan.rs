@@ -0,0 +1,70 @@
+use crate::api::cage::CagesClient;
+use crate::apply::{self, Plan};
+use crate::cli::config::validate_before_build;
+use crate::common::CliError;
+use crate::config::validate::CageUuids;
+use crate::config::CageConfig;
+use crate::get_auth_mode;
+use crate::output;
+use crate::report_error;
+use clap::Parser;
+
+/// Show the changes apply would make to bring the Cage in line with its cage.toml
+#[derive(Debug, Parser)]
+#[clap(name = "plan", about)]
+pub struct PlanArgs {
+    /// Path to cage.toml config file
+    #[clap(short = 'c', long = "config", default_value = "./cage.toml")]
+    pub config: String,
+}
+
+pub async fn run(plan_args: PlanArgs) -> exitcode::ExitCode {
+    let cages_client = CagesClient::new(get_auth_mode!());
+    let plan = match read_plan(&cages_client, &plan_args.config).await {
+        Ok((_, plan)) => plan,
+        Err(code) => return code,
+    };
+    if output::is_json() {
+        output::data(&plan);
+    } else {
+        log_plan(&plan);
+    }
+    exitcode::OK
+}
+
+/// Reads the config and plans the changes needed to bring the Cage in line with it
+pub(crate) async fn read_plan(
+    cages_client: &CagesClient,
+    config_path: &str,
+) -> Result<(CageConfig, Plan), exitcode::ExitCode> {
+    // Cages without a uuid are created by apply, so their uuids aren't required before planning
+    validate_before_build(config_path, &(), CageUuids::NotRequiredYet)?;
+    let config = match CageConfig::try_from_filepath(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error!("Failed to read Cage config - {}", e);
+            return Err(e.exitcode());
+        }
+    };
+    match apply::plan(cages_client, &config).await {
+        Ok(plan) => Ok((config, plan)),
+        Err(e) => {
+            report_error!("Failed to compare the Cage with its config - {}", e);
+            Err(e.exitcode())
+        }
+    }
+}
+
+pub(crate) fn log_plan(plan: &Plan) {
+    for note in plan.notes() {
+        log::warn!("{}", note);
+    }
+    if plan.is_empty() {
+        log::info!("The Cage matches its config. No changes are needed.");
+    } else {
+        log::info!(
+            "The following changes will be made to the Cage:\n\n{}",
+            plan
+        );
+    }
+}
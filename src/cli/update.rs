@@ -1,3 +1,4 @@
+use crate::report_error;
 use crate::{api, common::CliError};
 use clap::Parser;
 use dialoguer::Confirm;
@@ -15,7 +16,7 @@ pub async fn run(args: UpdateArgs) -> exitcode::ExitCode {
     let new_version = match assets_client.get_latest_cli_version().await {
         Ok(version) => version,
         Err(e) => {
-            log::error!("Failed to retrieve latest CLI version - {}", e);
+            report_error!("Failed to retrieve latest CLI version - {}", e);
             return e.exitcode();
         }
     };
@@ -44,7 +45,7 @@ pub async fn run(args: UpdateArgs) -> exitcode::ExitCode {
     let install_script = match assets_client.get_cli_install_script().await {
         Ok(script) => script,
         Err(e) => {
-            log::error!("Failed to pull CLI install script - {}", e);
+            report_error!("Failed to pull CLI install script - {}", e);
             return e.exitcode();
         }
     };
@@ -52,7 +53,7 @@ pub async fn run(args: UpdateArgs) -> exitcode::ExitCode {
     let tempfile = match tempfile::Builder::new().suffix(".sh").tempfile() {
         Ok(tmp_file) => tmp_file,
         Err(e) => {
-            log::error!(
+            report_error!(
                 "Failed to create tempfile to use during new version installation - {}",
                 e
             );
@@ -61,7 +62,7 @@ pub async fn run(args: UpdateArgs) -> exitcode::ExitCode {
     };
 
     if let Err(e) = tokio::fs::write(tempfile.path(), install_script.as_bytes()).await {
-        log::error!("Failed to populate contents of install script - {}", e);
+        report_error!("Failed to populate contents of install script - {}", e);
         return exitcode::IOERR;
     }
 
@@ -75,7 +76,7 @@ pub async fn run(args: UpdateArgs) -> exitcode::ExitCode {
     match result {
         Ok(output) => output.code().unwrap_or(exitcode::USAGE),
         Err(e) => {
-            log::error!("Failed to install latest version of Cages CLI - {}", e);
+            report_error!("Failed to install latest version of Cages CLI - {}", e);
             exitcode::SOFTWARE
         }
     }
@@ -2,7 +2,8 @@ use std::ffi::OsStr;
 use std::path::PathBuf;
 use thiserror::Error;
 
-use crate::config::{CageConfig, RuntimeVersions};
+use crate::config::edit::ConfigFile;
+use crate::config::RuntimeVersions;
 
 pub struct OutputPath {
     _tmp_dir: Option<tempfile::TempDir>,
@@ -72,26 +73,26 @@ pub fn resolve_output_path(
 }
 
 pub fn update_cage_config_with_eif_measurements(
-    cage_config: &mut CageConfig,
-    config_path: &str,
+    config_file: &mut ConfigFile,
     eif_measurements: &crate::enclave::EIFMeasurements,
     runtime_info: Option<RuntimeVersions>,
+    profile: Option<&str>,
 ) {
-    cage_config.set_attestation(eif_measurements);
-    runtime_info.map(|info| cage_config.set_runtime_info(info));
-
-    if let Ok(serialized_config) = toml::ser::to_vec(&cage_config) {
-        match std::fs::write(config_path, serialized_config) {
-            Ok(_) => log::debug!(
-                "Cage config updated with enclave attestation measures and reproducible info"
-            ),
-            Err(e) => log::error!(
-                "Failed to write attestation measures and reproducible info to cage config — {:?}",
-                e
-            ),
-        };
-    } else {
-        log::error!("Failed to serialize attestation measures in cage config");
+    let updated = config_file
+        .set_attestation(eif_measurements, profile)
+        .and_then(|_| match runtime_info {
+            Some(runtime_info) => config_file.set_runtime_info(&runtime_info, profile),
+            None => Ok(()),
+        })
+        .and_then(|_| config_file.save());
+    match updated {
+        Ok(_) => log::debug!(
+            "Cage config updated with enclave attestation measures and reproducible info"
+        ),
+        Err(e) => log::error!(
+            "Failed to write attestation measures and reproducible info to cage config — {}",
+            e
+        ),
     }
 }
 
@@ -105,15 +106,13 @@ pub trait CliError {
 }
 
 #[macro_export]
-macro_rules! get_api_key {
+macro_rules! get_auth_mode {
     () => {
-        match std::env::var("EV_API_KEY") {
-            Ok(api_key) => api_key,
-            Err(_) => {
-                log::error!(
-                    "No API Key given. Set the EV_API_KEY environment variable to authenticate."
-                );
-                return exitcode::NOUSER;
+        match $crate::auth::resolve_auth_with_refresh().await {
+            Ok(resolved) => resolved.auth,
+            Err(e) => {
+                $crate::report_error!("{}", e);
+                return $crate::common::CliError::exitcode(&e);
             }
         }
     };
@@ -0,0 +1,445 @@
+use super::validate::known_keys;
+use super::{CageConfig, CageConfigError, RuntimeVersions};
+use crate::api::cage::Cage;
+use crate::enclave::EIFMeasurements;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table};
+
+/// A cage.toml loaded for editing. Changes are applied to the parsed document in place, so comments,
+/// key order and keys the CLI doesn't know about survive being written back.
+pub struct ConfigFile {
+    path: PathBuf,
+    // Contents of the file when it was loaded, or None if it didn't exist yet
+    original: Option<String>,
+    document: DocumentMut,
+}
+
+impl ConfigFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CageConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let original = read_if_exists(&path)?;
+        let document = original.as_deref().unwrap_or_default().parse()?;
+        Ok(Self {
+            path,
+            original,
+            document,
+        })
+    }
+
+    /// Writes the attestation measurements into the base config, or into the given profile
+    pub fn set_attestation(
+        &mut self,
+        measurements: &EIFMeasurements,
+        profile: Option<&str>,
+    ) -> Result<(), CageConfigError> {
+        self.set_table(&profile_path(profile, &["attestation"]), measurements)
+    }
+
+    pub fn set_runtime_info(
+        &mut self,
+        runtime: &RuntimeVersions,
+        profile: Option<&str>,
+    ) -> Result<(), CageConfigError> {
+        self.set_table(&profile_path(profile, &["runtime"]), runtime)
+    }
+
+    /// Writes the uuids of `cage` into the document, for a config written before its Cage existed.
+    pub fn set_cage(&mut self, cage: &Cage, profile: Option<&str>) -> Result<(), CageConfigError> {
+        for (key, value) in [
+            ("uuid", cage.uuid()),
+            ("app_uuid", cage.app_uuid()),
+            ("team_uuid", cage.team_uuid()),
+        ] {
+            self.set_value(&[key], &toml::Value::String(value.to_string()), profile)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every field of `config` into the document. Fields the config doesn't set are removed,
+    /// but keys the CLI doesn't know about are kept.
+    pub fn set_config(&mut self, config: &CageConfig) -> Result<(), CageConfigError> {
+        if self.document.as_table().is_empty() {
+            self.document = toml::to_string(config)?.parse()?;
+            return Ok(());
+        }
+        let serialized = serialize_table(config)?;
+        merge_table(self.document.as_table_mut(), serialized, &mut Vec::new());
+        Ok(())
+    }
+
+    /// Sets a single value, such as `["egress", "ports"]`, in the base config or the given profile.
+    /// Comments around the old value are kept.
+    pub fn set_value(
+        &mut self,
+        key_path: &[&str],
+        value: &toml::Value,
+        profile: Option<&str>,
+    ) -> Result<(), CageConfigError> {
+        let key_path = profile_path(profile, key_path);
+        let (key, parent_path) = key_path.split_last().expect("Key path must not be empty");
+        let mut serialized = serialize_table(&BTreeMap::from([(*key, value)]))?;
+        let new_item = serialized.remove(key).expect("Serialized value is missing");
+
+        let parent = self.table_at(parent_path)?;
+        match (parent.get_mut(key), new_item) {
+            (Some(Item::Value(existing_value)), Item::Value(mut new_value)) => {
+                *new_value.decor_mut() = existing_value.decor().clone();
+                *existing_value = new_value;
+            }
+            (_, new_item) => {
+                parent.insert(key, new_item);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_table<T: Serialize>(
+        &mut self,
+        table_path: &[&str],
+        value: &T,
+    ) -> Result<(), CageConfigError> {
+        let serialized = serialize_table(value)?;
+        let (key, parent_path) = table_path
+            .split_last()
+            .expect("Table path must not be empty");
+
+        let parent = self.table_at(parent_path)?;
+        match parent.get_mut(key) {
+            Some(Item::Table(existing)) => {
+                merge_table(existing, serialized, &mut table_path.to_vec())
+            }
+            Some(_) => {
+                return Err(CageConfigError::UnsupportedTableLayout(
+                    table_path.join("."),
+                ))
+            }
+            None => {
+                parent.insert(key, Item::Table(serialized));
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the table at `table_path`, creating any tables which don't exist yet
+    fn table_at(&mut self, table_path: &[&str]) -> Result<&mut Table, CageConfigError> {
+        let mut table = self.document.as_table_mut();
+        for (depth, key) in table_path.iter().enumerate() {
+            let item = table.entry(key).or_insert_with(|| {
+                // Only the tables which hold values are written as headers
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            });
+            table = item.as_table_mut().ok_or_else(|| {
+                CageConfigError::UnsupportedTableLayout(table_path[..=depth].join("."))
+            })?;
+        }
+        Ok(table)
+    }
+
+    /// Writes the document back to disk. Refuses to overwrite the file if it has changed since it was
+    /// loaded, rather than discarding someone else's edits.
+    pub fn save(&self) -> Result<(), CageConfigError> {
+        if read_if_exists(&self.path)? != self.original {
+            return Err(CageConfigError::ConfigChangedOnDisk(
+                self.path.display().to_string(),
+            ));
+        }
+        std::fs::write(&self.path, self.document.to_string())?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+fn profile_path<'a>(profile: Option<&'a str>, path: &[&'a str]) -> Vec<&'a str> {
+    match profile {
+        Some(profile) => ["profiles", profile].iter().chain(path).copied().collect(),
+        None => path.to_vec(),
+    }
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<String>, CageConfigError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Serializes with the same serializer used for cage.toml files written from scratch, so new keys
+// are formatted consistently with them
+fn serialize_table<T: Serialize>(value: &T) -> Result<Table, CageConfigError> {
+    let serialized = toml::to_string(value)?;
+    let document: DocumentMut = serialized.parse()?;
+    Ok(fresh_table(document.as_table()))
+}
+
+// Copies a table without its formatting or position, so it is laid out after the existing tables
+// when inserted into another document
+fn fresh_table(table: &Table) -> Table {
+    let mut fresh = Table::new();
+    for (key, item) in table.iter() {
+        let item = match item {
+            Item::Table(child) => Item::Table(fresh_table(child)),
+            Item::Value(value) => {
+                let mut value = value.clone();
+                value.decor_mut().clear();
+                Item::Value(value)
+            }
+            other => other.clone(),
+        };
+        fresh.insert(key, item);
+    }
+    fresh
+}
+
+fn merge_table(existing: &mut Table, new: Table, table_path: &mut Vec<&str>) {
+    // Remove fields which are no longer set, leaving keys the CLI doesn't know about in place
+    if let Some(known_keys) = known_keys(table_path) {
+        let stale_keys: Vec<String> = existing
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .filter(|key| known_keys.contains(&key.as_str()) && !new.contains_key(key))
+            .collect();
+        for key in stale_keys {
+            existing.remove(&key);
+        }
+    }
+
+    for (key, new_item) in new {
+        let key = key.as_str();
+        match (existing.get_mut(key), new_item) {
+            (Some(Item::Table(existing_table)), Item::Table(new_table)) => {
+                let key = known_keys(table_path)
+                    .and_then(|keys| keys.iter().find(|known| **known == key).copied());
+                match key {
+                    Some(key) => {
+                        table_path.push(key);
+                        merge_table(existing_table, new_table, table_path);
+                        table_path.pop();
+                    }
+                    None => *existing_table = new_table,
+                }
+            }
+            (Some(Item::Value(existing_value)), Item::Value(mut new_value)) => {
+                // Keep the whitespace and comments around the old value
+                *new_value.decor_mut() = existing_value.decor().clone();
+                *existing_value = new_value;
+            }
+            (Some(existing_item), new_item) => *existing_item = new_item,
+            (None, new_item) => {
+                existing.insert(key, new_item);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG_WITH_COMMENTS: &str = r#"# The name of the Cage
+name = "my-cage" # Must be unique within the app
+uuid = "cage_123"
+app_uuid = "app_123"
+team_uuid = "team_123"
+debug = false
+custom_key = "kept"
+
+# Egress is needed to reach the payments provider
+[egress]
+enabled = true
+destinations = ["api.example.com"]
+
+[signing]
+certPath = "./cert.pem"
+keyPath = "./key.pem"
+
+[attestation]
+HashAlgorithm = "Sha384 { ... }"
+PCR0 = "old0"
+PCR1 = "old1"
+PCR2 = "old2"
+PCR8 = "old8"
+"#;
+
+    fn measurements() -> EIFMeasurements {
+        serde_json::from_value(serde_json::json!({
+            "HashAlgorithm": "Sha384 { ... }",
+            "PCR0": "new0",
+            "PCR1": "new1",
+            "PCR2": "new2"
+        }))
+        .unwrap()
+    }
+
+    fn write_config(contents: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cage.toml");
+        std::fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_setting_attestation_only_touches_attestation_and_runtime() {
+        let (_dir, path) = write_config(CONFIG_WITH_COMMENTS);
+        let mut config_file = ConfigFile::load(&path).unwrap();
+        config_file.set_attestation(&measurements(), None).unwrap();
+        config_file
+            .set_runtime_info(
+                &RuntimeVersions::new("1.2.3".to_string(), "4.5.6".to_string()),
+                None,
+            )
+            .unwrap();
+        config_file.save().unwrap();
+
+        let expected = CONFIG_WITH_COMMENTS
+            .replace("old0", "new0")
+            .replace("old1", "new1")
+            .replace("old2", "new2")
+            .replace("PCR8 = \"old8\"\n", "")
+            + "\n[runtime]\ndata_plane_version = \"1.2.3\"\ninstaller_version = \"4.5.6\"\n";
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_setting_attestation_for_profile_only_touches_profile() {
+        let source = format!(
+            "{CONFIG_WITH_COMMENTS}\n# Production overrides\n[profiles.production]\nname = \"my-cage-prod\"\n"
+        );
+        let (_dir, path) = write_config(&source);
+        let mut config_file = ConfigFile::load(&path).unwrap();
+        config_file
+            .set_attestation(&measurements(), Some("production"))
+            .unwrap();
+        config_file.save().unwrap();
+
+        let updated = std::fs::read_to_string(&path).unwrap();
+        assert!(updated.starts_with(CONFIG_WITH_COMMENTS));
+        assert!(updated
+            .contains("# Production overrides\n[profiles.production]\nname = \"my-cage-prod\"\n"));
+        let config: CageConfig = toml::from_str(&updated).unwrap();
+        assert_eq!(config.attestation.as_ref().unwrap().pcrs().pcr0, "old0");
+        let production = config.with_profile("production").unwrap();
+        assert_eq!(production.name, "my-cage-prod");
+        assert_eq!(production.attestation.unwrap().pcrs().pcr0, "new0");
+    }
+
+    #[test]
+    fn test_setting_attestation_for_new_profile() {
+        let (_dir, path) = write_config(CONFIG_WITH_COMMENTS);
+        let mut config_file = ConfigFile::load(path).unwrap();
+        config_file
+            .set_attestation(&measurements(), Some("staging"))
+            .unwrap();
+
+        let updated = config_file.to_string();
+        assert!(updated.starts_with(CONFIG_WITH_COMMENTS));
+        assert!(updated.ends_with("\n[profiles.staging.attestation]\nHashAlgorithm = \"Sha384 { ... }\"\nPCR0 = \"new0\"\nPCR1 = \"new1\"\nPCR2 = \"new2\"\n"));
+    }
+
+    #[test]
+    fn test_setting_values_keeps_comments() {
+        let (_dir, path) = write_config(CONFIG_WITH_COMMENTS);
+        let mut config_file = ConfigFile::load(path).unwrap();
+        config_file
+            .set_value(&["name"], &toml::Value::String("renamed".to_string()), None)
+            .unwrap();
+        config_file
+            .set_value(
+                &["egress", "ports"],
+                &toml::Value::Array(vec![toml::Value::String("443".to_string())]),
+                None,
+            )
+            .unwrap();
+        config_file
+            .set_value(&["debug"], &toml::Value::Boolean(true), Some("staging"))
+            .unwrap();
+
+        let updated = config_file.to_string();
+        assert!(updated.contains("name = \"renamed\" # Must be unique within the app\n"));
+        assert!(updated.contains("destinations = [\"api.example.com\"]\nports = [\"443\"]\n"));
+        assert!(updated.contains("debug = false\n"));
+        assert!(updated.ends_with("\n[profiles.staging]\ndebug = true\n"));
+    }
+
+    #[test]
+    fn test_setting_config_keeps_comments_and_unknown_keys() {
+        let (_dir, path) = write_config(CONFIG_WITH_COMMENTS);
+        let mut config: CageConfig = toml::from_str(CONFIG_WITH_COMMENTS).unwrap();
+        config.name = "renamed-cage".to_string();
+        config.uuid = Some("cage_456".to_string());
+        config.attestation = None;
+
+        let mut config_file = ConfigFile::load(path).unwrap();
+        config_file.set_config(&config).unwrap();
+        let updated = config_file.to_string();
+
+        assert!(
+            updated.starts_with("# The name of the Cage\nname = \"renamed-cage\" # Must be unique")
+        );
+        assert!(updated.contains("uuid = \"cage_456\"\n"));
+        assert!(updated.contains("custom_key = \"kept\"\n"));
+        assert!(updated.contains("# Egress is needed to reach the payments provider\n[egress]"));
+        assert!(!updated.contains("[attestation]"));
+        let reparsed: CageConfig = toml::from_str(&updated).unwrap();
+        assert_eq!(reparsed.uuid.as_deref(), Some("cage_456"));
+        assert!(reparsed.api_key_auth);
+    }
+
+    #[test]
+    fn test_new_config_file_is_created() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cage.toml");
+        let config: CageConfig = toml::from_str(CONFIG_WITH_COMMENTS).unwrap();
+
+        let mut config_file = ConfigFile::load(&path).unwrap();
+        config_file.set_config(&config).unwrap();
+        config_file.save().unwrap();
+
+        let written: CageConfig = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written.name, "my-cage");
+        assert_eq!(written.egress.destinations, config.egress.destinations);
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite_changed_file() {
+        let (_dir, path) = write_config(CONFIG_WITH_COMMENTS);
+        let mut config_file = ConfigFile::load(&path).unwrap();
+        config_file.set_attestation(&measurements(), None).unwrap();
+
+        std::fs::write(
+            &path,
+            CONFIG_WITH_COMMENTS.replace("debug = false", "debug = true"),
+        )
+        .unwrap();
+        assert!(matches!(
+            config_file.save(),
+            Err(CageConfigError::ConfigChangedOnDisk(_))
+        ));
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("debug = true"));
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite_file_created_after_loading() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cage.toml");
+        let mut config_file = ConfigFile::load(&path).unwrap();
+        config_file.set_attestation(&measurements(), None).unwrap();
+
+        std::fs::write(&path, CONFIG_WITH_COMMENTS).unwrap();
+        assert!(matches!(
+            config_file.save(),
+            Err(CageConfigError::ConfigChangedOnDisk(_))
+        ));
+    }
+}
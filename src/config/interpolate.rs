@@ -0,0 +1,180 @@
+use super::CageConfigError;
+use toml::Value;
+
+/// Resolves `${VAR}` and `${VAR:-default}` references in every string in `value`, using `lookup` to
+/// read variables. `$${` is left as a literal `${`.
+pub fn interpolate_value<F: Fn(&str) -> Option<String>>(
+    value: &mut Value,
+    key_path: &mut Vec<String>,
+    lookup: &F,
+) -> Result<(), CageConfigError> {
+    match value {
+        Value::String(string) => *string = interpolate(string, &key_path.join("."), lookup)?,
+        // Elements are reported against the key holding the list
+        Value::Array(values) => {
+            for value in values {
+                interpolate_value(value, key_path, lookup)?;
+            }
+        }
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                key_path.push(key.clone());
+                interpolate_value(value, key_path, lookup)?;
+                key_path.pop();
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Resolves the variable references in a single string, where `key` is the config key it's from
+pub fn interpolate<F: Fn(&str) -> Option<String>>(
+    input: &str,
+    key: &str,
+    lookup: &F,
+) -> Result<String, CageConfigError> {
+    let invalid = |reason: String| CageConfigError::InvalidInterpolation(key.to_string(), reason);
+
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let after_dollar = &rest[start + 1..];
+        if let Some(escaped) = after_dollar.strip_prefix("${") {
+            output.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(reference) = after_dollar.strip_prefix('{') else {
+            output.push('$');
+            rest = after_dollar;
+            continue;
+        };
+        let end = reference
+            .find('}')
+            .ok_or_else(|| invalid(format!("`${{{reference}` is missing a closing brace")))?;
+        let (name, default) = match reference[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&reference[..end], None),
+        };
+        if !is_valid_variable_name(name) {
+            return Err(invalid(format!(
+                "`{name}` is not a valid environment variable name"
+            )));
+        }
+
+        let value = match (lookup(name), default) {
+            // As in shells, an empty variable is replaced by the default
+            (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(CageConfigError::MissingEnvironmentVariable(
+                    name.to_string(),
+                    key.to_string(),
+                ))
+            }
+        };
+        output.push_str(&value);
+        rest = &reference[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn is_valid_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "CAGE_UUID" => Some("cage_123".to_string()),
+            "KEYS_DIR" => Some("/ci/keys".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn interpolate_str(input: &str) -> Result<String, CageConfigError> {
+        interpolate(input, "signing.keyPath", &lookup)
+    }
+
+    #[test]
+    fn test_variables_are_replaced() {
+        assert_eq!(
+            interpolate_str("${KEYS_DIR}/key.pem").unwrap(),
+            "/ci/keys/key.pem"
+        );
+        assert_eq!(
+            interpolate_str("${CAGE_UUID}-${KEYS_DIR}").unwrap(),
+            "cage_123-/ci/keys"
+        );
+        assert_eq!(interpolate_str("no variables").unwrap(), "no variables");
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(
+            interpolate_str("${MISSING:-./key.pem}").unwrap(),
+            "./key.pem"
+        );
+        assert_eq!(interpolate_str("${EMPTY:-fallback}").unwrap(), "fallback");
+        assert_eq!(interpolate_str("${MISSING:-}").unwrap(), "");
+        assert_eq!(
+            interpolate_str("${KEYS_DIR:-./keys}/key.pem").unwrap(),
+            "/ci/keys/key.pem"
+        );
+    }
+
+    #[test]
+    fn test_literal_dollars() {
+        assert_eq!(interpolate_str("$${KEYS_DIR}").unwrap(), "${KEYS_DIR}");
+        assert_eq!(interpolate_str("cost: $5").unwrap(), "cost: $5");
+        assert_eq!(interpolate_str("trailing $").unwrap(), "trailing $");
+    }
+
+    #[test]
+    fn test_missing_variables_and_invalid_references() {
+        assert!(matches!(
+            interpolate_str("${MISSING}/key.pem"),
+            Err(CageConfigError::MissingEnvironmentVariable(name, key))
+                if name == "MISSING" && key == "signing.keyPath"
+        ));
+        assert!(matches!(
+            interpolate_str("${KEYS_DIR/key.pem"),
+            Err(CageConfigError::InvalidInterpolation(..))
+        ));
+        assert!(matches!(
+            interpolate_str("${1DIR}"),
+            Err(CageConfigError::InvalidInterpolation(..))
+        ));
+    }
+
+    #[test]
+    fn test_values_are_interpolated_recursively() {
+        let source = r#"uuid = "${CAGE_UUID}"
+[egress]
+destinations = ["api.example.com", "${MISSING:-*.example.com}"]
+"#;
+        let mut value: Value = toml::from_str(source).unwrap();
+        interpolate_value(&mut value, &mut Vec::new(), &lookup).unwrap();
+        assert_eq!(value["uuid"].as_str(), Some("cage_123"));
+        assert_eq!(
+            value["egress"]["destinations"][1].as_str(),
+            Some("*.example.com")
+        );
+
+        let mut value: Value = toml::from_str(&source.replace(":-*.example.com", "")).unwrap();
+        assert!(matches!(
+            interpolate_value(&mut value, &mut Vec::new(), &lookup),
+            Err(CageConfigError::MissingEnvironmentVariable(_, key)) if key == "egress.destinations"
+        ));
+    }
+}
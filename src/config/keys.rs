@@ -0,0 +1,475 @@
+use super::validate::{is_valid_destination, is_valid_port, known_keys};
+use super::{convert_comma_list, CageConfig, CageConfigError, EgressSettings};
+use toml::Value;
+
+// Tables which are written by the CLI itself, rather than edited by hand
+const MANAGED_TABLES: &[&str] = &["attestation", "runtime", "profiles"];
+
+/// A dotted path to a value in the cage.toml, such as `egress.destinations`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigKey {
+    path: Vec<&'static str>,
+}
+
+impl std::str::FromStr for ConfigKey {
+    type Err = CageConfigError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let mut path = Vec::new();
+        for segment in key.split('.') {
+            // Keys below a value are reported against the table holding the value
+            let table = match known_keys(&path) {
+                Some(_) => &path[..],
+                None => &path[..path.len() - 1],
+            };
+            let unknown_key = || {
+                let expected: Vec<String> = known_keys(table)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|known| !MANAGED_TABLES.contains(known))
+                    .map(|known| {
+                        table
+                            .iter()
+                            .chain([known])
+                            .copied()
+                            .collect::<Vec<_>>()
+                            .join(".")
+                    })
+                    .collect();
+                CageConfigError::UnknownConfigKey(key.to_string(), expected.join(", "))
+            };
+            let segment = known_keys(&path)
+                .and_then(|known_keys| known_keys.iter().find(|known| **known == segment))
+                .copied()
+                .ok_or_else(unknown_key)?;
+            path.push(segment);
+        }
+        Ok(Self { path })
+    }
+}
+
+impl std::fmt::Display for ConfigKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.join("."))
+    }
+}
+
+impl ConfigKey {
+    pub fn path(&self) -> &[&'static str] {
+        &self.path
+    }
+
+    fn is_table(&self) -> bool {
+        known_keys(&self.path).is_some()
+    }
+
+    fn is_managed(&self) -> bool {
+        MANAGED_TABLES.contains(&self.path[0])
+    }
+}
+
+/// A change to make to the value of a key using `config set`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// Replace the value. Lists can be given as comma separated values, as with `init`.
+    Set(String),
+    /// Add values to a list, skipping any already in it
+    Add(Vec<String>),
+    /// Remove values from a list
+    Remove(Vec<String>),
+}
+
+/// Returns the value of `key` in the config, or None if it isn't set
+pub fn get_value(config: &CageConfig, key: &ConfigKey) -> Result<Option<Value>, CageConfigError> {
+    let serialized = Value::try_from(config)?;
+    Ok(key
+        .path
+        .iter()
+        .try_fold(&serialized, |value, segment| value.get(segment))
+        .cloned())
+}
+
+/// Applies a change to the config, checking the new value against the type of the field it's set on.
+/// Egress settings are completed in the same way as with `init`, so setting destinations enables egress.
+pub fn apply_change(
+    config: &CageConfig,
+    key: &ConfigKey,
+    change: &ConfigChange,
+) -> Result<CageConfig, CageConfigError> {
+    if key.is_managed() {
+        return Err(CageConfigError::ManagedConfigKey(key.to_string()));
+    }
+    if key.is_table() {
+        return Err(CageConfigError::ConfigKeyIsTable(key.to_string()));
+    }
+
+    let mut updated = match change {
+        ConfigChange::Set(value) => set_value(config, key, value)?,
+        ConfigChange::Add(values) | ConfigChange::Remove(values) => {
+            let mut list = match get_value(config, key)? {
+                Some(Value::Array(list)) => list,
+                Some(_) => return Err(CageConfigError::ConfigKeyIsNotList(key.to_string())),
+                None => Vec::new(),
+            };
+            for value in values {
+                let value = Value::String(value.clone());
+                let existing = list.iter().position(|item| *item == value);
+                match (change, existing) {
+                    (ConfigChange::Add(_), None) => list.push(value),
+                    (ConfigChange::Remove(_), Some(index)) => {
+                        list.remove(index);
+                    }
+                    _ => {}
+                }
+            }
+            with_value(config, key, Value::Array(list)).map_err(|e| match e {
+                CageConfigError::InvalidConfigValue(..) => {
+                    CageConfigError::ConfigKeyIsNotList(key.to_string())
+                }
+                e => e,
+            })?
+        }
+    };
+
+    if key.path[0] == "egress" {
+        check_egress_values(&updated.egress)?;
+        // Turning egress off is the only change that shouldn't be completed with the defaults
+        if updated.egress.enabled || key.path != ["egress", "enabled"] {
+            let egress = updated.egress;
+            updated.egress = EgressSettings::new(egress.ports, egress.destinations, egress.enabled);
+        }
+    }
+    Ok(updated)
+}
+
+/// Returns the values which differ between the two configs below the top level key of `key`, so that
+/// only those are written back to the cage.toml
+pub fn changed_values(
+    config: &CageConfig,
+    updated: &CageConfig,
+    key: &ConfigKey,
+) -> Result<Vec<(Vec<&'static str>, Value)>, CageConfigError> {
+    let table_key = &key.path[..1];
+    let table_key = ConfigKey {
+        path: table_key.to_vec(),
+    };
+    let old = get_value(config, &table_key)?;
+    let new = get_value(updated, &table_key)?;
+
+    let mut changes = Vec::new();
+    match (old, new) {
+        (Some(Value::Table(old)), Some(Value::Table(new))) => {
+            let known_keys = known_keys(&table_key.path).unwrap_or_default();
+            for known_key in known_keys {
+                match new.get(*known_key) {
+                    Some(value) if old.get(*known_key) != Some(value) => {
+                        changes.push((vec![table_key.path[0], *known_key], value.clone()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (old, Some(new)) if old.as_ref() != Some(&new) => changes.push((table_key.path, new)),
+        _ => {}
+    }
+    Ok(changes)
+}
+
+fn set_value(
+    config: &CageConfig,
+    key: &ConfigKey,
+    value: &str,
+) -> Result<CageConfig, CageConfigError> {
+    // Values are tried as TOML, such as `true` or `["443"]`, then as a string, then as a comma
+    // separated list. The string error is reported, as it describes the type the field expects.
+    let mut candidates = Vec::new();
+    if let Ok(Value::Table(mut parsed)) = format!("value = {value}").parse::<Value>() {
+        candidates.extend(parsed.remove("value"));
+    }
+    candidates.push(Value::String(value.to_string()));
+    candidates.extend(
+        convert_comma_list(Some(value.to_string()))
+            .map(|values| Value::Array(values.into_iter().map(Value::String).collect())),
+    );
+
+    let mut string_error = None;
+    for candidate in candidates {
+        let is_string = candidate.is_str();
+        match with_value(config, key, candidate) {
+            Ok(updated) => return Ok(updated),
+            Err(e) if is_string => string_error = Some(e),
+            Err(_) => {}
+        }
+    }
+    Err(string_error.expect("A string candidate is always tried"))
+}
+
+// Returns the config with `value` at `key`, if the value has the right type for the field
+fn with_value(
+    config: &CageConfig,
+    key: &ConfigKey,
+    value: Value,
+) -> Result<CageConfig, CageConfigError> {
+    let mut serialized = Value::try_from(config)?;
+    let (field, parents) = key.path.split_last().expect("Keys are never empty");
+    let mut table = &mut serialized;
+    for parent in parents {
+        table = table
+            .as_table_mut()
+            .expect("Parents of known keys are tables")
+            .entry(parent.to_string())
+            .or_insert_with(|| Value::Table(Default::default()));
+    }
+    table
+        .as_table_mut()
+        .expect("Parents of known keys are tables")
+        .insert(field.to_string(), value);
+
+    let mut updated: CageConfig = serialized.try_into().map_err(|e: toml::de::Error| {
+        CageConfigError::InvalidConfigValue(key.to_string(), e.to_string())
+    })?;
+    updated.active_profile = config.active_profile.clone();
+    Ok(updated)
+}
+
+// Values which reference environment variables are checked by config validate, once they're resolved
+fn is_interpolated(value: &str) -> bool {
+    value.contains("${")
+}
+
+fn check_egress_values(egress: &EgressSettings) -> Result<(), CageConfigError> {
+    if let Some(port) = egress
+        .ports
+        .iter()
+        .flatten()
+        .find(|port| !is_interpolated(port) && !is_valid_port(port))
+    {
+        return Err(CageConfigError::InvalidConfigValue(
+            "egress.ports".to_string(),
+            format!(
+                "`{port}` is not a valid egress port. Ports must be numbers between 1 and 65535"
+            ),
+        ));
+    }
+    if let Some(destination) = egress
+        .destinations
+        .iter()
+        .flatten()
+        .find(|destination| !is_interpolated(destination) && !is_valid_destination(destination))
+    {
+        return Err(CageConfigError::InvalidConfigValue(
+            "egress.destinations".to_string(),
+            format!("`{destination}` is not a valid egress destination. Destinations must be hostnames such as api.example.com, wildcard domains such as *.example.com, or * to allow all"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"name = "my-cage"
+uuid = "cage_123"
+debug = false
+
+[egress]
+enabled = false
+
+[signing]
+certPath = "./cert.pem"
+keyPath = "./key.pem"
+"#;
+
+    fn config() -> CageConfig {
+        toml::from_str(CONFIG).unwrap()
+    }
+
+    fn key(key: &str) -> ConfigKey {
+        key.parse().unwrap()
+    }
+
+    fn set(key_name: &str, value: &str) -> Result<CageConfig, CageConfigError> {
+        apply_change(
+            &config(),
+            &key(key_name),
+            &ConfigChange::Set(value.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_parsing_keys() {
+        assert_eq!(
+            key("egress.destinations").path(),
+            ["egress", "destinations"]
+        );
+        assert_eq!(key("signing.certPath").path(), ["signing", "certPath"]);
+        assert!(matches!(
+            "egress.destination".parse::<ConfigKey>(),
+            Err(CageConfigError::UnknownConfigKey(_, expected))
+                if expected == "egress.enabled, egress.destinations, egress.ports"
+        ));
+        assert!("debug.enabled".parse::<ConfigKey>().is_err());
+    }
+
+    #[test]
+    fn test_getting_values() {
+        assert_eq!(
+            get_value(&config(), &key("name")).unwrap(),
+            Some(Value::String("my-cage".to_string()))
+        );
+        assert_eq!(get_value(&config(), &key("app_uuid")).unwrap(), None);
+        assert!(get_value(&config(), &key("egress"))
+            .unwrap()
+            .unwrap()
+            .is_table());
+    }
+
+    #[test]
+    fn test_values_are_type_checked() {
+        assert!(
+            set("forward_proxy_protocol", "true")
+                .unwrap()
+                .forward_proxy_protocol
+        );
+        assert_eq!(set("name", "123").unwrap().name, "123");
+        assert!(matches!(
+            set("debug", "yes"),
+            Err(CageConfigError::InvalidConfigValue(key, message))
+                if key == "debug" && message.contains("expected a boolean")
+        ));
+    }
+
+    #[test]
+    fn test_setting_lists_completes_egress_settings() {
+        let updated = set("egress.destinations", "api.example.com,*.evervault.com").unwrap();
+        assert!(updated.egress.enabled);
+        assert_eq!(
+            updated.egress.destinations,
+            Some(vec![
+                "api.example.com".to_string(),
+                "*.evervault.com".to_string()
+            ])
+        );
+        assert_eq!(updated.egress.ports, Some(vec!["443".to_string()]));
+
+        let updated = set("egress.ports", r#"["443", "8443"]"#).unwrap();
+        assert_eq!(updated.egress.destinations, Some(vec!["*".to_string()]));
+        assert!(matches!(
+            set("egress.ports", "443,70000"),
+            Err(CageConfigError::InvalidConfigValue(..))
+        ));
+    }
+
+    #[test]
+    fn test_disabling_egress() {
+        let enabled = set("egress.destinations", "api.example.com").unwrap();
+        let disabled = apply_change(
+            &enabled,
+            &key("egress.enabled"),
+            &ConfigChange::Set("false".to_string()),
+        )
+        .unwrap();
+        assert!(!disabled.egress.enabled);
+    }
+
+    #[test]
+    fn test_adding_and_removing_list_values() {
+        let destinations = key("egress.destinations");
+        let updated = apply_change(
+            &config(),
+            &destinations,
+            &ConfigChange::Add(vec![
+                "a.example.com".to_string(),
+                "b.example.com".to_string(),
+            ]),
+        )
+        .unwrap();
+        let updated = apply_change(
+            &updated,
+            &destinations,
+            &ConfigChange::Add(vec!["a.example.com".to_string()]),
+        )
+        .unwrap();
+        let updated = apply_change(
+            &updated,
+            &destinations,
+            &ConfigChange::Remove(vec!["b.example.com".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(
+            updated.egress.destinations,
+            Some(vec!["a.example.com".to_string()])
+        );
+
+        assert!(matches!(
+            apply_change(
+                &config(),
+                &key("name"),
+                &ConfigChange::Add(vec!["a".to_string()])
+            ),
+            Err(CageConfigError::ConfigKeyIsNotList(_))
+        ));
+    }
+
+    #[test]
+    fn test_variable_references_are_kept() {
+        let config = apply_change(
+            &config(),
+            &key("egress.destinations"),
+            &ConfigChange::Set("${API_HOST}".to_string()),
+        )
+        .unwrap();
+        let updated = apply_change(
+            &config,
+            &key("egress.destinations"),
+            &ConfigChange::Add(vec!["api.example.com".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(
+            updated.egress.destinations,
+            Some(vec![
+                "${API_HOST}".to_string(),
+                "api.example.com".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tables_and_managed_keys_cant_be_set() {
+        assert!(matches!(
+            set("egress", "true"),
+            Err(CageConfigError::ConfigKeyIsTable(_))
+        ));
+        assert!(matches!(
+            set("attestation.PCR0", "000"),
+            Err(CageConfigError::ManagedConfigKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_changed_values_only_include_changes() {
+        let config = config();
+        let destinations = key("egress.destinations");
+        let updated = apply_change(
+            &config,
+            &destinations,
+            &ConfigChange::Set("api.example.com".to_string()),
+        )
+        .unwrap();
+        let changes = changed_values(&config, &updated, &destinations).unwrap();
+        let changed_keys: Vec<_> = changes.iter().map(|(path, _)| path.join(".")).collect();
+        assert_eq!(
+            changed_keys,
+            vec!["egress.enabled", "egress.destinations", "egress.ports"]
+        );
+
+        let name = key("name");
+        let updated =
+            apply_change(&config, &name, &ConfigChange::Set("renamed".to_string())).unwrap();
+        assert_eq!(
+            changed_values(&config, &updated, &name).unwrap(),
+            vec![(vec!["name"], Value::String("renamed".to_string()))]
+        );
+    }
+}
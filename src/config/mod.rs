@@ -1,16 +1,37 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 
 use crate::cert::{get_cert_validity_period, CertValidityPeriod};
 
 use super::common::CliError;
 use super::enclave::{EIFMeasurements, EnclaveSigningInfo};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+pub mod edit;
+pub mod interpolate;
+pub mod keys;
+pub mod schema;
+pub mod validate;
+
+pub const PROFILE_ENV_VAR: &str = "EV_CONFIG_PROFILE";
+
+/// The cage.toml profile selected using `--config-profile` or EV_CONFIG_PROFILE
+pub fn selected_profile_name() -> Option<String> {
+    std::env::var(PROFILE_ENV_VAR)
+        .ok()
+        .filter(|profile| !profile.is_empty())
+}
+
+/// Controls the traffic the Cage is allowed to send out of the enclave
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct EgressSettings {
+    /// Allow the Cage to make outbound requests
     pub enabled: bool,
+    /// Hostnames the Cage can reach, such as api.example.com, *.example.com, or * to allow all
     pub destinations: Option<Vec<String>>,
+    /// Ports the Cage can make outbound requests on
     pub ports: Option<Vec<String>>,
 }
 
@@ -53,12 +74,19 @@ impl EgressSettings {
     }
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The credentials used to sign the Cage's enclave image
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 pub struct SigningInfo {
+    /// Path to the signing cert
     #[serde(rename = "certPath")]
     pub cert: Option<String>,
+    /// Path to the signing key
     #[serde(rename = "keyPath")]
     pub key: Option<String>,
+    /// Lock the Cage to the signing cert with apply, so deployments signed by any other cert fail.
+    /// Set to false to unlock it from every cert. Locked certs are left as they are if not set.
+    #[serde(rename = "lockCert")]
+    pub lock_cert: Option<bool>,
 }
 
 impl SigningInfo {
@@ -95,9 +123,46 @@ impl CliError for SigningInfoError {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// A value in the `[env]` table
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvValue {
+    /// A plain environment variable
+    Plain(String),
+    /// An environment variable which can be marked as a secret
+    Detailed(EnvVarSettings),
+}
+
+/// An environment variable in the `[env]` table, written as an inline table
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EnvVarSettings {
+    /// Value of the variable. Use a `${VAR}` reference to keep secrets out of the file.
+    pub value: String,
+    /// Encrypt the value before it's stored with the Cage
+    #[serde(default)]
+    pub secret: bool,
+}
+
+impl EnvValue {
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Plain(value) => value,
+            Self::Detailed(settings) => &settings.value,
+        }
+    }
+
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Self::Detailed(settings) if settings.secret)
+    }
+}
+
+/// Versions of the Cage runtime used in the last build, written by build
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct RuntimeVersions {
+    /// Version of the data plane which runs alongside the user application in the enclave
     pub data_plane_version: String,
+    /// Version of the installer used to set up the enclave
     pub installer_version: String,
 }
 
@@ -186,7 +251,7 @@ pub enum CageConfigError {
     MissingConfigFile(String),
     #[error("Failed to read config file — {0:?}")]
     FailedToAccessConfig(#[from] std::io::Error),
-    #[error("Failed to parse Cage config")]
+    #[error("Failed to parse Cage config — {0}")]
     FailedToParseCageConfig(#[from] toml::de::Error),
     #[error("{0}. Signing credentials can be generated using the cert new command.")]
     MissingSigningInfo(#[from] SigningInfoError),
@@ -196,6 +261,32 @@ pub enum CageConfigError {
     MissingField(String),
     #[error("TLS Termination must be enabled to enable cage logging.")]
     LoggingEnabledWithoutTLSTermination(),
+    #[error("Failed to parse Cage config for editing — {0}")]
+    FailedToParseConfigDocument(#[from] toml_edit::TomlError),
+    #[error("Failed to serialize Cage config — {0}")]
+    FailedToSerializeCageConfig(#[from] toml::ser::Error),
+    #[error("No profile named {0} in the Cage config. Available profiles: {1}")]
+    ProfileNotFound(String, String),
+    #[error("Could not update {0} in the Cage config, as it is not a standard table")]
+    UnsupportedTableLayout(String),
+    #[error("{0} was changed by something else after it was read, so it has not been overwritten. Re-run the command to apply the changes.")]
+    ConfigChangedOnDisk(String),
+    #[error("Unknown config key `{0}`. Expected one of {1}")]
+    UnknownConfigKey(String, String),
+    #[error("`{0}` is a table. Set one of its keys instead, such as `{0}.enabled`")]
+    ConfigKeyIsTable(String),
+    #[error("`{0}` can't be changed using config set. Attestation and runtime values are written by build and deploy, and profiles are changed by passing --config-profile.")]
+    ManagedConfigKey(String),
+    #[error("`{0}` is not a list, so values can't be added to or removed from it")]
+    ConfigKeyIsNotList(String),
+    #[error("Invalid value for `{0}` — {1}")]
+    InvalidConfigValue(String, String),
+    #[error("`{0}` is not set in the Cage config")]
+    ConfigKeyNotSet(String),
+    #[error("The environment variable {0} used by `{1}` in the Cage config is not set. Set it, or give a default using ${{{0}:-default}}")]
+    MissingEnvironmentVariable(String, String),
+    #[error("Invalid environment variable reference in `{0}` — {1}. Use $${{ for a literal ${{")]
+    InvalidInterpolation(String, String),
 }
 
 impl CliError for CageConfigError {
@@ -205,12 +296,46 @@ impl CliError for CageConfigError {
             Self::FailedToParseCageConfig(_)
             | Self::MissingDockerfile
             | Self::MissingField(_)
-            | Self::LoggingEnabledWithoutTLSTermination() => exitcode::DATAERR,
+            | Self::LoggingEnabledWithoutTLSTermination()
+            | Self::FailedToParseConfigDocument(_)
+            | Self::ProfileNotFound(..)
+            | Self::UnsupportedTableLayout(_) => exitcode::DATAERR,
+            Self::FailedToSerializeCageConfig(_) => exitcode::SOFTWARE,
+            Self::ConfigChangedOnDisk(_) => exitcode::TEMPFAIL,
+            Self::UnknownConfigKey(..)
+            | Self::ConfigKeyIsTable(_)
+            | Self::ManagedConfigKey(_)
+            | Self::ConfigKeyIsNotList(_) => exitcode::USAGE,
+            Self::InvalidConfigValue(..)
+            | Self::ConfigKeyNotSet(_)
+            | Self::InvalidInterpolation(..) => exitcode::DATAERR,
+            Self::MissingEnvironmentVariable(..) => exitcode::CONFIG,
             Self::MissingSigningInfo(signing_err) => signing_err.exitcode(),
         }
     }
 }
 
+fn merge_toml_values(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge_toml_values(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Splits a comma separated list from the command line, such as `--egress-ports 443,8443`
+pub fn convert_comma_list(maybe_str: Option<String>) -> Option<Vec<String>> {
+    maybe_str.map(|str| str.split(',').map(|value| value.to_string()).collect())
+}
+
 pub fn default_dockerfile() -> String {
     "./Dockerfile".to_string()
 }
@@ -219,28 +344,55 @@ pub fn default_true() -> bool {
     true
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// The config for a Cage, stored in its cage.toml
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct CageConfig {
+    /// Name of the Cage
     pub name: String,
+    /// UUID of the Cage, set by init
     pub uuid: Option<String>,
+    /// UUID of the App the Cage belongs to, set by init
     pub app_uuid: Option<String>,
+    /// UUID of the team the Cage belongs to, set by init
     pub team_uuid: Option<String>,
+    /// Run the Cage in debug mode. Every PCR in the attestation document is 0 in debug mode.
     pub debug: bool,
+    /// Path to the Dockerfile used to build the Cage
     #[serde(default = "default_dockerfile")]
     pub dockerfile: String,
+    /// Require an Evervault API key on requests to the Cage
     #[serde(default = "default_true")]
     pub api_key_auth: bool,
+    /// Record transaction logs for requests to the Cage. Requires TLS termination.
     #[serde(default = "default_true")]
     pub trx_logging: bool,
+    /// Pass raw TCP streams to the Cage's service instead of terminating TLS in the enclave
     #[serde(default)]
     pub disable_tls_termination: bool,
+    /// Forward proxy protocol headers to the Cage's service when TLS termination is disabled
     #[serde(default)]
     pub forward_proxy_protocol: bool,
+    /// Port the data plane forwards traffic to. Defaults to the last port exposed in the Dockerfile.
+    pub forward_port: Option<u16>,
     // Table configs
+    /// Outbound traffic settings
     pub egress: EgressSettings,
+    /// Credentials used to sign the enclave image. Generated by init if not given.
     pub signing: Option<SigningInfo>,
+    /// PCRs of the last enclave image built. Clients compare these against the Cage's attestation document.
     pub attestation: Option<EIFMeasurements>,
+    /// Runtime versions used in the last build, so it can be reproduced
     pub runtime: Option<RuntimeVersions>,
+    /// Environment variables for the Cage, set by apply. Variables which aren't listed are deleted
+    /// from the Cage, while the Cage's environment is left as it is if the table isn't set.
+    pub env: Option<BTreeMap<String, EnvValue>>,
+    /// Named overlays, such as `[profiles.staging]`, which override any of the fields above
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(schema_with = "schema::profiles_schema")]
+    pub profiles: BTreeMap<String, toml::value::Table>,
+    /// The profile merged into this config, if any
+    #[serde(skip)]
+    pub active_profile: Option<String>,
 }
 
 impl CageConfig {
@@ -274,6 +426,7 @@ pub struct ValidatedCageBuildConfig {
     pub trx_logging_enabled: bool,
     pub runtime: Option<RuntimeVersions>,
     pub forward_proxy_protocol: bool,
+    pub forward_port: Option<u16>,
 }
 
 impl ValidatedCageBuildConfig {
@@ -334,6 +487,10 @@ impl ValidatedCageBuildConfig {
     pub fn forward_proxy_protocol(&self) -> bool {
         self.forward_proxy_protocol
     }
+
+    pub fn forward_port(&self) -> Option<u16> {
+        self.forward_port
+    }
 }
 
 impl CageConfig {
@@ -381,7 +538,36 @@ impl CageConfig {
         self.runtime = Some(runtime.clone());
     }
 
+    /// Returns the config with its relative Dockerfile and signing paths resolved against `dir`,
+    /// rather than the current directory
+    pub fn relative_to(&self, dir: &Path) -> Self {
+        let resolve = |path: &str| match Path::new(path) {
+            path if path.is_absolute() => path.display().to_string(),
+            path => dir
+                .join(path.strip_prefix(".").unwrap_or(path))
+                .display()
+                .to_string(),
+        };
+        let mut config = self.clone();
+        config.dockerfile = resolve(&self.dockerfile);
+        if let Some(signing) = config.signing.as_mut() {
+            signing.cert = signing.cert.as_deref().map(resolve);
+            signing.key = signing.key.as_deref().map(resolve);
+        }
+        config
+    }
+
+    /// Reads the config, merging in the profile selected with `--config-profile` and resolving
+    /// `${VAR}` references
     pub fn try_from_filepath(path: &str) -> Result<Self, CageConfigError> {
+        Self::read_uninterpolated(path)?
+            .with_selected_profile(selected_profile_name().as_deref())?
+            .interpolated()
+    }
+
+    /// Reads the config as it's written in the file, leaving `${VAR}` references in place. Used when
+    /// values will be written back, so the resolved values never end up in the file.
+    pub fn read_uninterpolated(path: &str) -> Result<Self, CageConfigError> {
         let config_path = std::path::Path::new(path);
         if !config_path.exists() {
             return Err(CageConfigError::MissingConfigFile(path.to_string()));
@@ -391,6 +577,51 @@ impl CageConfig {
         Ok(toml::de::from_slice(cage_config_content.as_slice())?)
     }
 
+    /// Returns the config with `${VAR}` and `${VAR:-default}` references in its strings replaced by
+    /// the values of the environment variables. Profiles are left as they are, as their variables may
+    /// only be set where they're used, so the selected profile should be merged in first.
+    pub fn interpolated(&self) -> Result<Self, CageConfigError> {
+        let mut base = self.clone();
+        let profiles = std::mem::take(&mut base.profiles);
+        let mut value = toml::Value::try_from(&base)?;
+        interpolate::interpolate_value(&mut value, &mut Vec::new(), &|name| {
+            std::env::var(name).ok()
+        })?;
+        let mut interpolated: Self = value.try_into()?;
+        interpolated.profiles = profiles;
+        interpolated.active_profile = self.active_profile.clone();
+        Ok(interpolated)
+    }
+
+    /// Merges in the profile selected with `--config-profile`, if any
+    pub fn with_selected_profile(self, profile: Option<&str>) -> Result<Self, CageConfigError> {
+        match profile {
+            Some(profile) => self.with_profile(profile),
+            None => Ok(self),
+        }
+    }
+
+    /// Returns the config with the named profile merged over the base config. Tables are merged key
+    /// by key, while any other value in the profile replaces the base value.
+    pub fn with_profile(&self, profile: &str) -> Result<Self, CageConfigError> {
+        let overlay = self.profiles.get(profile).cloned().ok_or_else(|| {
+            CageConfigError::ProfileNotFound(
+                profile.to_string(),
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", "),
+            )
+        })?;
+
+        let mut base = self.clone();
+        let profiles = std::mem::take(&mut base.profiles);
+        let mut merged = toml::Value::try_from(&base)?;
+        merge_toml_values(&mut merged, toml::Value::Table(overlay));
+
+        let mut merged: Self = merged.try_into()?;
+        merged.profiles = profiles;
+        merged.active_profile = Some(profile.to_string());
+        Ok(merged)
+    }
+
     pub fn get_cage_domain(&self) -> Result<String, CageConfigError> {
         if self.uuid.is_none() {
             return Err(CageConfigError::MissingField("cage_uuid".to_string()));
@@ -454,6 +685,7 @@ impl std::convert::TryFrom<&CageConfig> for ValidatedCageBuildConfig {
             trx_logging_enabled,
             runtime: config.runtime.clone(),
             forward_proxy_protocol: config.forward_proxy_protocol,
+            forward_port: config.forward_port,
         })
     }
 }
@@ -507,7 +739,8 @@ pub fn read_and_validate_config<B: BuildTimeConfig>(
 
 #[cfg(test)]
 mod test {
-    use super::{BuildTimeConfig, CageConfig};
+    use super::{BuildTimeConfig, CageConfig, CageConfigError};
+    use std::path::Path;
 
     struct ExampleArgs {
         cert: String,
@@ -549,7 +782,11 @@ mod test {
             api_key_auth: true,
             trx_logging: true,
             forward_proxy_protocol: false,
+            forward_port: None,
             runtime: None,
+            env: None,
+            profiles: Default::default(),
+            active_profile: None,
         };
 
         let test_args = ExampleArgs {
@@ -564,4 +801,124 @@ mod test {
         assert_eq!(merged.cert().unwrap(), test_args.certificate().unwrap());
         assert_eq!(merged.key().unwrap(), test_args.private_key().unwrap());
     }
+
+    const CONFIG_WITH_PROFILES: &str = r#"name = "my-cage"
+uuid = "cage_123"
+debug = true
+
+[egress]
+enabled = true
+destinations = ["api.example.com"]
+ports = ["443"]
+
+[signing]
+certPath = "./cert.pem"
+keyPath = "./key.pem"
+
+[profiles.production]
+name = "my-cage-prod"
+uuid = "cage_456"
+debug = false
+
+[profiles.production.egress]
+destinations = ["api.example.com", "payments.example.com"]
+"#;
+
+    #[test]
+    fn profile_overrides_base_config() {
+        let config: CageConfig = toml::from_str(CONFIG_WITH_PROFILES).unwrap();
+        let production = config.with_profile("production").unwrap();
+
+        assert_eq!(production.name, "my-cage-prod");
+        assert_eq!(production.uuid.as_deref(), Some("cage_456"));
+        assert!(!production.debug);
+        // Tables are merged, while lists replace the base value
+        assert!(production.egress.enabled);
+        assert_eq!(production.egress.ports, Some(vec!["443".to_string()]));
+        assert_eq!(
+            production.egress.destinations,
+            Some(vec![
+                "api.example.com".to_string(),
+                "payments.example.com".to_string()
+            ])
+        );
+        assert_eq!(production.cert(), Some("./cert.pem"));
+        assert_eq!(production.active_profile.as_deref(), Some("production"));
+        assert_eq!(production.profiles, config.profiles);
+    }
+
+    #[test]
+    fn environment_variables_are_only_resolved_when_reading_for_use() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("cage.toml");
+        std::fs::write(
+            &path,
+            CONFIG_WITH_PROFILES.replace(
+                "keyPath = \"./key.pem\"",
+                "keyPath = \"${EV_CAGE_TEST_UNSET_KEYS_DIR:-./keys}/key.pem\"",
+            ),
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = CageConfig::try_from_filepath(path).unwrap();
+        assert_eq!(config.key(), Some("./keys/key.pem"));
+        let config = CageConfig::read_uninterpolated(path).unwrap();
+        assert_eq!(
+            config.key(),
+            Some("${EV_CAGE_TEST_UNSET_KEYS_DIR:-./keys}/key.pem")
+        );
+    }
+
+    #[test]
+    fn only_the_selected_profile_is_interpolated() {
+        let config: CageConfig = toml::from_str(&CONFIG_WITH_PROFILES.replace(
+            "uuid = \"cage_456\"",
+            "uuid = \"${EV_CAGE_TEST_UNSET_PROD_UUID}\"",
+        ))
+        .unwrap();
+
+        let config = config
+            .with_selected_profile(None)
+            .unwrap()
+            .interpolated()
+            .unwrap();
+        assert_eq!(config.uuid.as_deref(), Some("cage_123"));
+        assert!(matches!(
+            config.with_profile("production").unwrap().interpolated(),
+            Err(CageConfigError::MissingEnvironmentVariable(name, _))
+                if name == "EV_CAGE_TEST_UNSET_PROD_UUID"
+        ));
+    }
+
+    #[test]
+    fn paths_can_be_resolved_against_another_directory() {
+        let config: CageConfig = toml::from_str(CONFIG_WITH_PROFILES).unwrap();
+        let mut config = config.relative_to(Path::new("services/payments"));
+        assert_eq!(config.dockerfile(), "services/payments/Dockerfile");
+        assert_eq!(config.cert(), Some("services/payments/cert.pem"));
+        assert_eq!(config.key(), Some("services/payments/key.pem"));
+
+        config.set_cert("/etc/cage/cert.pem".to_string());
+        let config = config.relative_to(Path::new("services/auth"));
+        assert_eq!(config.cert(), Some("/etc/cage/cert.pem"));
+    }
+
+    #[test]
+    fn missing_profile_is_an_error() {
+        let config: CageConfig = toml::from_str(CONFIG_WITH_PROFILES).unwrap();
+        assert!(matches!(
+            config.with_profile("staging"),
+            Err(CageConfigError::ProfileNotFound(profile, available))
+                if profile == "staging" && available == "production"
+        ));
+
+        // The profile is never silently ignored, even when the config has no profiles
+        let mut config: CageConfig = toml::from_str(CONFIG_WITH_PROFILES).unwrap();
+        config.profiles.clear();
+        assert!(matches!(
+            config.with_selected_profile(Some("production")),
+            Err(CageConfigError::ProfileNotFound(..))
+        ));
+    }
 }
@@ -0,0 +1,239 @@
+use super::CageConfig;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject};
+
+const PROFILE_DEFINITION: &str = "CageProfile";
+const DEFINITIONS_PATH: &str = "#/definitions/";
+
+/// Generates the JSON Schema for cage.toml files
+pub fn cage_config_schema() -> RootSchema {
+    // TOML has no null, so optional fields are left out rather than allowed to be null
+    let generator = SchemaSettings::draft07()
+        .with(|settings| settings.option_add_null_type = false)
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<CageConfig>();
+    schema.schema.metadata().title = Some("cage.toml".to_string());
+
+    // A profile can override any key from the base config, but doesn't need to set any of them, as
+    // tables are merged key by key
+    let mut profile = schema.schema.clone();
+    profile.metadata().title = Some(PROFILE_DEFINITION.to_string());
+    profile.metadata().description = Some(
+        "Overrides for the base config, applied when the profile is selected with --config-profile"
+            .to_string(),
+    );
+    profile.object().required.clear();
+    profile.object().properties.remove("profiles");
+    for property in profile.object().properties.values_mut() {
+        let Some(reference) = table_reference(property) else {
+            continue;
+        };
+        let name = reference.trim_start_matches(DEFINITIONS_PATH).to_string();
+        let partial_name = format!("{PROFILE_DEFINITION}{name}");
+        if let Some(Schema::Object(table)) = schema.definitions.get(&name) {
+            let mut partial_table = table.clone();
+            partial_table.object().required.clear();
+            schema
+                .definitions
+                .insert(partial_name.clone(), partial_table.into());
+            *reference = format!("{DEFINITIONS_PATH}{partial_name}");
+        }
+    }
+    schema
+        .definitions
+        .insert(PROFILE_DEFINITION.to_string(), profile.into());
+
+    // Reject unknown keys, so that misspelled keys such as cert_path are caught by editors and CI
+    // checks. The CLI itself only warns about them.
+    let tables =
+        std::iter::once(&mut schema.schema).chain(schema.definitions.values_mut().filter_map(
+            |definition| match definition {
+                Schema::Object(definition) => Some(definition),
+                Schema::Bool(_) => None,
+            },
+        ));
+    for table in tables {
+        if let Some(object) = table.object.as_mut() {
+            object
+                .additional_properties
+                .get_or_insert_with(|| Box::new(Schema::Bool(false)));
+        }
+    }
+    schema
+}
+
+// Returns the reference to the definition of a table. Tables with a description are wrapped in an
+// allOf, so the description can sit alongside the reference.
+fn table_reference(property: &mut Schema) -> Option<&mut String> {
+    let Schema::Object(property) = property else {
+        return None;
+    };
+    if property.reference.is_some() {
+        return property.reference.as_mut();
+    }
+    match property.subschemas.as_mut()?.all_of.as_mut()?.first_mut()? {
+        Schema::Object(table) => table.reference.as_mut(),
+        Schema::Bool(_) => None,
+    }
+}
+
+pub(super) fn profiles_schema(_: &mut SchemaGenerator) -> Schema {
+    let profile_ref = format!("{DEFINITIONS_PATH}{PROFILE_DEFINITION}");
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(ObjectValidation {
+            additional_properties: Some(Box::new(Schema::new_ref(profile_ref))),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::validate::known_keys;
+    use super::*;
+    use jsonschema::JSONSchema;
+
+    fn compiled_schema() -> JSONSchema {
+        let schema = serde_json::to_value(cage_config_schema()).unwrap();
+        JSONSchema::compile(&schema).unwrap()
+    }
+
+    fn toml_to_json(source: &str) -> serde_json::Value {
+        let value: toml::Value = toml::from_str(source).unwrap();
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn sorted_keys<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<String> {
+        let mut keys: Vec<_> = keys.cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn sorted(keys: &[&str]) -> Vec<String> {
+        let mut keys: Vec<_> = keys.iter().map(|key| key.to_string()).collect();
+        keys.sort();
+        keys
+    }
+
+    // Resolves a property to the schema it refers to in the definitions
+    fn property_schema<'a>(schema: &'a serde_json::Value, property: &str) -> &'a serde_json::Value {
+        let property = &schema["properties"][property];
+        let reference = property["$ref"]
+            .as_str()
+            .or_else(|| property["allOf"][0]["$ref"].as_str())
+            .unwrap_or_else(|| panic!("{property} is not a reference"));
+        &schema["definitions"][reference.trim_start_matches("#/definitions/")]
+    }
+
+    #[test]
+    fn test_schema_keys_match_known_keys() {
+        let schema = serde_json::to_value(cage_config_schema()).unwrap();
+        let properties = |schema: &serde_json::Value| {
+            sorted_keys(schema["properties"].as_object().unwrap().keys())
+        };
+
+        assert_eq!(properties(&schema), sorted(known_keys(&[]).unwrap()));
+        for table in ["egress", "signing", "attestation", "runtime"] {
+            assert_eq!(
+                properties(property_schema(&schema, table)),
+                sorted(known_keys(&[table]).unwrap()),
+                "{table}"
+            );
+        }
+
+        let profile = &schema["definitions"][PROFILE_DEFINITION];
+        let mut profile_keys = sorted(known_keys(&["profiles", "staging"]).unwrap());
+        profile_keys.retain(|key| key != "profiles");
+        assert_eq!(properties(profile), profile_keys);
+        assert!(profile.get("required").is_none());
+    }
+
+    #[test]
+    fn test_schema_includes_descriptions_and_defaults() {
+        let schema = serde_json::to_value(cage_config_schema()).unwrap();
+        assert_eq!(
+            schema["properties"]["dockerfile"]["default"],
+            "./Dockerfile"
+        );
+        assert_eq!(schema["properties"]["api_key_auth"]["default"], true);
+        assert!(schema["properties"]["debug"]["description"].is_string());
+        assert!(
+            property_schema(&schema, "signing")["properties"]["certPath"]["description"]
+                .is_string()
+        );
+    }
+
+    #[test]
+    fn test_configs_are_checked_against_schema() {
+        let schema = compiled_schema();
+        let config = r#"name = "my-cage"
+uuid = "cage_123"
+app_uuid = "app_123"
+team_uuid = "team_123"
+debug = false
+dockerfile = "./Dockerfile"
+
+[egress]
+enabled = true
+destinations = ["api.example.com"]
+ports = ["443"]
+
+[signing]
+certPath = "./cert.pem"
+keyPath = "./key.pem"
+lockCert = true
+
+[attestation]
+HashAlgorithm = "Sha384 { ... }"
+PCR0 = "000"
+PCR1 = "111"
+PCR2 = "222"
+
+[runtime]
+data_plane_version = "1.0.0"
+installer_version = "1.0.0"
+
+[env]
+LOG_LEVEL = "info"
+API_TOKEN = { value = "${API_TOKEN}", secret = true }
+
+[profiles.production]
+debug = false
+
+[profiles.production.egress]
+destinations = ["payments.example.com"]
+
+[profiles.production.signing]
+certPath = "./prod-cert.pem"
+"#;
+        assert!(schema.is_valid(&toml_to_json(config)));
+        assert!(schema.is_valid(&toml_to_json(
+            &std::fs::read_to_string("./test.cage.toml").unwrap()
+        )));
+
+        assert!(!schema.is_valid(&toml_to_json(
+            &config.replace("debug = false\ndockerfile", "debug = \"no\"\ndockerfile")
+        )));
+        assert!(!schema.is_valid(&toml_to_json(&config.replace("certPath", "cert_path"))));
+        assert!(!schema.is_valid(&toml_to_json(&config.replace("name = \"my-cage\"\n", ""))));
+        assert!(!schema.is_valid(&toml_to_json(&config.replace(
+            "[profiles.production]\ndebug = false",
+            "[profiles.production]\ndebug = 1"
+        ))));
+        assert!(!schema.is_valid(&toml_to_json(&config.replace(
+            "destinations = [\"payments.example.com\"]",
+            "destination = [\"payments.example.com\"]"
+        ))));
+        assert!(!schema.is_valid(&toml_to_json(
+            &config.replace("secret = true", "secret = \"yes\"")
+        )));
+        assert!(!schema.is_valid(&toml_to_json(
+            &config.replace("secret = true", "secert = true")
+        )));
+        // Tables in the base config still need their required keys
+        assert!(!schema.is_valid(&toml_to_json(&config.replace("enabled = true\n", ""))));
+    }
+}
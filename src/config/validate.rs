@@ -0,0 +1,887 @@
+use super::{CageConfig, CageConfigError};
+use crate::cert::cert_matches_key;
+use serde::Serialize;
+use std::ops::Range;
+use std::path::Path;
+use toml_edit::{ImDocument, Item, TableLike};
+
+const CAGE_CONFIG_KEYS: &[&str] = &[
+    "name",
+    "uuid",
+    "app_uuid",
+    "team_uuid",
+    "debug",
+    "dockerfile",
+    "api_key_auth",
+    "trx_logging",
+    "disable_tls_termination",
+    "forward_proxy_protocol",
+    "forward_port",
+    "egress",
+    "signing",
+    "attestation",
+    "runtime",
+    "env",
+    "profiles",
+];
+const EGRESS_KEYS: &[&str] = &["enabled", "destinations", "ports"];
+const SIGNING_KEYS: &[&str] = &["certPath", "keyPath", "lockCert"];
+const ATTESTATION_KEYS: &[&str] = &["HashAlgorithm", "PCR0", "PCR1", "PCR2", "PCR8"];
+const RUNTIME_KEYS: &[&str] = &["data_plane_version", "installer_version"];
+
+// Keys accepted in each table of the cage.toml, keyed by the path to the table
+pub(super) fn known_keys(table_path: &[&str]) -> Option<&'static [&'static str]> {
+    match table_path {
+        [] => Some(CAGE_CONFIG_KEYS),
+        ["egress"] => Some(EGRESS_KEYS),
+        ["signing"] => Some(SIGNING_KEYS),
+        ["attestation"] => Some(ATTESTATION_KEYS),
+        ["runtime"] => Some(RUNTIME_KEYS),
+        // Variables in the env table are named by the user
+        ["env"] => None,
+        // Each profile accepts the same keys as the base config
+        ["profiles", _, rest @ ..] => known_keys(rest),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// 1-based line of the problem in the config file, when it can be attributed to one
+    pub line: Option<usize>,
+    /// 1-based column of the problem in the config file, when it can be attributed to one
+    pub column: Option<usize>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            line: None,
+            column: None,
+            help: None,
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    fn at(mut self, source: &str, span: Option<Range<usize>>) -> Self {
+        if let Some(span) = span {
+            let (line, column) = line_and_column(source, span.start);
+            self.line = Some(line);
+            self.column = Some(column);
+        }
+        self
+    }
+
+    fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Formats the diagnostic for the terminal, prefixed with its location in the config file
+    pub fn render(&self, config_path: &str) -> String {
+        let location = match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{config_path}:{line}:{column}"),
+            _ => config_path.to_string(),
+        };
+        match &self.help {
+            Some(help) => format!("{location}: {}\n  = help: {help}", self.message),
+            None => format!("{location}: {}", self.message),
+        }
+    }
+}
+
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let preceding = source.get(..offset).unwrap_or(source);
+    let line = preceding.matches('\n').count() + 1;
+    let column = preceding
+        .rsplit('\n')
+        .next()
+        .map(|line| line.chars().count())
+        .unwrap_or(0)
+        + 1;
+    (line, column)
+}
+
+/// Whether the config must already hold the uuids of its Cage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CageUuids {
+    #[default]
+    Required,
+    /// The command creates the Cage, or attaches the config to it, before the uuids are used. A config
+    /// without a uuid is valid, as its Cage doesn't exist yet.
+    NotRequiredYet,
+}
+
+/// Validates the cage.toml at `config_path`, returning every problem found with it
+pub fn validate_config_file(config_path: &str) -> Result<Vec<Diagnostic>, CageConfigError> {
+    validate_config_file_with(config_path, CageUuids::Required, |_| {})
+}
+
+/// Validates the cage.toml at `config_path`, applying `overrides` to the parsed config before the
+/// semantic checks. Used to account for command line arguments that take precedence over the file.
+pub fn validate_config_file_with<F: FnOnce(&mut CageConfig)>(
+    config_path: &str,
+    uuids: CageUuids,
+    overrides: F,
+) -> Result<Vec<Diagnostic>, CageConfigError> {
+    if !Path::new(config_path).exists() {
+        return Err(CageConfigError::MissingConfigFile(config_path.to_string()));
+    }
+    let source = std::fs::read_to_string(config_path)?;
+    let profile = super::selected_profile_name();
+    Ok(validate_config_source(
+        &source,
+        profile.as_deref(),
+        uuids,
+        overrides,
+    ))
+}
+
+/// Validates the contents of a cage.toml, with `profile` merged over the base config when it's set
+pub fn validate_config_source<F: FnOnce(&mut CageConfig)>(
+    source: &str,
+    profile: Option<&str>,
+    uuids: CageUuids,
+    overrides: F,
+) -> Vec<Diagnostic> {
+    let document = match ImDocument::parse(source) {
+        Ok(document) => document,
+        Err(e) => {
+            return vec![Diagnostic::error(e.message().trim()).at(source, e.span())];
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    check_unknown_keys(
+        source,
+        document.as_table(),
+        &mut Vec::new(),
+        &mut diagnostics,
+    );
+
+    let config: CageConfig = match toml_edit::de::from_str(source) {
+        Ok(config) => config,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(e.message().trim()).at(source, e.span()));
+            return diagnostics;
+        }
+    };
+    let config = match config.with_selected_profile(profile) {
+        Ok(config) => config,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(e.to_string()).at(
+                source,
+                find_item(&document, &["profiles"]).and_then(Item::span),
+            ));
+            return diagnostics;
+        }
+    };
+
+    // Values set by the active profile are reported where the profile sets them
+    let profile_path = |path: &[&str]| -> Option<Vec<String>> {
+        let profile = profile?;
+        Some(
+            ["profiles", profile]
+                .iter()
+                .chain(path)
+                .map(|key| key.to_string())
+                .collect(),
+        )
+    };
+    let find = |path: &[&str]| {
+        profile_path(path)
+            .and_then(|profile_path| {
+                let profile_path: Vec<&str> = profile_path.iter().map(String::as_str).collect();
+                find_item(&document, &profile_path)
+            })
+            .or_else(|| find_item(&document, path))
+    };
+    let span_of = |path: &[&str]| find(path).and_then(Item::span);
+
+    // Only the selected profile is interpolated, so unselected profiles can use variables which
+    // aren't set
+    let mut config = match config.interpolated() {
+        Ok(config) => config,
+        Err(e) => {
+            let key_span = match &e {
+                CageConfigError::MissingEnvironmentVariable(_, key)
+                | CageConfigError::InvalidInterpolation(key, _) => {
+                    span_of(&key.split('.').collect::<Vec<_>>())
+                }
+                _ => None,
+            };
+            diagnostics.push(Diagnostic::error(e.to_string()).at(source, key_span));
+            return diagnostics;
+        }
+    };
+    overrides(&mut config);
+    check_cage_fields(&config, uuids, source, &span_of, &mut diagnostics);
+    check_egress(&config, source, &find, &mut diagnostics);
+    check_signing(&config, source, &span_of, &mut diagnostics);
+    check_tls_and_logging(&config, source, &span_of, &mut diagnostics);
+    check_forward_port(&config, source, &span_of, &mut diagnostics);
+    if config.debug {
+        diagnostics.push(
+            Diagnostic::warning("Debug mode is enabled")
+                .at(source, span_of(&["debug"]))
+                .with_help("Every PCR in the attestation document will be 0 while the Cage runs in debug mode, so clients can't verify it. Don't use debug mode in production."),
+        );
+    }
+    diagnostics
+}
+
+fn find_item<'a>(document: &'a ImDocument<&str>, path: &[&str]) -> Option<&'a Item> {
+    let mut item = document.as_item();
+    for key in path {
+        item = item.get(key)?;
+    }
+    Some(item)
+}
+
+fn check_unknown_keys<'a>(
+    source: &str,
+    table: &'a dyn TableLike,
+    table_path: &mut Vec<&'a str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if table_path.as_slice() == ["profiles"] {
+        // Profiles are named by the user, so only the keys inside each profile are checked
+        for (profile, item) in table.iter() {
+            if let Some(profile_table) = item.as_table_like() {
+                table_path.push(profile);
+                check_unknown_keys(source, profile_table, table_path, diagnostics);
+                table_path.pop();
+            }
+        }
+        return;
+    }
+    let Some(known_keys) = known_keys(table_path) else {
+        return;
+    };
+    // Profiles can't be nested inside another profile
+    let in_profile = table_path.first() == Some(&"profiles");
+    let known_keys: Vec<&str> = known_keys
+        .iter()
+        .copied()
+        .filter(|key| !(in_profile && *key == "profiles"))
+        .collect();
+    for (key, item) in table.iter() {
+        if !known_keys.contains(&key) {
+            let key_span = table.get_key_value(key).and_then(|(key, _)| key.span());
+            let message = if table_path.is_empty() {
+                format!("Unknown key `{key}`")
+            } else {
+                format!("Unknown key `{key}` in [{}]", table_path.join("."))
+            };
+            diagnostics.push(
+                Diagnostic::warning(message)
+                    .at(source, key_span)
+                    .with_help(format!("Expected one of {}", known_keys.join(", "))),
+            );
+        } else if let Some(child_table) = item.as_table_like() {
+            table_path.push(key);
+            check_unknown_keys(source, child_table, table_path, diagnostics);
+            table_path.pop();
+        }
+    }
+}
+
+fn check_cage_fields<F: Fn(&[&str]) -> Option<Range<usize>>>(
+    config: &CageConfig,
+    uuids: CageUuids,
+    source: &str,
+    span_of: &F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if config.name.trim().is_empty() {
+        diagnostics.push(Diagnostic::error("`name` can't be empty").at(source, span_of(&["name"])));
+    }
+    if uuids == CageUuids::NotRequiredYet && config.uuid.is_none() {
+        return;
+    }
+    let required_fields = [
+        ("uuid", &config.uuid),
+        ("app_uuid", &config.app_uuid),
+        ("team_uuid", &config.team_uuid),
+    ];
+    for (field, value) in required_fields {
+        if value.is_none() {
+            diagnostics.push(
+                Diagnostic::error(format!("`{field}` is not set")).with_help(
+                    "Run `ev-cage init --attach` or `ev-cage link` to fill in the uuids of the Cage",
+                ),
+            );
+        }
+    }
+}
+
+fn check_egress<'a, F: Fn(&[&str]) -> Option<&'a Item>>(
+    config: &CageConfig,
+    source: &str,
+    find: &F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let element_span = |key: &str, index: usize| {
+        find(&["egress", key])
+            .and_then(Item::as_array)
+            .and_then(|array| array.get(index))
+            .and_then(|value| value.span())
+    };
+
+    for (index, port) in config.egress.ports.iter().flatten().enumerate() {
+        if !is_valid_port(port) {
+            diagnostics.push(
+                Diagnostic::error(format!("`{port}` is not a valid egress port"))
+                    .at(source, element_span("ports", index))
+                    .with_help("Ports must be numbers between 1 and 65535"),
+            );
+        }
+    }
+
+    for (index, destination) in config.egress.destinations.iter().flatten().enumerate() {
+        if !is_valid_destination(destination) {
+            diagnostics.push(
+                Diagnostic::error(format!("`{destination}` is not a valid egress destination"))
+                    .at(source, element_span("destinations", index))
+                    .with_help("Destinations must be hostnames such as api.example.com, wildcard domains such as *.example.com, or * to allow all"),
+            );
+        }
+    }
+}
+
+pub fn is_valid_port(port: &str) -> bool {
+    matches!(port.parse::<u16>(), Ok(port) if port != 0)
+}
+
+pub fn is_valid_destination(destination: &str) -> bool {
+    if destination == "*" {
+        return true;
+    }
+    let hostname = destination.strip_prefix("*.").unwrap_or(destination);
+    hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn check_signing<F: Fn(&[&str]) -> Option<Range<usize>>>(
+    config: &CageConfig,
+    source: &str,
+    span_of: &F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let generate_help = "Signing credentials can be generated using the cert new command";
+    let (cert, key) = match (config.cert(), config.key()) {
+        (Some(cert), Some(key)) => (cert, key),
+        (cert, key) => {
+            if cert.is_none() {
+                diagnostics.push(
+                    Diagnostic::error("No signing cert given")
+                        .at(source, span_of(&["signing"]))
+                        .with_help(generate_help),
+                );
+            }
+            if key.is_none() {
+                diagnostics.push(
+                    Diagnostic::error("No signing key given")
+                        .at(source, span_of(&["signing"]))
+                        .with_help(generate_help),
+                );
+            }
+            return;
+        }
+    };
+
+    let mut files_exist = true;
+    for (path, key_name, description) in [(cert, "certPath", "cert"), (key, "keyPath", "key")] {
+        if !Path::new(path).exists() {
+            files_exist = false;
+            diagnostics.push(
+                Diagnostic::error(format!(
+                    "Could not find signing {description} file at {path}"
+                ))
+                .at(source, span_of(&["signing", key_name]))
+                .with_help(generate_help),
+            );
+        }
+    }
+    if !files_exist {
+        return;
+    }
+
+    match cert_matches_key(Path::new(cert), Path::new(key)) {
+        Ok(true) => {}
+        Ok(false) => diagnostics.push(
+            Diagnostic::error(format!("The signing key at {key} does not belong to the signing cert at {cert}"))
+                .at(source, span_of(&["signing", "keyPath"]))
+                .with_help("Make sure certPath and keyPath point to the cert and key generated together by the cert new command"),
+        ),
+        Err(e) => diagnostics.push(
+            Diagnostic::error(format!("Failed to read the signing cert and key — {e}"))
+                .at(source, span_of(&["signing"])),
+        ),
+    }
+}
+
+fn check_tls_and_logging<F: Fn(&[&str]) -> Option<Range<usize>>>(
+    config: &CageConfig,
+    source: &str,
+    span_of: &F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if config.trx_logging && config.disable_tls_termination {
+        diagnostics.push(
+            Diagnostic::error(CageConfigError::LoggingEnabledWithoutTLSTermination().to_string())
+                .at(source, span_of(&["disable_tls_termination"]))
+                .with_help("Transaction logs are recorded by the Cage's TLS terminating proxy, so they can't be collected when TLS termination is disabled. Set `trx_logging = false` to keep TLS termination disabled, or remove `disable_tls_termination`."),
+        );
+    }
+}
+
+fn check_forward_port<F: Fn(&[&str]) -> Option<Range<usize>>>(
+    config: &CageConfig,
+    source: &str,
+    span_of: &F,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let Some(port) = config.forward_port else {
+        return;
+    };
+    let help = match port {
+        0 => "Ports must be numbers between 1 and 65535",
+        443 => {
+            "The Cage's data plane listens on port 443, so your service must listen on another port"
+        }
+        _ => return,
+    };
+    diagnostics.push(
+        Diagnostic::error(format!("Traffic can't be forwarded to port {port}"))
+            .at(source, span_of(&["forward_port"]))
+            .with_help(help),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn valid_config(cert: &Path, key: &Path) -> String {
+        format!(
+            r#"name = "my-cage"
+uuid = "cage_123"
+app_uuid = "app_123"
+team_uuid = "team_123"
+debug = false
+
+[egress]
+enabled = true
+destinations = ["*.evervault.com", "api.example.com"]
+ports = ["443", "8443"]
+
+[signing]
+certPath = '{}'
+keyPath = '{}'
+"#,
+            cert.display(),
+            key.display()
+        )
+    }
+
+    fn generate_cert() -> (tempfile::TempDir, std::path::PathBuf, std::path::PathBuf) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (cert, key) =
+            crate::cert::create_new_cert(dir.path(), crate::cert::DistinguishedName::default())
+                .unwrap();
+        (dir, cert, key)
+    }
+
+    #[test]
+    fn test_valid_config_has_no_diagnostics() {
+        let (_dir, cert, key) = generate_cert();
+        assert_eq!(
+            validate_config_source(
+                &valid_config(&cert, &key),
+                None,
+                CageUuids::Required,
+                |_| {}
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors_report_line_and_column() {
+        let diagnostics = validate_config_source(
+            "name = \"my-cage\"\ndebug = tru\n",
+            None,
+            CageUuids::Required,
+            |_| {},
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(2), Some(9))
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_report_line_and_column() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key).replace("ports =", "portz =");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        let unknown_key = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic.message == "Unknown key `portz` in [egress]")
+            .unwrap();
+        assert_eq!(unknown_key.severity, Severity::Warning);
+        assert_eq!((unknown_key.line, unknown_key.column), (Some(10), Some(1)));
+    }
+
+    #[test]
+    fn test_env_variables_are_not_unknown_keys() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key)
+            + "\n[env]\nLOG_LEVEL = \"info\"\nAPI_TOKEN = { value = \"token\", secret = true }\n";
+        assert!(validate_config_source(&source, None, CageUuids::Required, |_| {}).is_empty());
+
+        let source = source.replace("secret = true", "secert = true");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, Some(18));
+    }
+
+    #[test]
+    fn test_type_errors_report_location() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key).replace("debug = false", "debug = \"no\"");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(5));
+    }
+
+    #[test]
+    fn test_invalid_egress_settings() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key)
+            .replace("\"8443\"", "\"70000\"")
+            .replace("api.example.com", "bad_host.com");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.message.as_str(),
+                    diagnostic.line,
+                    diagnostic.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("`70000` is not a valid egress port", Some(10), Some(17)),
+                (
+                    "`bad_host.com` is not a valid egress destination",
+                    Some(9),
+                    Some(36)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_destination_and_port_validation() {
+        assert!(is_valid_destination("*"));
+        assert!(is_valid_destination("*.example.com"));
+        assert!(is_valid_destination("10.0.0.1"));
+        assert!(!is_valid_destination("example..com"));
+        assert!(!is_valid_destination("-example.com"));
+        assert!(!is_valid_destination("*example.com"));
+        assert!(!is_valid_destination("https://example.com"));
+
+        assert!(is_valid_port("65535"));
+        assert!(!is_valid_port("0"));
+        assert!(!is_valid_port("65536"));
+        assert!(!is_valid_port("http"));
+    }
+
+    #[test]
+    fn test_mismatched_signing_credentials() {
+        let (_first_dir, cert, _) = generate_cert();
+        let (_second_dir, _, key) = generate_cert();
+        let diagnostics = validate_config_source(
+            &valid_config(&cert, &key),
+            None,
+            CageUuids::Required,
+            |_| {},
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message
+            .contains("does not belong to the signing cert"));
+        assert_eq!(diagnostics[0].line, Some(14));
+    }
+
+    #[test]
+    fn test_uuids_are_only_required_for_existing_cages() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key)
+            .replace("uuid = \"cage_123\"\n", "")
+            .replace("app_uuid = \"app_123\"\n", "")
+            .replace("team_uuid = \"team_123\"\n", "");
+        let messages: Vec<_> = validate_config_source(&source, None, CageUuids::Required, |_| {})
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "`uuid` is not set",
+                "`app_uuid` is not set",
+                "`team_uuid` is not set"
+            ]
+        );
+        assert_eq!(
+            validate_config_source(&source, None, CageUuids::NotRequiredYet, |_| {}),
+            vec![]
+        );
+
+        // A Cage which has been created needs all of its uuids
+        let source = valid_config(&cert, &key).replace("team_uuid = \"team_123\"\n", "");
+        let diagnostics = validate_config_source(&source, None, CageUuids::NotRequiredYet, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`team_uuid` is not set");
+    }
+
+    #[test]
+    fn test_missing_signing_files() {
+        let diagnostics = validate_config_source(
+            &valid_config(Path::new("missing-cert.pem"), Path::new("missing-key.pem")),
+            None,
+            CageUuids::Required,
+            |_| {},
+        );
+        let lines: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect();
+        assert_eq!(lines, vec![Some(13), Some(14)]);
+    }
+
+    #[test]
+    fn test_overrides_are_applied_before_checks() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(Path::new("missing-cert.pem"), &key);
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |config| {
+            config.set_cert(cert.display().to_string())
+        });
+        assert_eq!(diagnostics, vec![]);
+    }
+
+    #[test]
+    fn test_logging_without_tls_termination_and_debug_mode() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key).replace(
+            "debug = false",
+            "debug = true\ndisable_tls_termination = true",
+        );
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.line))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(Severity::Error, Some(6)), (Severity::Warning, Some(5))]
+        );
+        assert!(diagnostics[0].help.is_some());
+    }
+
+    #[test]
+    fn test_restricted_forward_port() {
+        let (_dir, cert, key) = generate_cert();
+        let source =
+            valid_config(&cert, &key).replace("debug = false", "debug = false\nforward_port = 443");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Traffic can't be forwarded to port 443"
+        );
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(6), Some(16))
+        );
+
+        let source = valid_config(&cert, &key)
+            .replace("debug = false", "debug = false\nforward_port = 3000");
+        assert_eq!(
+            validate_config_source(&source, None, CageUuids::Required, |_| {}),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_in_profiles() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key)
+            + "\n[profiles.staging]\nname = \"my-cage-staging\"\ndeubg = true\n\n[profiles.staging.egress]\nportz = [\"443\"]\n";
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.message.as_str(), diagnostic.line))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("Unknown key `deubg` in [profiles.staging]", Some(18)),
+                ("Unknown key `portz` in [profiles.staging.egress]", Some(21)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selected_profile_is_validated() {
+        let (_dir, cert, key) = generate_cert();
+        let source =
+            valid_config(&cert, &key) + "\n[profiles.staging.egress]\nports = [\"443\", \"0\"]\n";
+        // Profiles which aren't selected aren't merged into the config being checked
+        assert_eq!(
+            validate_config_source(&source, None, CageUuids::Required, |_| {}),
+            vec![]
+        );
+
+        let diagnostics =
+            validate_config_source(&source, Some("staging"), CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "`0` is not a valid egress port");
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(17), Some(17))
+        );
+
+        let diagnostics =
+            validate_config_source(&source, Some("production"), CageUuids::Required, |_| {});
+        assert!(diagnostics[0]
+            .message
+            .starts_with("No profile named production"));
+    }
+
+    #[test]
+    fn test_environment_variables_are_resolved_before_checks() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key).replace(
+            &format!("'{}'", key.display()),
+            &format!("'${{EV_CAGE_TEST_UNSET_KEY_PATH:-{}}}'", key.display()),
+        );
+        assert_eq!(
+            validate_config_source(&source, None, CageUuids::Required, |_| {}),
+            vec![]
+        );
+
+        let source = source.replace(":-", "");
+        let diagnostics = validate_config_source(&source, None, CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message
+            .contains("EV_CAGE_TEST_UNSET_KEY_PATH"));
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(14), Some(11))
+        );
+    }
+
+    #[test]
+    fn test_unselected_profiles_are_not_interpolated() {
+        let (_dir, cert, key) = generate_cert();
+        let source = valid_config(&cert, &key)
+            + "\n[profiles.production]\nname = \"${EV_CAGE_TEST_UNSET_PROD_NAME}\"\n";
+        assert_eq!(
+            validate_config_source(&source, None, CageUuids::Required, |_| {}),
+            vec![]
+        );
+
+        let diagnostics =
+            validate_config_source(&source, Some("production"), CageUuids::Required, |_| {});
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0]
+            .message
+            .contains("EV_CAGE_TEST_UNSET_PROD_NAME"));
+        // Reported where the profile sets the value
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(17), Some(8))
+        );
+    }
+
+    #[test]
+    fn test_known_keys_match_config_fields() {
+        let (_dir, cert, key) = generate_cert();
+        let mut config: CageConfig = toml::from_str(&valid_config(&cert, &key)).unwrap();
+        config.set_runtime_info(super::super::RuntimeVersions::new(
+            "1.0.0".to_string(),
+            "1.0.0".to_string(),
+        ));
+        config
+            .profiles
+            .insert("staging".to_string(), Default::default());
+        config.env = Some(Default::default());
+        config.signing.as_mut().unwrap().lock_cert = Some(true);
+        config.forward_port = Some(3000);
+        config.attestation = Some(
+            serde_json::from_value(serde_json::json!({
+                "HashAlgorithm": "Sha384 { ... }",
+                "PCR0": "0", "PCR1": "1", "PCR2": "2", "PCR8": "8"
+            }))
+            .unwrap(),
+        );
+        let serialized = toml::Value::try_from(&config).unwrap();
+
+        let keys = |value: &toml::Value| -> Vec<String> {
+            let mut keys: Vec<_> = value.as_table().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let sorted = |keys: &[&str]| -> Vec<String> {
+            let mut keys: Vec<_> = keys.iter().map(|key| key.to_string()).collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(&serialized), sorted(CAGE_CONFIG_KEYS));
+        for table in ["egress", "signing", "attestation", "runtime"] {
+            assert_eq!(
+                keys(&serialized[table]),
+                sorted(known_keys(&[table]).unwrap()),
+                "{table}"
+            );
+        }
+    }
+}
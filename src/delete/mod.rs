@@ -20,7 +20,7 @@ fn resolve_cage_uuid(
 pub async fn delete_cage(
     config: &str,
     cage_uuid: Option<&str>,
-    api_key: &str,
+    auth: AuthMode,
     background: bool,
 ) -> Result<(), DeleteError> {
     let maybe_cage_uuid = resolve_cage_uuid(cage_uuid, config)?;
@@ -29,7 +29,7 @@ pub async fn delete_cage(
         _ => return Err(DeleteError::MissingUuid),
     };
 
-    let cage_api = api::cage::CagesClient::new(AuthMode::ApiKey(api_key.to_string()));
+    let cage_api = api::cage::CagesClient::new(auth);
 
     let deleted_cage = match cage_api.delete_cage(&cage_uuid).await {
         Ok(cage_ref) => cage_ref,
@@ -22,7 +22,12 @@ pub fn describe_eif(eif_path: &str, verbose: bool) -> Result<enclave::DescribeEi
 
     let supplied_path: Option<&str> = None;
     let output_path = resolve_output_path(supplied_path).unwrap();
-    enclave::build_nitro_cli_image(output_path.path(), None, verbose)?;
+    enclave::build_nitro_cli_image(
+        output_path.path(),
+        None,
+        &enclave::BuildImages::default(),
+        verbose,
+    )?;
 
     let description = enclave::describe_eif(&absolute_path, verbose)?;
     describe_progress.finish_with_message("PCRs retrieved.");
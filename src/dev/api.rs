@@ -0,0 +1,938 @@
+use axum::body::Bytes;
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, put};
+use axum::{Extension, Form, Json, Router, Server};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+// Well known public keys (the generator points of each curve) returned from the mock keys endpoint.
+// The matching private key is 1, so values encrypted against the mock API must never be real secrets.
+const MOCK_ECDH_P256_KEY: &str = "A2sX0fLhLEJH+Lzm5WOkQPJ3A32BLeszoPShOUXYmMKW";
+const MOCK_ECDH_P256_KEY_UNCOMPRESSED: &str =
+    "BGsX0fLhLEJH+Lzm5WOkQPJ3A32BLeszoPShOUXYmMKWT+NC4v4af5uO5+tKfA+eFivOM1drMV7Oy7ZAaDe/UfU=";
+const MOCK_ECDH_KEY: &str = "Anm+Zn753LusVaBilc6HCwcCm/zbLc4o2VnygVsW+BeY";
+
+const MOCK_TEAM_UUID: &str = "team_000000000000";
+const MOCK_APP_UUID: &str = "app_000000000000";
+const MOCK_REGION: &str = "us-east-1";
+const MOCK_LOG_PAGE_SIZE: usize = 2;
+const MOCK_LOG_MESSAGES: [&str; 3] = [
+    "Starting data plane",
+    "Listening on port 8008",
+    "Cage is ready to receive traffic",
+];
+
+type SharedState = Arc<Mutex<MockState>>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum DeploymentStage {
+    AwaitingUpload,
+    Building,
+    Built,
+    Deploying,
+    Deployed,
+}
+
+impl DeploymentStage {
+    // Each poll of an uploaded deployment moves it one step through the build and deploy lifecycle
+    fn next(&self) -> Self {
+        match self {
+            Self::AwaitingUpload => Self::AwaitingUpload,
+            Self::Building => Self::Built,
+            Self::Built => Self::Deploying,
+            Self::Deploying | Self::Deployed => Self::Deployed,
+        }
+    }
+
+    fn build_status(&self) -> &'static str {
+        match self {
+            Self::AwaitingUpload => "pending",
+            Self::Building => "building",
+            Self::Built | Self::Deploying | Self::Deployed => "ready",
+        }
+    }
+
+    fn deploy_status(&self) -> &'static str {
+        match self {
+            Self::AwaitingUpload | Self::Building | Self::Built => "pending",
+            Self::Deploying => "deploying",
+            Self::Deployed => "ready",
+        }
+    }
+
+    fn detailed_status(&self) -> Option<&'static str> {
+        match self {
+            Self::Deploying => Some("Provisioning Trusted Execution Environment."),
+            Self::Deployed => Some("Deployment complete."),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MockCage {
+    uuid: String,
+    name: String,
+    state: &'static str,
+    created_at: String,
+    updated_at: String,
+    locked_certs: Vec<String>,
+    secrets: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug)]
+struct MockDeployment {
+    uuid: String,
+    cage_uuid: String,
+    version_uuid: String,
+    version: u32,
+    debug_mode: bool,
+    signing_cert_uuid: String,
+    data_plane_version: String,
+    installer_version: Option<String>,
+    pcrs: crate::enclave::PCRs,
+    started_at: String,
+    completed_at: Option<String>,
+    stage: DeploymentStage,
+}
+
+#[derive(Clone, Debug)]
+struct MockSigningCert {
+    uuid: String,
+    name: String,
+    cert_hash: String,
+    not_before: String,
+    not_after: String,
+}
+
+#[derive(Debug)]
+struct MockState {
+    base_url: String,
+    next_id: u64,
+    cages: HashMap<String, MockCage>,
+    deployments: HashMap<String, MockDeployment>,
+    certs: HashMap<String, MockSigningCert>,
+    // Device codes awaiting exchange, mapped to the number of polls left before they're approved
+    device_codes: HashMap<String, u32>,
+    refresh_tokens: Vec<String>,
+}
+
+impl MockState {
+    fn new(base_url: String) -> Self {
+        Self {
+            base_url,
+            next_id: 0,
+            cages: HashMap::new(),
+            deployments: HashMap::new(),
+            certs: HashMap::new(),
+            device_codes: HashMap::new(),
+            refresh_tokens: Vec::new(),
+        }
+    }
+
+    fn generate_uuid(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}_{:012x}", self.next_id)
+    }
+
+    fn cage_json(&self, cage: &MockCage) -> Value {
+        json!({
+            "uuid": cage.uuid,
+            "name": cage.name,
+            "teamUuid": MOCK_TEAM_UUID,
+            "appUuid": MOCK_APP_UUID,
+            "domain": format!("{}.{}.cages.localhost", cage.name, MOCK_APP_UUID),
+            "state": cage.state,
+            "createdAt": cage.created_at,
+            "updatedAt": cage.updated_at,
+        })
+    }
+
+    fn cert_json(&self, cert: &MockSigningCert) -> Value {
+        json!({
+            "name": cert.name,
+            "uuid": cert.uuid,
+            "appUuid": MOCK_APP_UUID,
+            "certHash": cert.cert_hash,
+            "notBefore": cert.not_before,
+            "notAfter": cert.not_after,
+        })
+    }
+
+    fn deployment_json(&self, deployment: &MockDeployment) -> Value {
+        json!({
+            "uuid": deployment.uuid,
+            "cageUuid": deployment.cage_uuid,
+            "versionUuid": deployment.version_uuid,
+            "signingCertUuid": deployment.signing_cert_uuid,
+            "debugMode": deployment.debug_mode,
+            "startedAt": deployment.started_at,
+            "completedAt": deployment.completed_at,
+        })
+    }
+
+    fn version_json(&self, deployment: &MockDeployment) -> Value {
+        let mut version = json!({
+            "uuid": deployment.version_uuid,
+            "version": deployment.version,
+            "controlPlaneImgUrl": null,
+            "controlPlaneVersion": null,
+            "dataPlaneVersion": deployment.data_plane_version,
+            "installerVersion": deployment.installer_version,
+            "buildStatus": deployment.stage.build_status(),
+            "failureReason": null,
+            "startedAt": deployment.started_at,
+        });
+        if let (Value::Object(version), Ok(Value::Object(pcrs))) =
+            (&mut version, serde_json::to_value(&deployment.pcrs))
+        {
+            version.extend(pcrs);
+        }
+        version
+    }
+
+    fn regional_deployment_json(&self, deployment: &MockDeployment) -> Value {
+        json!({
+            "uuid": format!("{}-{}", deployment.uuid, MOCK_REGION),
+            "deploymentUuid": deployment.uuid,
+            "deploymentOrder": 1,
+            "region": MOCK_REGION,
+            "failureReason": null,
+            "deployStatus": deployment.stage.deploy_status(),
+            "startedAt": deployment.started_at,
+            "completedAt": deployment.completed_at,
+            "detailedStatus": deployment.stage.detailed_status(),
+        })
+    }
+
+    fn signing_cert_for_deployment(&self, deployment: &MockDeployment) -> Value {
+        match self.certs.get(&deployment.signing_cert_uuid) {
+            Some(cert) => self.cert_json(cert),
+            None => json!({
+                "name": null,
+                "uuid": deployment.signing_cert_uuid,
+                "appUuid": MOCK_APP_UUID,
+                "certHash": "",
+                "notBefore": null,
+                "notAfter": null,
+            }),
+        }
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+// Mirrors the problem details returned by the API alongside error statuses
+fn problem(status: StatusCode, title: &str, detail: String) -> Response {
+    let body = json!({
+        "title": title,
+        "detail": detail,
+        "code": title.to_lowercase().replace(' ', "-"),
+        "requestId": format!("req_{}", chrono::Utc::now().timestamp_millis()),
+    });
+    (status, Json(body)).into_response()
+}
+
+fn not_found() -> Response {
+    problem(
+        StatusCode::NOT_FOUND,
+        "Not Found",
+        "The requested resource does not exist".to_string(),
+    )
+}
+
+fn is_authorised(headers: &HeaderMap) -> bool {
+    headers.contains_key("api-key") || headers.contains_key("authorization")
+}
+
+macro_rules! require_auth {
+    ($headers:expr) => {
+        if !is_authorised(&$headers) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+}
+
+pub fn mock_cages_api_router(base_url: String) -> Router {
+    let state: SharedState = Arc::new(Mutex::new(MockState::new(base_url)));
+    Router::new()
+        .route("/v2/cages/", get(list_cages).post(create_cage))
+        .route(
+            "/v2/cages/signing/certs",
+            get(list_signing_certs).post(create_signing_cert),
+        )
+        .route("/v2/cages/signing/certs/:cert_uuid", get(get_signing_cert))
+        .route("/v2/cages/:cage_uuid", get(get_cage).delete(delete_cage))
+        .route(
+            "/v2/cages/:cage_uuid/credentials",
+            axum::routing::post(create_deployment_intent),
+        )
+        .route(
+            "/v2/cages/:cage_uuid/deployments/:deployment_uuid",
+            get(get_deployment),
+        )
+        .route(
+            "/v2/cages/:cage_uuid/signing/certs",
+            get(get_locked_certs).put(lock_certs),
+        )
+        .route(
+            "/v2/cages/:cage_uuid/secrets",
+            get(get_secrets).put(add_secret),
+        )
+        .route("/v2/cages/:cage_uuid/secrets/:name", delete(delete_secret))
+        .route("/v2/cages/:cage_uuid/logs", get(get_logs))
+        .route("/keys/:team_uuid/apps/:app_uuid", get(get_app_keys))
+        .route("/upload/:deployment_uuid", put(upload_eif))
+        .route(
+            "/oauth/device/code",
+            axum::routing::post(create_device_code),
+        )
+        .route("/oauth/token", axum::routing::post(exchange_token))
+        .layer(Extension(state))
+}
+
+pub async fn run_mock_cages_api(port: u16) {
+    let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+    let router = mock_cages_api_router(format!("http://{addr}"));
+
+    println!("Starting mock Cages API on port: {port}");
+    println!("Set EV_API_URL=http://{addr} to point the CLI at it");
+    Server::bind(&addr)
+        .serve(router.into_make_service())
+        .await
+        .expect("Could not start Cages API");
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateCageBody {
+    name: String,
+}
+
+async fn create_cage(
+    headers: HeaderMap,
+    Json(body): Json<CreateCageBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    let uuid = state.generate_uuid("cage");
+    let timestamp = now();
+    let cage = MockCage {
+        uuid: uuid.clone(),
+        name: body.name,
+        state: "pending",
+        created_at: timestamp.clone(),
+        updated_at: timestamp,
+        locked_certs: Vec::new(),
+        secrets: Vec::new(),
+    };
+    let response = state.cage_json(&cage);
+    state.cages.insert(uuid, cage);
+    Json(response).into_response()
+}
+
+async fn list_cages(headers: HeaderMap, Extension(state): Extension<SharedState>) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    let cages: Vec<Value> = state
+        .cages
+        .values()
+        .filter(|cage| cage.state != "deleted")
+        .map(|cage| state.cage_json(cage))
+        .collect();
+    Json(json!({ "cages": cages })).into_response()
+}
+
+async fn get_cage(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    // Deletions complete on the first read after they are requested
+    let cage = match state.cages.get_mut(&cage_uuid) {
+        Some(cage) => {
+            if cage.state == "deleting" {
+                cage.state = "deleted";
+                cage.updated_at = now();
+            }
+            cage.clone()
+        }
+        None => return not_found(),
+    };
+
+    let mut response = state.cage_json(&cage);
+    let deployments: Vec<Value> = state
+        .deployments
+        .values()
+        .filter(|deployment| deployment.cage_uuid == cage_uuid)
+        .map(|deployment| {
+            let mut deployment_json = state.deployment_json(deployment);
+            deployment_json["teeCageVersion"] = state.version_json(deployment);
+            deployment_json
+        })
+        .collect();
+    response["teeCageDeployments"] = json!(deployments);
+    Json(response).into_response()
+}
+
+async fn delete_cage(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    let cage = match state.cages.get_mut(&cage_uuid) {
+        Some(cage) => {
+            cage.state = "deleting";
+            cage.updated_at = now();
+            cage.clone()
+        }
+        None => return not_found(),
+    };
+    Json(state.cage_json(&cage)).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeploymentIntentBody {
+    #[serde(flatten)]
+    pcrs: crate::enclave::PCRs,
+    debug_mode: bool,
+    metadata: DeploymentIntentMetadata,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeploymentIntentMetadata {
+    data_plane_version: String,
+    installer_version: Option<String>,
+}
+
+async fn create_deployment_intent(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Json(body): Json<DeploymentIntentBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    let signing_cert_uuid = match state.cages.get(&cage_uuid) {
+        Some(cage) => cage.locked_certs.first().cloned().unwrap_or_default(),
+        None => return not_found(),
+    };
+    let version = state
+        .deployments
+        .values()
+        .filter(|deployment| deployment.cage_uuid == cage_uuid)
+        .count() as u32
+        + 1;
+    let deployment_uuid = state.generate_uuid("deployment");
+    let version_uuid = state.generate_uuid("version");
+    let deployment = MockDeployment {
+        uuid: deployment_uuid.clone(),
+        cage_uuid: cage_uuid.clone(),
+        version_uuid,
+        version,
+        debug_mode: body.debug_mode,
+        signing_cert_uuid,
+        data_plane_version: body.metadata.data_plane_version,
+        installer_version: body.metadata.installer_version,
+        pcrs: body.pcrs,
+        started_at: now(),
+        completed_at: None,
+        stage: DeploymentStage::AwaitingUpload,
+    };
+    state
+        .deployments
+        .insert(deployment_uuid.clone(), deployment);
+
+    Json(json!({
+        "signedUrl": format!("{}/upload/{}", state.base_url, deployment_uuid),
+        "cageUuid": cage_uuid,
+        "deploymentUuid": deployment_uuid,
+        "version": version,
+    }))
+    .into_response()
+}
+
+async fn upload_eif(
+    Path(deployment_uuid): Path<String>,
+    body: Bytes,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    match state.deployments.get_mut(&deployment_uuid) {
+        Some(deployment) if deployment.stage == DeploymentStage::AwaitingUpload => {
+            log::debug!("Received {} byte upload for {deployment_uuid}", body.len());
+            deployment.stage = DeploymentStage::Building;
+            StatusCode::OK.into_response()
+        }
+        Some(_) => StatusCode::CONFLICT.into_response(),
+        None => not_found(),
+    }
+}
+
+async fn get_deployment(
+    headers: HeaderMap,
+    Path((cage_uuid, deployment_uuid)): Path<(String, String)>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    let deployment = match state.deployments.get_mut(&deployment_uuid) {
+        Some(deployment) if deployment.cage_uuid == cage_uuid => {
+            let current = deployment.clone();
+            deployment.stage = deployment.stage.next();
+            if deployment.stage == DeploymentStage::Deployed && deployment.completed_at.is_none() {
+                deployment.completed_at = Some(now());
+            }
+            current
+        }
+        _ => return not_found(),
+    };
+    if deployment.stage == DeploymentStage::Deployed {
+        if let Some(cage) = state.cages.get_mut(&cage_uuid) {
+            cage.state = "active";
+        }
+    }
+
+    let mut response = state.deployment_json(&deployment);
+    response["teeCageVersion"] = state.version_json(&deployment);
+    response["teeCageSigningCert"] = state.signing_cert_for_deployment(&deployment);
+    response["teeCageRegionalDeployments"] = json!([state.regional_deployment_json(&deployment)]);
+    Json(response).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSigningCertBody {
+    cert_hash: String,
+    name: String,
+    not_before: String,
+    not_after: String,
+}
+
+async fn create_signing_cert(
+    headers: HeaderMap,
+    Json(body): Json<CreateSigningCertBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    let uuid = state.generate_uuid("cert");
+    let cert = MockSigningCert {
+        uuid: uuid.clone(),
+        name: body.name,
+        cert_hash: body.cert_hash,
+        not_before: body.not_before,
+        not_after: body.not_after,
+    };
+    let response = state.cert_json(&cert);
+    state.certs.insert(uuid, cert);
+    Json(response).into_response()
+}
+
+async fn list_signing_certs(
+    headers: HeaderMap,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    let certs: Vec<Value> = state
+        .certs
+        .values()
+        .map(|cert| state.cert_json(cert))
+        .collect();
+    Json(json!({ "certs": certs })).into_response()
+}
+
+async fn get_signing_cert(
+    headers: HeaderMap,
+    Path(cert_uuid): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    match state.certs.get(&cert_uuid) {
+        Some(cert) => Json(state.cert_json(cert)).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn get_locked_certs(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    let cage = match state.cages.get(&cage_uuid) {
+        Some(cage) => cage,
+        None => return not_found(),
+    };
+    let certs: Vec<Value> = cage
+        .locked_certs
+        .iter()
+        .filter_map(|cert_uuid| state.certs.get(cert_uuid))
+        .map(|cert| state.cert_json(cert))
+        .collect();
+    Json(json!(certs)).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LockCertsBody {
+    cert_uuids: Vec<String>,
+}
+
+async fn lock_certs(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Json(body): Json<LockCertsBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    if let Some(unknown_cert) = body
+        .cert_uuids
+        .iter()
+        .find(|cert_uuid| !state.certs.contains_key(*cert_uuid))
+    {
+        log::debug!("Attempted to lock cage to unknown cert {unknown_cert}");
+        return problem(
+            StatusCode::BAD_REQUEST,
+            "Unknown Signing Cert",
+            format!("No signing cert exists with uuid {unknown_cert}"),
+        );
+    }
+    let cage = match state.cages.get_mut(&cage_uuid) {
+        Some(cage) => cage,
+        None => return not_found(),
+    };
+    cage.locked_certs = body.cert_uuids.clone();
+    let response: Vec<Value> = body
+        .cert_uuids
+        .iter()
+        .map(|cert_uuid| json!({ "cageUuid": cage_uuid, "signingCertUuid": cert_uuid }))
+        .collect();
+    Json(json!(response)).into_response()
+}
+
+#[derive(Deserialize)]
+struct AddSecretBody {
+    name: String,
+    secret: String,
+}
+
+async fn add_secret(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Json(body): Json<AddSecretBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    match state.cages.get_mut(&cage_uuid) {
+        Some(cage) => {
+            cage.secrets.retain(|(name, _)| name != &body.name);
+            cage.secrets.push((body.name, body.secret));
+            StatusCode::OK.into_response()
+        }
+        None => not_found(),
+    }
+}
+
+async fn get_secrets(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    match state.cages.get(&cage_uuid) {
+        Some(cage) => {
+            let secrets: Vec<Value> = cage
+                .secrets
+                .iter()
+                .map(|(name, secret)| json!({ "name": name, "secret": secret }))
+                .collect();
+            Json(json!({ "secrets": secrets })).into_response()
+        }
+        None => not_found(),
+    }
+}
+
+async fn delete_secret(
+    headers: HeaderMap,
+    Path((cage_uuid, name)): Path<(String, String)>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let mut state = state.lock().unwrap();
+    match state.cages.get_mut(&cage_uuid) {
+        Some(cage)
+            if cage
+                .secrets
+                .iter()
+                .any(|(secret_name, _)| secret_name == &name) =>
+        {
+            cage.secrets.retain(|(secret_name, _)| secret_name != &name);
+            StatusCode::OK.into_response()
+        }
+        _ => not_found(),
+    }
+}
+
+async fn get_logs(
+    headers: HeaderMap,
+    Path(cage_uuid): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    require_auth!(headers);
+    let state = state.lock().unwrap();
+    if !state.cages.contains_key(&cage_uuid) {
+        return not_found();
+    }
+    let start_time = params.get("startTime").cloned().unwrap_or_default();
+    let end_time = params.get("endTime").cloned().unwrap_or_default();
+    let timestamp = end_time.parse::<i64>().unwrap_or_default();
+    // The next token is the offset of the next page of events
+    let offset = params
+        .get("nextToken")
+        .and_then(|next_token| next_token.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut deployments: Vec<&MockDeployment> = state
+        .deployments
+        .values()
+        .filter(|deployment| {
+            deployment.cage_uuid == cage_uuid && deployment.stage == DeploymentStage::Deployed
+        })
+        .collect();
+    deployments.sort_by_key(|deployment| deployment.version);
+    let all_events: Vec<Value> = deployments
+        .into_iter()
+        .flat_map(|deployment| {
+            MOCK_LOG_MESSAGES
+                .iter()
+                .enumerate()
+                .map(move |(index, message)| {
+                    let event_time = timestamp - (MOCK_LOG_MESSAGES.len() - index) as i64 * 1000;
+                    json!({
+                        "timestamp": event_time,
+                        "message": format!("{message} (version {})", deployment.version),
+                        "ingestionTime": event_time,
+                        "instanceId": format!("i-{}", deployment.uuid),
+                    })
+                })
+        })
+        .collect();
+    let log_events: Vec<&Value> = all_events
+        .iter()
+        .skip(offset)
+        .take(MOCK_LOG_PAGE_SIZE)
+        .collect();
+    let next_offset = offset + log_events.len();
+    let next_token = (next_offset < all_events.len()).then(|| next_offset.to_string());
+    Json(json!({
+        "logEvents": log_events,
+        "nextToken": next_token,
+        "startTime": start_time,
+        "endTime": end_time,
+    }))
+    .into_response()
+}
+
+async fn get_app_keys(Path((_team_uuid, _app_uuid)): Path<(String, String)>) -> Response {
+    Json(json!({
+        "ecdhP256KeyUncompressed": MOCK_ECDH_P256_KEY_UNCOMPRESSED,
+        "ecdhP256Key": MOCK_ECDH_P256_KEY,
+        "ecdhKey": MOCK_ECDH_KEY,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct TokenRequestBody {
+    grant_type: String,
+    device_code: Option<String>,
+    refresh_token: Option<String>,
+}
+
+fn token_error(error: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
+
+fn issue_token(state: &mut MockState) -> Response {
+    let access_token = state.generate_uuid("access");
+    let refresh_token = state.generate_uuid("refresh");
+    state.refresh_tokens.push(refresh_token.clone());
+    Json(json!({
+        "access_token": access_token,
+        "refresh_token": refresh_token,
+        "token_type": "Bearer",
+        "expires_in": 3600,
+    }))
+    .into_response()
+}
+
+async fn create_device_code(Extension(state): Extension<SharedState>) -> Response {
+    let mut state = state.lock().unwrap();
+    let device_code = state.generate_uuid("device");
+    let user_code = format!("MOCK-{:04}", state.next_id);
+    // Report the login as pending once, as a real server would while waiting on the user
+    state.device_codes.insert(device_code.clone(), 1);
+    let verification_uri = format!("{}/activate", state.base_url);
+    Json(json!({
+        "device_code": device_code,
+        "user_code": user_code,
+        "verification_uri_complete": format!("{verification_uri}?user_code={user_code}"),
+        "verification_uri": verification_uri,
+        "expires_in": 300,
+        "interval": 1,
+    }))
+    .into_response()
+}
+
+async fn exchange_token(
+    Form(body): Form<TokenRequestBody>,
+    Extension(state): Extension<SharedState>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+    match body.grant_type.as_str() {
+        "urn:ietf:params:oauth:grant-type:device_code" => {
+            let device_code = body.device_code.unwrap_or_default();
+            match state.device_codes.get_mut(&device_code) {
+                Some(pending_polls) if *pending_polls > 0 => {
+                    *pending_polls -= 1;
+                    token_error("authorization_pending")
+                }
+                Some(_) => {
+                    state.device_codes.remove(&device_code);
+                    issue_token(&mut state)
+                }
+                None => token_error("invalid_grant"),
+            }
+        }
+        "refresh_token" => {
+            let refresh_token = body.refresh_token.unwrap_or_default();
+            match state
+                .refresh_tokens
+                .iter()
+                .position(|token| token == &refresh_token)
+            {
+                Some(index) => {
+                    state.refresh_tokens.remove(index);
+                    issue_token(&mut state)
+                }
+                None => token_error("invalid_grant"),
+            }
+        }
+        _ => token_error("unsupported_grant_type"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::cage::{
+        Cage, CreateCageDeploymentIntentResponse, GetCageDeploymentResponse, GetCageResponse,
+    };
+    use crate::test_utils::start_mock_cages_api;
+
+    #[tokio::test]
+    async fn test_mock_api_rejects_unauthenticated_requests() {
+        let base_url = start_mock_cages_api().await;
+        let response = reqwest::get(format!("{base_url}/v2/cages/")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_mock_api_moves_deployment_through_lifecycle() {
+        let base_url = start_mock_cages_api().await;
+        let client = reqwest::Client::new();
+
+        let cage: Cage = client
+            .post(format!("{base_url}/v2/cages/"))
+            .header("api-key", "test")
+            .json(&json!({ "name": "mock-cage", "isTimeBound": false }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let intent: CreateCageDeploymentIntentResponse = client
+            .post(format!("{base_url}/v2/cages/{}/credentials", cage.uuid()))
+            .header("api-key", "test")
+            .json(&json!({
+                "PCR0": "000",
+                "PCR1": "111",
+                "PCR2": "222",
+                "debugMode": false,
+                "metadata": { "dataPlaneVersion": "0.0.0" }
+            }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(intent.version(), 1);
+
+        let upload_response = client
+            .put(intent.signed_url())
+            .body("eif")
+            .send()
+            .await
+            .unwrap();
+        assert!(upload_response.status().is_success());
+
+        let deployment_url = format!(
+            "{base_url}/v2/cages/{}/deployments/{}",
+            intent.cage_uuid(),
+            intent.deployment_uuid()
+        );
+        let mut observed = Vec::new();
+        for _ in 0..4 {
+            let deployment: GetCageDeploymentResponse = client
+                .get(&deployment_url)
+                .header("api-key", "test")
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            observed.push((deployment.is_built(), deployment.is_finished()));
+        }
+        assert_eq!(
+            observed,
+            vec![(false, false), (true, false), (true, false), (true, true)]
+        );
+
+        let cage: GetCageResponse = client
+            .get(format!("{base_url}/v2/cages/{}", cage.uuid()))
+            .header("api-key", "test")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let active_pcrs = cage.active_deployment().unwrap().version().pcrs().cloned();
+        assert_eq!(active_pcrs.map(|pcrs| pcrs.pcr0), Some("000".to_string()));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod api;
+
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::routing::post;
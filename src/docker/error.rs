@@ -38,6 +38,8 @@ pub enum DockerError {
     DaemonNotRunning,
     #[error("Restricted port exposed. Cannot forward traffic to :{0}, address is already in use.")]
     RestrictedPortExposed(u16),
+    #[error("Port {0}/udp is exposed, but Cages can only forward TCP traffic. Remove it from the EXPOSE directives in the Dockerfile.")]
+    UdpPortExposed(u16),
     #[error(transparent)]
     CommandError(#[from] CommandError),
 }
@@ -1,4 +1,6 @@
 pub mod command;
 pub mod error;
 pub mod parse;
+pub mod span;
 pub mod utils;
+pub mod vars;
@@ -1,3 +1,5 @@
+use super::span::{Span, Spanned};
+use super::vars::{BuildVariables, Expansion};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::StreamExt;
 use itertools::join;
@@ -34,12 +36,75 @@ impl From<u8> for Mode {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A port exposed by an EXPOSE directive, such as `8080` or `53/udp`. The protocol defaults to TCP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExposedPort {
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+impl ExposedPort {
+    pub fn tcp(port: u16) -> Self {
+        Self {
+            port,
+            protocol: Protocol::Tcp,
+        }
+    }
+
+    /// Parses each of the whitespace separated ports in the arguments of an EXPOSE directive
+    pub fn parse_all(arguments: &str) -> Result<Vec<Self>, DecodeError> {
+        arguments.split_whitespace().map(str::parse).collect()
+    }
+}
+
+impl std::str::FromStr for ExposedPort {
+    type Err = DecodeError;
+
+    fn from_str(exposed: &str) -> Result<Self, Self::Err> {
+        let (port, protocol) = match exposed.split_once('/') {
+            Some((port, protocol)) => (port, Some(protocol)),
+            None => (exposed, None),
+        };
+        let protocol = match protocol.map(str::to_ascii_lowercase).as_deref() {
+            None | Some("tcp") => Protocol::Tcp,
+            Some("udp") => Protocol::Udp,
+            Some(_) => return Err(DecodeError::InvalidExposedProtocol(exposed.to_string())),
+        };
+        Ok(Self {
+            port: port.parse().map_err(DecodeError::InvalidExposedPort)?,
+            protocol,
+        })
+    }
+}
+
+impl std::fmt::Display for ExposedPort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.port, self.protocol)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Directive {
     Add {
         source_url: String,
         destination_path: String,
     },
+    Arg(Bytes),
     Comment(Bytes),
     Entrypoint {
         mode: Option<Mode>,
@@ -49,12 +114,16 @@ pub enum Directive {
         mode: Option<Mode>,
         tokens: Vec<String>,
     },
+    // The ports are only parsed once any variables in the arguments have been expanded
     Expose {
-        port: Option<u16>,
+        ports: Option<Vec<ExposedPort>>,
+        arguments: Bytes,
     },
+    Env(Bytes),
+    From(Bytes),
     Run(Bytes),
     User(Bytes),
-    // we only need to care about entrypoint, cmd, expose, run and user for cages
+    // we only need to care about arg, entrypoint, cmd, env, expose, from, run and user for cages
     Other {
         directive: String,
         arguments: Bytes,
@@ -74,6 +143,10 @@ impl Directive {
         matches!(self, Self::Expose { .. })
     }
 
+    pub fn is_from(&self) -> bool {
+        matches!(self, Self::From(_))
+    }
+
     #[allow(dead_code)]
     pub fn is_run(&self) -> bool {
         matches!(self, Self::Run(_))
@@ -83,6 +156,17 @@ impl Directive {
         matches!(self, Self::User(_))
     }
 
+    // BuildKit supports heredocs in RUN, COPY and ADD
+    fn supports_heredocs(&self) -> bool {
+        match self {
+            Self::Run(_) => true,
+            Self::Other { directive, .. } => {
+                directive.eq_ignore_ascii_case("COPY") || directive.eq_ignore_ascii_case("ADD")
+            }
+            _ => false,
+        }
+    }
+
     pub fn set_mode(&mut self, new_mode: Mode) {
         match self {
             Self::Entrypoint { mode, .. } | Self::Cmd { mode, .. } => {
@@ -102,29 +186,18 @@ impl Directive {
     pub fn set_arguments(&mut self, given_arguments: Vec<u8>) -> Result<(), DecodeError> {
         match self {
             Self::Entrypoint { mode, tokens } | Self::Cmd { mode, tokens } => {
-                let mode = mode.as_ref().unwrap();
-                if mode.is_exec() {
-                    // docker exec commands are given in the form of: ["exec_cmd", "arg1", "arg2"]
-                    // so to isolate the individual tokens we need to:
-                    // - remove the first and last characters ('[', ']')
-                    // - split on "," to get individual terms
-                    // - trim each term and remove first and last ('"', '"')
-                    let terms = &given_arguments[1..given_arguments.len() - 1]; // remove square brackets
-                    let parsed_tokens: Vec<String> = terms
-                        .split(|byte| &[*byte] == b",")
-                        .filter_map(|token_slice| std::str::from_utf8(token_slice).ok())
-                        .map(|token| {
-                            let trimmed_token = token.trim();
-                            let token_without_leading_quote =
-                                trimmed_token.strip_prefix('"').unwrap_or(trimmed_token);
-                            token_without_leading_quote
-                                .strip_suffix('"')
-                                .unwrap_or(token_without_leading_quote)
-                                .to_string()
-                        })
-                        .collect();
-                    *tokens = parsed_tokens;
+                // exec form arguments are a JSON array of strings. Like Docker, anything which
+                // isn't valid JSON is run with the shell instead.
+                let exec_tokens = mode
+                    .as_ref()
+                    .unwrap()
+                    .is_exec()
+                    .then(|| parse_exec_form(&given_arguments))
+                    .flatten();
+                if let Some(exec_tokens) = exec_tokens {
+                    *tokens = exec_tokens;
                 } else {
+                    *mode = Some(Mode::Shell);
                     // docker shell commands are given in the form of: exec_cmd arg1 arg2
                     // so we need to split on space and convert to strings
                     *tokens = given_arguments
@@ -154,13 +227,20 @@ impl Directive {
                     .ok_or_else(|| DecodeError::IncompleteInstruction)?
                     .to_string();
             }
-            Self::Expose { port } => {
-                let port_str = std::str::from_utf8(&given_arguments)?;
-                let parsed_port = port_str.parse().map_err(DecodeError::InvalidExposedPort)?;
-                *port = Some(parsed_port);
+            Self::Expose { ports, arguments } => {
+                let ports_str = std::str::from_utf8(&given_arguments)?;
+                *ports = if ports_str.contains('$') {
+                    None
+                } else {
+                    Some(ExposedPort::parse_all(ports_str)?)
+                };
+                *arguments = Bytes::from(given_arguments);
             }
             Self::Other { arguments, .. }
+            | Self::Arg(arguments)
             | Self::Comment(arguments)
+            | Self::Env(arguments)
+            | Self::From(arguments)
             | Self::Run(arguments)
             | Self::User(arguments) => *arguments = Bytes::from(given_arguments),
         };
@@ -173,7 +253,13 @@ impl Directive {
                 source_url,
                 destination_path,
             } => format!("{source_url} {destination_path}"),
-            Self::Comment(bytes)
+            Self::Arg(bytes)
+            | Self::Comment(bytes)
+            | Self::Env(bytes)
+            | Self::Expose {
+                arguments: bytes, ..
+            }
+            | Self::From(bytes)
             | Self::Run(bytes)
             | Self::User(bytes)
             | Self::Other {
@@ -183,16 +269,15 @@ impl Directive {
                 .to_string(),
             Self::Entrypoint { mode, tokens } | Self::Cmd { mode, tokens } => {
                 if mode.as_ref().map(|mode| mode.is_exec()).unwrap_or(false) {
-                    // Recreate an exec mode command — wrap tokens in quotes, and join with ", "
-                    let exec_args = tokens.iter().map(|token| format!("\"{}\"", token));
+                    // Recreate an exec mode command — encode tokens as JSON strings, and join with ", "
+                    let exec_args = tokens
+                        .iter()
+                        .map(|token| serde_json::to_string(token).unwrap_or_default());
                     format!("[{}]", join(exec_args, ", "))
                 } else {
                     join(tokens.as_slice(), " ")
                 }
             }
-            Self::Expose { port } => {
-                return port.as_ref().map(|port| port.to_string());
-            }
         };
         Some(formatted_args)
     }
@@ -225,17 +310,11 @@ impl Directive {
 
     pub fn new_env(key: &str, val: &str) -> Self {
         let env_string = format!("{}={}", key, val);
-        Self::Other {
-            directive: "ENV".into(),
-            arguments: env_string.into(),
-        }
+        Self::Env(env_string.into())
     }
 
     pub fn new_from(key: String) -> Self {
-        Self::Other {
-            directive: "FROM".into(),
-            arguments: key.clone().into(),
-        }
+        Self::From(key.into())
     }
 
     pub fn new_copy(key: String) -> Self {
@@ -255,16 +334,159 @@ impl Directive {
     pub fn new_user<S: Into<Bytes>>(user: S) -> Self {
         Self::User(user.into())
     }
+
+    // FROM arguments take the form: [--platform=<platform>] <image> [AS <name>]
+    fn parsed_from_arguments(&self) -> Vec<&str> {
+        match self {
+            Self::From(arguments) => std::str::from_utf8(arguments.as_ref())
+                .map(|arguments| {
+                    arguments
+                        .split_whitespace()
+                        .filter(|argument| !argument.starts_with("--"))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The image a FROM directive builds on, which may be the name of an earlier stage
+    pub fn base_image(&self) -> Option<&str> {
+        self.parsed_from_arguments().first().copied()
+    }
+
+    /// The name given to the stage started by a FROM directive with `AS <name>`
+    pub fn stage_name(&self) -> Option<&str> {
+        match self.parsed_from_arguments().as_slice() {
+            [_, keyword, name] if keyword.eq_ignore_ascii_case("as") => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of the directive with the variables in scope expanded, for the directives the
+    /// CLI interprets. Any other directive is returned as it is.
+    pub fn expand(&self, vars: &BuildVariables) -> Result<Directive, DecodeError> {
+        let expand_arguments = |directive: &Directive, expansion| {
+            let arguments = directive.arguments().unwrap_or_default();
+            let mut expanded = directive.clone();
+            expanded.set_arguments(vars.expand(&arguments, expansion).into_bytes())?;
+            Ok(expanded)
+        };
+        match self {
+            Self::Entrypoint { mode, tokens } | Self::Cmd { mode, tokens }
+                if mode.as_ref().is_some_and(Mode::is_exec) =>
+            {
+                let mut expanded = self.clone();
+                if let Self::Entrypoint {
+                    tokens: expanded_tokens,
+                    ..
+                }
+                | Self::Cmd {
+                    tokens: expanded_tokens,
+                    ..
+                } = &mut expanded
+                {
+                    *expanded_tokens = tokens
+                        .iter()
+                        .map(|token| vars.expand(token, Expansion::Exec))
+                        .collect();
+                }
+                Ok(expanded)
+            }
+            Self::Entrypoint { .. } | Self::Cmd { .. } => expand_arguments(self, Expansion::Shell),
+            Self::Expose { .. } => {
+                let mut expanded = expand_arguments(self, Expansion::Words)?;
+                // Unlike the decoder, a port which still isn't a number after expansion is an error
+                if let Self::Expose { ports, arguments } = &mut expanded {
+                    *ports = Some(ExposedPort::parse_all(std::str::from_utf8(arguments)?)?);
+                }
+                Ok(expanded)
+            }
+            Self::From(_) | Self::User(_) => expand_arguments(self, Expansion::Words),
+            _ => Ok(self.clone()),
+        }
+    }
+}
+
+/// A stage of a multi-stage build, made up of a FROM directive and the directives up to the next
+/// FROM. Directives before the first FROM, such as global ARGs, are kept in a stage of their own
+/// without a FROM.
+#[derive(Clone, Debug)]
+pub struct BuildStage {
+    from: Option<Spanned<Directive>>,
+    directives: Vec<Spanned<Directive>>,
+}
+
+impl BuildStage {
+    pub fn new(from: Option<Spanned<Directive>>, directives: Vec<Spanned<Directive>>) -> Self {
+        Self { from, directives }
+    }
+
+    pub fn from_directive(&self) -> Option<&Spanned<Directive>> {
+        self.from.as_ref()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.from.as_ref().and_then(|from| from.stage_name())
+    }
+
+    pub fn base_image(&self) -> Option<&str> {
+        self.from.as_ref().and_then(|from| from.base_image())
+    }
+
+    /// The directives in the stage, not including its FROM
+    pub fn directives(&self) -> &[Spanned<Directive>] {
+        &self.directives
+    }
+
+    /// Returns every directive in the stage, starting with its FROM
+    pub fn into_directives(self) -> Vec<Directive> {
+        self.from
+            .into_iter()
+            .chain(self.directives)
+            .map(Spanned::into_inner)
+            .collect()
+    }
+
+    /// Splits a Dockerfile's directives into the stages they belong to
+    pub fn split(directives: Vec<Spanned<Directive>>) -> Vec<BuildStage> {
+        let mut stages = Vec::new();
+        let mut current = BuildStage {
+            from: None,
+            directives: Vec::new(),
+        };
+        for directive in directives {
+            if directive.is_from() {
+                let previous = std::mem::replace(
+                    &mut current,
+                    BuildStage {
+                        from: Some(directive),
+                        directives: Vec::new(),
+                    },
+                );
+                if previous.from.is_some() || !previous.directives.is_empty() {
+                    stages.push(previous);
+                }
+            } else {
+                current.directives.push(directive);
+            }
+        }
+        stages.push(current);
+        stages
+    }
 }
 
 impl std::fmt::Display for Directive {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let prefix = match self {
             Self::Add { .. } => "ADD",
+            Self::Arg(_) => "ARG",
             Self::Comment(_) => "#",
             Self::Entrypoint { .. } => "ENTRYPOINT",
             Self::Cmd { .. } => "CMD",
             Self::Expose { .. } => "EXPOSE",
+            Self::Env(_) => "ENV",
+            Self::From(_) => "FROM",
             Self::Run(_) => "RUN",
             Self::User(_) => "USER",
             Self::Other { directive, .. } => directive.as_str(),
@@ -300,7 +522,13 @@ impl TryFrom<&[u8]> for Directive {
                 mode: None,
                 tokens: Vec::new(),
             },
-            "EXPOSE" => Self::Expose { port: None },
+            "ARG" => Self::Arg(Bytes::new()),
+            "ENV" => Self::Env(Bytes::new()),
+            "EXPOSE" => Self::Expose {
+                ports: None,
+                arguments: Bytes::new(),
+            },
+            "FROM" => Self::From(Bytes::new()),
             "RUN" => Self::Run(Bytes::new()),
             "USER" => Self::User(Bytes::new()),
             _ => Self::Other {
@@ -313,6 +541,14 @@ impl TryFrom<&[u8]> for Directive {
     }
 }
 
+// Decodes exec form arguments, which must be a JSON array of strings. Line continuations are removed
+// first, as Docker removes them before parsing the instruction.
+fn parse_exec_form(arguments: &[u8]) -> Option<Vec<String>> {
+    let arguments = std::str::from_utf8(arguments).ok()?;
+    let arguments = arguments.replace("\\\r\n", "").replace("\\\n", "");
+    serde_json::from_str(arguments.trim()).ok()
+}
+
 #[derive(Clone)]
 enum NewLineBehaviour {
     Escaped,
@@ -384,6 +620,90 @@ impl std::fmt::Display for StringStack {
     }
 }
 
+// A BuildKit heredoc, such as `RUN <<EOF`. Its body runs from the next line up to a line holding
+// only the delimiter, which can be indented with tabs when it's written as `<<-EOF`.
+#[derive(Clone, Debug, PartialEq)]
+struct Heredoc {
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+impl Heredoc {
+    fn find_all(arguments: &[u8]) -> Vec<Heredoc> {
+        let Ok(arguments) = std::str::from_utf8(arguments) else {
+            return Vec::new();
+        };
+        shell_words(arguments)
+            .into_iter()
+            .filter_map(|word| {
+                // heredocs can be redirected to a file descriptor, e.g. 3<<EOF
+                let word = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                let heredoc = word.strip_prefix("<<")?;
+                let (strip_tabs, delimiter) = match heredoc.strip_prefix('-') {
+                    Some(delimiter) => (true, delimiter),
+                    None => (false, heredoc),
+                };
+                let delimiter = delimiter.trim_matches(|c| c == '"' || c == '\'');
+                if delimiter.is_empty() || delimiter.contains('<') {
+                    return None;
+                }
+                Some(Heredoc {
+                    delimiter: delimiter.to_string(),
+                    strip_tabs,
+                })
+            })
+            .collect()
+    }
+
+    fn is_terminated_by(&self, line: &[u8]) -> bool {
+        let line = if self.strip_tabs {
+            let indent = line.iter().take_while(|byte| **byte == b'\t').count();
+            &line[indent..]
+        } else {
+            line
+        };
+        line == self.delimiter.as_bytes()
+    }
+}
+
+// Splits arguments into words at unquoted whitespace, keeping their quotes, so `<<` inside a quoted
+// string isn't mistaken for a heredoc
+fn shell_words(arguments: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut word_start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in arguments.char_indices() {
+        if c.is_whitespace() && quote.is_none() && !escaped {
+            if let Some(start) = word_start.take() {
+                words.push(&arguments[start..index]);
+            }
+            continue;
+        }
+        word_start.get_or_insert(index);
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        }
+    }
+    words.extend(word_start.map(|start| &arguments[start..]));
+    words
+}
+
+// The line currently being read, after the last newline in the arguments
+fn last_line(arguments: &[u8]) -> &[u8] {
+    let line_start = arguments
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |index| index + 1);
+    &arguments[line_start..]
+}
+
 // States for the Dockerfile decoder's internal state management
 #[derive(Clone)]
 enum DecoderState {
@@ -393,6 +713,8 @@ enum DecoderState {
         arguments: Option<BytesMut>,
         new_line_behaviour: NewLineBehaviour,
         string_stack: StringStack,
+        // Heredocs whose body is still being read, in the order they appear in the directive
+        heredocs: Vec<Heredoc>,
     },
     Comment(BytesMut),
     Whitespace,
@@ -409,9 +731,16 @@ impl std::convert::TryInto<Option<Directive>> for DecoderState {
             Self::DirectiveArguments {
                 mut directive,
                 arguments,
+                heredocs,
                 ..
             } => {
                 let arguments = arguments.ok_or(DecodeError::IncompleteInstruction)?;
+                // The last heredoc can be ended by the final line of a file without a newline
+                match heredocs.as_slice() {
+                    [] => {}
+                    [heredoc] if heredoc.is_terminated_by(last_line(&arguments)) => {}
+                    _ => return Err(DecodeError::IncompleteInstruction),
+                }
                 directive.set_arguments(arguments.to_vec())?;
                 Ok(Some(directive))
             }
@@ -434,6 +763,30 @@ pub enum DecodeError {
     IncompleteInstruction,
     #[error("Failed to parse the exposed port")]
     InvalidExposedPort(ParseIntError),
+    #[error("Invalid protocol in exposed port {0}, expected tcp or udp")]
+    InvalidExposedProtocol(String),
+    #[error("{error}")]
+    At { error: Box<DecodeError>, span: Span },
+}
+
+impl DecodeError {
+    /// Attaches the location of the offending instruction, unless the error already has one
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            Self::At { .. } => self,
+            error => Self::At {
+                error: Box::new(error),
+                span,
+            },
+        }
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::At { span, .. } => Some(span),
+            _ => None,
+        }
+    }
 }
 
 impl std::convert::TryFrom<u8> for DecoderState {
@@ -454,8 +807,32 @@ impl std::convert::TryFrom<u8> for DecoderState {
     }
 }
 
+/// Where the decoder is in its source
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl std::default::Default for Position {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
 pub struct DockerfileDecoder {
     current_state: Option<DecoderState>,
+    // position of the next byte to be read
+    position: Position,
+    // position of the byte most recently read
+    last_position: Position,
+    // position of the first byte of the directive being decoded
+    directive_start: Position,
 }
 
 #[allow(dead_code)]
@@ -469,29 +846,57 @@ impl DockerfileDecoder {
     pub fn new() -> Self {
         Self {
             current_state: None,
+            position: Position::default(),
+            last_position: Position::default(),
+            directive_start: Position::default(),
         }
     }
 
-    pub fn flush(&mut self) -> Result<Option<Directive>, DecodeError> {
-        if self.current_state.is_none() {
-            Ok(None)
-        } else {
-            self.current_state.take().unwrap().try_into()
+    pub fn flush(&mut self) -> Result<Option<Spanned<Directive>>, DecodeError> {
+        match self.current_state.take() {
+            None => Ok(None),
+            Some(state) => {
+                let span = self.span_to(self.position.offset);
+                let directive: Option<Directive> =
+                    state.try_into().map_err(|e: DecodeError| e.at(span))?;
+                Ok(directive.map(|directive| Spanned::new(directive, span)))
+            }
         }
     }
 
     fn read_u8(&mut self, src: &mut BytesMut) -> Option<u8> {
         if src.has_remaining() {
-            Some(src.get_u8())
+            let byte = src.get_u8();
+            self.last_position = self.position;
+            self.position.offset += 1;
+            if byte == b'\n' {
+                self.position.line += 1;
+                self.position.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // continuation bytes of a multi-byte character don't start a new column
+                self.position.column += 1;
+            }
+            Some(byte)
         } else {
             None
         }
     }
 
+    /// The span from the start of the current directive up to the given offset
+    fn span_to(&self, end: usize) -> Span {
+        Span {
+            start: self.directive_start.offset,
+            end: end.max(self.directive_start.offset),
+            line: self.directive_start.line,
+            column: self.directive_start.column,
+        }
+    }
+
     fn derive_new_line_state(
         &mut self,
         first_byte: u8,
     ) -> Result<Option<DecoderState>, DecodeError> {
+        self.directive_start = self.last_position;
         let initial_state = if first_byte.is_ascii_whitespace() {
             DecoderState::Whitespace
         } else if first_byte.is_ascii_alphabetic() {
@@ -557,6 +962,7 @@ impl DockerfileDecoder {
                         arguments: None,
                         new_line_behaviour: NewLineBehaviour::Observe,
                         string_stack: StringStack::new(),
+                        heredocs: Vec::new(),
                     }));
                 }
                 Some(byte) if byte.is_ascii() => {
@@ -576,9 +982,27 @@ impl DockerfileDecoder {
         arguments: &mut Option<BytesMut>,
         new_line_behaviour: &mut NewLineBehaviour,
         string_stack: &mut StringStack,
+        heredocs: &mut Vec<Heredoc>,
     ) -> Result<Option<Directive>, DecodeError> {
         // read until new line, not preceded by '\'
         loop {
+            if !heredocs.is_empty() {
+                // heredoc bodies are read verbatim, up to the line holding their delimiter
+                let Some(next_byte) = self.read_u8(src) else {
+                    return Ok(None);
+                };
+                // safety: heredocs are only found once the directive has arguments
+                let argument_mut = arguments.as_mut().unwrap();
+                if next_byte == b'\n' && heredocs[0].is_terminated_by(last_line(argument_mut)) {
+                    heredocs.remove(0);
+                    if heredocs.is_empty() {
+                        directive.set_arguments(argument_mut.to_vec())?;
+                        return Ok(Some(directive.clone()));
+                    }
+                }
+                argument_mut.put_u8(next_byte);
+                continue;
+            }
             match self.read_u8(src) {
                 // if we see a newline character or backslash as the first character for a directives argument
                 // return an error
@@ -598,8 +1022,15 @@ impl DockerfileDecoder {
                 // new line signifies end of directive if unescaped
                 Some(next_byte) if next_byte == b'\n' => {
                     // safety: first arm will be matched if next_byte is a newline and arguments is None
-                    let content = arguments.as_ref().unwrap().to_vec();
-                    directive.set_arguments(content)?;
+                    let argument_mut = arguments.as_mut().unwrap();
+                    if directive.supports_heredocs() {
+                        *heredocs = Heredoc::find_all(argument_mut);
+                        if !heredocs.is_empty() {
+                            argument_mut.put_u8(next_byte);
+                            continue;
+                        }
+                    }
+                    directive.set_arguments(argument_mut.to_vec())?;
                     return Ok(Some(directive.clone()));
                 }
                 // if a newline character is next, escape it, if already escaped then observe (\\)
@@ -669,6 +1100,14 @@ impl DockerfileDecoder {
     pub async fn decode_dockerfile_from_src<R: AsyncRead + std::marker::Unpin>(
         dockerfile_src: R,
     ) -> Result<Vec<Directive>, super::error::DockerError> {
+        let directives = Self::decode_spanned_dockerfile_from_src(dockerfile_src).await?;
+        Ok(directives.into_iter().map(Spanned::into_inner).collect())
+    }
+
+    /// Decodes the directives in a Dockerfile, along with where each was found in it
+    pub async fn decode_spanned_dockerfile_from_src<R: AsyncRead + std::marker::Unpin>(
+        dockerfile_src: R,
+    ) -> Result<Vec<Spanned<Directive>>, super::error::DockerError> {
         let mut dockerfile_reader = FramedRead::new(dockerfile_src, Self::new());
 
         let mut directives = Vec::new();
@@ -679,13 +1118,40 @@ impl DockerfileDecoder {
 
         Ok(directives)
     }
+
+    pub async fn decode_stages_from_src<R: AsyncRead + std::marker::Unpin>(
+        dockerfile_src: R,
+    ) -> Result<Vec<BuildStage>, super::error::DockerError> {
+        let directives = Self::decode_spanned_dockerfile_from_src(dockerfile_src).await?;
+        Ok(BuildStage::split(directives))
+    }
 }
 
 impl Decoder for DockerfileDecoder {
-    type Item = Directive;
+    type Item = Spanned<Directive>;
     type Error = super::error::DockerError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode_next(src) {
+            // directives are emitted on the newline which ends them, which is left out of the span
+            Ok(directive) => Ok(directive.map(|directive| {
+                let span = self.span_to(self.position.offset - 1);
+                Spanned::new(directive, span)
+            })),
+            Err(e) => Err(e.at(self.span_to(self.position.offset)).into()),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(buf)? {
+            Some(directive) => Ok(Some(directive)),
+            None => Ok(self.flush()?),
+        }
+    }
+}
+
+impl DockerfileDecoder {
+    fn decode_next(&mut self, src: &mut BytesMut) -> Result<Option<Directive>, DecodeError> {
         let mut decode_state = if self.current_state.is_none() {
             let first_byte = match self.read_u8(src) {
                 Some(byte) => byte,
@@ -723,6 +1189,7 @@ impl Decoder for DockerfileDecoder {
                     mut arguments,
                     mut new_line_behaviour,
                     mut string_stack,
+                    mut heredocs,
                 } => {
                     return match self.decode_directive_arguments(
                         src,
@@ -730,6 +1197,7 @@ impl Decoder for DockerfileDecoder {
                         &mut arguments,
                         &mut new_line_behaviour,
                         &mut string_stack,
+                        &mut heredocs,
                     )? {
                         Some(instruction) => Ok(Some(instruction)),
                         None => {
@@ -738,6 +1206,7 @@ impl Decoder for DockerfileDecoder {
                                 arguments,
                                 new_line_behaviour,
                                 string_stack,
+                                heredocs,
                             });
                             Ok(None)
                         }
@@ -753,13 +1222,6 @@ impl Decoder for DockerfileDecoder {
             }
         }
     }
-
-    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match self.decode(buf)? {
-            Some(directive) => Ok(Some(directive)),
-            None => Ok(self.flush()?),
-        }
-    }
 }
 
 #[cfg(test)]
@@ -767,16 +1229,16 @@ mod tests {
     use super::*;
 
     fn assert_directive_has_been_parsed<E: std::error::Error>(
-        parsed_directive: Result<Option<Directive>, E>,
+        parsed_directive: Result<Option<Spanned<Directive>>, E>,
     ) -> Directive {
         assert_eq!(parsed_directive.is_ok(), true);
         let directive = parsed_directive.unwrap();
         assert_eq!(directive.is_some(), true);
-        directive.unwrap()
+        directive.unwrap().into_inner()
     }
 
     fn assert_directive_has_not_been_parsed<E: std::error::Error>(
-        parsed_directive: Result<Option<Directive>, E>,
+        parsed_directive: Result<Option<Spanned<Directive>>, E>,
     ) {
         assert_eq!(parsed_directive.is_ok(), true);
         let directive = parsed_directive.unwrap();
@@ -819,6 +1281,56 @@ mod tests {
         assert_eq!(flushed_state.is_err(), true);
     }
 
+    #[tokio::test]
+    async fn test_spans_of_decoded_directives() {
+        let dockerfile = "# café\nFROM alpine\n\n  RUN apk update && \\\n    apk add curl\nCMD sh";
+        let directives =
+            DockerfileDecoder::decode_spanned_dockerfile_from_src(dockerfile.as_bytes())
+                .await
+                .unwrap();
+        let spans: Vec<(usize, usize, &str)> = directives
+            .iter()
+            .map(|directive| {
+                let span = directive.span();
+                (span.line, span.column, &dockerfile[span.start..span.end])
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (1, 1, "# café"),
+                (2, 1, "FROM alpine"),
+                (4, 3, "RUN apk update && \\\n    apk add curl"),
+                (6, 1, "CMD sh"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_errors_have_spans() {
+        let dockerfile = "FROM alpine\nCMD ré\n\tRUNé echo\n";
+        let error = DockerfileDecoder::decode_dockerfile_from_src(dockerfile.as_bytes())
+            .await
+            .unwrap_err();
+        let super::super::error::DockerError::ParserDecodeError(error) = error else {
+            panic!("Expected a decode error");
+        };
+        assert!(matches!(
+            &error,
+            DecodeError::At { error, .. } if matches!(**error, DecodeError::UnexpectedToken)
+        ));
+        let span = error.span().unwrap();
+        assert_eq!((span.line, span.column), (3, 2));
+        assert_eq!(&dockerfile.as_bytes()[span.start..span.end], b"RUN\xc3");
+
+        let mut decoder = DockerfileDecoder::new();
+        let mut dockerfile_content = BytesMut::from("FROM alpine\nENTRYPOINT ");
+        assert!(decoder.decode(&mut dockerfile_content).unwrap().is_some());
+        assert!(decoder.decode(&mut dockerfile_content).unwrap().is_none());
+        let error = decoder.flush().unwrap_err();
+        assert_eq!(error.span().map(|span| span.line), Some(2));
+    }
+
     #[test]
     fn test_multiline_directive_with_embedded_comments() {
         let mut decoder = DockerfileDecoder::new();
@@ -931,7 +1443,41 @@ ENTRYPOINT apk update && apk add python3 glib make g++ gcc libc-dev &&\
         let directive = assert_directive_has_been_parsed(expose_directive);
         assert_eq!(directive.to_string(), test_dockerfile.to_string());
         assert_eq!(directive.is_expose(), true);
-        assert!(matches!(directive, Directive::Expose { port: Some(80) }));
+        assert!(matches!(
+            directive,
+            Directive::Expose { ports: Some(ports), .. } if ports == [ExposedPort::tcp(80)]
+        ));
+    }
+
+    #[test]
+    fn test_parsing_of_exposed_ports_and_protocols() {
+        let mut decoder = DockerfileDecoder::new();
+        let mut buffer = BytesMut::from("EXPOSE 8080/tcp  9090\t53/UDP\nEXPOSE 80/sctp\n");
+        let directive = assert_directive_has_been_parsed(decoder.decode(&mut buffer));
+        let Directive::Expose {
+            ports: Some(ports), ..
+        } = directive
+        else {
+            panic!("Expected the ports to be parsed");
+        };
+        assert_eq!(
+            ports,
+            vec![
+                ExposedPort::tcp(8080),
+                ExposedPort::tcp(9090),
+                ExposedPort {
+                    port: 53,
+                    protocol: Protocol::Udp
+                },
+            ]
+        );
+        assert_eq!(ports[2].to_string(), "53/udp");
+
+        let error = decoder.decode(&mut buffer).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid protocol in exposed port 80/sctp, expected tcp or udp"
+        );
     }
 
     #[tokio::test]
@@ -947,7 +1493,7 @@ ENTRYPOINT apk update && apk add python3 glib make g++ gcc libc-dev &&\
         let expose_directive = decoded_file.get(0).unwrap();
         assert!(matches!(
             expose_directive,
-            Directive::Expose { port: Some(80) }
+            Directive::Expose { ports: Some(ports), .. } if ports == &[ExposedPort::tcp(80)]
         ));
         let entrypoint_directive = decoded_file.get(1).unwrap();
         assert!(entrypoint_directive.is_entrypoint());
@@ -987,4 +1533,198 @@ ENTRYPOINT apk update && apk add python3 glib make g++ gcc libc-dev &&\
             String::from("CMD echo 'Test'")
         )
     }
+
+    #[test]
+    fn test_parsing_of_from_directive_with_stage_name() {
+        let mut decoder = DockerfileDecoder::new();
+        let test_dockerfile = "FROM --platform=linux/amd64 rust:1.70 as builder";
+        let dockerfile_contents = format!("{}\n", test_dockerfile);
+        let mut buffer = BytesMut::from(dockerfile_contents.as_str());
+        let from_directive = decoder.decode(&mut buffer);
+        let directive = assert_directive_has_been_parsed(from_directive);
+        assert!(directive.is_from());
+        assert_eq!(directive.to_string(), test_dockerfile.to_string());
+        assert_eq!(directive.base_image(), Some("rust:1.70"));
+        assert_eq!(directive.stage_name(), Some("builder"));
+    }
+
+    #[tokio::test]
+    async fn test_decode_stages_from_async_src() {
+        let test_dockerfile = b"ARG VERSION=3.18\nFROM alpine:${VERSION} AS builder\nUSER builder\nEXPOSE 8080\nFROM builder\nCMD [\"echo\",\"yo\"]\n";
+        let stages = DockerfileDecoder::decode_stages_from_src(test_dockerfile.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(stages.len(), 3);
+
+        assert!(stages[0].from_directive().is_none());
+        assert_eq!(stages[0].directives().len(), 1);
+
+        assert_eq!(stages[1].name(), Some("builder"));
+        assert_eq!(stages[1].base_image(), Some("alpine:${VERSION}"));
+        assert!(stages[1].directives()[0].is_user());
+        assert!(stages[1].directives()[1].is_expose());
+
+        assert_eq!(stages[2].name(), None);
+        assert_eq!(stages[2].base_image(), Some("builder"));
+        assert!(stages[2].directives()[0].is_cmd());
+        assert_eq!(stages[2].clone().into_directives().len(), 2);
+    }
+
+    async fn decode_to_strings(dockerfile: &str) -> Vec<String> {
+        DockerfileDecoder::decode_dockerfile_from_src(dockerfile.as_bytes())
+            .await
+            .unwrap()
+            .iter()
+            .map(|directive| directive.to_string())
+            .collect()
+    }
+
+    // Golden cases for exec form arguments: the directive, the mode and tokens it decodes to, and how
+    // it's written back out
+    #[test]
+    fn test_exec_form_golden_cases() {
+        let cases: &[(&str, Mode, &[&str], &str)] = &[
+            (
+                r#"CMD ["node", "server.js"]"#,
+                Mode::Exec,
+                &["node", "server.js"],
+                r#"CMD ["node", "server.js"]"#,
+            ),
+            (
+                r#"CMD ["sh","-c","echo \"hi\" \\ there"]"#,
+                Mode::Exec,
+                &["sh", "-c", r#"echo "hi" \ there"#],
+                r#"CMD ["sh", "-c", "echo \"hi\" \\ there"]"#,
+            ),
+            (
+                r#"ENTRYPOINT ["echo", "café", "a,b"]"#,
+                Mode::Exec,
+                &["echo", "café", "a,b"],
+                r#"ENTRYPOINT ["echo", "café", "a,b"]"#,
+            ),
+            (
+                "ENTRYPOINT [\"node\", \\\n    \"server.js\"]",
+                Mode::Exec,
+                &["node", "server.js"],
+                r#"ENTRYPOINT ["node", "server.js"]"#,
+            ),
+            (r#"CMD [ ]"#, Mode::Exec, &[], r#"CMD []"#),
+            // Like Docker, arguments which aren't a JSON array of strings are run with the shell
+            (
+                r#"CMD [node, server.js]"#,
+                Mode::Shell,
+                &["[node,", "server.js]"],
+                r#"CMD [node, server.js]"#,
+            ),
+            (
+                r#"CMD ['node', 'server.js']"#,
+                Mode::Shell,
+                &["['node',", "'server.js']"],
+                r#"CMD ['node', 'server.js']"#,
+            ),
+            (
+                r#"CMD ["node" "server.js"]"#,
+                Mode::Shell,
+                &[r#"["node""#, r#""server.js"]"#],
+                r#"CMD ["node" "server.js"]"#,
+            ),
+            (
+                r#"CMD ["port", 3000]"#,
+                Mode::Shell,
+                &[r#"["port","#, "3000]"],
+                r#"CMD ["port", 3000]"#,
+            ),
+        ];
+        for (input, mode, tokens, output) in cases {
+            let mut decoder = DockerfileDecoder::new();
+            let mut buffer = BytesMut::from(format!("{input}\n").as_str());
+            let directive = assert_directive_has_been_parsed(decoder.decode(&mut buffer));
+            assert_eq!(directive.mode(), Some(mode), "{input}");
+            assert_eq!(directive.tokens().unwrap(), *tokens, "{input}");
+            assert_eq!(directive.to_string(), *output, "{input}");
+        }
+    }
+
+    // Golden cases for heredocs: the Dockerfile, and the directives it decodes to
+    #[tokio::test]
+    async fn test_heredoc_golden_cases() {
+        let cases: &[(&str, &[&str])] = &[
+            (
+                "RUN <<EOF\napt-get update\n# not a comment\necho \"unbalanced ' \\\nEOF\nCMD [\"node\"]\n",
+                &[
+                    "RUN <<EOF\napt-get update\n# not a comment\necho \"unbalanced ' \\\nEOF",
+                    r#"CMD ["node"]"#,
+                ],
+            ),
+            (
+                "RUN <<-EOF\n\techo indented\n\tEOF\nUSER app\n",
+                &["RUN <<-EOF\n\techo indented\n\tEOF", "USER app"],
+            ),
+            (
+                "RUN python3 <<'PY' > /out\nprint(\"$HOME\")\nPY\n",
+                &["RUN python3 <<'PY' > /out\nprint(\"$HOME\")\nPY"],
+            ),
+            (
+                "COPY <<one.txt <<\"two.txt\" /dest/\nfirst\none.txt\nsecond\ntwo.txt\n",
+                &["COPY <<one.txt <<\"two.txt\" /dest/\nfirst\none.txt\nsecond\ntwo.txt"],
+            ),
+            (
+                "ADD <<EOF /etc/motd\nhello world\nEOF\n",
+                &["ADD <<EOF /etc/motd\nhello world\nEOF"],
+            ),
+            (
+                "RUN cat 3<<EOF /dev/fd/3\n  EOF is indented, so this continues\nEOF",
+                &["RUN cat 3<<EOF /dev/fd/3\n  EOF is indented, so this continues\nEOF"],
+            ),
+            // Heredocs are only read from RUN, COPY and ADD, and not from inside quotes
+            (
+                "RUN echo \"<<EOF\"\nENTRYPOINT cat <<EOF\n",
+                &[r#"RUN echo "<<EOF""#, "ENTRYPOINT cat <<EOF"],
+            ),
+            (
+                "RUN echo \"a <<EOF\" 'b <<EOF'\nCMD [\"sh\"]\n",
+                &[r#"RUN echo "a <<EOF" 'b <<EOF'"#, r#"CMD ["sh"]"#],
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(decode_to_strings(input).await, *expected, "{input}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unterminated_heredoc_is_incomplete() {
+        for dockerfile in ["RUN <<EOF\necho one\n", "RUN <<EOF\necho one\nCMD [\"sh\"]"] {
+            let error = DockerfileDecoder::decode_dockerfile_from_src(dockerfile.as_bytes())
+                .await
+                .unwrap_err();
+            let super::super::error::DockerError::ParserDecodeError(error) = error else {
+                panic!("Expected a decode error");
+            };
+            assert!(
+                matches!(
+                    &error,
+                    DecodeError::At { error, .. }
+                        if matches!(**error, DecodeError::IncompleteInstruction)
+                ),
+                "{dockerfile}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heredoc_split_across_reads() {
+        let mut mock_builder = tokio_test::io::Builder::new();
+        mock_builder
+            .read(b"FROM alpine\nRUN <<E")
+            .read(b"OF\necho one\nEO")
+            .read(b"F\nCMD [\"sh\"]");
+        let decoded_file = DockerfileDecoder::decode_dockerfile_from_src(mock_builder.build())
+            .await
+            .unwrap();
+        let decoded_file: Vec<String> = decoded_file.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            decoded_file,
+            &["FROM alpine", "RUN <<EOF\necho one\nEOF", r#"CMD ["sh"]"#,].map(String::from)
+        );
+    }
 }
@@ -0,0 +1,161 @@
+use std::fmt::Formatter;
+use std::ops::Deref;
+
+/// A range of bytes in a Dockerfile, along with the 1-based line and column it starts at
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A value decoded from a Dockerfile, along with where it was found
+#[derive(Clone, Debug)]
+pub struct Spanned<T> {
+    value: T,
+    span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Span) -> Self {
+        Self { value, span }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// Renders the lines of `source` covered by `span` in the style of rustc's diagnostics, with the
+/// span underlined:
+///
+/// ```text
+///  --> ./Dockerfile:3:1
+///   |
+/// 3 | EXPOSE 443
+///   | ^^^^^^^^^^
+/// ```
+pub fn render_snippet(source: &str, path: &str, span: &Span) -> String {
+    let start = span.start.min(source.len());
+    let mut end = span.end.clamp(start, source.len());
+    while !source.is_char_boundary(end) {
+        end += 1;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[end..]
+        .find('\n')
+        .map_or(source.len(), |index| end + index);
+    let lines: Vec<&str> = source[line_start..line_end]
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect();
+
+    let gutter = (span.line + lines.len() - 1).to_string().len();
+    let padding = " ".repeat(gutter);
+    let mut snippet = format!(
+        "{padding}--> {path}:{}:{}\n{padding} |\n",
+        span.line, span.column
+    );
+    match lines.as_slice() {
+        [line] => {
+            let underline = source[start..end].chars().count().max(1);
+            snippet.push_str(&format!("{:>gutter$} | {line}\n", span.line));
+            snippet.push_str(&format!(
+                "{padding} | {}{}",
+                " ".repeat(span.column.saturating_sub(1)),
+                "^".repeat(underline)
+            ));
+        }
+        lines => {
+            for (index, line) in lines.iter().enumerate() {
+                let marker = if index == 0 { '/' } else { '|' };
+                snippet.push_str(&format!(
+                    "{:>gutter$} | {marker} {line}\n",
+                    span.line + index
+                ));
+            }
+            let last_line = lines.last().map_or(0, |line| line.chars().count());
+            snippet.push_str(&format!("{padding} | |{}^", "_".repeat(last_line)));
+        }
+    }
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_single_line_snippet() {
+        let source = "FROM alpine\nUSER app\nEXPOSE 443\n";
+        let span = Span {
+            start: 21,
+            end: 31,
+            line: 3,
+            column: 1,
+        };
+        assert_eq!(
+            render_snippet(source, "./Dockerfile", &span),
+            " --> ./Dockerfile:3:1
+  |
+3 | EXPOSE 443
+  | ^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_multi_line_snippet() {
+        let source = "FROM alpine\n\n\n\n\n\n\n\nRUN apk update && \\\n    apk add curl\nCMD sh\n";
+        let span = Span {
+            start: 19,
+            end: 55,
+            line: 9,
+            column: 1,
+        };
+        assert_eq!(
+            render_snippet(source, "Dockerfile", &span),
+            "  --> Dockerfile:9:1
+   |
+ 9 | / RUN apk update && \\
+10 | |     apk add curl
+   | |________________^"
+        );
+    }
+
+    #[test]
+    fn test_render_snippet_for_span_inside_line() {
+        let source = "ENTRYPOINT [\"café\"] ~";
+        let span = Span {
+            start: 21,
+            end: 22,
+            line: 1,
+            column: 21,
+        };
+        assert_eq!(
+            render_snippet(source, "Dockerfile", &span),
+            " --> Dockerfile:1:21
+  |
+1 | ENTRYPOINT [\"café\"] ~
+  |                     ^"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::docker::parse::{DecodeError, Directive};
+use crate::docker::parse::{DecodeError, Directive, Mode};
 use itertools::join;
 
 /*
@@ -19,19 +19,23 @@ pub fn create_combined_docker_entrypoint(
     entrypoint: Option<Directive>,
     cmd: Option<Directive>,
 ) -> Result<String, super::error::DockerError> {
-    let format_tokens = |tokens: &[String]| -> String { join(tokens, " ") };
+    // exec form tokens are separate arguments, so each is quoted to keep it whole in the script
+    let format_tokens = |directive: &Directive| -> String {
+        let tokens = directive.tokens().unwrap();
+        if directive.mode().is_some_and(Mode::is_exec) {
+            join(tokens.iter().map(|token| shell_quote(token)), " ")
+        } else {
+            join(tokens, " ")
+        }
+    };
     let entrypoint = match (entrypoint.as_ref(), cmd.as_ref()) {
-        (Some(entrypoint), None) => format_tokens(entrypoint.tokens().unwrap()),
-        (None, Some(cmd)) => format_tokens(cmd.tokens().unwrap()),
+        (Some(entrypoint), None) => format_tokens(entrypoint),
+        (None, Some(cmd)) => format_tokens(cmd),
         (Some(entrypoint), Some(cmd)) => {
             if entrypoint.mode().unwrap().is_shell() {
-                format_tokens(entrypoint.tokens().unwrap())
+                format_tokens(entrypoint)
             } else {
-                format!(
-                    "{} {}",
-                    format_tokens(entrypoint.tokens().unwrap()),
-                    format_tokens(cmd.tokens().unwrap())
-                )
+                format!("{} {}", format_tokens(entrypoint), format_tokens(cmd))
             }
         }
         (None, None) => return Err(DecodeError::NoEntrypoint.into()),
@@ -39,6 +43,64 @@ pub fn create_combined_docker_entrypoint(
     Ok(entrypoint)
 }
 
+// Single quotes a token for the shell, unless it's made up of characters the shell leaves alone.
+// Variable references are double quoted instead, so the run script still expands them.
+fn shell_quote(token: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !token.is_empty() && token.chars().all(is_safe) {
+        return token.to_string();
+    }
+    if token.is_empty() {
+        return "''".to_string();
+    }
+    let quote_literal = |literal: &str| format!("'{}'", literal.replace('\'', r"'\''"));
+    let mut quoted = String::new();
+    let mut rest = token;
+    while !rest.is_empty() {
+        let (literal, reference) = match find_reference(rest) {
+            Some((start, end)) => (&rest[..start], &rest[start..end]),
+            None => (rest, ""),
+        };
+        if !literal.is_empty() {
+            quoted.push_str(&quote_literal(literal));
+        }
+        if !reference.is_empty() {
+            quoted.push_str(&format!("\"{reference}\""));
+        }
+        rest = &rest[literal.len() + reference.len()..];
+    }
+    quoted
+}
+
+// Finds the byte range of the first `$NAME` or `${...}` reference in some text
+fn find_reference(text: &str) -> Option<(usize, usize)> {
+    text.match_indices('$').find_map(|(start, _)| {
+        let after = &text[start + 1..];
+        let len = if let Some(body) = after.strip_prefix('{') {
+            let mut depth = 0;
+            body.char_indices().find_map(|(i, c)| match c {
+                '{' => {
+                    depth += 1;
+                    None
+                }
+                '}' if depth == 0 => Some(i + 2),
+                '}' => {
+                    depth -= 1;
+                    None
+                }
+                _ => None,
+            })?
+        } else if after.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len())
+        } else {
+            return None;
+        };
+        Some((start, start + 1 + len))
+    })
+}
+
 // Takes a command A and produces a command B which writes A to a bash script.
 // Useful for creating scripts within in Dockerfiles
 pub fn write_command_to_script(command: &str, script_path: &str, arguments: &[&str]) -> String {
@@ -53,6 +115,25 @@ pub fn write_command_to_script(command: &str, script_path: &str, arguments: &[&s
     script_writer.join("")
 }
 
+// Escapes a command written to a script with `write_command_to_script`, so printf writes it as is.
+// Shell expansions are left for the script to expand when it runs rather than the shell writing it
+pub fn escape_for_script(command: &str) -> String {
+    command.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            // a backslash is unescaped by the shell's double quotes, then by printf
+            '\\' => escaped.push_str(r"\\\\"),
+            '%' => escaped.push_str("%%"),
+            '\n' => escaped.push_str(r"\n"),
+            '"' | '$' | '`' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+        escaped
+    })
+}
+
 pub fn verify_docker_is_running() -> Result<bool, super::error::DockerError> {
     let exit_status = super::command::docker_info()?;
     Ok(exit_status.success())
@@ -70,4 +151,72 @@ mod tests {
             r##"printf "#!/bin/sh\necho hello\n" > hello-script.sh && chmod +x hello-script.sh"##
         )
     }
+
+    #[test]
+    fn test_escaped_expansions_are_written_to_the_script() {
+        let script_dir = tempfile::TempDir::new().unwrap();
+        let script_path = script_dir.path().join("run");
+        let command = escape_for_script("exec server --level ${LOG_LEVEL:-info} `hostname`");
+        let script_command = write_command_to_script(&command, script_path.to_str().unwrap(), &[]);
+        let status = std::process::Command::new("sh")
+            .args(["-c", &script_command])
+            .env("LOG_LEVEL", "debug")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            std::fs::read_to_string(script_path).unwrap(),
+            "#!/bin/sh\nexec server --level ${LOG_LEVEL:-info} `hostname`\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_form_arguments_reach_the_entrypoint_unchanged() {
+        let arguments = [
+            "hi there",
+            r#"echo "hi there""#,
+            "it's",
+            "café ☕",
+            "`hostname` $(hostname) $",
+            r"back\slash \n",
+            "100%",
+            "two\nlines",
+            "",
+        ];
+        let tokens = ["printf", "<%s>"]
+            .into_iter()
+            .chain(arguments)
+            .chain(["$GREETING, ${NAME:-world}'s"])
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let entrypoint = Directive::new_entrypoint(Mode::Exec, tokens);
+        let command = create_combined_docker_entrypoint(Some(entrypoint), None).unwrap();
+
+        let script_dir = tempfile::TempDir::new().unwrap();
+        let script_path = script_dir.path().join("run");
+        let script_command = write_command_to_script(
+            &format!("exec {}", escape_for_script(&command)),
+            script_path.to_str().unwrap(),
+            &[],
+        );
+        let status = std::process::Command::new("sh")
+            .args(["-c", &script_command])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Variable references are still expanded when the script runs
+        let output = std::process::Command::new(&script_path)
+            .env("GREETING", "hello there")
+            .env_remove("NAME")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let expected = arguments
+            .iter()
+            .map(|argument| format!("<{argument}>"))
+            .chain(["<hello there, world's>".to_string()])
+            .collect::<String>();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
 }
@@ -0,0 +1,436 @@
+use super::parse::{BuildStage, DecodeError, Directive};
+use super::span::Spanned;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// How variable references are expanded, which depends on the directive they appear in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expansion {
+    /// Directives such as USER and EXPOSE, which Docker expands itself. Quotes are removed and unset
+    /// variables expand to nothing.
+    Words,
+    /// Shell form CMD and ENTRYPOINT. Quotes are kept for the shell, and nothing inside single quotes
+    /// is expanded.
+    Shell,
+    /// Exec form CMD and ENTRYPOINT tokens, where quotes have no special meaning
+    Exec,
+}
+
+// ENV values are set in the image, and can be overridden by the Cage's environment when it starts,
+// so only ARG values are substituted into commands. Anything else is escaped when the entrypoint is
+// written to its run script, and expanded by the shell when the Cage starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    Arg,
+    Env,
+}
+
+/// The ARG and ENV values in scope at a point in a Dockerfile. Build args given on the command line
+/// override ARG defaults, and ENV values override ARGs of the same name, as they do in Docker.
+#[derive(Clone, Debug, Default)]
+pub struct BuildVariables {
+    build_args: HashMap<String, String>,
+    // ARGs declared before the first FROM, which a stage can bring into scope by redeclaring them
+    global_args: HashMap<String, String>,
+    values: HashMap<String, (String, Source)>,
+}
+
+impl BuildVariables {
+    pub fn new(build_args: HashMap<String, String>) -> Self {
+        Self {
+            build_args,
+            ..Default::default()
+        }
+    }
+
+    /// Parses build args in the form passed to docker: `--build-arg KEY=VALUE`. Args without a value
+    /// are taken from the environment, and left unset if it doesn't have them.
+    pub fn from_docker_build_args(docker_build_args: &[&str]) -> Self {
+        let build_args = docker_build_args
+            .windows(2)
+            .filter(|pair| pair[0] == "--build-arg")
+            .map(|pair| pair[1])
+            .filter_map(|build_arg| match build_arg.split_once('=') {
+                Some((key, value)) => Some((key.to_string(), value.to_string())),
+                None => std::env::var(build_arg)
+                    .ok()
+                    .map(|value| (build_arg.to_string(), value)),
+            })
+            .collect();
+        Self::new(build_args)
+    }
+
+    // Variables in scope at the start of a stage, which inherits the ENV of the stage it's built
+    // from. ARGs declared before the first FROM are only in scope once the stage redeclares them.
+    fn stage_scope(&self, base_env: Option<&HashMap<String, String>>) -> Self {
+        let values = base_env
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), (value.clone(), Source::Env)))
+            .collect();
+        Self {
+            build_args: self.build_args.clone(),
+            global_args: self
+                .values
+                .iter()
+                .map(|(key, (value, _))| (key.clone(), value.clone()))
+                .collect(),
+            values,
+        }
+    }
+
+    fn env(&self) -> HashMap<String, String> {
+        self.values
+            .iter()
+            .filter(|(_, (_, source))| *source == Source::Env)
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Brings the variables declared by an ARG or ENV directive into scope
+    pub fn declare(&mut self, directive: &Directive) {
+        match directive {
+            Directive::Arg(arguments) => {
+                for word in self.words(&String::from_utf8_lossy(arguments)) {
+                    let (key, default) = match word.split_once('=') {
+                        Some((key, default)) => (key.to_string(), Some(default.to_string())),
+                        None => (word, None),
+                    };
+                    if matches!(self.values.get(&key), Some((_, Source::Env))) {
+                        continue;
+                    }
+                    let value = self
+                        .build_args
+                        .get(&key)
+                        .cloned()
+                        .or(default)
+                        .or_else(|| self.global_args.get(&key).cloned());
+                    match value {
+                        Some(value) => self.values.insert(key, (value, Source::Arg)),
+                        None => self.values.remove(&key),
+                    };
+                }
+            }
+            Directive::Env(arguments) => {
+                let words = self.words(&String::from_utf8_lossy(arguments));
+                let assignments: Vec<(String, String)> = match words.first() {
+                    Some(first) if first.contains('=') => words
+                        .iter()
+                        .filter_map(|word| word.split_once('='))
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                    // The legacy form sets a single variable to the rest of the line: ENV KEY value
+                    Some(key) => vec![(key.clone(), words[1..].join(" "))],
+                    None => Vec::new(),
+                };
+                for (key, value) in assignments {
+                    self.values.insert(key, (value, Source::Env));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Expands the variable references in `text`, using Docker's `$VAR`, `${VAR}`, `${VAR:-default}`
+    /// and `${VAR:+alternative}` forms
+    pub fn expand(&self, text: &str, expansion: Expansion) -> String {
+        self.lex(text, expansion, false).concat()
+    }
+
+    // Splits on unquoted whitespace, removing quotes and expanding variables in each word
+    fn words(&self, text: &str) -> Vec<String> {
+        self.lex(text, Expansion::Words, true)
+    }
+
+    fn lex(&self, text: &str, expansion: Expansion, split_words: bool) -> Vec<String> {
+        let quotes_apply = expansion != Expansion::Exec;
+        let remove_quotes = expansion == Expansion::Words;
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut quote: Option<char> = None;
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let in_single_quotes = quotes_apply && quote == Some('\'');
+            match c {
+                '\\' if !in_single_quotes => {
+                    match chars.next() {
+                        // Line continuations are removed from the words Docker expands itself
+                        Some('\n') if remove_quotes => {}
+                        Some(escaped) => {
+                            if !remove_quotes {
+                                word.push('\\');
+                            }
+                            word.push(escaped);
+                        }
+                        None => word.push('\\'),
+                    }
+                    in_word = true;
+                }
+                '\'' | '"' if quotes_apply && quote.is_none_or(|open| open == c) => {
+                    quote = if quote.is_some() { None } else { Some(c) };
+                    if !remove_quotes {
+                        word.push(c);
+                    }
+                    in_word = true;
+                }
+                '$' if !in_single_quotes => {
+                    self.expand_reference(&mut chars, expansion, &mut word);
+                    in_word = true;
+                }
+                c if split_words && quote.is_none() && c.is_whitespace() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                }
+                c => {
+                    word.push(c);
+                    in_word = true;
+                }
+            }
+        }
+        if in_word || !split_words {
+            words.push(word);
+        }
+        words
+    }
+
+    // Expands a reference which starts after a `$`
+    fn expand_reference(
+        &self,
+        chars: &mut Peekable<Chars>,
+        expansion: Expansion,
+        out: &mut String,
+    ) {
+        match chars.peek() {
+            Some('{') => {
+                chars.next();
+                let mut body = String::new();
+                let mut depth = 0;
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => {
+                            closed = true;
+                            break;
+                        }
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    body.push(c);
+                }
+                let original = format!("${{{body}{}", if closed { "}" } else { "" });
+                if !closed {
+                    out.push_str(&original);
+                    return;
+                }
+                let name_len = body.find(|c: char| !is_name_char(c)).unwrap_or(body.len());
+                let (name, modifier) = body.split_at(name_len);
+                self.substitute(name, modifier, &original, expansion, out);
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| is_name_char(*c)) {
+                    name.push(c);
+                }
+                self.substitute(&name, "", &format!("${name}"), expansion, out);
+            }
+            _ => out.push('$'),
+        }
+    }
+
+    fn substitute(
+        &self,
+        name: &str,
+        modifier: &str,
+        original: &str,
+        expansion: Expansion,
+        out: &mut String,
+    ) {
+        let value = match (self.values.get(name), expansion) {
+            (Some((value, _)), Expansion::Words) => Some(value.as_str()),
+            (None, Expansion::Words) => None,
+            (Some((value, Source::Arg)), _) => Some(value.as_str()),
+            // Left for the shell to expand when the Cage runs
+            _ => {
+                out.push_str(original);
+                return;
+            }
+        };
+        let (check_empty, operator) = match modifier.strip_prefix(':') {
+            Some(operator) => (true, operator),
+            None => (false, modifier),
+        };
+        let is_set = value.is_some_and(|value| !(check_empty && value.is_empty()));
+        let expanded = if modifier.is_empty() {
+            value.unwrap_or_default().to_string()
+        } else if let Some(default) = operator.strip_prefix('-') {
+            match is_set {
+                true => value.unwrap_or_default().to_string(),
+                false => self.expand(default, expansion),
+            }
+        } else if let Some(alternative) = operator.strip_prefix('+') {
+            match is_set {
+                true => self.expand(alternative, expansion),
+                false => String::new(),
+            }
+        } else {
+            // Unsupported modifiers are left as they are
+            original.to_string()
+        };
+        out.push_str(&expanded);
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Expands the directives the CLI interprets in each stage, using the ARG and ENV values in scope
+/// where they appear. The stages returned have the same directives in the same order, so they can be
+/// matched up with the originals, which are what's written to the processed Dockerfile.
+pub fn expand_stages(
+    stages: &[BuildStage],
+    globals: &BuildVariables,
+) -> Result<Vec<BuildStage>, DecodeError> {
+    let mut globals = globals.clone();
+    let mut stage_envs: Vec<(Option<String>, HashMap<String, String>)> = Vec::new();
+    let mut expanded_stages = Vec::with_capacity(stages.len());
+
+    for stage in stages {
+        let Some(from) = stage.from_directive() else {
+            // Directives before the first FROM can only declare ARGs
+            for directive in stage.directives() {
+                globals.declare(directive);
+            }
+            expanded_stages.push(stage.clone());
+            continue;
+        };
+        let from = expand_spanned(from, &globals)?;
+        let base_env = from.base_image().and_then(|image| {
+            stage_envs
+                .iter()
+                .rev()
+                .find(|(name, _)| {
+                    name.as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(image))
+                })
+                .map(|(_, env)| env)
+        });
+
+        let mut vars = globals.stage_scope(base_env);
+        let mut directives = Vec::with_capacity(stage.directives().len());
+        for directive in stage.directives() {
+            directives.push(expand_spanned(directive, &vars)?);
+            vars.declare(directive);
+        }
+        stage_envs.push((from.stage_name().map(str::to_string), vars.env()));
+        expanded_stages.push(BuildStage::new(Some(from), directives));
+    }
+    Ok(expanded_stages)
+}
+
+fn expand_spanned(
+    directive: &Spanned<Directive>,
+    vars: &BuildVariables,
+) -> Result<Spanned<Directive>, DecodeError> {
+    let span = *directive.span();
+    let expanded = directive.expand(vars).map_err(|e| e.at(span))?;
+    Ok(Spanned::new(expanded, span))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(dockerfile: &str, build_args: &[(&str, &str)]) -> BuildVariables {
+        let build_args = build_args
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let mut vars = BuildVariables::new(build_args);
+        for line in dockerfile.lines() {
+            let (directive, arguments) = line.split_once(' ').unwrap();
+            let mut directive = Directive::try_from(directive.as_bytes()).unwrap();
+            directive
+                .set_arguments(arguments.as_bytes().to_vec())
+                .unwrap();
+            vars.declare(&directive);
+        }
+        vars
+    }
+
+    #[test]
+    fn test_expansion_of_references() {
+        let vars = vars("ARG PORT=3000\nENV APP_USER=app EMPTY=\"\"", &[]);
+        assert_eq!(vars.expand("${PORT}", Expansion::Words), "3000");
+        assert_eq!(vars.expand("$APP_USER:$PORT", Expansion::Words), "app:3000");
+        assert_eq!(vars.expand("${MISSING:-8080}", Expansion::Words), "8080");
+        assert_eq!(vars.expand("${EMPTY:-8080}", Expansion::Words), "8080");
+        assert_eq!(vars.expand("${EMPTY-8080}", Expansion::Words), "");
+        assert_eq!(vars.expand("${PORT:+set}", Expansion::Words), "set");
+        assert_eq!(vars.expand("${MISSING:+set}", Expansion::Words), "");
+        assert_eq!(vars.expand("$MISSING", Expansion::Words), "");
+        assert_eq!(
+            vars.expand("\\$PORT '$PORT'", Expansion::Words),
+            "$PORT $PORT"
+        );
+        assert_eq!(vars.expand("$ 5$", Expansion::Words), "$ 5$");
+    }
+
+    #[test]
+    fn test_only_args_are_substituted_into_commands() {
+        let vars = vars("ARG BIN=/usr/bin/server\nENV LOG_LEVEL=info", &[]);
+        assert_eq!(
+            vars.expand("$BIN --log $LOG_LEVEL", Expansion::Exec),
+            "/usr/bin/server --log $LOG_LEVEL"
+        );
+        assert_eq!(
+            vars.expand("${API_KEY:-none} '$BIN' \"$BIN\"", Expansion::Shell),
+            "${API_KEY:-none} '$BIN' \"/usr/bin/server\""
+        );
+        assert_eq!(vars.expand("'$BIN'", Expansion::Exec), "'/usr/bin/server'");
+    }
+
+    #[test]
+    fn test_build_args_override_arg_defaults_but_not_env() {
+        let vars = vars(
+            "ARG PORT=3000\nARG USER\nENV NAME=env\nARG NAME=arg",
+            &[("PORT", "4000"), ("USER", "admin"), ("NAME", "build-arg")],
+        );
+        assert_eq!(
+            vars.expand("$PORT $USER $NAME", Expansion::Words),
+            "4000 admin env"
+        );
+    }
+
+    #[test]
+    fn test_env_forms() {
+        let vars = vars(
+            "ENV GREETING hello world\nENV A=1\nENV A=2 B=\"two words\" C=${A}",
+            &[],
+        );
+        assert_eq!(vars.expand("$GREETING", Expansion::Words), "hello world");
+        // References see the values from before the ENV directive, as they do in Docker
+        assert_eq!(vars.expand("$A-$B-$C", Expansion::Words), "2-two words-1");
+    }
+
+    #[test]
+    fn test_parsing_of_docker_build_args() {
+        let vars = BuildVariables::from_docker_build_args(&[
+            "--build-arg",
+            "PORT=4000",
+            "--build-arg",
+            "EV_CAGE_SURELY_UNSET_BUILD_ARG",
+        ]);
+        assert_eq!(
+            vars.build_args.get("PORT").map(String::as_str),
+            Some("4000")
+        );
+        assert_eq!(vars.build_args.len(), 1);
+    }
+}
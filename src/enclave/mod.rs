@@ -19,12 +19,52 @@ const NITRO_CLI_GENERIC_IMAGE_NAME: &str = "nitro-cli-generic-image";
 pub const NITRO_CLI_IMAGE_FILENAME: &str = "nitro-cli-image.Dockerfile";
 pub const ENCLAVE_FILENAME: &str = "enclave.eif";
 
+/// Names of the docker images an enclave is built with. Builds that run side by side each need their
+/// own images, otherwise one build could convert the user image of another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildImages {
+    user_image: String,
+    nitro_cli_image: String,
+}
+
+impl Default for BuildImages {
+    fn default() -> Self {
+        Self {
+            user_image: EV_USER_IMAGE_NAME.to_string(),
+            nitro_cli_image: NITRO_CLI_BUILDER_IMAGE_NAME.to_string(),
+        }
+    }
+}
+
+impl BuildImages {
+    /// Images scoped to a single Cage, named after it
+    pub fn for_cage(cage_name: &str) -> Self {
+        let suffix: String = cage_name
+            .to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+                _ => '-',
+            })
+            .collect();
+        Self {
+            user_image: format!("{EV_USER_IMAGE_NAME}-{suffix}"),
+            nitro_cli_image: format!("{NITRO_CLI_BUILDER_IMAGE_NAME}-{suffix}"),
+        }
+    }
+
+    fn user_image_tag(&self) -> String {
+        format!("{}:latest", self.user_image)
+    }
+}
+
 pub fn build_user_image(
     user_dockerfile_path: &std::path::Path,
     user_context_path: &std::path::Path,
     verbose: bool,
     docker_build_args: Option<Vec<&str>>,
     timestamp: String,
+    images: &BuildImages,
 ) -> Result<(), EnclaveError> {
     let mut command_line_args = vec![user_context_path.as_os_str()];
 
@@ -33,7 +73,7 @@ pub fn build_user_image(
         command_line_args.append(&mut docker_build_args);
     }
 
-    let tag_name = format!("{EV_USER_IMAGE_NAME}:latest");
+    let tag_name = images.user_image_tag();
     let build_output = command::build_image_repro(
         user_dockerfile_path,
         tag_name.as_str(),
@@ -102,6 +142,7 @@ fn move_signing_info_into_scope(
 pub fn build_nitro_cli_image(
     output_dir: &std::path::PathBuf,
     signing_info: Option<&EnclaveSigningInfo>,
+    images: &BuildImages,
     verbose: bool,
 ) -> Result<(), EnclaveError> {
     let mut nitro_cli_dockerfile_contents = include_bytes!("nitro-cli-image.Dockerfile").to_vec();
@@ -128,7 +169,7 @@ pub fn build_nitro_cli_image(
     let build_image_result = command::build_image(
         nitro_cli_dockerfile_path.as_path(),
         if signing_info.is_some() {
-            NITRO_CLI_BUILDER_IMAGE_NAME
+            images.nitro_cli_image.as_str()
         } else {
             NITRO_CLI_GENERIC_IMAGE_NAME
         },
@@ -181,11 +222,12 @@ pub fn build_nitro_cli_image(
 
 pub fn run_conversion_to_enclave(
     output_dir: &std::path::Path,
+    images: &BuildImages,
     verbose: bool,
 ) -> Result<BuiltEnclave, EnclaveError> {
     let mounted_volume = format!("{}:{}", output_dir.display(), IN_CONTAINER_VOLUME_DIR);
     let output_location = format!("{}/{}", IN_CONTAINER_VOLUME_DIR, ENCLAVE_FILENAME);
-    let docker_uri = format!("{}:latest", EV_USER_IMAGE_NAME);
+    let docker_uri = images.user_image_tag();
 
     let nitro_run_args = vec![
         "build-enclave".as_ref(),
@@ -200,7 +242,7 @@ pub fn run_conversion_to_enclave(
     ];
 
     let run_conversion_result = command::run_image(
-        NITRO_CLI_BUILDER_IMAGE_NAME,
+        &images.nitro_cli_image,
         vec![
             "/var/run/docker.sock:/var/run/docker.sock",
             mounted_volume.as_str(),
@@ -1,3 +1,4 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -49,8 +50,10 @@ impl CleanUpMode {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Measurements of the Cage's enclave image, written by build and deploy
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct EIFMeasurements {
+    /// The hash algorithm used to compute the PCRs
     #[serde(rename = "HashAlgorithm")]
     hash_algorithm: String,
     #[serde(flatten)] // serialize as though these are attribtues on this struct
@@ -58,20 +61,31 @@ pub struct EIFMeasurements {
 }
 
 impl EIFMeasurements {
+    pub fn new(hash_algorithm: String, pcrs: PCRs) -> Self {
+        Self {
+            hash_algorithm,
+            pcrs,
+        }
+    }
+
     pub fn pcrs(&self) -> &PCRs {
         &self.pcrs
     }
 }
 
 // Isolated PCRs from remainder of the measures to use in API requests
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
 pub struct PCRs {
+    /// Hash of the enclave image file
     #[serde(rename = "PCR0")]
     pub pcr0: String,
+    /// Hash of the Linux kernel and bootstrap process
     #[serde(rename = "PCR1")]
     pub pcr1: String,
+    /// Hash of the user application
     #[serde(rename = "PCR2")]
     pub pcr2: String,
+    /// Hash of the certificate used to sign the enclave image
     #[serde(rename = "PCR8")]
     pub pcr8: Option<String>,
 }
@@ -30,6 +30,17 @@ pub async fn encrypt(
     curve: CurveName,
 ) -> Result<String, EncryptError> {
     let cage_api = CagesClient::new(AuthMode::NoAuth);
+    encrypt_with(&cage_api, value, team_uuid, app_uuid, curve).await
+}
+
+/// Encrypts `value` with the App's keys, fetched using `cage_api`
+pub async fn encrypt_with(
+    cage_api: &CagesClient,
+    value: String,
+    team_uuid: String,
+    app_uuid: String,
+    curve: CurveName,
+) -> Result<String, EncryptError> {
     let keys = cage_api.get_app_keys(&team_uuid, &app_uuid).await?;
 
     let result = match curve {
@@ -13,7 +13,7 @@ pub enum EnvError {
     Base64DecodeError(#[from] base64::DecodeError),
     #[error("An error occurred during decryption — {0}")]
     EvervaultCryptoError(#[from] EvervaultCryptoError),
-    #[error("App and team uuid need to be provided in cage.toml or as args")]
+    #[error("The Cage's uuid, and its app and team uuids, need to be provided in cage.toml. The app and team can also be set on the credentials profile.")]
     MissingAppInfo,
     #[error("An error occured during encryption — {0}")]
     EncryptError(#[from] encrypt::EncryptError),
@@ -64,17 +64,14 @@ pub struct CageInfo {
 
 fn get_cage_details(config_path: String) -> Result<CageInfo, EnvError> {
     let cage_config = CageConfig::try_from_filepath(&config_path)?;
-
-    if cage_config.app_uuid.is_none()
-        || cage_config.team_uuid.is_none()
-        || cage_config.uuid.is_none()
-    {
-        Err(EnvError::MissingAppInfo)
-    } else {
-        Ok(CageInfo {
-            app_uuid: cage_config.app_uuid.unwrap(),
-            team_uuid: cage_config.team_uuid.unwrap(),
-            uuid: cage_config.uuid.unwrap(),
-        })
-    }
+    let uuid = cage_config.uuid.ok_or(EnvError::MissingAppInfo)?;
+    // The app and team default to those of the active credentials profile
+    let (app_uuid, team_uuid) =
+        crate::auth::resolve_app_and_team(cage_config.app_uuid, cage_config.team_uuid)
+            .ok_or(EnvError::MissingAppInfo)?;
+    Ok(CageInfo {
+        uuid,
+        team_uuid,
+        app_uuid,
+    })
 }
@@ -1,6 +1,8 @@
 pub mod api;
+pub mod apply;
 #[cfg(not(target_os = "windows"))]
 pub mod attest;
+pub mod auth;
 pub mod build;
 pub mod cert;
 pub mod cli;
@@ -14,7 +16,10 @@ pub mod docker;
 pub mod enclave;
 pub mod encrypt;
 pub mod env;
+pub mod logs;
+pub mod output;
 pub mod progress;
+pub mod workspace;
 
 #[cfg(test)]
 pub mod test_utils;
@@ -0,0 +1,25 @@
+use crate::common::CliError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LogsError {
+    #[error("Could not parse {0} as a time. Use an RFC 3339 timestamp (e.g. 2023-01-01T12:00:00Z), a relative time (e.g. 30m, 2h, 1d12h) or now.")]
+    InvalidTime(String),
+    #[error("The start of the time range ({0}) must be before the end ({1})")]
+    InvalidTimeRange(String, String),
+    #[error("Invalid --grep pattern — {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("An error occurred contacting the API — {0}")]
+    ApiError(#[from] crate::api::client::ApiError),
+}
+
+impl CliError for LogsError {
+    fn exitcode(&self) -> exitcode::ExitCode {
+        match self {
+            Self::InvalidTime(_) | Self::InvalidTimeRange(..) | Self::InvalidPattern(_) => {
+                exitcode::USAGE
+            }
+            Self::ApiError(api_err) => api_err.exitcode(),
+        }
+    }
+}
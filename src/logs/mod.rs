@@ -0,0 +1,501 @@
+pub mod error;
+
+use crate::api::cage::{CageLogs, CagesClient, LogEvent};
+use crate::api::client::ApiResult;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use console::Color;
+use error::LogsError;
+use regex::Regex;
+use std::collections::HashSet;
+
+// Events can be ingested out of order, so each poll re-reads a short window before the newest event seen
+const FOLLOW_LOOKBACK_MS: i64 = 30_000;
+const INSTANCE_COLOURS: [Color; 6] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+/// Parses a time given on the command line. Accepts RFC 3339 timestamps, `now`, or a time relative
+/// to now such as `30m`, `2h` or `1d12h`.
+pub fn parse_time(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, LogsError> {
+    let input = input.trim();
+    if input.eq_ignore_ascii_case("now") {
+        return Ok(now);
+    }
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(input) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    parse_relative_duration(input)
+        .and_then(|duration| now.checked_sub_signed(duration))
+        .ok_or_else(|| LogsError::InvalidTime(input.to_string()))
+}
+
+fn parse_relative_duration(input: &str) -> Option<Duration> {
+    let input = input.strip_suffix(" ago").unwrap_or(input);
+    if input.is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let amount: i64 = digits.parse().ok()?;
+        digits.clear();
+        let duration = match c {
+            's' => Duration::seconds(amount),
+            'm' => Duration::minutes(amount),
+            'h' => Duration::hours(amount),
+            'd' => Duration::days(amount),
+            'w' => Duration::weeks(amount),
+            _ => return None,
+        };
+        total = total.checked_add(&duration)?;
+    }
+    // Every amount must be followed by a unit
+    if !digits.is_empty() {
+        return None;
+    }
+    Some(total)
+}
+
+/// Resolves the `--since` and `--until` flags into a range of epoch millis
+pub fn resolve_time_range(
+    since: &str,
+    until: &str,
+    now: DateTime<Utc>,
+) -> Result<(i64, i64), LogsError> {
+    let start = parse_time(since, now)?;
+    let end = parse_time(until, now)?;
+    if start >= end {
+        return Err(LogsError::InvalidTimeRange(
+            format_datetime(start),
+            format_datetime(end),
+        ));
+    }
+    Ok((start.timestamp_millis(), end.timestamp_millis()))
+}
+
+/// Pages through a Cage's logs for a time range, following the API's pagination tokens
+pub struct LogPageStream<'a> {
+    cages_client: &'a CagesClient,
+    cage_uuid: String,
+    start_time: i64,
+    end_time: i64,
+    next_token: Option<String>,
+    exhausted: bool,
+}
+
+impl<'a> LogPageStream<'a> {
+    pub fn new(
+        cages_client: &'a CagesClient,
+        cage_uuid: &str,
+        start_time: i64,
+        end_time: i64,
+    ) -> Self {
+        Self {
+            cages_client,
+            cage_uuid: cage_uuid.to_string(),
+            start_time,
+            end_time,
+            next_token: None,
+            exhausted: false,
+        }
+    }
+
+    pub async fn next_page(&mut self) -> ApiResult<Option<CageLogs>> {
+        if self.exhausted {
+            return Ok(None);
+        }
+        let page = self
+            .cages_client
+            .get_cage_logs(
+                &self.cage_uuid,
+                self.start_time as u128,
+                self.end_time as u128,
+                self.next_token.as_deref(),
+            )
+            .await?;
+        // The final page can repeat the token it was requested with, rather than omitting it
+        let next_token = page.next_token().map(str::to_string);
+        self.exhausted = next_token.is_none() || next_token == self.next_token;
+        self.next_token = next_token;
+        Ok(Some(page))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, clap::ArgEnum)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn from_token(token: &str) -> Option<Self> {
+        match token.to_ascii_lowercase().as_str() {
+            "trace" => Some(Self::Trace),
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" | "warning" => Some(Self::Warn),
+            "error" | "fatal" | "critical" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Filters applied to log events before they're output
+pub struct LogFilter {
+    instance: Option<String>,
+    pattern: Option<Regex>,
+    min_level: Option<LogLevel>,
+    level_pattern: Regex,
+}
+
+impl LogFilter {
+    pub fn new(
+        instance: Option<String>,
+        pattern: Option<&str>,
+        min_level: Option<LogLevel>,
+    ) -> Result<Self, LogsError> {
+        Ok(Self {
+            instance,
+            pattern: pattern.map(Regex::new).transpose()?,
+            min_level,
+            level_pattern: Regex::new(
+                r"(?i)\b(trace|debug|info|warn|warning|error|fatal|critical)\b",
+            )
+            .expect("Failed to compile hardcoded log level pattern"),
+        })
+    }
+
+    /// The level of an event, taken from the first level name that appears in its message
+    pub fn event_level(&self, event: &LogEvent) -> Option<LogLevel> {
+        self.level_pattern
+            .find(event.message())
+            .and_then(|level| LogLevel::from_token(level.as_str()))
+    }
+
+    /// Instances can be given either as their full id, or the short id shown in the log prefix
+    pub fn matches(&self, event: &LogEvent) -> bool {
+        let instance_matches = self
+            .instance
+            .as_deref()
+            .map(|instance| event.instance_id().contains(instance))
+            .unwrap_or(true);
+        let pattern_matches = self
+            .pattern
+            .as_ref()
+            .map(|pattern| pattern.is_match(event.message()))
+            .unwrap_or(true);
+        // Events without a recognisable level are excluded when filtering by level
+        let level_matches = self
+            .min_level
+            .map(|min_level| {
+                self.event_level(event)
+                    .map(|level| level >= min_level)
+                    .unwrap_or(false)
+            })
+            .unwrap_or(true);
+        instance_matches && pattern_matches && level_matches
+    }
+}
+
+type EventKey = (i64, i64, String, String);
+
+fn event_key(event: &LogEvent) -> EventKey {
+    (
+        event.timestamp(),
+        event.ingestion_time(),
+        event.instance_id().to_string(),
+        event.message().to_string(),
+    )
+}
+
+/// Repeatedly polls a Cage's logs, only returning events which haven't been returned before
+pub struct LogFollower {
+    start_time: i64,
+    cursor: i64,
+    seen: HashSet<EventKey>,
+}
+
+impl LogFollower {
+    pub fn new(start_time: i64) -> Self {
+        Self {
+            start_time,
+            cursor: start_time,
+            seen: HashSet::new(),
+        }
+    }
+
+    fn query_start(&self) -> i64 {
+        (self.cursor - FOLLOW_LOOKBACK_MS).max(self.start_time)
+    }
+
+    /// Filters out events already seen, returning the remainder in the order they were logged
+    pub fn take_new_events(&mut self, events: impl IntoIterator<Item = LogEvent>) -> Vec<LogEvent> {
+        let mut new_events: Vec<LogEvent> = events
+            .into_iter()
+            .filter(|event| self.seen.insert(event_key(event)))
+            .collect();
+        new_events.sort_by_key(|event| (event.timestamp(), event.ingestion_time()));
+
+        if let Some(latest) = new_events.last() {
+            self.cursor = self.cursor.max(latest.timestamp());
+        }
+        // Events older than the lookback window can't be returned again, so stop tracking them
+        let query_start = self.query_start();
+        self.seen
+            .retain(|(timestamp, ..)| *timestamp >= query_start);
+        new_events
+    }
+
+    pub async fn poll(
+        &mut self,
+        cages_client: &CagesClient,
+        cage_uuid: &str,
+        now: i64,
+    ) -> ApiResult<Vec<LogEvent>> {
+        let mut log_pages = LogPageStream::new(cages_client, cage_uuid, self.query_start(), now);
+        let mut events = Vec::new();
+        while let Some(page) = log_pages.next_page().await? {
+            events.extend(page.log_events().iter().cloned());
+        }
+        Ok(self.take_new_events(events))
+    }
+}
+
+pub fn format_timestamp(epoch_millis: i64) -> Option<String> {
+    Utc.timestamp_millis_opt(epoch_millis)
+        .single()
+        .map(format_datetime)
+}
+
+fn format_datetime(datetime: DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn format_log_prefix(event: &LogEvent) -> Option<String> {
+    let instance_id = event.instance_id();
+    let short_instance_id = &instance_id[instance_id.len().saturating_sub(6)..];
+    format_timestamp(event.timestamp())
+        .map(|timestamp| format!("[ Instance-{} @ {} ]", short_instance_id, timestamp))
+}
+
+pub fn format_log_event(event: &LogEvent) -> Option<String> {
+    format_log_prefix(event).map(|prefix| format!("{} {}", prefix, event.message()))
+}
+
+/// Picks a stable colour for an instance, so its logs can be told apart from other instances
+pub fn instance_colour(instance_id: &str) -> Color {
+    let hash = instance_id.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    });
+    INSTANCE_COLOURS[hash % INSTANCE_COLOURS.len()]
+}
+
+/// Formats a log event with its prefix coloured by instance. Colours are dropped when stdout isn't a terminal.
+pub fn format_coloured_log_event(event: &LogEvent) -> Option<String> {
+    format_log_prefix(event).map(|prefix| {
+        let colour = instance_colour(event.instance_id());
+        format!("{} {}", console::style(prefix).fg(colour), event.message())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::AuthMode;
+    use crate::test_utils::{deploy_mock_cage, start_mock_cages_api};
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2023-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_relative_times() {
+        assert_eq!(parse_time("now", now()).unwrap(), now());
+        assert_eq!(
+            parse_time("30m", now()).unwrap(),
+            now() - Duration::minutes(30)
+        );
+        assert_eq!(
+            parse_time("1d12h", now()).unwrap(),
+            now() - Duration::hours(36)
+        );
+        assert_eq!(
+            parse_time("2w ago", now()).unwrap(),
+            now() - Duration::weeks(2)
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc3339_times() {
+        assert_eq!(
+            parse_time("2023-06-01T10:30:00+01:00", now()).unwrap(),
+            DateTime::parse_from_rfc3339("2023-06-01T09:30:00Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_times() {
+        for input in ["", "30", "m", "30x", "yesterday", "2023-06-01"] {
+            assert!(
+                matches!(parse_time(input, now()), Err(LogsError::InvalidTime(_))),
+                "Expected {input} to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_time_range_must_be_ordered() {
+        let (start, end) = resolve_time_range("1h", "now", now()).unwrap();
+        assert_eq!(end - start, 60 * 60 * 1000);
+        assert!(matches!(
+            resolve_time_range("now", "1h", now()),
+            Err(LogsError::InvalidTimeRange(..))
+        ));
+    }
+
+    #[test]
+    fn test_format_log_event() {
+        let event: LogEvent = serde_json::from_value(serde_json::json!({
+            "timestamp": 1685620800000_i64,
+            "message": "Listening on port 8008",
+            "ingestionTime": 1685620800000_i64,
+            "instanceId": "i-0123456789abcdef",
+        }))
+        .unwrap();
+        assert_eq!(
+            format_log_event(&event).unwrap(),
+            "[ Instance-abcdef @ 2023-06-01T12:00:00Z ] Listening on port 8008"
+        );
+    }
+
+    fn log_event(timestamp: i64, ingestion_time: i64, message: &str) -> LogEvent {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": timestamp,
+            "message": message,
+            "ingestionTime": ingestion_time,
+            "instanceId": "i-0123456789abcdef",
+        }))
+        .unwrap()
+    }
+
+    fn instance_event(instance_id: &str, message: &str) -> LogEvent {
+        serde_json::from_value(serde_json::json!({
+            "timestamp": 0,
+            "message": message,
+            "ingestionTime": 0,
+            "instanceId": instance_id,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_filter_by_instance_and_pattern() {
+        let filter = LogFilter::new(Some("abcdef".to_string()), Some(r"port \d+"), None).unwrap();
+        assert!(filter.matches(&instance_event(
+            "i-0123456789abcdef",
+            "Listening on port 8008"
+        )));
+        assert!(!filter.matches(&instance_event("i-0123456789abcdef", "Starting up")));
+        assert!(!filter.matches(&instance_event(
+            "i-0123456789fedcba",
+            "Listening on port 8008"
+        )));
+        assert!(matches!(
+            LogFilter::new(None, Some("("), None),
+            Err(LogsError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_filter_by_minimum_level() {
+        let filter = LogFilter::new(None, None, Some(LogLevel::Warn)).unwrap();
+        assert!(filter.matches(&instance_event("i-1", "[ERROR] Failed to connect")));
+        assert!(filter.matches(&instance_event("i-1", "warning: disk almost full")));
+        assert!(!filter.matches(&instance_event("i-1", "INFO Listening on port 8008")));
+        assert!(!filter.matches(&instance_event("i-1", "no level here")));
+
+        let unfiltered = LogFilter::new(None, None, None).unwrap();
+        assert!(unfiltered.matches(&instance_event("i-1", "no level here")));
+        assert_eq!(
+            unfiltered.event_level(&instance_event("i-1", "level=Debug msg=hello")),
+            Some(LogLevel::Debug)
+        );
+    }
+
+    #[test]
+    fn test_follower_removes_duplicate_events() {
+        let mut follower = LogFollower::new(0);
+        let first_poll = follower.take_new_events(vec![
+            log_event(2_000, 2_100, "second"),
+            log_event(1_000, 1_100, "first"),
+        ]);
+        let messages: Vec<&str> = first_poll.iter().map(|event| event.message()).collect();
+        assert_eq!(messages, vec!["first", "second"]);
+
+        // The lookback window returns both events again, along with a late arriving one
+        let second_poll = follower.take_new_events(vec![
+            log_event(1_000, 1_100, "first"),
+            log_event(1_500, 3_000, "late"),
+            log_event(2_000, 2_100, "second"),
+            log_event(3_000, 3_100, "third"),
+        ]);
+        let messages: Vec<&str> = second_poll.iter().map(|event| event.message()).collect();
+        assert_eq!(messages, vec!["late", "third"]);
+        assert!(follower
+            .take_new_events(vec![log_event(3_000, 3_100, "third")])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_follower_queries_from_lookback_window() {
+        let mut follower = LogFollower::new(10_000);
+        assert_eq!(follower.query_start(), 10_000);
+        follower.take_new_events(vec![log_event(100_000, 100_000, "event")]);
+        assert_eq!(follower.query_start(), 100_000 - FOLLOW_LOOKBACK_MS);
+    }
+
+    #[test]
+    fn test_instance_colours_are_stable() {
+        assert_eq!(
+            instance_colour("i-0123456789abcdef"),
+            instance_colour("i-0123456789abcdef")
+        );
+        let colours: HashSet<_> = ["i-a", "i-b", "i-c", "i-d", "i-e", "i-f"]
+            .iter()
+            .map(|instance_id| format!("{:?}", instance_colour(instance_id)))
+            .collect();
+        assert!(colours.len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_log_page_stream_follows_next_token() {
+        let base_url = start_mock_cages_api().await;
+        let cage_uuid = deploy_mock_cage(&base_url).await;
+        let cages_client =
+            CagesClient::new(AuthMode::ApiKey("test".to_string())).with_base_url(base_url);
+
+        let end_time = Utc::now().timestamp_millis();
+        let mut stream = LogPageStream::new(&cages_client, &cage_uuid, end_time - 60_000, end_time);
+        let mut page_sizes = Vec::new();
+        while let Some(page) = stream.next_page().await.unwrap() {
+            page_sizes.push(page.log_events().len());
+        }
+        assert_eq!(page_sizes, vec![2, 1]);
+        assert!(stream.next_page().await.unwrap().is_none());
+    }
+}
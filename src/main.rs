@@ -5,8 +5,10 @@ use env_logger::{Builder, Env};
 #[cfg(not(target_os = "windows"))]
 use ev_cage::cli::attest;
 use ev_cage::cli::{
-    build, cert, delete, deploy, describe, dev, encrypt, env, init, list, logs, update, Command,
+    apply, auth, build, cert, config, delete, deploy, describe, dev, encrypt, env, init, link,
+    list, login, logs, plan, update, Command,
 };
+use ev_cage::output;
 use human_panic::setup_panic;
 use log::Record;
 use std::io::Write;
@@ -24,10 +26,19 @@ pub struct BaseArgs {
     #[clap(short, long, global = true)]
     pub verbose: bool,
 
-    /// Toggle JSON output for stdout
+    /// Write the result of the command to stdout as a versioned JSON envelope, including any errors
     #[clap(long, global = true)]
     pub json: bool,
 
+    /// The credentials profile to use. Can also be set using the EV_PROFILE environment variable
+    #[clap(long, global = true)]
+    pub profile: Option<String>,
+
+    /// The profile from cage.toml to apply. Can also be set using the EV_CONFIG_PROFILE environment
+    /// variable
+    #[clap(long, global = true)]
+    pub config_profile: Option<String>,
+
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -44,22 +55,37 @@ async fn main() {
 
     let base_args: BaseArgs = BaseArgs::parse();
     setup_logger(base_args.verbose);
+    output::set_json(base_args.json);
+    // The shared auth resolver and config reader read the selected profiles from the environment
+    if let Some(profile) = base_args.profile.as_deref() {
+        std::env::set_var(ev_cage::auth::PROFILE_ENV_VAR, profile);
+    }
+    if let Some(config_profile) = base_args.config_profile.as_deref() {
+        std::env::set_var(ev_cage::config::PROFILE_ENV_VAR, config_profile);
+    }
     let exit_code = match base_args.command {
+        Command::Apply(apply_args) => apply::run(apply_args).await,
+        Command::Auth(auth_args) => auth::run(auth_args).await,
         Command::Build(build_args) => build::run(build_args).await,
         Command::Cert(cert_args) => cert::run(cert_args).await,
+        Command::Config(config_args) => config::run(config_args).await,
         Command::Delete(delete_args) => delete::run(delete_args).await,
         Command::Deploy(deploy_args) => deploy::run(deploy_args).await,
         Command::Describe(describe_args) => describe::run(describe_args).await,
         Command::Dev(dev_args) => dev::run(dev_args).await,
         Command::Init(init_args) => init::run(init_args).await,
+        Command::Link(link_args) => link::run(link_args).await,
         Command::List(list_args) => list::run(list_args).await,
+        Command::Login(login_args) => login::run(login_args).await,
         Command::Logs(log_args) => logs::run(log_args).await,
+        Command::Plan(plan_args) => plan::run(plan_args).await,
         Command::Update(update_args) => update::run(update_args).await,
         #[cfg(not(target_os = "windows"))]
         Command::Attest(attest_args) => attest::run(attest_args).await,
         Command::Env(env_args) => env::run(env_args).await,
         Command::Encrypt(env_args) => encrypt::run(env_args).await,
     };
+    output::finish(exit_code);
     std::process::exit(exit_code);
 }
 
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Version of the JSON envelope. Bump this whenever a change could break consumers of `--json` output.
+pub const ENVELOPE_VERSION: u32 = 1;
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+static COMMAND_OUTPUT: Mutex<CommandOutput> = Mutex::new(CommandOutput {
+    data: None,
+    errors: Vec::new(),
+});
+
+struct CommandOutput {
+    data: Option<Value>,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorOutput {
+    pub message: String,
+    pub exit_code: exitcode::ExitCode,
+}
+
+/// The document written to stdout when `--json` is set
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub version: u32,
+    pub status: Status,
+    pub data: Option<Value>,
+    pub error: Option<ErrorOutput>,
+}
+
+impl Envelope {
+    pub fn new(data: Option<Value>, errors: &[String], exit_code: exitcode::ExitCode) -> Self {
+        if exit_code == exitcode::OK {
+            return Self {
+                version: ENVELOPE_VERSION,
+                status: Status::Success,
+                data,
+                error: None,
+            };
+        }
+        let message = if errors.is_empty() {
+            format!("Command failed with exit code {}", exit_code)
+        } else {
+            errors.join("\n")
+        };
+        Self {
+            version: ENVELOPE_VERSION,
+            status: Status::Error,
+            data,
+            error: Some(ErrorOutput { message, exit_code }),
+        }
+    }
+}
+
+pub fn set_json(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Writes the result of a command. With `--json`, it is held back and emitted as the envelope's data
+/// once the command exits, otherwise it is printed to stdout as pretty JSON.
+pub fn data<T: Serialize>(value: &T) {
+    let value = serde_json::to_value(value).unwrap_or(Value::Null);
+    if is_json() {
+        COMMAND_OUTPUT.lock().unwrap().data = Some(value);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+    }
+}
+
+/// Records an error message to include in the envelope. Use `report_error!` rather than calling this directly.
+pub fn record_error(message: String) {
+    COMMAND_OUTPUT.lock().unwrap().errors.push(message);
+}
+
+/// Writes the envelope for the finished command to stdout, when `--json` is set
+pub fn finish(exit_code: exitcode::ExitCode) {
+    if !is_json() {
+        return;
+    }
+    let mut output = COMMAND_OUTPUT.lock().unwrap();
+    let envelope = Envelope::new(output.data.take(), &output.errors, exit_code);
+    println!("{}", serde_json::to_string_pretty(&envelope).unwrap());
+}
+
+/// Logs an error, and records it so that it's included in the `--json` envelope
+#[macro_export]
+macro_rules! report_error {
+    ($($arg:tt)+) => {{
+        let message = format!($($arg)+);
+        log::error!("{}", message);
+        $crate::output::record_error(message);
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_success_envelope() {
+        let envelope = Envelope::new(Some(json!({ "uuid": "cage_123" })), &[], exitcode::OK);
+        assert_eq!(
+            serde_json::to_value(envelope).unwrap(),
+            json!({
+                "version": ENVELOPE_VERSION,
+                "status": "success",
+                "data": { "uuid": "cage_123" },
+                "error": null
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_envelope_includes_messages_and_exit_code() {
+        let errors = vec!["Cage not found".to_string(), "Try again".to_string()];
+        let envelope = Envelope::new(None, &errors, exitcode::DATAERR);
+        assert_eq!(
+            serde_json::to_value(envelope).unwrap(),
+            json!({
+                "version": ENVELOPE_VERSION,
+                "status": "error",
+                "data": null,
+                "error": { "message": "Cage not found\nTry again", "exitCode": exitcode::DATAERR }
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_envelope_without_messages() {
+        let envelope = Envelope::new(None, &[], exitcode::SOFTWARE);
+        assert_eq!(
+            envelope.error.unwrap().message,
+            format!("Command failed with exit code {}", exitcode::SOFTWARE)
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use atty::Stream;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 use crate::api::cage::CagesClient;
+use crate::api::client::ApiClient;
 use crate::common::CliError;
 
-const MAX_SUCCESSIVE_POLLING_ERRORS: i32 = 5; // #attempts allowed at 6s intervals
+const POLLING_INTERVAL: std::time::Duration = std::time::Duration::from_millis(6000);
+const MAX_SUCCESSIVE_POLLING_ERRORS: u32 = 5;
 
 fn get_progress_bar(start_msg: &str, upload_len: Option<u64>) -> ProgressBar {
     match upload_len {
@@ -107,6 +109,86 @@ pub fn get_tracker(
     }
 }
 
+/// Tracks tasks that run side by side, such as the builds of workspace members. On a TTY each task
+/// gets its own spinner, otherwise its messages are logged with its label.
+pub struct MultiTracker {
+    progress: Option<MultiProgress>,
+}
+
+impl Default for MultiTracker {
+    fn default() -> Self {
+        let progress = atty::is(Stream::Stdout).then(MultiProgress::new);
+        Self { progress }
+    }
+}
+
+impl MultiTracker {
+    pub fn add(&self, label: &str, first_message: &str) -> Box<dyn ProgressLogger + Send + Sync> {
+        match self.progress.as_ref() {
+            Some(progress) => {
+                let progress_bar = progress.add(ProgressBar::new_spinner());
+                progress_bar.set_style(
+                    ProgressStyle::default_spinner()
+                        .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷", "•"])
+                        .template("{spinner:.green} {prefix:.bold} {msg}")
+                        .expect("Failed to create progress bar template from hardcoded template"),
+                );
+                progress_bar.set_prefix(label.to_string());
+                progress_bar.set_message(first_message.to_string());
+                progress_bar.enable_steady_tick(std::time::Duration::from_millis(80));
+                Box::new(LabelledTty { progress_bar })
+            }
+            None => {
+                log::info!("[{label}] {first_message}");
+                Box::new(LabelledNonTty {
+                    label: label.to_string(),
+                })
+            }
+        }
+    }
+}
+
+// Finished tasks keep their line, so the outcome of each stays visible until they're all done
+struct LabelledTty {
+    progress_bar: ProgressBar,
+}
+
+struct LabelledNonTty {
+    label: String,
+}
+
+impl ProgressLogger for LabelledTty {
+    fn set_message(&self, message: &str) {
+        self.progress_bar.set_message(message.to_string());
+    }
+    fn finish_with_message(&self, message: &str) {
+        self.progress_bar.finish_with_message(message.to_string());
+    }
+    fn finish(&self) {
+        self.progress_bar.finish();
+    }
+
+    fn set_position(&self, bytes: u64) {
+        self.progress_bar.set_position(bytes);
+    }
+}
+
+impl ProgressLogger for LabelledNonTty {
+    fn set_message(&self, message: &str) {
+        log::info!("[{}] {message}", self.label)
+    }
+    fn finish_with_message(&self, message: &str) {
+        log::info!("[{}] {message}", self.label)
+    }
+    fn finish(&self) {
+        // no op
+    }
+
+    fn set_position(&self, _bytes: u64) {
+        // no op
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum StatusReport {
     Update(String),
@@ -154,6 +236,8 @@ where
             .map(|given_msg| given_msg != new_msg)
             .unwrap_or(true)
     };
+    // Polls after an error are backed off according to the client's retry policy
+    let retry_policy = api_client.retry_policy().clone();
     let mut poll_err_count = 0;
 
     loop {
@@ -182,8 +266,11 @@ where
                     progress_bar.finish();
                     return Err(e);
                 }
+                let backoff = retry_policy.backoff(poll_err_count).max(POLLING_INTERVAL);
+                tokio::time::sleep(backoff).await;
+                continue;
             }
         };
-        tokio::time::sleep(std::time::Duration::from_millis(6000)).await;
+        tokio::time::sleep(POLLING_INTERVAL).await;
     }
 }
@@ -1,9 +1,10 @@
 use crate::api::assets::AssetsClient;
-use crate::build::build_enclave_image_file;
 use crate::build::error::BuildError;
+use crate::build::{build_enclave_image_file, BuildOptions};
 use crate::common::OutputPath;
 use crate::config::{read_and_validate_config, ValidatedCageBuildConfig};
-use crate::enclave::BuiltEnclave;
+use crate::dev::api::mock_cages_api_router;
+use crate::enclave::{BuildImages, BuiltEnclave};
 
 pub async fn build_test_cage(
     output_dir: Option<&str>,
@@ -23,12 +24,15 @@ pub async fn build_test_cage(
         &build_args,
         ".",
         output_dir,
-        false,
-        None,
-        data_plane_version,
-        installer_version,
-        timestamp,
-        from_existing,
+        BuildOptions {
+            verbose: false,
+            docker_build_args: None,
+            data_plane_version,
+            installer_version,
+            timestamp,
+            from_existing,
+            images: &BuildImages::default(),
+        },
     )
     .await
 }
@@ -38,3 +42,71 @@ fn get_test_build_args() -> ValidatedCageBuildConfig {
         .expect("Testing config failed to validate");
     validated_config
 }
+
+/// Starts the mock Cages API from `ev-cage dev api` on a random port, returning its base url
+pub async fn start_mock_cages_api() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let router = mock_cages_api_router(base_url.clone());
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service())
+            .await
+            .unwrap();
+    });
+    base_url
+}
+
+/// Creates a Cage on the mock API and polls its first deployment to completion, returning the Cage uuid
+pub async fn deploy_mock_cage(base_url: &str) -> String {
+    let client = reqwest::Client::new();
+    let cage: serde_json::Value = client
+        .post(format!("{base_url}/v2/cages/"))
+        .header("api-key", "test")
+        .json(&serde_json::json!({ "name": "mock-cage", "isTimeBound": false }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let cage_uuid = cage["uuid"].as_str().unwrap().to_string();
+
+    let intent: serde_json::Value = client
+        .post(format!("{base_url}/v2/cages/{cage_uuid}/credentials"))
+        .header("api-key", "test")
+        .json(&serde_json::json!({
+            "PCR0": "000",
+            "PCR1": "111",
+            "PCR2": "222",
+            "debugMode": false,
+            "metadata": { "dataPlaneVersion": "0.0.0", "installerVersion": "0.0.0" }
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    client
+        .put(intent["signedUrl"].as_str().unwrap())
+        .body("eif")
+        .send()
+        .await
+        .unwrap();
+
+    let deployment_url = format!(
+        "{base_url}/v2/cages/{cage_uuid}/deployments/{}",
+        intent["deploymentUuid"].as_str().unwrap()
+    );
+    for _ in 0..4 {
+        client
+            .get(&deployment_url)
+            .header("api-key", "test")
+            .send()
+            .await
+            .unwrap();
+    }
+    cage_uuid
+}
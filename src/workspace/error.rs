@@ -0,0 +1,37 @@
+use crate::common::CliError;
+use crate::config::CageConfigError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WorkspaceError {
+    #[error("Failed to find workspace manifest at {0}")]
+    MissingManifest(String),
+    #[error("Failed to read workspace manifest — {0:?}")]
+    FailedToAccessManifest(#[from] std::io::Error),
+    #[error("Failed to parse workspace manifest — {0}")]
+    FailedToParseManifest(#[from] toml::de::Error),
+    #[error("The workspace manifest at {0} does not list any members")]
+    NoMembers(String),
+    #[error("Failed to find a cage.toml for workspace member {0} at {1}")]
+    MissingMemberConfig(String, String),
+    #[error("Failed to read the config for workspace member {0} — {1}")]
+    InvalidMemberConfig(String, CageConfigError),
+    #[error("More than one workspace member is named {0}. Cage names must be unique within a workspace.")]
+    DuplicateMember(String),
+    #[error("No workspace member named {0}. Available members: {1}")]
+    UnknownMember(String, String),
+}
+
+impl CliError for WorkspaceError {
+    fn exitcode(&self) -> exitcode::ExitCode {
+        match self {
+            Self::MissingManifest(_) | Self::MissingMemberConfig(..) => exitcode::NOINPUT,
+            Self::FailedToAccessManifest(_) => exitcode::IOERR,
+            Self::FailedToParseManifest(_) | Self::NoMembers(_) | Self::DuplicateMember(_) => {
+                exitcode::DATAERR
+            }
+            Self::InvalidMemberConfig(_, inner) => inner.exitcode(),
+            Self::UnknownMember(..) => exitcode::USAGE,
+        }
+    }
+}
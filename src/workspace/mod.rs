@@ -0,0 +1,584 @@
+pub mod error;
+use error::WorkspaceError;
+
+use crate::build::error::BuildError;
+use crate::build::{build_enclave_image_file, BuildOptions};
+use crate::common::OutputPath;
+use crate::config::{BuildTimeConfig, CageConfig, ValidatedCageBuildConfig};
+use crate::enclave::{BuildImages, BuiltEnclave, EIFMeasurements};
+use crate::output;
+use crate::progress::MultiTracker;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const MEMBER_CONFIG_FILENAME: &str = "cage.toml";
+// PCRs are abbreviated in the summary table to keep it readable. The JSON output has them in full.
+const PCR_DISPLAY_LENGTH: usize = 16;
+
+/// Options for running a command for each Cage in a workspace
+#[derive(Args, Clone, Debug, Default)]
+pub struct WorkspaceArgs {
+    /// Path to a workspace manifest. The command will run for each member Cage listed in it.
+    #[clap(long = "workspace")]
+    pub manifest: Option<String>,
+
+    /// Name of a workspace member to run for, rather than every member. Can be given more than once.
+    #[clap(long = "member", requires = "manifest")]
+    pub members: Vec<String>,
+}
+
+impl WorkspaceArgs {
+    pub fn is_workspace(&self) -> bool {
+        self.manifest.is_some()
+    }
+
+    /// Loads the workspace manifest, and returns the members selected with `--member`
+    pub fn load_members(&self) -> Result<Vec<WorkspaceMember>, WorkspaceError> {
+        let manifest = self.manifest.as_deref().unwrap_or("./cage-workspace.toml");
+        Workspace::load(manifest)?.select(&self.members)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceManifest {
+    workspace: WorkspaceTable,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceTable {
+    members: Vec<String>,
+}
+
+/// A set of Cages which are built and deployed together, listed in a workspace manifest
+#[derive(Debug)]
+pub struct Workspace {
+    members: Vec<WorkspaceMember>,
+}
+
+impl Workspace {
+    /// Reads the manifest at `path`. Members are paths to a cage.toml, or to the directory holding
+    /// it, relative to the manifest.
+    pub fn load(path: &str) -> Result<Self, WorkspaceError> {
+        let manifest_path = Path::new(path);
+        if !manifest_path.exists() {
+            return Err(WorkspaceError::MissingManifest(path.to_string()));
+        }
+        let manifest: WorkspaceManifest = toml::from_str(&std::fs::read_to_string(manifest_path)?)?;
+        if manifest.workspace.members.is_empty() {
+            return Err(WorkspaceError::NoMembers(path.to_string()));
+        }
+
+        let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut members: Vec<WorkspaceMember> = Vec::new();
+        for entry in manifest.workspace.members {
+            let member = WorkspaceMember::load(manifest_dir, &entry)?;
+            if members.iter().any(|existing| existing.name == member.name) {
+                return Err(WorkspaceError::DuplicateMember(member.name));
+            }
+            members.push(member);
+        }
+        Ok(Self { members })
+    }
+
+    pub fn members(&self) -> &[WorkspaceMember] {
+        &self.members
+    }
+
+    /// Returns the members with the given names, in the order they're listed in the manifest. Every
+    /// member is returned when no names are given.
+    pub fn select(self, names: &[String]) -> Result<Vec<WorkspaceMember>, WorkspaceError> {
+        if let Some(unknown) = names
+            .iter()
+            .find(|name| !self.members.iter().any(|member| &member.name == *name))
+        {
+            let available: Vec<_> = self.members.iter().map(WorkspaceMember::name).collect();
+            return Err(WorkspaceError::UnknownMember(
+                unknown.clone(),
+                available.join(", "),
+            ));
+        }
+        Ok(self
+            .members
+            .into_iter()
+            .filter(|member| names.is_empty() || names.contains(&member.name))
+            .collect())
+    }
+}
+
+/// A Cage in a workspace, named after the Cage in its config
+#[derive(Clone, Debug)]
+pub struct WorkspaceMember {
+    name: String,
+    config_path: String,
+    dir: PathBuf,
+}
+
+impl WorkspaceMember {
+    fn load(manifest_dir: &Path, entry: &str) -> Result<Self, WorkspaceError> {
+        let mut config_path = manifest_dir.join(entry);
+        if config_path.is_dir() {
+            config_path.push(MEMBER_CONFIG_FILENAME);
+        }
+        let config_path = config_path.display().to_string();
+        if !Path::new(&config_path).is_file() {
+            return Err(WorkspaceError::MissingMemberConfig(
+                entry.to_string(),
+                config_path,
+            ));
+        }
+        let config = CageConfig::read_uninterpolated(&config_path)
+            .map_err(|e| WorkspaceError::InvalidMemberConfig(entry.to_string(), e))?;
+
+        let dir = match Path::new(&config_path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Ok(Self {
+            name: config.name,
+            config_path,
+            dir,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config_path(&self) -> &str {
+        &self.config_path
+    }
+
+    /// The directory holding the member's cage.toml, which is also its Docker build context
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The directory within `output_dir` that the member's build artifacts are saved to, so members
+    /// don't overwrite each other's
+    pub fn output_dir(&self, output_dir: &str) -> PathBuf {
+        Path::new(output_dir).join(&self.name)
+    }
+
+    /// Docker images for the member's build, so members can be built side by side
+    pub fn build_images(&self) -> BuildImages {
+        BuildImages::for_cage(&self.name)
+    }
+
+    pub fn with_args<'a, B: BuildTimeConfig>(&'a self, args: &'a B) -> MemberArgs<'a, B> {
+        MemberArgs { member: self, args }
+    }
+}
+
+/// Command line overrides for a workspace member. Paths in the member's cage.toml are resolved
+/// against its directory, while paths given on the command line stay relative to the current one.
+pub struct MemberArgs<'a, B> {
+    member: &'a WorkspaceMember,
+    args: &'a B,
+}
+
+impl<B: BuildTimeConfig> BuildTimeConfig for MemberArgs<'_, B> {
+    fn certificate(&self) -> Option<&str> {
+        self.args.certificate()
+    }
+
+    fn dockerfile(&self) -> Option<&str> {
+        self.args.dockerfile()
+    }
+
+    fn private_key(&self) -> Option<&str> {
+        self.args.private_key()
+    }
+
+    fn merge_with_config(&self, config: &CageConfig) -> CageConfig {
+        self.args
+            .merge_with_config(&config.relative_to(self.member.dir()))
+    }
+}
+
+/// Everything needed to build one member's enclave, owned so the build can run on its own thread
+pub struct MemberBuild {
+    pub config: ValidatedCageBuildConfig,
+    pub context_path: String,
+    pub output_dir: Option<String>,
+    pub docker_build_args: Option<Vec<String>>,
+    pub data_plane_version: String,
+    pub installer_version: String,
+    pub timestamp: String,
+    pub images: BuildImages,
+}
+
+impl MemberBuild {
+    async fn run(self) -> Result<(BuiltEnclave, OutputPath), BuildError> {
+        let docker_build_args = self
+            .docker_build_args
+            .as_ref()
+            .map(|args| args.iter().map(AsRef::as_ref).collect());
+        // Docker output from builds running side by side would be interleaved, so it's not shown
+        build_enclave_image_file(
+            &self.config,
+            &self.context_path,
+            self.output_dir.as_deref(),
+            BuildOptions {
+                verbose: false,
+                docker_build_args,
+                data_plane_version: self.data_plane_version,
+                installer_version: self.installer_version,
+                timestamp: self.timestamp,
+                from_existing: None,
+                images: &self.images,
+            },
+        )
+        .await
+    }
+}
+
+/// Runs the builds side by side, with a progress line for each, and returns their results in the same
+/// order. The docker commands block, so each build gets its own thread.
+pub async fn build_members(
+    builds: Vec<(String, MemberBuild)>,
+) -> Vec<Result<(BuiltEnclave, OutputPath), BuildError>> {
+    let tracker = MultiTracker::default();
+    let runtime = tokio::runtime::Handle::current();
+    let tasks: Vec<_> = builds
+        .into_iter()
+        .map(|(name, build)| {
+            let progress = tracker.add(&name, "Building enclave...");
+            let runtime = runtime.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = runtime.block_on(build.run());
+                match &result {
+                    Ok(_) => progress.finish_with_message("Enclave built."),
+                    Err(e) => progress.finish_with_message(&format!("Build failed — {e}")),
+                }
+                result
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        results.push(task.await.expect("Workspace member build panicked"));
+    }
+    results
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Built,
+    Deployed,
+    Attested,
+    Listed,
+    Failed,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let outcome = match self {
+            Self::Built => "built",
+            Self::Deployed => "deployed",
+            Self::Attested => "attested",
+            Self::Listed => "listed",
+            Self::Failed => "failed",
+        };
+        write!(f, "{outcome}")
+    }
+}
+
+/// The result of running a command for one workspace member
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberReport {
+    pub member: String,
+    pub config: String,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurements: Option<EIFMeasurements>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub exit_code: exitcode::ExitCode,
+}
+
+impl MemberReport {
+    pub fn new(member: &WorkspaceMember, outcome: Outcome) -> Self {
+        Self {
+            member: member.name.clone(),
+            config: member.config_path.clone(),
+            outcome,
+            measurements: None,
+            data: None,
+            error: None,
+            exit_code: exitcode::OK,
+        }
+    }
+
+    pub fn failed(member: &WorkspaceMember, error: String, exit_code: exitcode::ExitCode) -> Self {
+        Self {
+            error: Some(error),
+            exit_code,
+            ..Self::new(member, Outcome::Failed)
+        }
+    }
+
+    pub fn with_measurements(mut self, measurements: &EIFMeasurements) -> Self {
+        self.measurements = Some(measurements.clone());
+        self
+    }
+
+    pub fn with_data<T: Serialize>(mut self, data: &T) -> Self {
+        self.data = serde_json::to_value(data).ok();
+        self
+    }
+}
+
+/// Formats the reports as a table of each member's outcome and PCRs
+pub fn summary_table(reports: &[MemberReport]) -> String {
+    let abbreviate = |pcr: Option<&String>| match pcr {
+        Some(pcr) if pcr.len() > PCR_DISPLAY_LENGTH => format!("{}…", &pcr[..PCR_DISPLAY_LENGTH]),
+        Some(pcr) => pcr.clone(),
+        None => "-".to_string(),
+    };
+    let header = ["MEMBER", "OUTCOME", "PCR0", "PCR1", "PCR2"].map(String::from);
+    let rows: Vec<[String; 5]> = reports
+        .iter()
+        .map(|report| {
+            let pcrs = report.measurements.as_ref().map(EIFMeasurements::pcrs);
+            [
+                report.member.clone(),
+                report.outcome.to_string(),
+                abbreviate(pcrs.map(|pcrs| &pcrs.pcr0)),
+                abbreviate(pcrs.map(|pcrs| &pcrs.pcr1)),
+                abbreviate(pcrs.map(|pcrs| &pcrs.pcr2)),
+            ]
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+/// Writes the reports as a summary table in the order the members are listed, or as data with
+/// `--json`. Returns the exit code of the first member that failed.
+pub fn report_summary(
+    members: &[WorkspaceMember],
+    mut reports: Vec<MemberReport>,
+) -> exitcode::ExitCode {
+    reports.sort_by_key(|report| {
+        members
+            .iter()
+            .position(|member| member.name == report.member)
+    });
+    if output::is_json() {
+        output::data(&reports);
+    } else {
+        print!("{}", summary_table(&reports));
+    }
+    exit_code(&reports)
+}
+
+/// The exit code of the first member that failed, if any did
+pub fn exit_code(reports: &[MemberReport]) -> exitcode::ExitCode {
+    reports
+        .iter()
+        .find(|report| report.outcome == Outcome::Failed)
+        .map_or(exitcode::OK, |report| report.exit_code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_member(dir: &Path, member_dir: &str, name: &str) {
+        let member_dir = dir.join(member_dir);
+        std::fs::create_dir_all(&member_dir).unwrap();
+        std::fs::write(
+            member_dir.join("cage.toml"),
+            format!(
+                r#"name = "{name}"
+debug = false
+dockerfile = "./Dockerfile"
+
+[egress]
+enabled = false
+
+[signing]
+certPath = "./cert.pem"
+keyPath = "../shared/key.pem"
+"#
+            ),
+        )
+        .unwrap();
+    }
+
+    fn workspace_dir(manifest: &str) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write_member(dir.path(), "services/payments", "payments");
+        write_member(dir.path(), "services/auth", "auth");
+        std::fs::write(dir.path().join("cage-workspace.toml"), manifest).unwrap();
+        dir
+    }
+
+    fn load(dir: &TempDir) -> Result<Workspace, WorkspaceError> {
+        Workspace::load(dir.path().join("cage-workspace.toml").to_str().unwrap())
+    }
+
+    #[test]
+    fn test_members_are_loaded_relative_to_the_manifest() {
+        let dir = workspace_dir(
+            r#"[workspace]
+members = ["services/payments", "services/auth/cage.toml"]
+"#,
+        );
+        let workspace = load(&dir).unwrap();
+        let names: Vec<_> = workspace.members().iter().map(|m| m.name()).collect();
+        assert_eq!(names, ["payments", "auth"]);
+
+        let payments = &workspace.members()[0];
+        assert_eq!(payments.dir(), dir.path().join("services/payments"));
+        assert_eq!(
+            payments.config_path(),
+            dir.path()
+                .join("services/payments/cage.toml")
+                .display()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        let dir = workspace_dir("[workspace]\nmembers = []\n");
+        assert!(matches!(load(&dir), Err(WorkspaceError::NoMembers(_))));
+
+        let dir = workspace_dir("[workspace]\nmembers = [\"services/billing\"]\n");
+        assert!(matches!(
+            load(&dir),
+            Err(WorkspaceError::MissingMemberConfig(entry, _)) if entry == "services/billing"
+        ));
+
+        let dir = workspace_dir("[workspace]\nmember = [\"services/auth\"]\n");
+        assert!(matches!(
+            load(&dir),
+            Err(WorkspaceError::FailedToParseManifest(_))
+        ));
+
+        let dir = workspace_dir(
+            "[workspace]\nmembers = [\"services/auth\", \"services/auth/cage.toml\"]\n",
+        );
+        assert!(matches!(
+            load(&dir),
+            Err(WorkspaceError::DuplicateMember(name)) if name == "auth"
+        ));
+
+        assert!(matches!(
+            Workspace::load("./missing-workspace.toml"),
+            Err(WorkspaceError::MissingManifest(_))
+        ));
+    }
+
+    #[test]
+    fn test_members_can_be_selected_by_name() {
+        let dir = workspace_dir(
+            r#"[workspace]
+members = ["services/payments", "services/auth"]
+"#,
+        );
+        let all = load(&dir).unwrap().select(&[]).unwrap();
+        assert_eq!(all.len(), 2);
+
+        let selected = load(&dir).unwrap().select(&["auth".to_string()]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].name(), "auth");
+        assert_eq!(selected[0].output_dir("./out"), Path::new("./out/auth"));
+
+        assert!(matches!(
+            load(&dir).unwrap().select(&["billing".to_string()]),
+            Err(WorkspaceError::UnknownMember(name, available))
+                if name == "billing" && available == "payments, auth"
+        ));
+    }
+
+    #[test]
+    fn test_member_paths_are_resolved_against_the_member_directory() {
+        let dir = workspace_dir("[workspace]\nmembers = [\"services/payments\"]\n");
+        let workspace = load(&dir).unwrap();
+        let member = &workspace.members()[0];
+        let config = CageConfig::read_uninterpolated(member.config_path()).unwrap();
+
+        let merged = member.with_args(&()).merge_with_config(&config);
+        let member_dir = dir.path().join("services/payments");
+        assert_eq!(
+            merged.dockerfile(),
+            member_dir.join("Dockerfile").display().to_string()
+        );
+        assert_eq!(
+            merged.key(),
+            Some(
+                member_dir
+                    .join("../shared/key.pem")
+                    .display()
+                    .to_string()
+                    .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn test_summary_table() {
+        let dir = workspace_dir(
+            r#"[workspace]
+members = ["services/payments", "services/auth"]
+"#,
+        );
+        let members = load(&dir).unwrap().select(&[]).unwrap();
+        let measurements: EIFMeasurements = serde_json::from_value(serde_json::json!({
+            "HashAlgorithm": "Sha384 { ... }",
+            "PCR0": "0".repeat(96),
+            "PCR1": "1".repeat(96),
+            "PCR2": "2".repeat(96),
+            "PCR8": "8".repeat(96),
+        }))
+        .unwrap();
+        let reports = [
+            MemberReport::new(&members[0], Outcome::Built).with_measurements(&measurements),
+            MemberReport::failed(&members[1], "Build failed".to_string(), exitcode::SOFTWARE),
+        ];
+
+        let abbreviated = |digit: &str| format!("{}…", digit.repeat(PCR_DISPLAY_LENGTH));
+        assert_eq!(
+            summary_table(&reports),
+            format!(
+                "MEMBER    OUTCOME  PCR0               PCR1               PCR2\n\
+                 payments  built    {}  {}  {}\n\
+                 auth      failed   -                  -                  -\n",
+                abbreviated("0"),
+                abbreviated("1"),
+                abbreviated("2"),
+            )
+        );
+    }
+}